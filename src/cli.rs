@@ -0,0 +1,314 @@
+use crate::error::SkySyncError;
+use crate::sync::{TickOutcome, TickSummary};
+use clap::{Args, Parser, Subcommand};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Command-line interface of the `skysync` binary.
+#[derive(Parser, Debug)]
+#[command(name = "skysync", version, about = "Keeps DNS records pointed at this machine's public IP")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Read settings from this TOML file instead of searching the default locations
+    #[arg(long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+    /// Never color the console output. Also implied by `NO_COLOR` or a non-terminal stdout
+    #[arg(long, global = true)]
+    pub no_color: bool,
+    /// Run a single check/update cycle on the saved state and exit with 0 when the records are up
+    /// to date, 1 on a configuration error, 2 if the public IP can't be fetched or 3 if a record
+    /// can't be updated
+    #[arg(long)]
+    pub once: bool,
+    /// Run the first check before the daemon's loop starts, log how it went on its own, and exit
+    /// with the codes of --once if it fails
+    #[arg(long, alias = "interval-once-then-loop", conflicts_with = "once")]
+    pub check_first: bool,
+    /// Load and validate the settings without any network request, print every problem found,
+    /// and exit with 0 if there's none or 1 otherwise
+    #[arg(long, conflicts_with_all = ["once", "check_first"])]
+    pub validate_config: bool,
+    /// Push the current IP even if the records already point at it
+    #[arg(long, requires = "once")]
+    pub force: bool,
+    /// Also log every request to Cloudflare and its raw response, with credentials masked
+    #[arg(long, short, global = true)]
+    pub verbose: bool,
+    /// Print nothing while running, for supervisors that capture stdout. The log file and the
+    /// notifications are unaffected. Also implied by `QUIET=true`
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+    #[command(flatten)]
+    pub settings: SettingFlags,
+}
+
+/// Settings given on the command line, which win over the environment, `.env` and the TOML file.
+#[derive(Args, Debug, Default, PartialEq)]
+#[command(next_help_heading = "Settings")]
+pub struct SettingFlags {
+    /// Check the public IP every this many milliseconds, like CHECK_INTERVAL
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub interval: Option<u64>,
+    /// Record to keep in sync, like CF_DNS_NAME. Repeat it for several. Replaces ZONES
+    #[arg(long = "record", value_name = "NAME")]
+    pub records: Vec<String>,
+    /// Zone holding the records, like CF_ZONE_ID. Replaces ZONES
+    #[arg(long, value_name = "ID")]
+    pub zone_id: Option<String>,
+    /// Cloudflare API key, like CF_API_KEY
+    #[arg(long, value_name = "KEY")]
+    pub api_key: Option<String>,
+    /// Email of the Cloudflare account, like CF_EMAIL
+    #[arg(long, value_name = "EMAIL")]
+    pub email: Option<String>,
+    /// Type of the records, like CF_RECORD_TYPE
+    #[arg(long = "type", value_name = "TYPE")]
+    pub record_type: Option<String>,
+    /// TTL the records are kept at, "auto" or 30 to 86400 seconds, like CF_TTL
+    #[arg(long, value_name = "TTL")]
+    pub ttl: Option<String>,
+    /// Point the records at this public IP instead of detecting it, like IP_OVERRIDE
+    #[arg(long, value_name = "ADDRESS")]
+    pub ip: Option<String>,
+    /// Whether the records are proxied through Cloudflare, like CF_PROXIED
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub proxied: Option<bool>,
+}
+
+impl SettingFlags {
+    /// The flags that were given, as the variables they stand for.
+    pub fn vars(&self) -> HashMap<String, String> {
+        let mut vars = HashMap::new();
+        let mut set = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                vars.insert(key.to_string(), value);
+            }
+        };
+        set("CHECK_INTERVAL", self.interval.map(|ms| ms.to_string()));
+        set("CF_DNS_NAME", (!self.records.is_empty()).then(|| self.records.join(",")));
+        set("CF_ZONE_ID", self.zone_id.clone());
+        set("CF_API_KEY", self.api_key.clone());
+        set("CF_EMAIL", self.email.clone());
+        set("CF_RECORD_TYPE", self.record_type.clone());
+        set("CF_TTL", self.ttl.clone());
+        set("CF_PROXIED", self.proxied.map(|proxied| proxied.to_string()));
+        set("IP_OVERRIDE", self.ip.clone());
+        vars
+    }
+}
+
+/// Subcommands. Without one, SkySync runs as a daemon.
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// Compare every managed record with the detected IP and print what a tick would change,
+    /// without changing anything
+    Diff,
+    /// Print the recorded public IP changes
+    History,
+    /// Print the public IP the configured sources detect, without touching any record
+    PrintIp,
+    /// Write a commented skysync.toml with every setting and its default
+    #[command(alias = "export-config")]
+    Init {
+        /// Where to write it. Prints it when omitted
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+        /// Ask for the zone ID, record name, API credentials and webhook first
+        #[arg(long, short)]
+        interactive: bool,
+        /// Replace the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// List the records currently in the configured Cloudflare zones
+    Records {
+        /// Only list records of this type, e.g. A or AAAA
+        #[arg(long = "type", value_name = "TYPE")]
+        record_type: Option<String>,
+    },
+    /// Run under the Windows service control manager (see `sc create`)
+    Service,
+    /// Walk through a first-time setup, checking the answers with the DNS provider, and write
+    /// a ready-to-run skysync.toml
+    Setup {
+        /// Where to write it
+        #[arg(value_name = "PATH", default_value = "skysync.toml")]
+        path: PathBuf,
+        /// Replace the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Send a sample test event through every configured notifier and report how each went
+    TestNotify,
+}
+
+/// Exit codes of `skysync --once`, for external schedulers. The daemon also exits with
+/// `DnsUpdate` when it can't reach the provider on startup.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exit {
+    /// Nothing needed to change, or every record was brought up to date.
+    Success = 0,
+    /// The configuration couldn't be loaded.
+    Config = 1,
+    /// The public IP couldn't be determined.
+    IpFetch = 2,
+    /// A DNS record couldn't be read, found or written.
+    DnsUpdate = 3,
+}
+
+impl Exit {
+    /// The exit code for the result of a single cycle.
+    pub fn of(result: &Result<TickSummary, SkySyncError>) -> Exit {
+        match result {
+            Ok(summary) if summary.failed() == 0 => Exit::Success,
+            Ok(_)
+            | Err(SkySyncError::Provider(_) | SkySyncError::RecordNotFound(_) | SkySyncError::DuplicateRecords { .. } | SkySyncError::CircuitOpen { .. }) => Exit::DnsUpdate,
+            Err(SkySyncError::IpFetch(_)) => Exit::IpFetch,
+        }
+    }
+
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+/// The name of an outcome in the result line, and how much it says about the cycle as a whole.
+pub(crate) fn rank(outcome: &Result<TickOutcome, SkySyncError>) -> (u8, &'static str) {
+    match outcome {
+        Ok(TickOutcome::Disabled) => (0, "disabled"),
+        Ok(TickOutcome::Unchanged { .. }) => (1, "unchanged"),
+        Ok(TickOutcome::Pending { .. }) => (2, "pending"),
+        Ok(TickOutcome::Deferred { .. }) => (3, "deferred"),
+        Ok(TickOutcome::Reconfigured { .. }) => (4, "reconfigured"),
+        Ok(TickOutcome::Detected { .. }) => (5, "changed"),
+        Ok(TickOutcome::Created { .. }) => (6, "created"),
+        Ok(TickOutcome::Updated { .. }) => (7, "updated"),
+        Ok(TickOutcome::BackingOff { .. }) => (8, "backing_off"),
+        Ok(TickOutcome::Failed { .. }) | Err(_) => (9, "failed"),
+    }
+}
+
+/// Formats the final `key=value` line `skysync --once` prints, e.g.
+/// `result=updated old=1.1.1.1 new=2.2.2.2 record=home.example.com`.
+///
+/// With several records, `result` is the most significant outcome (a failure over an update over
+/// no change) and `record` lists every record that ended up that way.
+///
+/// # Arguments
+/// * `result` - The result of the cycle.
+pub fn result_line(result: &Result<TickSummary, SkySyncError>) -> String {
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(SkySyncError::IpFetch(_)) => return "result=ip_fetch_failed".to_string(),
+        Err(SkySyncError::Provider(_)) => return "result=failed".to_string(),
+        Err(SkySyncError::CircuitOpen { .. }) => return "result=circuit_open".to_string(),
+        Err(SkySyncError::RecordNotFound(name) | SkySyncError::DuplicateRecords { name, .. }) => return format!("result=failed record={}", name),
+    };
+    let Some((_, result)) = summary.records.iter().map(|record| rank(&record.result)).max() else {
+        return "result=unchanged".to_string();
+    };
+    let records: Vec<_> = summary.records.iter().filter(|record| rank(&record.result).1 == result).collect();
+
+    let mut line = format!("result={}", result);
+    match &records[0].result {
+        Ok(TickOutcome::Updated { old, new } | TickOutcome::Detected { old, new }) => line.push_str(&format!(" old={} new={}", old, new)),
+        Ok(
+            TickOutcome::Unchanged { ip }
+            | TickOutcome::Created { ip }
+            | TickOutcome::Reconfigured { ip }
+            | TickOutcome::Pending { ip, .. }
+            | TickOutcome::Deferred { ip, .. }
+            | TickOutcome::BackingOff { ip, .. },
+        ) => {
+            line.push_str(&format!(" new={}", ip))
+        }
+        Ok(TickOutcome::Failed { .. } | TickOutcome::Disabled) | Err(_) => {}
+    }
+    let names: Vec<&str> = records.iter().map(|record| record.name.as_str()).collect();
+    line.push_str(&format!(" record={}", names.join(",")));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::RecordOutcome;
+
+    /// Tests that no subcommand means running the daemon.
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Cli::parse_from(["skysync"]).command, None);
+        assert_eq!(Cli::parse_from(["skysync", "history"]).command, Some(Command::History));
+        assert_eq!(Cli::parse_from(["skysync", "diff"]).command, Some(Command::Diff));
+        assert_eq!(Cli::parse_from(["skysync", "print-ip"]).command, Some(Command::PrintIp));
+        assert_eq!(Cli::parse_from(["skysync", "service"]).command, Some(Command::Service));
+        assert_eq!(Cli::parse_from(["skysync", "setup"]).command, Some(Command::Setup { path: PathBuf::from("skysync.toml"), force: false }));
+        assert_eq!(
+            Cli::parse_from(["skysync", "export-config", "a.toml", "--force"]).command,
+            Some(Command::Init { path: Some(PathBuf::from("a.toml")), interactive: false, force: true }),
+        );
+        assert_eq!(Cli::parse_from(["skysync", "records", "--type", "AAAA"]).command, Some(Command::Records { record_type: Some("AAAA".to_string()) }));
+    }
+
+    /// Tests that `--config` is accepted before or after a subcommand.
+    #[test]
+    fn test_config_flag() {
+        assert_eq!(Cli::parse_from(["skysync"]).config, None);
+        assert_eq!(Cli::parse_from(["skysync", "--config", "a.toml"]).config, Some(PathBuf::from("a.toml")));
+        assert_eq!(Cli::parse_from(["skysync", "service", "--config", "a.toml"]).config, Some(PathBuf::from("a.toml")));
+    }
+
+    fn summary(outcomes: Vec<(&str, Result<TickOutcome, SkySyncError>)>) -> Result<TickSummary, SkySyncError> {
+        Ok(TickSummary {
+            records: outcomes.into_iter().map(|(name, result)| RecordOutcome { name: name.to_string(), result }).collect(),
+        })
+    }
+
+    /// Tests the exit code and result line of every kind of outcome.
+    #[test]
+    fn test_exit_codes_and_result_line() {
+        let updated = || Ok(TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        let unchanged = || Ok(TickOutcome::Unchanged { ip: "2.2.2.2".to_string() });
+        let failed = || Ok(TickOutcome::Failed { error: "rejected".to_string() });
+
+        let cases = [
+            (summary(vec![("home.example.com", updated())]), Exit::Success, "result=updated old=1.1.1.1 new=2.2.2.2 record=home.example.com"),
+            (summary(vec![("a.example.com", unchanged()), ("b.example.com", unchanged())]), Exit::Success, "result=unchanged new=2.2.2.2 record=a.example.com,b.example.com"),
+            (summary(vec![("a.example.com", updated()), ("b.example.com", failed())]), Exit::DnsUpdate, "result=failed record=b.example.com"),
+            (Err(SkySyncError::IpFetch("timed out".into())), Exit::IpFetch, "result=ip_fetch_failed"),
+            (Err(SkySyncError::Provider("forbidden".into())), Exit::DnsUpdate, "result=failed"),
+        ];
+        for (result, exit, line) in cases {
+            assert_eq!(Exit::of(&result), exit, "{}", line);
+            assert_eq!(result_line(&result), line);
+        }
+        assert_eq!((Exit::Success.code(), Exit::Config.code(), Exit::IpFetch.code(), Exit::DnsUpdate.code()), (0, 1, 2, 3));
+    }
+
+    /// Tests that setting flags stand for their variables, and that `records --type` is still its own.
+    #[test]
+    fn test_setting_flags() {
+        assert!(Cli::parse_from(["skysync"]).settings.vars().is_empty());
+
+        let cli = Cli::parse_from(["skysync", "--interval", "60000", "--record", "a.example.com", "--record", "b.example.com", "--proxied", "--ttl", "auto"]);
+        assert_eq!(cli.settings.vars(), HashMap::from([
+            ("CHECK_INTERVAL".to_string(), "60000".to_string()),
+            ("CF_DNS_NAME".to_string(), "a.example.com,b.example.com".to_string()),
+            ("CF_PROXIED".to_string(), "true".to_string()),
+            ("CF_TTL".to_string(), "auto".to_string()),
+        ]));
+        assert_eq!(Cli::parse_from(["skysync", "--proxied=false"]).settings.proxied, Some(false));
+        assert_eq!(Cli::parse_from(["skysync", "--ip", "203.0.113.7"]).settings.vars()["IP_OVERRIDE"], "203.0.113.7");
+        assert!(Cli::try_parse_from(["skysync", "--interval", "0"]).is_err());
+        assert_eq!(Cli::parse_from(["skysync", "records", "--type", "AAAA"]).settings.record_type, None);
+    }
+
+    /// Tests that `--force` is only accepted together with `--once`.
+    #[test]
+    fn test_force_requires_once() {
+        let cli = Cli::parse_from(["skysync", "--once", "--force"]);
+        assert!(cli.once && cli.force);
+        assert!(Cli::try_parse_from(["skysync", "--force"]).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::sync::{RecordOutcome, TickOutcome, TickSummary};
+use owo_colors::OwoColorize;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether console output is colored. Off until `init` decides otherwise.
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Whether console output is suppressed, set by `--quiet` or `QUIET`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// How a line of console output is colored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tone {
+    /// A record was written: green.
+    Success,
+    /// Nothing needed to change, or a change is still settling: yellow.
+    Unchanged,
+    /// Something failed: red.
+    Error,
+}
+
+/// Turns colors on when stdout is a terminal, unless `--no-color` or `NO_COLOR` say otherwise.
+///
+/// The file log is never colored.
+///
+/// # Arguments
+/// * `no_color` - Whether `--no-color` was given.
+pub fn init(no_color: bool) {
+    let enabled = wants_color(no_color, std::env::var_os("NO_COLOR"), std::io::stdout().is_terminal());
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Silences `out` and `err`, and with them everything the daemon prints. The log file is
+/// written as usual.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Whether console output is suppressed.
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints a line to stdout, unless quiet.
+pub fn out(text: &str) {
+    emit(std::io::stdout().lock(), quiet(), text);
+}
+
+/// Prints a line to stderr, unless quiet.
+pub fn err(text: &str) {
+    emit(std::io::stderr().lock(), quiet(), text);
+}
+
+/// Writes `text` as a line to `console`, unless `quiet`. Like `println!`, a console that went
+/// away is ignored rather than failing the caller.
+pub(crate) fn emit(mut console: impl Write, quiet: bool, text: &str) {
+    if !quiet {
+        let _ = writeln!(console, "{}", crate::log::tag(text));
+    }
+}
+
+/// Decides whether to color, following <https://no-color.org>: any non-empty `NO_COLOR` disables it.
+fn wants_color(no_color: bool, no_color_env: Option<OsString>, terminal: bool) -> bool {
+    terminal && !no_color && no_color_env.is_none_or(|value| value.is_empty())
+}
+
+/// Colors `text` in `tone`, or returns it as-is when colors are off.
+pub fn paint(tone: Tone, text: &str) -> String {
+    if !COLOR.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+
+    match tone {
+        Tone::Success => text.green().to_string(),
+        Tone::Unchanged => text.yellow().to_string(),
+        Tone::Error => text.red().to_string(),
+    }
+}
+
+/// Lays out rows under a header in left-aligned columns, two spaces apart.
+///
+/// # Arguments
+/// * `header` - The column titles.
+/// * `rows` - The cells of every row, as many as there are titles.
+pub fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let header = header.map(str::to_string);
+    let widths: Vec<usize> = (0..N)
+        .map(|column| rows.iter().chain([&header]).map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+
+    let mut table = String::new();
+    for row in [&header].into_iter().chain(rows) {
+        let line: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// The tone of a record's outcome.
+pub fn tone(outcome: &RecordOutcome) -> Tone {
+    match &outcome.result {
+        Ok(TickOutcome::Updated { .. } | TickOutcome::Created { .. } | TickOutcome::Reconfigured { .. } | TickOutcome::Detected { .. }) => Tone::Success,
+        Ok(TickOutcome::Unchanged { .. } | TickOutcome::Pending { .. } | TickOutcome::Deferred { .. } | TickOutcome::Disabled) => Tone::Unchanged,
+        Ok(TickOutcome::Failed { .. } | TickOutcome::BackingOff { .. }) | Err(_) => Tone::Error,
+    }
+}
+
+/// A tick summary for the console, each record colored by its outcome.
+pub fn summary(summary: &TickSummary) -> String {
+    summary.headline().into_iter()
+        .chain(summary.records.iter().map(|record| paint(tone(record), &record.to_string())))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SkySyncError;
+
+    /// Tests that colors need a terminal and are turned off by the flag or a non-empty `NO_COLOR`.
+    #[test]
+    fn test_wants_color() {
+        assert!(wants_color(false, None, true));
+        assert!(wants_color(false, Some(OsString::new()), true));
+        assert!(!wants_color(false, None, false));
+        assert!(!wants_color(true, None, true));
+        assert!(!wants_color(false, Some(OsString::from("1")), true));
+    }
+
+    /// Tests that outcomes map to green, yellow and red.
+    #[test]
+    fn test_tone() {
+        let outcome = |result| RecordOutcome { name: "home.example.com".to_string(), result };
+        assert_eq!(tone(&outcome(Ok(TickOutcome::Created { ip: "2.2.2.2".to_string() }))), Tone::Success);
+        assert_eq!(tone(&outcome(Ok(TickOutcome::Pending { ip: "2.2.2.2".to_string(), seen: 1, required: 2 }))), Tone::Unchanged);
+        assert_eq!(tone(&outcome(Ok(TickOutcome::Failed { error: "rejected".to_string() }))), Tone::Error);
+        assert_eq!(tone(&outcome(Err(SkySyncError::RecordNotFound("home.example.com".to_string())))), Tone::Error);
+    }
+}
@@ -0,0 +1,1204 @@
+use crate::cli::Exit;
+use crate::config::{Config, ConfigLoader, Mode};
+use crate::console::{self, Tone};
+use crate::error::SkySyncError;
+use crate::history::History;
+use crate::http;
+use crate::ip::IpCache;
+use crate::last_run::{self, LastRun};
+use crate::log::{self, report, write_log, LogBuffer};
+use crate::notifier::{self, EventStatus, NotifyQueue};
+use crate::server::{self, Trigger};
+use crate::status_file::{Snapshot, StatusFile};
+#[cfg(all(unix, feature = "systemd"))]
+use crate::services::systemd::notify as systemd;
+use crate::sync::{Clients, State, Syncer, TickSummary};
+use crate::update::{UpdateChecker, CURRENT_VERSION, DEFAULT_RELEASES_URL};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, Notify};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+
+#[cfg(all(windows, feature = "windows-service"))]
+pub use crate::services::windows::service::run_as_service;
+
+/// The wait before a tick that couldn't resolve a hostname is retried, doubled after every retry
+/// that fails the same way.
+const RESOLUTION_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// The short hash of the commit SkySync was built from, set by `build.rs`. Empty outside a checkout.
+const GIT_COMMIT: &str = env!("SKYSYNC_GIT_COMMIT");
+
+/// When SkySync was built, in seconds since the epoch, set by `build.rs`.
+const BUILD_TIME: &str = env!("SKYSYNC_BUILD_TIME");
+
+/// The lines printed when the daemon starts, saying what's running and against what, e.g.
+///
+/// ```text
+/// SkySync 0.1.0 (1a2b3c4, built 2026-10-14 12:00 UTC)
+/// Mode sync, provider cloudflare, IP source http, notifiers discord, every 1800s
+/// ```
+pub fn banner(config: &Config) -> String {
+    let built = BUILD_TIME.parse::<i64>().ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|built| format!("built {}", built.format("%Y-%m-%d %H:%M UTC")));
+    let build: Vec<String> = [(!GIT_COMMIT.is_empty()).then(|| GIT_COMMIT.to_string()), built].into_iter().flatten().collect();
+    let version = if build.is_empty() {
+        format!("SkySync {}", CURRENT_VERSION)
+    } else {
+        format!("SkySync {} ({})", CURRENT_VERSION, build.join(", "))
+    };
+
+    format!("{}\n{}", version, settings(config))
+}
+
+/// The settings line of the banner, also sent with `NOTIFY_ON_START`.
+fn settings(config: &Config) -> String {
+    let notifiers: Vec<&str> = config.notifiers.iter().map(|kind| kind.as_str()).collect();
+    let schedule = match &config.cron_schedule {
+        Some(schedule) => format!("on schedule {}", schedule),
+        None => format!("every {:?}", config.cron_interval),
+    };
+    format!(
+        "Mode {}, provider {}, IP source {}, notifiers {}, {}",
+        config.mode.as_str(),
+        config.provider.as_str(),
+        config.ip_source.as_str(),
+        if notifiers.is_empty() { "none".to_string() } else { notifiers.join(", ") },
+        schedule,
+    )
+}
+
+/// Runs the cron loop of every profile until `shutdown` resolves.
+///
+/// A tick already running when `shutdown` resolves is finished first, then the notifications
+/// still waiting are delivered and the state is saved for the next start.
+///
+/// # Arguments
+/// * `loader` - The loader `profiles` came from, used again on `SIGHUP`.
+/// * `profiles` - The configuration of each profile for its first tick, at least one. The log,
+///   the HTTP server and the update check follow the first.
+/// * `check_first` - Run the first tick as the `--check-first` startup check, before the loop.
+/// * `shutdown` - Resolves when the service manager or the user asks SkySync to stop.
+///
+/// # Returns
+/// The exit code to stop with when the startup check failed, even after `STARTUP_RETRIES`
+/// retries, or the first tick did with `check_first`. A profile that fails this way stops
+/// alone, so that's only once every profile did.
+pub async fn run(loader: ConfigLoader, profiles: Vec<Config>, check_first: bool, shutdown: impl Future<Output = ()>) -> Result<(), Exit> {
+    let config = profiles[0].clone();
+    LogBuffer::global().set_limit(config.log_buffer_length);
+
+    // Runs alongside the first tick; a failed check is never worth a message
+    if let Ok(client) = http::client(config.proxy.as_ref(), &config.pool) {
+        let enabled = config.check_updates;
+        tokio::spawn(async move {
+            if let Some(notice) = UpdateChecker::new(DEFAULT_RELEASES_URL, client).notice(enabled).await {
+                console::out(&notice);
+                write_log(&format!("{}\n", notice)).await;
+            }
+        });
+    }
+
+    let (status_tx, status_rx) = watch::channel(State::default());
+    let wake = Arc::new(Notify::new());
+    let http = match config.http_bind {
+        Some(addr) => match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                let history = History::new(config.profile_path(History::default_path()), config.history_length).with_rotate_size(config.history_rotate_size);
+                let trigger = config.trigger.clone().map(|trigger| Trigger::new(trigger, wake.clone()));
+                Some(tokio::spawn(server::serve(listener, status_rx, history, LogBuffer::global().clone(), trigger)))
+            }
+            Err(err) => {
+                report(&format!("Failed to listen on {}, running without the HTTP server: {}", addr, err)).await;
+                None
+            }
+        },
+        None => None,
+    };
+
+    let (config_txs, config_rxs): (Vec<_>, Vec<_>) = profiles.into_iter().map(watch::channel).unzip();
+
+    #[cfg(unix)]
+    let reload = tokio::spawn(reload_on_sighup(loader, config_txs));
+    #[cfg(not(unix))]
+    drop((loader, config_txs));
+
+    #[cfg(all(unix, feature = "systemd"))]
+    let watchdog = systemd::spawn_watchdog();
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+    // The HTTP server reports and triggers the first profile, the others keep to themselves
+    let mut server = Some((status_tx, wake));
+    let profiles = futures::future::join_all(config_rxs.into_iter().map(|config_rx| {
+        let (status_tx, wake) = server.take().unwrap_or_else(|| (watch::channel(State::default()).0, Arc::new(Notify::new())));
+        let profile = config_rx.borrow().profile.clone();
+        log::in_profile(profile, run_profile(config_rx, stop_rx.clone(), check_first, status_tx, wake))
+    }));
+    tokio::pin!(shutdown);
+    tokio::pin!(profiles);
+
+    let results = tokio::select! {
+        results = &mut profiles => results,
+        _ = &mut shutdown => {
+            console::out("Stopping SkySync");
+            stop_tx.send_replace(true);
+            let results = profiles.await;
+            write_log("SkySync stopped\n").await;
+            results
+        }
+    };
+
+    #[cfg(all(unix, feature = "systemd"))]
+    {
+        systemd::stopping();
+        if let Some(watchdog) = watchdog {
+            watchdog.abort();
+        }
+    }
+    #[cfg(unix)]
+    reload.abort();
+    if let Some(http) = http {
+        http.abort();
+    }
+    match results.iter().all(Result::is_err) {
+        true => results.into_iter().find_map(Result::err).map_or(Ok(()), Err),
+        false => Ok(()),
+    }
+}
+
+/// Runs one profile: announces it, runs the startup check and `--check-first`, then the cron
+/// loop until `stop_rx` turns `true`. Its state, history and `last-run.txt` are its own.
+///
+/// # Arguments
+/// * `config_rx` - The latest configuration of the profile.
+/// * `stop_rx` - Set once on shutdown, which also cuts the startup check short.
+/// * `check_first` - Run the first tick as the `--check-first` startup check, before the loop.
+/// * `status_tx` - Receives a copy of the state after every tick.
+/// * `wake` - Starts a check right away when notified, by `POST /trigger`.
+///
+/// # Returns
+/// The exit code the profile stopped with when its startup check failed.
+async fn run_profile(
+    config_rx: watch::Receiver<Config>,
+    mut stop_rx: watch::Receiver<bool>,
+    check_first: bool,
+    status_tx: watch::Sender<State>,
+    wake: Arc<Notify>,
+) -> Result<(), Exit> {
+    let config = config_rx.borrow().clone();
+    let banner = banner(&config);
+    console::out(&banner);
+    write_log(&format!("{}\n", banner)).await;
+    console::out(&config.to_string());
+    write_log(&format!("{}\n", config)).await;
+
+    let syncer = Syncer::from_config(&config);
+    let verified = tokio::select! {
+        verified = start(&syncer, &config) => verified,
+        _ = stop_rx.wait_for(|stop| *stop) => return Ok(()),
+    };
+    if let Err(err) = verified {
+        let msg = format!("Giving up after {} startup attempt(s): {}", config.startup_retries + 1, err);
+        console::err(&console::paint(Tone::Error, &msg));
+        write_log(&format!("{}\n", msg)).await;
+        return Err(Exit::of(&Err(err)));
+    }
+    let state_path = config.profile_path(State::default_path());
+    let first_wait = match check_first {
+        true => {
+            startup_tick(&syncer, &config, &state_path).await?;
+            next_wait(&config)
+        }
+        false => Duration::ZERO,
+    };
+
+    cron(config_rx, stop_rx, state_path, first_wait, status_tx, wake, Syncer::from_clients).await;
+    Ok(())
+}
+
+/// Waits out `INITIAL_DELAY`, then runs the startup check.
+///
+/// # Arguments
+/// * `syncer` - The services the daemon runs with.
+/// * `config` - The configuration of the first tick.
+///
+/// # Returns
+/// The error of the startup check, as `verify_startup` returns it.
+async fn start(syncer: &Syncer, config: &Config) -> Result<(), SkySyncError> {
+    if !config.initial_delay.is_zero() {
+        console::out(&format!("Waiting {:?} before the first check", config.initial_delay));
+        sleep(config.initial_delay).await;
+    }
+    // Monitoring never talks to the provider, so there's nothing to check
+    match config.mode {
+        Mode::Sync => verify_startup(syncer, config.startup_retries, config.startup_retry_delay).await,
+        Mode::Monitor => Ok(()),
+    }
+}
+
+/// Runs the first tick of `--check-first` on the saved state, and logs how it went apart from
+/// the ticks of the loop.
+///
+/// # Arguments
+/// * `syncer` - The services the daemon runs with.
+/// * `config` - The configuration of the first tick.
+/// * `state_path` - Where the state is loaded from, and saved to for the loop to pick up.
+///
+/// # Returns
+/// The exit code to stop with, if the tick failed or any record couldn't be updated.
+async fn startup_tick(syncer: &Syncer, config: &Config, state_path: &Path) -> Result<(), Exit> {
+    console::out("Running the startup check");
+    // The loop reports a state it can't read, and starts fresh just the same
+    let mut state = State::load(state_path, config.state_key.as_ref()).await.unwrap_or_default();
+    let outcome = syncer.reconcile(config, &mut state).await;
+    if let Err(err) = state.save(state_path, config.state_key.as_ref()).await {
+        report(&format!("Failed to save {}: {}", state_path.display(), err)).await;
+    }
+
+    let result = match &outcome {
+        Ok(summary) => summary.to_string(),
+        Err(err) => err.to_string(),
+    };
+    match Exit::of(&outcome) {
+        Exit::Success => {
+            let msg = format!("Startup check passed:\n{}", result);
+            console::out(&console::paint(Tone::Success, &msg));
+            write_log(&format!("{}\n", msg)).await;
+            Ok(())
+        }
+        exit => {
+            let msg = format!("Startup check failed, not starting the loop:\n{}", result);
+            console::err(&console::paint(Tone::Error, &msg));
+            write_log(&format!("{}\n", msg)).await;
+            Err(exit)
+        }
+    }
+}
+
+/// How long until the tick after one starting now: the next `CRON_SCHEDULE` time, or else the
+/// (jittered) interval.
+fn next_wait(config: &Config) -> Duration {
+    match config.cron_schedule.as_ref().and_then(|schedule| schedule.next_after(config.timezone.now(), config.timezone)) {
+        Some(next) => (next - config.timezone.now()).to_std().unwrap_or_default(),
+        None => config.interval_jitter.apply(config.cron_interval, &mut rand::thread_rng()),
+    }
+}
+
+/// Whether two configurations space their ticks the same way, so a reload from one to the other
+/// can leave the wait under way alone.
+fn same_cadence(a: &Config, b: &Config) -> bool {
+    a.cron_interval == b.cron_interval && a.cron_schedule == b.cron_schedule && a.interval_jitter == b.interval_jitter
+}
+
+/// When the next tick fires once a reload changed how ticks are spaced: the new interval counted
+/// from `waiting_since`, when the previous tick ended, or the next time of the new schedule.
+///
+/// A new interval that's already over fires the tick right away, once.
+fn rescheduled(config: &Config, waiting_since: Instant) -> Instant {
+    match config.cron_schedule {
+        Some(_) => Instant::now() + next_wait(config),
+        None => waiting_since + next_wait(config),
+    }
+}
+
+/// Checks that the provider can be reached with the configured credentials, retrying with a
+/// doubling wait so a daemon started at boot outlives a network that isn't up yet.
+///
+/// # Arguments
+/// * `syncer` - The services the daemon runs with.
+/// * `retries` - How many times a failed check is retried.
+/// * `delay` - The wait before the first retry.
+///
+/// # Returns
+/// The error of the last attempt, if none succeeded.
+async fn verify_startup(syncer: &Syncer, retries: u32, delay: Duration) -> Result<(), SkySyncError> {
+    let mut delay = delay;
+    let mut attempt = 1;
+    loop {
+        let err = match syncer.verify().await {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt > retries => return Err(err),
+            Err(err) => err,
+        };
+
+        let msg = format!("Startup check failed (attempt {} of {}), retrying in {:?}: {}", attempt, retries + 1, delay, err);
+        report(&msg).await;
+        sleep(delay).await;
+
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// How soon a tick is retried when it failed because a hostname couldn't be resolved, e.g. right
+/// after a router reboot, instead of waiting for the next regular tick.
+///
+/// # Arguments
+/// * `outcome` - How the tick went.
+/// * `retries` - How many ticks in a row were already retried this way.
+/// * `wait` - The wait until the next regular tick, which a retry never goes past.
+///
+/// # Returns
+/// `RESOLUTION_RETRY_DELAY`, doubled for every earlier retry, or `None` for any other outcome.
+fn resolution_retry(outcome: &Result<TickSummary, SkySyncError>, retries: u32, wait: Duration) -> Option<Duration> {
+    let Err(err) = outcome else {
+        return None;
+    };
+    err.is_resolution_error().then(|| RESOLUTION_RETRY_DELAY.saturating_mul(2u32.saturating_pow(retries)).min(wait))
+}
+
+/// Resolves on Ctrl-C, or on `SIGTERM` on Unix.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Checks and updates the DNS records until `stop_rx` turns `true`, sleeping the (jittered)
+/// interval between ticks.
+///
+/// # Arguments
+/// * `config_rx` - The latest configuration, read at the start of every tick.
+/// * `stop_rx` - Set once on shutdown. A tick in progress runs to completion first.
+/// * `state_path` - Where the state is loaded from at start and saved to after every tick and
+///   on the way out. `last-run.txt` is kept next to it.
+/// * `first_wait` - How long the first tick waits, once `--check-first` already ran one.
+/// * `status_tx` - Receives a copy of the state after every tick, for the HTTP server.
+/// * `wake` - Starts a check right away when notified, instead of waiting for the next one.
+/// * `syncer` - Builds the services of a tick from its configuration, with the HTTP clients
+///   kept across ticks.
+async fn cron(
+    mut config_rx: watch::Receiver<Config>,
+    mut stop_rx: watch::Receiver<bool>,
+    state_path: PathBuf,
+    first_wait: Duration,
+    status_tx: watch::Sender<State>,
+    wake: Arc<Notify>,
+    syncer: impl Fn(&Config, &Clients) -> Syncer,
+) {
+    let state_key = config_rx.borrow().state_key.clone();
+    let mut state = match State::load(&state_path, state_key.as_ref()).await {
+        Ok(state) => state,
+        Err(err) => {
+            report(&format!("Failed to read {}, starting fresh: {}", state_path.display(), err)).await;
+            State::default()
+        }
+    };
+    status_tx.send_replace(state.clone());
+
+    // Connections are kept open across ticks, unless a reload changes how they're made
+    let mut clients = Clients::from_config(&config_rx.borrow());
+    // Ticks only queue their notifications, so a slow webhook can't hold up the next one
+    let (queue, delivery) = NotifyQueue::spawn(notifier::QUEUE_CAPACITY);
+
+    // A crashed or stopped daemon can't report itself, so the next start does
+    let last_run = LastRun::new(state_path.with_file_name(last_run::FILE_NAME));
+    if let Some(previous) = last_run.load().await {
+        let config = config_rx.borrow().clone();
+        if let Some(downtime) = last_run::downtime(&config, previous, config.timezone.now()) {
+            report(&format!("{}, the last successful tick ran at {}", notifier::downtime(downtime.num_minutes()), previous)).await;
+            syncer(&config, &clients).with_queue(queue.clone()).notify_downtime(&config, &state, downtime.num_minutes()).await;
+        }
+    }
+    let started = Instant::now();
+    let config = config_rx.borrow().clone();
+    if config.notify_on_start {
+        syncer(&config, &clients).with_queue(queue.clone()).notify_lifecycle(&config, &state, EventStatus::Started {
+            version: CURRENT_VERSION.to_string(),
+            settings: settings(&config),
+        }).await;
+    }
+    let ip_cache = IpCache::default();
+    // The first run happens right away, later ones wait the (jittered) interval
+    let mut next_check = Instant::now() + first_wait;
+    // When the wait for `next_check` started, and with what schedule, so a reload can redo it
+    let mut waiting_since = Instant::now();
+    let mut scheduled_with = config;
+    // Resyncs are counted from the startup reconciliation, and only scheduled with RESYNC_INTERVAL
+    let mut next_resync: Option<Instant> = None;
+    // The saved state may be out of date, so check it against the records until a tick gets through
+    let mut reconciled = false;
+    // How many ticks in a row were retried early because a hostname couldn't be resolved
+    let mut resolution_retries = 0;
+    #[cfg(all(unix, feature = "systemd"))]
+    let mut ready = false;
+
+    loop {
+        // Wait for the next check or resync, whichever is first, unless asked to stop in the meantime
+        let deadline = next_resync.map_or(next_check, |resync| resync.min(next_check));
+        let triggered = tokio::select! {
+            _ = sleep_until(deadline) => false,
+            _ = wake.notified() => true,
+            Ok(()) = config_rx.changed() => {
+                let reloaded = config_rx.borrow().clone();
+                // A retry of a failed resolution keeps its own, shorter wait
+                if resolution_retries == 0 && !same_cadence(&reloaded, &scheduled_with) {
+                    next_check = rescheduled(&reloaded, waiting_since);
+                    let msg = format!("Interval changed, next run at {:?}", reloaded.timezone.now() + next_check.saturating_duration_since(Instant::now()));
+                    console::out(&msg);
+                    write_log(&format!("{}\n", msg)).await;
+                    scheduled_with = reloaded;
+                }
+                continue;
+            }
+            _ = stop_rx.wait_for(|stop| *stop) => break,
+        };
+        let now = Instant::now();
+        let check = triggered || next_check <= now;
+        let resync = next_resync.is_some_and(|resync| resync <= now);
+
+        // Pick up any configuration reloaded since the last tick
+        let config = config_rx.borrow_and_update().clone();
+        let next_fire = config.cron_schedule.as_ref().and_then(|schedule| schedule.next_after(config.timezone.now(), config.timezone));
+        let wait = next_wait(&config);
+
+        let mut msg: String = String::new();
+        let start_msg = match (triggered, check) {
+            (true, _) => format!("Running triggered check at {:?}\nNext run at {:?}", config.timezone.now(), config.timezone.now() + wait),
+            (false, true) => format!("Running cron job at {:?}\nNext run at {:?}", config.timezone.now(), config.timezone.now() + wait),
+            (false, false) => format!("Running resync at {:?}", config.timezone.now()),
+        };
+        msg.push_str(&start_msg);
+        console::out(&start_msg);
+
+        if !clients.fit(&config) {
+            clients = Clients::from_config(&config);
+        }
+        let syncer = syncer(&config, &clients).with_ip_cache(ip_cache.clone()).with_queue(queue.clone());
+        let outcome = if reconciled && !resync {
+            syncer.run_once(&config, &mut state).await
+        } else {
+            syncer.reconcile(&config, &mut state).await
+        };
+        reconciled |= outcome.is_ok();
+        // Every tick moves the state on, and a crash before the shutdown mustn't lose it
+        if let Err(err) = state.save(&state_path, config.state_key.as_ref()).await {
+            report(&format!("Failed to save {}: {}", state_path.display(), err)).await;
+        }
+        status_tx.send_replace(state.clone());
+        if let Some(path) = &config.status_file {
+            let file = StatusFile::new(path);
+            if let Err(err) = file.write(&Snapshot::new(config.timezone.now(), &state, &outcome)).await {
+                report(&format!("Failed to write {}: {}", file.path().display(), err)).await;
+            }
+        }
+
+        if outcome.as_ref().is_ok_and(|summary| summary.failed() == 0) {
+            if let Err(err) = last_run.record(config.timezone.now()).await {
+                report(&format!("Failed to write {}: {}", last_run.path().display(), err)).await;
+            }
+        }
+
+        // systemd only considers the unit started once a tick has gone through
+        #[cfg(all(unix, feature = "systemd"))]
+        if !ready && outcome.as_ref().is_ok_and(|summary| summary.failed() == 0) {
+            systemd::ready();
+            ready = true;
+        }
+
+        // Scheduled ticks fire at their time, however long this one took
+        let regular = match next_fire {
+            Some(next) => (next - config.timezone.now()).to_std().unwrap_or_default(),
+            None => wait,
+        };
+        let retry = resolution_retry(&outcome, resolution_retries, regular);
+        let result = match outcome {
+            Ok(summary) => {
+                console::out(&console::summary(&summary));
+                summary.to_string()
+            }
+            Err(err) => {
+                console::out(&console::paint(Tone::Error, &err.to_string()));
+                err.to_string()
+            }
+        };
+        msg.push_str(&format!("\n{}", result));
+
+        write_log(&msg).await;
+        if let Some(delay) = retry {
+            report(&format!("Couldn't resolve a hostname, the network may still be coming up: retrying in {:?}", delay)).await;
+            resolution_retries += 1;
+            waiting_since = Instant::now();
+            next_check = waiting_since + delay;
+            scheduled_with = config.clone();
+        } else if check {
+            waiting_since = Instant::now();
+            next_check = waiting_since + regular;
+            scheduled_with = config.clone();
+        }
+        if retry.is_none() {
+            resolution_retries = 0;
+        }
+        // A check tick doesn't move a pending resync, and a changed RESYNC_INTERVAL applies from the next one
+        next_resync = config.resync_interval.map(|interval| match next_resync {
+            Some(at) if !resync => at,
+            _ => Instant::now() + interval,
+        });
+    }
+
+    let config = config_rx.borrow().clone();
+    if !clients.fit(&config) {
+        clients = Clients::from_config(&config);
+    }
+    if config.notify_on_stop {
+        let minutes = (started.elapsed().as_secs() / 60) as i64;
+        syncer(&config, &clients).with_queue(queue.clone()).notify_lifecycle(&config, &state, EventStatus::Stopped { minutes }).await;
+    }
+    if !queue.is_empty() {
+        console::out(&format!("Delivering {} queued notification(s) before stopping", queue.len()));
+    }
+    queue.close();
+    if let Err(err) = delivery.await {
+        report(&format!("Notification delivery stopped unexpectedly: {}", err)).await;
+    }
+
+    if let Err(err) = state.save(&state_path, config.state_key.as_ref()).await {
+        report(&format!("Failed to save {}: {}", state_path.display(), err)).await;
+    }
+}
+
+/// Re-reads the configuration every time the process receives `SIGHUP`.
+///
+/// A configuration that fails to load or validate is logged and discarded, so the running
+/// loops keep using the last good one. So is one that adds or removes profiles, which takes a
+/// restart.
+///
+/// # Arguments
+/// * `loader` - The loader used at startup.
+/// * `config_txs` - The channels the cron loop of each profile reads its configuration from.
+#[cfg(unix)]
+async fn reload_on_sighup(loader: ConfigLoader, config_txs: Vec<watch::Sender<Config>>) {
+    let mut hangup = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+
+    while hangup.recv().await.is_some() {
+        let profiles = match loader.load_profiles() {
+            Ok(profiles) => profiles,
+            Err(err) => {
+                report(&format!("Rejected configuration reload, keeping the current one: {}", err)).await;
+                continue;
+            }
+        };
+        if profiles.iter().map(|config| config.profile.clone()).ne(config_txs.iter().map(|config_tx| config_tx.borrow().profile.clone())) {
+            report("Rejected configuration reload, keeping the current one: PROFILES names other profiles, which takes a restart").await;
+            continue;
+        }
+
+        console::out("Configuration reloaded");
+        write_log("Configuration reloaded\n").await;
+        for (config_tx, config) in config_txs.iter().zip(profiles) {
+            log::in_profile(config.profile.clone(), async {
+                for warning in config.warnings() {
+                    report(&format!("Warning: {}", warning)).await;
+                }
+            }).await;
+            config_tx.send_replace(config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::BoxError;
+    use crate::http::tests::resolution_error;
+    use crate::history::History;
+    use crate::ip::IpSource;
+    use crate::provider::{DnsProvider, DnsRecord};
+    use crate::notifier::{EventStatus, Notifier, UpdateEvent};
+    use crate::sync::tests::{record, test_config, MockProvider, RecordingNotifier, StaticIp};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::{mpsc, Semaphore};
+
+    /// An IP source that reports when a tick has started, then takes a while to answer.
+    struct SlowIp(mpsc::UnboundedSender<()>);
+
+    #[async_trait]
+    impl IpSource for SlowIp {
+        async fn public_ip(&self) -> Result<String, BoxError> {
+            let _ = self.0.send(());
+            sleep(Duration::from_millis(50)).await;
+            Ok("2.2.2.2\n".to_string())
+        }
+    }
+
+    /// Tests that the banner names the version and the resolved provider, source and notifiers.
+    #[test]
+    fn test_banner() {
+        let config = Config { provider: crate::config::ProviderKind::Gandi, ..test_config() };
+
+        let banner = banner(&config);
+
+        assert!(banner.starts_with(&format!("SkySync {}", CURRENT_VERSION)), "{}", banner);
+        assert!(banner.contains("provider gandi, IP source http"), "{}", banner);
+        assert_eq!(banner.lines().count(), 2);
+    }
+
+    /// Tests that a shutdown in the middle of a tick lets it finish and saves the state it ended in.
+    #[tokio::test]
+    async fn test_shutdown_mid_tick_saves_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+        let history_path = dir.path().join("history.json");
+
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let (_config_tx, config_rx) = watch::channel(test_config());
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (status_tx, status_rx) = watch::channel(State::default());
+
+        let syncer = {
+            let provider = provider.clone();
+            let history_path = history_path.clone();
+            move |config: &Config, _: &Clients| {
+                Syncer::new(Box::new(provider.clone()), Box::new(SlowIp(started_tx.clone())), Vec::new())
+                    .with_history(History::new(&history_path, config.history_length))
+            }
+        };
+        let shutdown = async {
+            started_rx.recv().await.unwrap();
+            stop_tx.send_replace(true);
+        };
+        tokio::join!(cron(config_rx, stop_rx, state_path.clone(), Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer), shutdown);
+
+        let state = State::load(&state_path, None).await.unwrap();
+        assert_eq!(state.last_public_ip.as_deref(), Some("2.2.2.2"));
+        assert_eq!(status_rx.borrow().records, state.records);
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+
+        let entries = History::new(&history_path, 50).load().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].new_ip, "2.2.2.2");
+        assert!(!dir.path().join("state.json.tmp").exists());
+        assert!(LastRun::new(dir.path().join(last_run::FILE_NAME)).load().await.is_some());
+    }
+
+    /// Tests that every tick saves the state, without waiting for the daemon to stop.
+    #[tokio::test]
+    async fn test_tick_saves_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let (_config_tx, config_rx) = watch::channel(test_config());
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (status_tx, mut status_rx) = watch::channel(State::default());
+
+        let syncer = {
+            let provider = provider.clone();
+            move |_: &Config, _: &Clients| Syncer::new(Box::new(provider.clone()), Box::new(StaticIp("2.2.2.2")), Vec::new())
+        };
+        let saved = async {
+            status_rx.wait_for(|state| state.last_check.is_some()).await.unwrap();
+            let state = State::load(&state_path, None).await.unwrap();
+            stop_tx.send_replace(true);
+            state
+        };
+        let (_, state) = tokio::join!(cron(config_rx, stop_rx, state_path.clone(), Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer), saved);
+
+        assert_eq!(state.last_public_ip.as_deref(), Some("2.2.2.2"));
+        assert_eq!(state.records.get("home.example.com").map(String::as_str), Some("2.2.2.2"));
+    }
+
+    /// Tests that a start long after the last successful tick reports the downtime, and that the
+    /// tick then replaces the old time.
+    #[tokio::test]
+    async fn test_reports_downtime_on_stale_last_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let last_run = LastRun::new(dir.path().join(last_run::FILE_NAME));
+        let stale = test_config().timezone.now() - chrono::Duration::hours(2);
+        last_run.record(stale).await.unwrap();
+
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let (_config_tx, config_rx) = watch::channel(test_config());
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (status_tx, mut status_rx) = watch::channel(State::default());
+
+        let syncer = {
+            let provider = provider.clone();
+            let notifier = notifier.clone();
+            move |_: &Config, _: &Clients| Syncer::new(Box::new(provider.clone()), Box::new(StaticIp("2.2.2.2")), vec![Box::new(notifier.clone())])
+        };
+        let shutdown = async {
+            status_rx.wait_for(|state| state.last_check.is_some()).await.unwrap();
+            stop_tx.send_replace(true);
+        };
+        tokio::join!(cron(config_rx, stop_rx, dir.path().join("state.json"), Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer), shutdown);
+
+        let events = notifier.events.lock().unwrap().clone();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0].status, EventStatus::Downtime { minutes: 120 }), "{:?}", events[0].status);
+        assert!(last_run.load().await.unwrap() > stale);
+    }
+
+    /// Tests that `NOTIFY_ON_START` and `NOTIFY_ON_STOP` notify exactly once each over a run of
+    /// the loop, and nothing at all when left off.
+    #[tokio::test]
+    async fn test_lifecycle_notifications() {
+        for enabled in [true, false] {
+            let dir = tempfile::tempdir().unwrap();
+            let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+            let notifier = RecordingNotifier::default();
+            let config = Config { notify_on_start: enabled, notify_on_stop: enabled, ..test_config() };
+            let (_config_tx, config_rx) = watch::channel(config);
+            let (stop_tx, stop_rx) = watch::channel(false);
+            let (status_tx, mut status_rx) = watch::channel(State::default());
+
+            let syncer = {
+                let provider = provider.clone();
+                let notifier = notifier.clone();
+                move |_: &Config, _: &Clients| Syncer::new(Box::new(provider.clone()), Box::new(StaticIp("2.2.2.2")), vec![Box::new(notifier.clone())])
+            };
+            let shutdown = async {
+                status_rx.wait_for(|state| state.last_check.is_some()).await.unwrap();
+                stop_tx.send_replace(true);
+            };
+            tokio::join!(cron(config_rx, stop_rx, dir.path().join("state.json"), Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer), shutdown);
+
+            let statuses: Vec<EventStatus> = notifier.events.lock().unwrap().iter().map(|event| event.status.clone()).collect();
+            if enabled {
+                assert_eq!(statuses.len(), 2, "{:?}", statuses);
+                assert!(matches!(&statuses[0], EventStatus::Started { version, .. } if version == CURRENT_VERSION), "{:?}", statuses);
+                assert_eq!(statuses[1], EventStatus::Stopped { minutes: 0 });
+                assert_eq!(notifier.events.lock().unwrap()[1].new_ip, "2.2.2.2");
+            } else {
+                assert!(statuses.is_empty(), "{:?}", statuses);
+            }
+        }
+    }
+
+    /// Tests that a resync tick reads the records and fixes one edited behind SkySync's back, even
+    /// though the public IP hasn't changed and no check tick is due.
+    #[tokio::test]
+    async fn test_resync_reconciles_unchanged_ip() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let config = Config {
+            cron_interval: Duration::from_secs(3600),
+            resync_interval: Some(Duration::from_millis(20)),
+            ..test_config()
+        };
+        let (_config_tx, config_rx) = watch::channel(config);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (status_tx, _status_rx) = watch::channel(State::default());
+
+        let syncer = {
+            let provider = provider.clone();
+            move |_: &Config, _: &Clients| Syncer::new(Box::new(provider.clone()), Box::new(StaticIp("2.2.2.2")), Vec::new())
+        };
+        let drift = async {
+            while *provider.listings.lock().unwrap() == 0 {
+                sleep(Duration::from_millis(1)).await;
+            }
+            provider.records.lock().unwrap()[0].content = "9.9.9.9".to_string();
+            while provider.writes.lock().unwrap().is_empty() {
+                sleep(Duration::from_millis(1)).await;
+            }
+            stop_tx.send_replace(true);
+        };
+        let run = async { tokio::join!(cron(config_rx, stop_rx, dir.path().join("state.json"), Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer), drift) };
+        tokio::time::timeout(Duration::from_secs(5), run).await.expect("the resync never ran");
+
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+        assert_eq!(*provider.listings.lock().unwrap(), 2);
+    }
+
+    /// An IP source that counts the ticks asking it for the public IP.
+    #[derive(Clone, Default)]
+    struct CountingIp(Arc<AtomicU32>);
+
+    #[async_trait]
+    impl IpSource for CountingIp {
+        async fn public_ip(&self) -> Result<String, BoxError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok("2.2.2.2\n".to_string())
+        }
+    }
+
+    /// Tests that two profiles tick on their own intervals, and that neither one's failures, state
+    /// or notifications end up in the other's.
+    #[tokio::test]
+    async fn test_profiles_tick_independently() {
+        let dir = tempfile::tempdir().unwrap();
+        let fast = Config { profile: Some("fast".to_string()), cron_interval: Duration::from_millis(10), ..test_config() };
+        let slow = Config { profile: Some("slow".to_string()), cron_interval: Duration::from_secs(3600), ..test_config() };
+        let fast_path = fast.profile_path(dir.path().join("state.json"));
+        let slow_path = slow.profile_path(dir.path().join("state.json"));
+
+        let fast_provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let slow_provider = MockProvider { fail_listings: true, ..MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]) };
+        let (fast_ip, slow_ip) = (CountingIp::default(), CountingIp::default());
+        let notifier = RecordingNotifier::default();
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let profile = |config: Config, provider: MockProvider, ip: CountingIp, state_path: PathBuf| {
+            let (_config_tx, config_rx) = watch::channel(config.clone());
+            let (status_tx, _status_rx) = watch::channel(State::default());
+            let notifier = notifier.clone();
+            let syncer = move |config: &Config, _: &Clients| {
+                Syncer::new(Box::new(provider.clone()), Box::new(ip.clone()), vec![Box::new(notifier.clone())]).with_profile(config.profile.clone())
+            };
+            log::in_profile(config.profile, cron(config_rx, stop_rx.clone(), state_path, Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer))
+        };
+
+        let stop = async {
+            while fast_ip.0.load(Ordering::SeqCst) < 3 {
+                sleep(Duration::from_millis(1)).await;
+            }
+            stop_tx.send_replace(true);
+        };
+        let run = async {
+            tokio::join!(
+                profile(fast, fast_provider.clone(), fast_ip.clone(), fast_path.clone()),
+                profile(slow, slow_provider.clone(), slow_ip.clone(), slow_path.clone()),
+                stop,
+            )
+        };
+        tokio::time::timeout(Duration::from_secs(5), run).await.expect("the fast profile stopped ticking");
+
+        // The slow profile's only tick fails listing the records, before it asks for the IP
+        assert!(fast_ip.0.load(Ordering::SeqCst) >= 3);
+        assert_eq!((*slow_provider.listings.lock().unwrap(), slow_ip.0.load(Ordering::SeqCst)), (1, 0));
+        let (fast_state, slow_state) = (State::load(&fast_path, None).await.unwrap(), State::load(&slow_path, None).await.unwrap());
+        assert_eq!(fast_state.records.get("home.example.com").map(String::as_str), Some("2.2.2.2"));
+        assert_eq!(fast_state.failure_streak, 0);
+        assert!(slow_state.records.is_empty());
+        assert!(slow_provider.writes.lock().unwrap().is_empty());
+        assert!(fast_path.with_file_name(last_run::FILE_NAME).exists());
+        assert!(!slow_path.with_file_name(last_run::FILE_NAME).exists());
+
+        let domains: Vec<String> = notifier.events.lock().unwrap().iter().map(|event| event.domain.clone()).collect();
+        assert!(domains.contains(&"[fast] home.example.com".to_string()), "{:?}", domains);
+        assert!(domains.iter().all(|domain| domain.starts_with("[fast] ") || domain.starts_with("[slow] ")), "{:?}", domains);
+    }
+
+    /// Tests that `POST /trigger` runs a check right away for a caller with the secret, long
+    /// before the next one is due, and that callers without it or too soon after are turned away.
+    #[tokio::test]
+    async fn test_trigger_runs_check() {
+        use crate::config::TriggerConfig;
+
+        let dir = tempfile::tempdir().unwrap();
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let ip = CountingIp::default();
+        let config = Config { cron_interval: Duration::from_secs(3600), ..test_config() };
+        let (_config_tx, config_rx) = watch::channel(config);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (status_tx, status_rx) = watch::channel(State::default());
+        let wake = Arc::new(Notify::new());
+        let trigger = Trigger::new(TriggerConfig { secret: "s3cret".to_string(), min_interval: Duration::from_secs(60) }, wake.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/trigger", listener.local_addr().unwrap());
+        let history = History::new(dir.path().join("history.json"), 50);
+        let server = tokio::spawn(server::serve(listener, status_rx, history, LogBuffer::new(10), Some(trigger)));
+        let syncer = {
+            let provider = provider.clone();
+            let ip = ip.clone();
+            move |_: &Config, _: &Clients| Syncer::new(Box::new(provider.clone()), Box::new(ip.clone()), Vec::new())
+        };
+        let ticks = ip.0.clone();
+        let wait_for = |count: u32| {
+            let ticks = ticks.clone();
+            async move {
+                while ticks.load(Ordering::SeqCst) < count {
+                    sleep(Duration::from_millis(1)).await;
+                }
+            }
+        };
+        let client = reqwest::Client::new();
+        let calls = async {
+            wait_for(1).await;
+            let rejected = client.post(&url).bearer_auth("guess").send().await.unwrap();
+            assert_eq!(rejected.status(), reqwest::StatusCode::UNAUTHORIZED);
+            sleep(Duration::from_millis(50)).await;
+            assert_eq!(ticks.load(Ordering::SeqCst), 1);
+
+            let accepted = client.post(&url).bearer_auth("s3cret").send().await.unwrap();
+            assert_eq!(accepted.status(), reqwest::StatusCode::ACCEPTED);
+            wait_for(2).await;
+
+            let limited = client.post(&url).bearer_auth("s3cret").send().await.unwrap();
+            assert_eq!(limited.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(limited.headers()["retry-after"], "60");
+            stop_tx.send_replace(true);
+        };
+        let run = async { tokio::join!(cron(config_rx, stop_rx, dir.path().join("state.json"), Duration::ZERO, status_tx, wake.clone(), syncer), calls) };
+        tokio::time::timeout(Duration::from_secs(5), run).await.expect("the trigger never ran a check");
+        server.abort();
+
+        assert_eq!(ticks.load(Ordering::SeqCst), 2);
+    }
+
+    /// Tests that a reload shortening the interval cuts the wait already under way short, so the
+    /// following ticks fire at the new cadence instead of after the old interval.
+    #[tokio::test]
+    async fn test_reloaded_interval_applies_to_pending_wait() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let ip = CountingIp::default();
+        let config = Config { cron_interval: Duration::from_secs(3600), ..test_config() };
+        let (config_tx, config_rx) = watch::channel(config.clone());
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (status_tx, _status_rx) = watch::channel(State::default());
+
+        let syncer = {
+            let provider = provider.clone();
+            let ip = ip.clone();
+            move |_: &Config, _: &Clients| Syncer::new(Box::new(provider.clone()), Box::new(ip.clone()), Vec::new())
+        };
+        let reload = async {
+            while ip.0.load(Ordering::SeqCst) < 1 {
+                sleep(Duration::from_millis(1)).await;
+            }
+            // The first tick is done and waiting out the hour when the interval drops
+            sleep(Duration::from_millis(20)).await;
+            assert_eq!(ip.0.load(Ordering::SeqCst), 1);
+            config_tx.send_replace(Config { cron_interval: Duration::from_millis(10), ..config });
+            while ip.0.load(Ordering::SeqCst) < 4 {
+                sleep(Duration::from_millis(1)).await;
+            }
+            stop_tx.send_replace(true);
+        };
+        let run = async { tokio::join!(cron(config_rx, stop_rx, dir.path().join("state.json"), Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer), reload) };
+        tokio::time::timeout(Duration::from_secs(5), run).await.expect("the ticks kept the old interval");
+
+        assert!(ip.0.load(Ordering::SeqCst) >= 4);
+    }
+
+    /// Tests that a reload only moves the wait under way when the ticks are spaced another way.
+    #[test]
+    fn test_rescheduled_wait() {
+        let config = Config { cron_interval: Duration::from_secs(60), ..test_config() };
+        let since = Instant::now();
+
+        assert!(same_cadence(&config, &Config { startup_retries: 9, ..config.clone() }));
+        assert!(!same_cadence(&config, &Config { cron_interval: Duration::from_secs(30), ..config.clone() }));
+        assert_eq!(rescheduled(&Config { cron_interval: Duration::from_secs(30), ..config.clone() }, since), since + Duration::from_secs(30));
+        // An interval that's already over fires once, right away
+        assert!(rescheduled(&Config { cron_interval: Duration::ZERO, ..config }, since) <= Instant::now());
+    }
+
+    /// A notifier that holds every delivery until the test lets it through.
+    #[derive(Clone)]
+    struct GatedNotifier {
+        gate: Arc<Semaphore>,
+        inner: RecordingNotifier,
+    }
+
+    #[async_trait]
+    impl Notifier for GatedNotifier {
+        fn name(&self) -> &'static str {
+            "gated"
+        }
+
+        async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+            self.gate.acquire().await?.forget();
+            self.inner.notify(event).await
+        }
+    }
+
+    /// Tests that a notifier stuck delivering doesn't hold up the next tick, and that what it
+    /// still had queued is delivered before the loop returns.
+    #[tokio::test]
+    async fn test_slow_notifier_doesnt_delay_ticks() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let ip = CountingIp::default();
+        let notifier = GatedNotifier { gate: Arc::new(Semaphore::new(0)), inner: RecordingNotifier::default() };
+        let config = Config { cron_interval: Duration::from_millis(10), ..test_config() };
+        let (_config_tx, config_rx) = watch::channel(config);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (status_tx, _status_rx) = watch::channel(State::default());
+
+        let syncer = {
+            let provider = provider.clone();
+            let ip = ip.clone();
+            let notifier = notifier.clone();
+            move |_: &Config, _: &Clients| Syncer::new(Box::new(provider.clone()), Box::new(ip.clone()), vec![Box::new(notifier.clone())])
+        };
+        let release = async {
+            while ip.0.load(Ordering::SeqCst) < 3 {
+                sleep(Duration::from_millis(1)).await;
+            }
+            assert!(notifier.inner.events.lock().unwrap().is_empty());
+            stop_tx.send_replace(true);
+            notifier.gate.add_permits(1);
+        };
+        let run = async { tokio::join!(cron(config_rx, stop_rx, dir.path().join("state.json"), Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer), release) };
+        tokio::time::timeout(Duration::from_secs(5), run).await.expect("the ticks waited for the notifier");
+
+        let events = notifier.inner.events.lock().unwrap().clone();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0].status, EventStatus::Reconciled { updated: 1, .. }), "{:?}", events[0].status);
+    }
+
+    /// A provider that can't be reached for its first `failures` listings.
+    #[derive(Default)]
+    struct UnreachableProvider {
+        failures: u32,
+        listings: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl DnsProvider for UnreachableProvider {
+        fn name(&self) -> &'static str {
+            "unreachable"
+        }
+
+        async fn list_records(&self) -> Result<Vec<DnsRecord>, BoxError> {
+            if self.listings.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err("network is unreachable".into());
+            }
+            Ok(Vec::new())
+        }
+
+        async fn update_record(&self, _: &DnsRecord, _: &str) -> Result<(), BoxError> {
+            Err("network is unreachable".into())
+        }
+
+        async fn create_record(&self, _: &str, _: &str) -> Result<(), BoxError> {
+            Err("network is unreachable".into())
+        }
+    }
+
+    /// An IP source failing every lookup with one of the resolution errors it was given, and
+    /// noting when it was asked.
+    #[derive(Clone, Default)]
+    struct UnresolvedIp {
+        errors: Arc<std::sync::Mutex<Vec<BoxError>>>,
+        asked: Arc<std::sync::Mutex<Vec<Instant>>>,
+    }
+
+    #[async_trait]
+    impl IpSource for UnresolvedIp {
+        async fn public_ip(&self) -> Result<String, BoxError> {
+            self.asked.lock().unwrap().push(Instant::now());
+            Err(self.errors.lock().unwrap().pop().unwrap_or_else(|| "out of resolution errors".into()))
+        }
+    }
+
+    /// Tests that a tick failing to resolve the IP endpoint is retried sooner and sooner, never
+    /// past the next regular tick, while other failures wait for it as usual.
+    #[tokio::test]
+    async fn test_resolution_failure_retries_early() {
+        use crate::ip::HttpIpSource;
+
+        let unresolved = Err(SkySyncError::IpFetch(resolution_error().await));
+        let client = http::ip_client(None, &Default::default(), &[], None).unwrap();
+        let refused = Err(SkySyncError::IpFetch(HttpIpSource::new("http://127.0.0.1:9/", client).public_ip().await.unwrap_err()));
+        let interval = Duration::from_secs(1800);
+
+        assert_eq!(resolution_retry(&unresolved, 0, interval), Some(RESOLUTION_RETRY_DELAY));
+        assert_eq!(resolution_retry(&unresolved, 3, interval), Some(RESOLUTION_RETRY_DELAY * 8));
+        assert_eq!(resolution_retry(&unresolved, 40, interval), Some(interval));
+        assert_eq!(resolution_retry(&refused, 0, interval), None);
+        assert_eq!(resolution_retry(&Ok(TickSummary { records: Vec::new() }), 0, interval), None);
+    }
+
+    /// Tests that the cron loop waits `RESOLUTION_RETRY_DELAY` before retrying a tick that
+    /// couldn't resolve a hostname, instead of retrying it right away.
+    #[tokio::test]
+    async fn test_resolution_retry_waits_the_delay() {
+        let dir = tempfile::tempdir().unwrap();
+        let ip = UnresolvedIp::default();
+        // Made before the clock is paused, as the lookups time out on it
+        for _ in 0..3 {
+            let err = resolution_error().await;
+            ip.errors.lock().unwrap().push(err);
+        }
+        tokio::time::pause();
+        let config = Config { cron_interval: Duration::from_secs(1800), ..test_config() };
+        let (_config_tx, config_rx) = watch::channel(config);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (status_tx, _status_rx) = watch::channel(State::default());
+        let syncer = {
+            let ip = ip.clone();
+            move |_: &Config, _: &Clients| Syncer::new(Box::new(MockProvider::default()), Box::new(ip.clone()), Vec::new())
+        };
+
+        let release = async {
+            while ip.asked.lock().unwrap().len() < 2 {
+                sleep(Duration::from_millis(1)).await;
+            }
+            stop_tx.send_replace(true);
+        };
+        let run = async { tokio::join!(cron(config_rx, stop_rx, dir.path().join("state.json"), Duration::ZERO, status_tx, Arc::new(Notify::new()), syncer), release) };
+        tokio::time::timeout(Duration::from_secs(600), run).await.expect("the tick was retried");
+
+        let asked = ip.asked.lock().unwrap().clone();
+        let waited = asked[1] - asked[0];
+        assert!(waited >= RESOLUTION_RETRY_DELAY && waited < RESOLUTION_RETRY_DELAY + Duration::from_secs(1), "{:?}", waited);
+    }
+
+    /// Tests that the startup check outlives two failures, and gives up once the retries run out.
+    #[tokio::test]
+    async fn test_startup_check_retries() {
+        let syncer = |listings: &Arc<AtomicU32>| {
+            let provider = UnreachableProvider { failures: 2, listings: listings.clone() };
+            Syncer::new(Box::new(provider), Box::new(StaticIp("2.2.2.2")), Vec::new())
+        };
+
+        let listings = Arc::new(AtomicU32::new(0));
+        verify_startup(&syncer(&listings), 2, Duration::from_millis(1)).await.unwrap();
+        assert_eq!(listings.load(Ordering::SeqCst), 3);
+
+        let listings = Arc::new(AtomicU32::new(0));
+        let err = verify_startup(&syncer(&listings), 1, Duration::from_millis(1)).await.unwrap_err();
+        assert!(matches!(err, SkySyncError::Provider(_)), "{}", err);
+        assert_eq!(listings.load(Ordering::SeqCst), 2);
+    }
+
+    /// Tests that nothing is asked of the provider until `INITIAL_DELAY` has passed.
+    #[tokio::test]
+    async fn test_initial_delay() {
+        let listings = Arc::new(AtomicU32::new(0));
+        let provider = UnreachableProvider { failures: 0, listings: listings.clone() };
+        let syncer = Syncer::new(Box::new(provider), Box::new(StaticIp("2.2.2.2")), Vec::new());
+        let config = Config { initial_delay: Duration::from_millis(200), ..test_config() };
+
+        let started = Instant::now();
+        let check = async {
+            sleep(Duration::from_millis(100)).await;
+            listings.load(Ordering::SeqCst)
+        };
+        let (verified, early) = tokio::join!(start(&syncer, &config), check);
+
+        verified.unwrap();
+        assert_eq!(early, 0);
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    /// Tests that a failed `--check-first` tick stops the daemon with the exit code of `--once`,
+    /// and that a passing one leaves its state for the loop.
+    #[tokio::test]
+    async fn test_startup_tick() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+
+        let provider = UnreachableProvider { failures: u32::MAX, listings: Arc::new(AtomicU32::new(0)) };
+        let syncer = Syncer::new(Box::new(provider), Box::new(StaticIp("2.2.2.2")), Vec::new());
+        assert_eq!(startup_tick(&syncer, &test_config(), &state_path).await, Err(Exit::DnsUpdate));
+
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let syncer = Syncer::new(Box::new(provider.clone()), Box::new(StaticIp("2.2.2.2")), Vec::new());
+        startup_tick(&syncer, &test_config(), &state_path).await.unwrap();
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+        assert_eq!(State::load(&state_path, None).await.unwrap().records.get("home.example.com").map(String::as_str), Some("2.2.2.2"));
+    }
+}
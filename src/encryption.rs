@@ -0,0 +1,113 @@
+//! Encryption of `state.json` at rest, with a key derived from `SKYSYNC_STATE_KEY`.
+//!
+//! An encrypted file starts with `HEADER`, followed by the PBKDF2 salt, the nonce and the state
+//! sealed with ChaCha20-Poly1305, so a damaged file or a wrong key is caught rather than read as
+//! garbage. Sealing and opening need the `state-encryption` feature.
+use crate::config::StateKey;
+use crate::error::BoxError;
+
+/// What an encrypted file starts with, and the version of its layout.
+pub const HEADER: &[u8] = b"SKYSYNC-ENCRYPTED-1\n";
+
+/// How many rounds of PBKDF2-HMAC-SHA256 the key is derived with.
+#[cfg(feature = "state-encryption")]
+const ITERATIONS: u32 = 100_000;
+
+#[cfg(feature = "state-encryption")]
+const SALT_LEN: usize = 16;
+
+/// Whether `data` was written by `seal`.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(HEADER)
+}
+
+#[cfg(feature = "state-encryption")]
+fn derive(key: &StateKey, salt: &[u8]) -> ring::aead::LessSafeKey {
+    use ring::aead::{LessSafeKey, UnboundKey, CHACHA20_POLY1305};
+    let mut bytes = [0; 32];
+    let iterations = std::num::NonZeroU32::new(ITERATIONS).unwrap_or(std::num::NonZeroU32::MIN);
+    ring::pbkdf2::derive(ring::pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, key.passphrase().as_bytes(), &mut bytes);
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &bytes).expect("ChaCha20-Poly1305 takes a 32-byte key"))
+}
+
+/// Encrypts `plaintext` with a fresh salt and nonce.
+///
+/// # Returns
+/// The file contents, starting with `HEADER`.
+pub fn seal(key: &StateKey, plaintext: &[u8]) -> Result<Vec<u8>, BoxError> {
+    #[cfg(feature = "state-encryption")]
+    {
+        use ring::aead::{Aad, Nonce, NONCE_LEN};
+        use ring::rand::{SecureRandom, SystemRandom};
+
+        let random = SystemRandom::new();
+        let (mut salt, mut nonce) = ([0; SALT_LEN], [0; NONCE_LEN]);
+        random.fill(&mut salt).map_err(|_| "no randomness available for the salt")?;
+        random.fill(&mut nonce).map_err(|_| "no randomness available for the nonce")?;
+
+        let mut sealed = plaintext.to_vec();
+        derive(key, &salt)
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(HEADER), &mut sealed)
+            .map_err(|_| "failed to encrypt")?;
+        Ok([HEADER, &salt, &nonce, &sealed].concat())
+    }
+    #[cfg(not(feature = "state-encryption"))]
+    {
+        let _ = (key, plaintext);
+        Err("SkySync was built without the `state-encryption` feature".into())
+    }
+}
+
+/// Decrypts what `seal` wrote.
+///
+/// # Returns
+/// The plaintext, or an error if `data` isn't an encrypted file, or can't be opened with `key`
+/// because the key is wrong or the file was damaged.
+pub fn open(key: &StateKey, data: &[u8]) -> Result<Vec<u8>, BoxError> {
+    #[cfg(feature = "state-encryption")]
+    {
+        use ring::aead::{Aad, Nonce, CHACHA20_POLY1305, NONCE_LEN};
+
+        let body = data.strip_prefix(HEADER).ok_or("the file isn't encrypted")?;
+        if body.len() < SALT_LEN + NONCE_LEN + CHACHA20_POLY1305.tag_len() {
+            return Err("the encrypted file is truncated".into());
+        }
+        let (salt, rest) = body.split_at(SALT_LEN);
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "the encrypted file is truncated")?;
+
+        let mut sealed = sealed.to_vec();
+        let plaintext = derive(key, salt)
+            .open_in_place(nonce, Aad::from(HEADER), &mut sealed)
+            .map_err(|_| "can't be decrypted: SKYSYNC_STATE_KEY is wrong, or the file is damaged")?;
+        Ok(plaintext.to_vec())
+    }
+    #[cfg(not(feature = "state-encryption"))]
+    {
+        let _ = (key, data);
+        Err("SkySync was built without the `state-encryption` feature".into())
+    }
+}
+
+#[cfg(all(test, feature = "state-encryption"))]
+mod tests {
+    use super::*;
+
+    /// Tests that sealed data opens with the same key only, and that tampering is caught.
+    #[test]
+    fn test_round_trip() {
+        let key = StateKey::new("correct horse battery staple");
+        let sealed = seal(&key, b"{\"current_ip\":\"2.2.2.2\"}").unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.windows(7).any(|window| window == b"2.2.2.2"));
+        assert_eq!(open(&key, &sealed).unwrap(), b"{\"current_ip\":\"2.2.2.2\"}");
+
+        let err = open(&StateKey::new("wrong"), &sealed).unwrap_err();
+        assert_eq!(err.to_string(), "can't be decrypted: SKYSYNC_STATE_KEY is wrong, or the file is damaged");
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(&key, &tampered).is_err());
+        assert!(open(&key, &sealed[..HEADER.len() + 8]).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::http;
+use chrono::{DateTime, FixedOffset};
+use std::error::Error;
+use std::fmt;
+
+/// A boxed error that can cross task boundaries.
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Errors that stop a tick from completing.
+#[derive(Debug)]
+pub enum SkySyncError {
+    /// The public IP couldn't be determined.
+    IpFetch(BoxError),
+    /// The DNS provider couldn't be queried.
+    Provider(BoxError),
+    /// The managed record doesn't exist and creating it is disabled.
+    RecordNotFound(String),
+    /// Several records share the managed name and type, and `DUPLICATE_RECORDS` refuses to pick.
+    DuplicateRecords { name: String, count: usize },
+    /// The provider failed too many ticks in a row, and `CIRCUIT_BREAKER_THRESHOLD` leaves it
+    /// alone until `until`.
+    CircuitOpen { until: DateTime<FixedOffset> },
+}
+
+impl SkySyncError {
+    /// Whether a hostname couldn't be resolved, which usually clears up within seconds as the
+    /// network comes up.
+    pub fn is_resolution_error(&self) -> bool {
+        match self {
+            SkySyncError::IpFetch(err) | SkySyncError::Provider(err) => http::is_resolution_error(err.as_ref()),
+            SkySyncError::RecordNotFound(_) | SkySyncError::DuplicateRecords { .. } | SkySyncError::CircuitOpen { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for SkySyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkySyncError::IpFetch(err) => write!(f, "Failed to fetch the public IP: {}", err),
+            SkySyncError::Provider(err) => write!(f, "Failed to fetch DNS records: {}", err),
+            SkySyncError::RecordNotFound(name) => write!(f, "Failed to find DNS record {}", name),
+            SkySyncError::DuplicateRecords { name, count } => write!(
+                f,
+                "Found {} DNS records named {}, set DUPLICATE_RECORDS to update-all or update-first to update them",
+                count, name,
+            ),
+            SkySyncError::CircuitOpen { until } => write!(f, "Skipped the tick, the DNS provider is left alone until {} after failing too often", until.to_rfc3339()),
+        }
+    }
+}
+
+impl Error for SkySyncError {}
@@ -0,0 +1,114 @@
+use crate::error::BoxError;
+use serde::Deserialize;
+use std::fmt;
+
+/// The ip-api.com endpoint queried when `ENRICH_GEO` is enabled. The free tier is HTTP only.
+pub const DEFAULT_GEO_URL: &str = "http://ip-api.com/json";
+
+/// Where an IP address is and who routes it, as reported by the geo-IP service.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct GeoInfo {
+    pub country: String,
+    #[serde(rename = "regionName")]
+    pub region: String,
+    pub isp: String,
+    /// The AS number and name, e.g. `AS15169 Google LLC`.
+    #[serde(rename = "as")]
+    pub asn: String,
+}
+
+impl fmt::Display for GeoInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {} ({}, {})", self.region, self.country, self.isp, self.asn)
+    }
+}
+
+/// The ip-api.com response. Failed lookups only carry `status` and `message`.
+#[derive(Deserialize)]
+struct GeoResponse {
+    status: String,
+    message: Option<String>,
+    #[serde(flatten)]
+    info: Option<GeoInfo>,
+}
+
+/// Looks up countries and ISPs of IP addresses.
+pub struct GeoLocator {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl GeoLocator {
+    pub fn new(url: impl Into<String>, client: reqwest::Client) -> GeoLocator {
+        GeoLocator { url: url.into(), client }
+    }
+
+    /// Fetches the location and ISP of an address.
+    ///
+    /// # Arguments
+    /// * `ip` - The address to look up.
+    ///
+    /// # Returns
+    /// The `GeoInfo`, or an error if the service couldn't be reached or doesn't know the address.
+    pub async fn lookup(&self, ip: &str) -> Result<GeoInfo, BoxError> {
+        let response: GeoResponse = self.client
+            .get(format!("{}/{}", self.url.trim_end_matches('/'), ip))
+            .query(&[("fields", "status,message,country,regionName,isp,as")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        match (response.status.as_str(), response.info) {
+            ("success", Some(info)) => Ok(info),
+            _ => Err(format!("Geo lookup of {} failed: {}", ip, response.message.unwrap_or(response.status)).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Tests that a successful lookup is parsed into a `GeoInfo`.
+    #[tokio::test]
+    async fn test_lookup() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/json/203.0.113.7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "status": "success",
+                "country": "Brazil",
+                "regionName": "São Paulo",
+                "isp": "Example Telecom",
+                "as": "AS64496 Example Telecom S.A."
+            })))
+            .mount(&server)
+            .await;
+
+        let locator = GeoLocator::new(format!("{}/json", server.uri()), reqwest::Client::new());
+        let info = locator.lookup("203.0.113.7").await.unwrap();
+
+        assert_eq!(info.country, "Brazil");
+        assert_eq!(info.to_string(), "São Paulo, Brazil (Example Telecom, AS64496 Example Telecom S.A.)");
+    }
+
+    /// Tests that addresses the service can't locate are reported as errors.
+    #[tokio::test]
+    async fn test_lookup_failure() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "fail", "message": "private range" })))
+            .mount(&server)
+            .await;
+
+        let locator = GeoLocator::new(server.uri(), reqwest::Client::new());
+        let err = locator.lookup("10.0.0.1").await.unwrap_err();
+
+        assert!(err.to_string().contains("private range"));
+    }
+}
@@ -0,0 +1,324 @@
+use crate::console::table;
+use crate::error::BoxError;
+use chrono::{DateTime, Local};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, read, read_dir, remove_file, rename, write};
+
+/// How many changes are kept when `HISTORY_LENGTH` isn't set.
+pub const DEFAULT_HISTORY_LENGTH: usize = 50;
+
+/// How many rotated segments are kept when `HISTORY_ROTATE_KEEP` isn't set.
+pub const DEFAULT_HISTORY_ROTATE_KEEP: usize = 10;
+
+/// A single observed change of the public IP.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Local>,
+    pub old_ip: Option<String>,
+    pub new_ip: String,
+}
+
+/// A bounded, persisted log of IP changes. The oldest entries are dropped first, unless the file
+/// grows past its rotation size, in which case they're compressed into a segment next to it.
+#[derive(Clone)]
+pub struct History {
+    path: PathBuf,
+    limit: usize,
+    rotate_size: u64,
+    rotate_keep: usize,
+}
+
+impl History {
+    /// Creates a history stored at `path`, keeping at most `limit` entries.
+    pub fn new(path: impl Into<PathBuf>, limit: usize) -> History {
+        History { path: path.into(), limit, rotate_size: 0, rotate_keep: DEFAULT_HISTORY_ROTATE_KEEP }
+    }
+
+    /// Compresses the entries into a segment once the file grows past `size` bytes, see
+    /// `HISTORY_ROTATE_SIZE`. Zero never rotates it.
+    pub fn with_rotate_size(mut self, size: u64) -> History {
+        self.rotate_size = size;
+        self
+    }
+
+    /// Deletes the oldest segments once there are more than `keep`, see `HISTORY_ROTATE_KEEP`.
+    /// Zero keeps every one.
+    pub fn with_rotate_keep(mut self, keep: usize) -> History {
+        self.rotate_keep = keep;
+        self
+    }
+
+    /// The default location, `history.json` next to the log file.
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir().expect("Failed to get data directory").join("SkySync").join("history.json")
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the stored entries, oldest first. A missing file is an empty history.
+    ///
+    /// Only the current file is read, see `load_all` for the rotated segments too.
+    pub async fn load(&self) -> Result<Vec<HistoryEntry>, BoxError> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        read_segment(&self.path).await
+    }
+
+    /// Reads the entries of every rotated segment and then of the current file, oldest first.
+    pub async fn load_all(&self) -> Result<Vec<HistoryEntry>, BoxError> {
+        let mut entries = Vec::new();
+        for segment in self.segments().await? {
+            entries.extend(read_segment(&segment).await.map_err(|err| format!("{}: {}", segment.display(), err))?);
+        }
+        entries.extend(self.load().await?);
+        Ok(entries)
+    }
+
+    /// Reads the newest `count` entries, oldest first, from the current file and as many of the
+    /// newest segments as it takes.
+    pub async fn load_latest(&self, count: usize) -> Result<Vec<HistoryEntry>, BoxError> {
+        let mut entries = self.load().await?;
+        let mut segments = self.segments().await?;
+        while entries.len() < count {
+            let Some(segment) = segments.pop() else {
+                break;
+            };
+            let mut older = read_segment(&segment).await.map_err(|err| format!("{}: {}", segment.display(), err))?;
+            older.append(&mut entries);
+            entries = older;
+        }
+        entries.drain(..entries.len().saturating_sub(count));
+        Ok(entries)
+    }
+
+    /// The rotated segments next to the file, e.g. `history-20261014T090000000000.json.gz`,
+    /// oldest first.
+    async fn segments(&self) -> Result<Vec<PathBuf>, BoxError> {
+        let (Some(dir), Some(stem)) = (self.path.parent(), self.path.file_stem().and_then(|stem| stem.to_str())) else {
+            return Ok(Vec::new());
+        };
+        let mut listing = match read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }).await {
+            Ok(listing) => listing,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let prefix = format!("{}-", stem);
+        let mut segments = Vec::new();
+        while let Some(entry) = listing.next_entry().await? {
+            let name = entry.file_name();
+            // Timestamps are fixed-width, so the names sort in the order they were rotated
+            if name.to_str().is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".json.gz")) {
+                segments.push(entry.path());
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Where the segment starting with an entry observed at `oldest` is compressed to.
+    fn segment_path(&self, oldest: DateTime<Local>) -> PathBuf {
+        let stem = self.path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("history");
+        self.path.with_file_name(format!("{}-{}.json.gz", stem, oldest.format("%Y%m%dT%H%M%S%6f")))
+    }
+
+    /// Records a change, unless it's the same change as the newest entry.
+    ///
+    /// # Arguments
+    /// * `old_ip` - The address the record pointed at before, if known.
+    /// * `new_ip` - The newly detected address.
+    pub async fn append(&self, old_ip: Option<&str>, new_ip: &str) -> Result<(), BoxError> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+
+        let mut entries = self.load().await?;
+        // A change that keeps failing to apply is detected again on every tick
+        if entries.last().is_some_and(|last| last.new_ip == new_ip) {
+            return Ok(());
+        }
+
+        entries.push(HistoryEntry {
+            timestamp: Local::now(),
+            old_ip: old_ip.map(str::to_string),
+            new_ip: new_ip.to_string(),
+        });
+        if entries.len() > self.limit {
+            entries.drain(..entries.len() - self.limit);
+        }
+
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent).await?;
+        }
+        let mut data = serde_json::to_string_pretty(&entries)?;
+        if self.rotate_size > 0 && data.len() as u64 > self.rotate_size && entries.len() > 1 {
+            // The segment is in place before the entries leave the file, so a crash in between
+            // repeats them rather than losing them
+            let newest = entries.split_off(entries.len() - 1);
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(serde_json::to_string(&entries)?.as_bytes())?;
+            replace(&self.segment_path(entries[0].timestamp), &encoder.finish()?).await?;
+            data = serde_json::to_string_pretty(&newest)?;
+            replace(&self.path, data.as_bytes()).await?;
+            return self.prune().await;
+        }
+        replace(&self.path, data.as_bytes()).await
+    }
+
+    /// Deletes the oldest segments past `HISTORY_ROTATE_KEEP`.
+    async fn prune(&self) -> Result<(), BoxError> {
+        if self.rotate_keep == 0 {
+            return Ok(());
+        }
+        let segments = self.segments().await?;
+        for segment in &segments[..segments.len().saturating_sub(self.rotate_keep)] {
+            remove_file(segment).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the entries of the file at `path`, decompressing it when it's a `.gz` segment.
+async fn read_segment(path: &Path) -> Result<Vec<HistoryEntry>, BoxError> {
+    let data = read(path).await?;
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        let mut json = String::new();
+        GzDecoder::new(data.as_slice()).read_to_string(&mut json)?;
+        return Ok(serde_json::from_str(&json)?);
+    }
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Replaces the file at `path` with `data`.
+///
+/// The data is written next to `path` first and then moved over it, so a crash mid-write leaves
+/// the previous file intact.
+async fn replace(path: &Path, data: &[u8]) -> Result<(), BoxError> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    write(&temp, data).await?;
+    rename(&temp, path).await?;
+    Ok(())
+}
+
+/// Formats history entries as a plain-text table, oldest first.
+pub fn render_table(entries: &[HistoryEntry]) -> String {
+    let rows: Vec<[String; 3]> = entries.iter().map(|entry| [
+        entry.timestamp.format("%Y-%m-%d %H:%M:%S %:z").to_string(),
+        entry.old_ip.clone().unwrap_or_else(|| "-".to_string()),
+        entry.new_ip.clone(),
+    ]).collect();
+
+    table(["TIMESTAMP", "OLD IP", "NEW IP"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that entries are appended and the oldest are dropped past the limit.
+    #[tokio::test]
+    async fn test_append_and_trim() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("history.json"), 3);
+
+        for i in 1..=5 {
+            history.append(Some(&format!("10.0.0.{}", i - 1)), &format!("10.0.0.{}", i)).await.unwrap();
+        }
+
+        let entries = history.load().await.unwrap();
+        let new_ips: Vec<&str> = entries.iter().map(|entry| entry.new_ip.as_str()).collect();
+        assert_eq!(new_ips, vec!["10.0.0.3", "10.0.0.4", "10.0.0.5"]);
+    }
+
+    /// Tests that detecting the same change twice records it once.
+    #[tokio::test]
+    async fn test_append_skips_repeated_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("history.json"), 10);
+
+        history.append(Some("1.1.1.1"), "2.2.2.2").await.unwrap();
+        history.append(Some("1.1.1.1"), "2.2.2.2").await.unwrap();
+
+        assert_eq!(history.load().await.unwrap().len(), 1);
+    }
+
+    /// Tests that a write interrupted halfway leaves the previous entries readable, and that the
+    /// next one goes through.
+    #[tokio::test]
+    async fn test_survives_interrupted_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("history.json"), 10);
+        history.append(None, "1.1.1.1").await.unwrap();
+
+        // What a crash halfway through the next write leaves behind
+        std::fs::write(dir.path().join("history.json.tmp"), "[{\"timestamp\": \"2026-10-").unwrap();
+        assert_eq!(history.load().await.unwrap().len(), 1);
+
+        history.append(Some("1.1.1.1"), "2.2.2.2").await.unwrap();
+        let new_ips: Vec<String> = history.load().await.unwrap().into_iter().map(|entry| entry.new_ip).collect();
+        assert_eq!(new_ips, vec!["1.1.1.1", "2.2.2.2"]);
+        assert!(!dir.path().join("history.json.tmp").exists());
+    }
+
+    /// Tests that past the rotation size the entries move to compressed segments, which are read
+    /// back in order along with the current file.
+    #[tokio::test]
+    async fn test_rotates_into_compressed_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("history.json"), 50).with_rotate_size(300);
+
+        for i in 1..=8 {
+            history.append(Some(&format!("10.0.0.{}", i - 1)), &format!("10.0.0.{}", i)).await.unwrap();
+        }
+
+        let segments = history.segments().await.unwrap();
+        assert!(!segments.is_empty());
+        let compressed = std::fs::read(&segments[0]).unwrap();
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+        assert!(std::fs::metadata(history.path()).unwrap().len() <= 300);
+
+        let all: Vec<String> = history.load_all().await.unwrap().into_iter().map(|entry| entry.new_ip).collect();
+        assert_eq!(all, (1..=8).map(|i| format!("10.0.0.{}", i)).collect::<Vec<_>>());
+        assert!(history.load().await.unwrap().len() < 8);
+        let latest: Vec<String> = history.load_latest(5).await.unwrap().into_iter().map(|entry| entry.new_ip).collect();
+        assert_eq!(latest, (4..=8).map(|i| format!("10.0.0.{}", i)).collect::<Vec<_>>());
+    }
+
+    /// Tests that only the newest `HISTORY_ROTATE_KEEP` segments are kept.
+    #[tokio::test]
+    async fn test_prunes_oldest_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        // Small enough that every change past the first rotates
+        let history = History::new(dir.path().join("history.json"), 50).with_rotate_size(1).with_rotate_keep(2);
+
+        for i in 1..=6 {
+            history.append(Some(&format!("10.0.0.{}", i - 1)), &format!("10.0.0.{}", i)).await.unwrap();
+        }
+
+        assert_eq!(history.segments().await.unwrap().len(), 2);
+        let all: Vec<String> = history.load_all().await.unwrap().into_iter().map(|entry| entry.new_ip).collect();
+        assert_eq!(all, vec!["10.0.0.4", "10.0.0.5", "10.0.0.6"]);
+    }
+
+    /// Tests the table layout.
+    #[test]
+    fn test_render_table() {
+        let timestamp = DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap().with_timezone(&Local);
+        let entries = vec![HistoryEntry { timestamp, old_ip: None, new_ip: "2.2.2.2".to_string() }];
+
+        let table = render_table(&entries);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("TIMESTAMP"));
+        assert!(lines[1].ends_with("-       2.2.2.2"));
+    }
+}
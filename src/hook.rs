@@ -0,0 +1,119 @@
+//! `ON_CHANGE_COMMAND`, a command run whenever a record is pointed at a new IP, for integrations
+//! SkySync doesn't have (a firewall rule, another service to ping).
+use crate::config::HookConfig;
+use crate::error::BoxError;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+/// Runs the configured command for every change.
+pub struct ChangeHook {
+    config: HookConfig,
+}
+
+impl ChangeHook {
+    pub fn new(config: HookConfig) -> ChangeHook {
+        ChangeHook { config }
+    }
+
+    /// Runs the command for a change of `record`, and waits for it to exit.
+    ///
+    /// The command goes through the shell, with the record, old IP and new IP appended as
+    /// arguments and also set as `SKYSYNC_RECORD`, `SKYSYNC_OLD_IP` and `SKYSYNC_NEW_IP`. A
+    /// record that was just created has no old IP, which is passed as an empty string.
+    ///
+    /// # Arguments
+    /// * `record` - The name of the record that changed.
+    /// * `old_ip` - The address it pointed at before, if any.
+    /// * `new_ip` - The address it points at now.
+    ///
+    /// # Returns
+    /// What the command printed, stdout then stderr, or an error if it couldn't be started,
+    /// exited with a failure or was killed for outliving `ON_CHANGE_TIMEOUT`.
+    pub async fn run(&self, record: &str, old_ip: Option<&str>, new_ip: &str) -> Result<String, BoxError> {
+        let old_ip = old_ip.unwrap_or_default();
+        let mut command = shell(&self.config.command);
+        command
+            .args([record, old_ip, new_ip])
+            .env("SKYSYNC_RECORD", record)
+            .env("SKYSYNC_OLD_IP", old_ip)
+            .env("SKYSYNC_NEW_IP", new_ip)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Dropping the output future on a timeout then kills the command
+            .kill_on_drop(true);
+
+        let child = command.spawn().map_err(|err| format!("couldn't start `{}`: {}", self.config.command, err))?;
+        let output = timeout(self.config.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| format!("`{}` was killed after running for {:?}", self.config.command, self.config.timeout))??;
+
+        let printed = [&output.stdout, &output.stderr]
+            .iter()
+            .map(|stream| String::from_utf8_lossy(stream).trim().to_string())
+            .filter(|stream| !stream.is_empty())
+            .collect::<Vec<String>>()
+            .join("\n");
+        if !output.status.success() {
+            return Err(format!("`{}` failed ({}): {}", self.config.command, output.status, printed).into());
+        }
+        Ok(printed)
+    }
+}
+
+/// The shell invocation of `command`, which passes on the arguments added after it.
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    // "$0" is the first argument after the script, so a placeholder takes its place
+    shell.arg("-c").arg(format!("{} \"$@\"", command)).arg("skysync");
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// A hook running a script of `body`, killed after `timeout`.
+    fn hook(dir: &tempfile::TempDir, body: &str, timeout: Duration) -> ChangeHook {
+        let script = dir.path().join("on-change.sh");
+        std::fs::write(&script, format!("{}\n", body)).unwrap();
+        // Run through `sh` rather than executed, which fails while another test's fork holds it open
+        ChangeHook::new(HookConfig { command: format!("sh {}", script.display()), timeout })
+    }
+
+    /// Tests that a script gets the change as arguments and environment variables, and that
+    /// what it prints is returned.
+    #[tokio::test]
+    async fn test_passes_change_to_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let hook = hook(&dir, "echo \"args: $1 $2 $3\"\necho \"env: $SKYSYNC_RECORD $SKYSYNC_OLD_IP $SKYSYNC_NEW_IP\" >&2", Duration::from_secs(5));
+
+        let printed = hook.run("home.example.com", Some("1.1.1.1"), "2.2.2.2").await.unwrap();
+
+        assert_eq!(printed, "args: home.example.com 1.1.1.1 2.2.2.2\nenv: home.example.com 1.1.1.1 2.2.2.2");
+    }
+
+    /// Tests that a command outliving the timeout is killed and reported, and that a failing one
+    /// is reported with what it printed.
+    #[tokio::test]
+    async fn test_timeout_and_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let started = Instant::now();
+        let err = hook(&dir, "sleep 10", Duration::from_millis(100)).run("home.example.com", None, "2.2.2.2").await.unwrap_err();
+        assert!(err.to_string().contains("was killed after running for 100ms"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let err = hook(&dir, "echo denied >&2\nexit 3", Duration::from_secs(5)).run("home.example.com", None, "2.2.2.2").await.unwrap_err();
+        assert!(err.to_string().ends_with("failed (exit status: 3): denied"), "{}", err);
+    }
+}
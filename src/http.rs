@@ -0,0 +1,564 @@
+use crate::config::{PoolConfig, ProxyConfig};
+use crate::error::BoxError;
+#[cfg(feature = "hickory-dns")]
+use crate::resolver::Resolver;
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(feature = "hickory-dns")]
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long an unused connection is kept open by default, from `POOL_IDLE_TIMEOUT`.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// How many unused connections are kept open per host by default, from `POOL_MAX_IDLE`.
+pub const DEFAULT_POOL_MAX_IDLE: usize = 4;
+
+/// How much of the body of a failed response is read, to tell what went wrong.
+pub const ERROR_BODY_LIMIT: usize = 64 * 1024;
+
+/// The longest wait a rate-limited service can ask for before the retry.
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// The address family the public IP lookup is pinned to, from `FORCE_IPV4_SOURCE` or
+/// `FORCE_IPV6_SOURCE`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressFamily::V4 => "IPv4",
+            AddressFamily::V6 => "IPv6",
+        }
+    }
+
+    /// The family `address` belongs to.
+    pub fn of(address: IpAddr) -> AddressFamily {
+        match address {
+            IpAddr::V4(_) => AddressFamily::V4,
+            IpAddr::V6(_) => AddressFamily::V6,
+        }
+    }
+
+    /// The unspecified address of the family. Connections bound to it can only reach addresses
+    /// of the same family.
+    pub fn unspecified(&self) -> IpAddr {
+        match self {
+            AddressFamily::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            AddressFamily::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+/// A response body longer than the limit it was read with.
+#[derive(Debug, PartialEq)]
+pub struct BodyTooLarge {
+    pub url: String,
+    pub limit: usize,
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} answered with a body over the {} byte limit", self.url, self.limit)
+    }
+}
+
+impl Error for BodyTooLarge {}
+
+/// What the status of a failed response says about the request, and whether it's worth retrying.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatusClass {
+    /// `401` or `403`: the credentials were refused, and will be again.
+    Auth,
+    /// `404`: what was asked for doesn't exist.
+    NotFound,
+    /// `429`: too many requests, with how long the server asked to wait, if it said.
+    RateLimited { retry_after: Option<Duration> },
+    /// `5xx`: the server or something in front of it failed, which usually passes.
+    Transient,
+    /// Any other status: the request itself was rejected.
+    Client,
+}
+
+/// A response whose status says the request failed.
+#[derive(Debug, PartialEq)]
+pub struct StatusError {
+    pub status: u16,
+    pub class: StatusClass,
+    /// What went wrong: the body, or what the service made of it.
+    pub detail: String,
+}
+
+impl StatusError {
+    /// Whether the same request may succeed if it's sent again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.class, StatusClass::Transient | StatusClass::RateLimited { .. })
+    }
+
+    /// Replaces the body with what the service made of it, e.g. the error message it carried.
+    pub fn explained(self, detail: impl fmt::Display) -> StatusError {
+        StatusError { detail: detail.to_string(), ..self }
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.detail.trim() {
+            "" => write!(f, "HTTP {}", self.status),
+            detail => write!(f, "{}", detail),
+        }
+    }
+}
+
+impl Error for StatusError {}
+
+/// How long a rate-limited response asks to wait, from Discord's `X-RateLimit-Reset-After` or
+/// the standard `Retry-After`, both in seconds.
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    ["x-ratelimit-reset-after", "retry-after"].iter()
+        .find_map(|name| headers.get(*name)?.to_str().ok()?.trim().parse::<f64>().ok())
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+}
+
+/// Classifies the status of a response.
+///
+/// # Returns
+/// `None` for a successful status, or what a failed one says about the request.
+pub fn classify(status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap) -> Option<StatusClass> {
+    Some(match status.as_u16() {
+        200..=299 => return None,
+        401 | 403 => StatusClass::Auth,
+        404 => StatusClass::NotFound,
+        429 => StatusClass::RateLimited { retry_after: retry_after(headers) },
+        500..=599 => StatusClass::Transient,
+        _ => StatusClass::Client,
+    })
+}
+
+/// Checks the status of a response before its body is read, so every service tells failures
+/// apart, and decides on retrying them, the same way.
+///
+/// # Returns
+/// The response when its status is successful, or a `StatusError` with the start of its body.
+pub async fn classify_response(response: reqwest::Response) -> Result<reqwest::Response, StatusError> {
+    let Some(class) = classify(response.status(), response.headers()) else {
+        return Ok(response);
+    };
+    let status = response.status().as_u16();
+    let detail = read_limited(response, ERROR_BODY_LIMIT).await.unwrap_or_default();
+    Err(StatusError { status, class, detail })
+}
+
+/// Whether `err` is a request that failed because its hostname couldn't be resolved, rather
+/// than one the server or the connection failed, e.g. while the network is still coming up.
+pub fn is_resolution_error(err: &(dyn Error + 'static)) -> bool {
+    let Some(err) = err.downcast_ref::<reqwest::Error>().filter(|err| err.is_connect()) else {
+        return false;
+    };
+    // hyper wraps every failed lookup, by the system resolver or `RESOLVER`, in an error of its own
+    let mut source = err.source();
+    while let Some(err) = source {
+        if err.to_string().starts_with("dns error") {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Reads the body of a response as text, a chunk at a time, giving up as soon as it's longer
+/// than `limit` instead of buffering whatever the server sends.
+///
+/// reqwest asks for and decodes `gzip`, `deflate` and `br` bodies, so the chunks are already
+/// decoded and `limit` caps the decoded length, however small the compressed body is.
+///
+/// # Returns
+/// The body, or `BodyTooLarge` once it declares or delivers more than `limit` bytes.
+pub async fn read_limited(mut response: reqwest::Response, limit: usize) -> Result<String, BoxError> {
+    let too_large = |response: &reqwest::Response| BodyTooLarge { url: response.url().to_string(), limit };
+    if response.content_length().is_some_and(|length| length > limit as u64) {
+        return Err(too_large(&response).into());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large(&response).into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Builds the HTTP client shared by the IP lookup, the DNS provider and the webhook notifiers.
+///
+/// Without an explicit proxy, reqwest honors `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and
+/// `NO_PROXY` from the process environment. HTTP/2 is used with servers that offer it during the
+/// TLS handshake, as Cloudflare does, so concurrent record updates share one connection.
+///
+/// # Arguments
+/// * `proxy` - The proxy every request should go through, from `SKYSYNC_PROXY`.
+/// * `pool` - How connections are made and kept, from `BIND_ADDRESS`, `POOL_IDLE_TIMEOUT` and
+///   `POOL_MAX_IDLE`.
+///
+/// # Returns
+/// The client, or an error if the proxy URL is invalid or no TLS backend is available.
+pub fn client(proxy: Option<&ProxyConfig>, pool: &PoolConfig) -> Result<reqwest::Client, reqwest::Error> {
+    builder(proxy, pool)?.build()
+}
+
+/// Builds the HTTP client the public IP is looked up with.
+///
+/// Behind an HTTP proxy, hostnames are resolved by the proxy and `resolver` has no effect, and
+/// `family` only pins the connection to the proxy. A `BIND_ADDRESS` of the other family isn't
+/// used while `family` is set.
+///
+/// # Arguments
+/// * `proxy` - The proxy every request should go through, from `SKYSYNC_PROXY`.
+/// * `pool` - How connections are made and kept, from `BIND_ADDRESS`, `POOL_IDLE_TIMEOUT` and
+///   `POOL_MAX_IDLE`.
+/// * `resolver` - The DNS servers hostnames are resolved with, in order, from `RESOLVER`, instead
+///   of the system resolver when there are any.
+/// * `family` - The only address family connections are made over, so a dual-stack hostname
+///   can't be reached over the other one. `resolver` then only asks for addresses of it.
+///
+/// # Returns
+/// The client, or an error if the proxy URL is invalid or no TLS backend is available.
+pub fn ip_client(proxy: Option<&ProxyConfig>, pool: &PoolConfig, resolver: &[SocketAddr], family: Option<AddressFamily>) -> Result<reqwest::Client, reqwest::Error> {
+    let mut builder = builder(proxy, pool)?;
+    if let Some(family) = family.filter(|family| pool.bind_address.is_none_or(|address| AddressFamily::of(address) != *family)) {
+        builder = builder.local_address(family.unspecified());
+    }
+    match resolver {
+        // Rejected by the config loader when the feature is disabled
+        #[cfg(feature = "hickory-dns")]
+        servers @ [_, ..] => builder.dns_resolver(Arc::new(Resolver::new(servers).with_family(family))).build(),
+        _ => builder.build(),
+    }
+}
+
+/// The settings shared by every client.
+fn builder(proxy: Option<&ProxyConfig>, pool: &PoolConfig) -> Result<reqwest::ClientBuilder, reqwest::Error> {
+    let mut builder = reqwest::Client::builder()
+        .pool_idle_timeout(pool.idle_timeout)
+        .pool_max_idle_per_host(pool.max_idle)
+        .local_address(pool.bind_address);
+
+    if let Some(config) = proxy {
+        let mut proxy = reqwest::Proxy::all(&config.url)?;
+        if let Some(user) = &config.user {
+            proxy = proxy.basic_auth(user, config.pass.as_deref().unwrap_or(""));
+        }
+        builder = builder.proxy(proxy);
+    }
+
+    Ok(builder)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ip::{HttpIpSource, IpSource};
+    #[cfg(feature = "hickory-dns")]
+    use crate::resolver::tests::{dns_server, dual_stack_dns_server};
+    use reqwest::dns::{Name, Resolve, Resolving};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Tests that each class of status is told apart, with the wait a rate limit asks for.
+    #[test]
+    fn test_classify() {
+        let classify = |status: u16, headers: &[(&'static str, &'static str)]| {
+            let headers = headers.iter().map(|(name, value)| (reqwest::header::HeaderName::from_static(name), value.parse().unwrap())).collect();
+            super::classify(reqwest::StatusCode::from_u16(status).unwrap(), &headers)
+        };
+
+        assert_eq!(classify(200, &[]), None);
+        assert_eq!(classify(204, &[]), None);
+        assert_eq!(classify(401, &[]), Some(StatusClass::Auth));
+        assert_eq!(classify(403, &[]), Some(StatusClass::Auth));
+        assert_eq!(classify(404, &[]), Some(StatusClass::NotFound));
+        assert_eq!(classify(429, &[("retry-after", "30")]), Some(StatusClass::RateLimited { retry_after: Some(Duration::from_secs(30)) }));
+        assert_eq!(classify(429, &[("x-ratelimit-reset-after", "1.5")]), Some(StatusClass::RateLimited { retry_after: Some(Duration::from_millis(1500)) }));
+        assert_eq!(classify(429, &[("retry-after", "Wed, 21 Oct 2026 07:28:00 GMT")]), Some(StatusClass::RateLimited { retry_after: None }));
+        assert_eq!(classify(500, &[]), Some(StatusClass::Transient));
+        assert_eq!(classify(503, &[]), Some(StatusClass::Transient));
+        assert_eq!(classify(400, &[]), Some(StatusClass::Client));
+        assert_eq!(classify(422, &[]), Some(StatusClass::Client));
+        assert_eq!(classify(304, &[]), Some(StatusClass::Client));
+    }
+
+    /// Tests that a successful response is handed back unread, and a failed one is read into
+    /// the error.
+    #[tokio::test]
+    async fn test_classify_response() {
+        let server = MockServer::start().await;
+        Mock::given(path("/ok")).respond_with(ResponseTemplate::new(200).set_body_string("fine")).mount(&server).await;
+        Mock::given(path("/down")).respond_with(ResponseTemplate::new(503).set_body_string("maintenance")).mount(&server).await;
+        let get = |route: &str| reqwest::get(format!("{}{}", server.uri(), route));
+
+        let response = classify_response(get("/ok").await.unwrap()).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "fine");
+
+        let err = classify_response(get("/down").await.unwrap()).await.unwrap_err();
+        assert_eq!(err, StatusError { status: 503, class: StatusClass::Transient, detail: "maintenance".to_string() });
+        assert!(err.is_retryable());
+        assert_eq!(err.explained("").to_string(), "HTTP 503");
+    }
+
+    /// Tests that requests are sent through the configured proxy, with its credentials.
+    #[tokio::test]
+    async fn test_requests_go_through_proxy() {
+        let proxy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .and(header("Host", "ip.skysync.invalid"))
+            .and(header("Proxy-Authorization", "Basic dXNlcjpwYXNz"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("203.0.113.7\n"))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let config = ProxyConfig { url: proxy.uri(), user: Some("user".to_string()), pass: Some("pass".to_string()) };
+        let source = HttpIpSource::new("http://ip.skysync.invalid/ip", client(Some(&config), &PoolConfig::default()).unwrap());
+
+        assert_eq!(source.public_ip().await.unwrap(), "203.0.113.7");
+    }
+
+    /// Tests that requests leave from `BIND_ADDRESS`, also when the IP lookup is pinned to its
+    /// family. Linux answers on all of 127.0.0.0/8, so a second loopback address tells them apart.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_address() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let peers = tokio::spawn(async move {
+            let mut peers = Vec::new();
+            for _ in 0..2 {
+                let (mut socket, peer) = listener.accept().await.unwrap();
+                peers.push(peer.ip());
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await.unwrap();
+                socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok").await.unwrap();
+            }
+            peers
+        });
+        let bound: IpAddr = Ipv4Addr::new(127, 0, 0, 2).into();
+        let pool = PoolConfig { bind_address: Some(bound), ..PoolConfig::default() };
+
+        client(None, &pool).unwrap().get(&url).send().await.unwrap();
+        ip_client(None, &pool, &[], Some(AddressFamily::V4)).unwrap().get(&url).send().await.unwrap();
+
+        assert_eq!(peers.await.unwrap(), vec![bound, bound]);
+    }
+
+    /// Starts an HTTP/1.1 server that keeps connections alive, counting how many are opened.
+    async fn counting_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while matches!(socket.read(&mut buf).await, Ok(read) if read > 0) {
+                        if socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        (url, connections)
+    }
+
+    /// Sends two requests, `pause` apart, and returns how many connections they took.
+    async fn connections_for(pool: PoolConfig, pause: Duration) -> usize {
+        let (url, connections) = counting_server().await;
+        let client = client(None, &pool).unwrap();
+        for _ in 0..2 {
+            assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "ok");
+            tokio::time::sleep(pause).await;
+        }
+        connections.load(Ordering::SeqCst)
+    }
+
+    /// Tests that the pool settings decide whether a connection is reused.
+    #[tokio::test]
+    async fn test_pool_settings() {
+        let pause = Duration::from_millis(200);
+
+        assert_eq!(connections_for(PoolConfig::default(), pause).await, 1);
+        assert_eq!(connections_for(PoolConfig { max_idle: 0, ..PoolConfig::default() }, pause).await, 2);
+        assert_eq!(connections_for(PoolConfig { idle_timeout: Duration::from_millis(50), ..PoolConfig::default() }, pause).await, 2);
+    }
+
+    /// Tests that the IP lookup resolves its hostname with the configured DNS server.
+    #[cfg(feature = "hickory-dns")]
+    #[tokio::test]
+    async fn test_ip_client_uses_resolver() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ip"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("203.0.113.7\n"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // Only the test DNS server knows this name
+        let resolver = dns_server(Ipv4Addr::LOCALHOST).await;
+
+        let url = format!("http://ip.skysync.test:{}/ip", server.address().port());
+        let source = HttpIpSource::new(url, ip_client(None, &PoolConfig::default(), &[resolver], None).unwrap());
+
+        assert_eq!(source.public_ip().await.unwrap(), "203.0.113.7");
+    }
+
+    /// Answers every request on `listener` with `body`.
+    #[cfg(feature = "hickory-dns")]
+    fn serve_body(listener: TcpListener, body: &'static str) {
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+    }
+
+    /// Fetches `url` with an IP client pinned to `family`.
+    async fn fetch(url: &str, resolver: &[SocketAddr], family: Option<AddressFamily>) -> Result<String, BoxError> {
+        HttpIpSource::new(url, ip_client(None, &PoolConfig::default(), resolver, family).unwrap()).public_ip().await
+    }
+
+    /// Tests that a pinned family decides which address of a dual-stack name is connected to, and
+    /// that the other family can't be reached at all.
+    #[cfg(feature = "hickory-dns")]
+    #[tokio::test]
+    async fn test_ip_client_pins_family() {
+        let v4 = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = v4.local_addr().unwrap().port();
+        let v6 = TcpListener::bind((Ipv6Addr::LOCALHOST, port)).await.unwrap();
+        serve_body(v4, "v4");
+        serve_body(v6, "v6");
+        let resolver = dual_stack_dns_server(Ipv4Addr::LOCALHOST, Some(Ipv6Addr::LOCALHOST)).await;
+        let url = format!("http://ip.skysync.test:{}/", port);
+
+        assert_eq!(fetch(&url, &[resolver], None).await.unwrap(), "v4");
+        assert_eq!(fetch(&url, &[resolver], Some(AddressFamily::V4)).await.unwrap(), "v4");
+        assert_eq!(fetch(&url, &[resolver], Some(AddressFamily::V6)).await.unwrap(), "v6");
+
+        assert!(fetch(&format!("http://[::1]:{}/", port), &[], Some(AddressFamily::V4)).await.is_err());
+        assert!(fetch(&format!("http://127.0.0.1:{}/", port), &[], Some(AddressFamily::V6)).await.is_err());
+    }
+
+    /// A resolver that fails every lookup, the way it does when the network is down.
+    struct FailingResolver;
+
+    impl Resolve for FailingResolver {
+        fn resolve(&self, name: Name) -> Resolving {
+            let host = name.as_str().to_string();
+            Box::pin(async move { Err(format!("no DNS server could be reached to resolve {}", host).into()) })
+        }
+    }
+
+    /// Fails a lookup of a hostname the way the network being down does.
+    pub(crate) async fn resolution_error() -> BoxError {
+        let client = builder(None, &PoolConfig::default()).unwrap().dns_resolver(Arc::new(FailingResolver)).build().unwrap();
+        HttpIpSource::new("http://ip.skysync.invalid:9/", client).public_ip().await.unwrap_err()
+    }
+
+    /// Tests that a hostname that can't be resolved is told apart from a refused connection.
+    #[tokio::test]
+    async fn test_is_resolution_error() {
+        let err = resolution_error().await;
+        assert!(is_resolution_error(err.as_ref()), "{:?}", err);
+
+        let err = fetch("http://127.0.0.1:9/", &[], None).await.unwrap_err();
+        assert!(!is_resolution_error(err.as_ref()), "{:?}", err);
+        assert!(!is_resolution_error(&BodyTooLarge { url: "http://127.0.0.1/".to_string(), limit: 1 }));
+    }
+
+    /// Tests that a body without a declared length is cut off once it passes the limit.
+    #[tokio::test]
+    async fn test_read_limited_streamed_body() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                // Read until the connection closes, with no Content-Length to go on
+                let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n").await;
+                let _ = socket.write_all(&[b'1'; 64 * 1024]).await;
+            }
+        });
+        let client = reqwest::Client::new();
+
+        let err = read_limited(client.get(&url).send().await.unwrap(), 1024).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<BodyTooLarge>(), Some(&BodyTooLarge { url: url.clone(), limit: 1024 }));
+        assert_eq!(read_limited(client.get(&url).send().await.unwrap(), 64 * 1024).await.unwrap().len(), 64 * 1024);
+    }
+
+    /// Compresses `body` as `encoding` names it.
+    fn compress(encoding: &str, body: &[u8]) -> Vec<u8> {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        match encoding {
+            "gzip" => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            }
+            _ => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    /// Tests that gzip and deflate bodies are decoded, and that the limit applies to the decoded
+    /// length, however small the compressed body is.
+    #[tokio::test]
+    async fn test_read_limited_decodes_compressed_body() {
+        let server = MockServer::start().await;
+        for encoding in ["gzip", "deflate"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", encoding)))
+                .respond_with(ResponseTemplate::new(200)
+                    .insert_header("Content-Encoding", encoding)
+                    .set_body_bytes(compress(encoding, br#"{"success":true}"#)))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/bomb"))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_bytes(compress("gzip", &[b'1'; 64 * 1024])))
+            .mount(&server)
+            .await;
+        let client = reqwest::Client::new();
+        let get = |route: &str| client.get(format!("{}/{}", server.uri(), route)).send();
+
+        assert_eq!(read_limited(get("gzip").await.unwrap(), 1024).await.unwrap(), r#"{"success":true}"#);
+        assert_eq!(read_limited(get("deflate").await.unwrap(), 1024).await.unwrap(), r#"{"success":true}"#);
+
+        let err = read_limited(get("bomb").await.unwrap(), 1024).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<BodyTooLarge>(), Some(&BodyTooLarge { url: format!("{}/bomb", server.uri()), limit: 1024 }));
+    }
+}
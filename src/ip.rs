@@ -0,0 +1,542 @@
+use crate::error::BoxError;
+use crate::http::read_limited;
+use crate::retry::RetryPolicy;
+use async_trait::async_trait;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// How long a fetched IP is reused by readers outside the cron tick, unless `IP_CACHE_TTL` is set.
+pub const DEFAULT_IP_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// The longest body an IP endpoint may answer with, unless `IP_RESPONSE_LIMIT` is set.
+pub const DEFAULT_IP_RESPONSE_LIMIT: usize = 4 * 1024;
+
+/// The endpoint queried for the public IP address.
+pub const DEFAULT_IP_URL: &str = "https://ipv4.icanhazip.com";
+
+/// The endpoint queried for the public IPv6 address, by records sourced from `http-v6`.
+pub const DEFAULT_IPV6_URL: &str = "https://ipv6.icanhazip.com";
+
+/// Somewhere the public IP address of the current machine can be read from.
+#[async_trait]
+pub trait IpSource: Send + Sync {
+    /// Fetches the public IP address, exactly as returned by the source.
+    async fn public_ip(&self) -> Result<String, BoxError>;
+}
+
+/// How the body of an IP endpoint holds the address.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum IpResponse {
+    /// The body is the address, give or take whitespace around it.
+    #[default]
+    Plain,
+    /// The body is a JSON object, with the address under this dotted path, e.g. `ip` or `data.ip`.
+    Json(String),
+}
+
+impl IpResponse {
+    /// Reads the address out of `body`.
+    ///
+    /// # Arguments
+    /// * `url` - The endpoint that answered, for the errors.
+    /// * `body` - What it answered with.
+    ///
+    /// # Returns
+    /// The body itself for plain-text endpoints, which the tick validates, or the address found
+    /// at the path for JSON ones, which must be a well-formed one. A plain-text body with
+    /// whitespace inside, like an error page, is rejected.
+    pub fn extract(&self, url: &str, body: String) -> Result<String, BoxError> {
+        let IpResponse::Json(path) = self else {
+            if body.contains(char::is_whitespace) {
+                return Err(format!("{} answered with {:?}, which is not an IP address", url, body).into());
+            }
+            return Ok(body);
+        };
+
+        let value: serde_json::Value = serde_json::from_str(&body).map_err(|err| format!("{} didn't answer with JSON: {}", url, err))?;
+        let pointer: String = path.split('.').map(|field| format!("/{}", field)).collect();
+        let Some(found) = value.pointer(&pointer) else {
+            return Err(format!("{} answered without a {} field", url, path).into());
+        };
+        match found.as_str().map(str::trim) {
+            Some(ip) if ip.parse::<IpAddr>().is_ok() => Ok(ip.to_string()),
+            _ => Err(format!("{} answered with {} in {}, which is not an IP address", url, found, path).into()),
+        }
+    }
+}
+
+/// An endpoint of `IP_ENDPOINTS`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IpEndpoint {
+    pub url: String,
+    pub response: IpResponse,
+}
+
+/// Reads the public IP from an HTTP endpoint.
+pub struct HttpIpSource {
+    url: String,
+    client: reqwest::Client,
+    limit: usize,
+    response: IpResponse,
+}
+
+impl HttpIpSource {
+    pub fn new(url: impl Into<String>, client: reqwest::Client) -> HttpIpSource {
+        HttpIpSource { url: url.into(), client, limit: DEFAULT_IP_RESPONSE_LIMIT, response: IpResponse::Plain }
+    }
+
+    /// Rejects answers longer than `limit` bytes instead of `DEFAULT_IP_RESPONSE_LIMIT`.
+    pub fn with_limit(mut self, limit: usize) -> HttpIpSource {
+        self.limit = limit;
+        self
+    }
+
+    /// Reads the address out of the body as `response` says instead of taking the body as is.
+    pub fn with_response(mut self, response: IpResponse) -> HttpIpSource {
+        self.response = response;
+        self
+    }
+}
+
+impl Default for HttpIpSource {
+    fn default() -> HttpIpSource {
+        HttpIpSource::new(DEFAULT_IP_URL, reqwest::Client::new())
+    }
+}
+
+/// Fetches the public IP address of the current machine.
+///
+/// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `url` - The plain-text endpoint to query.
+/// * `limit` - The longest answer accepted, in bytes.
+///
+/// # Returns
+/// The answer without the whitespace (`\r\n` line endings, spaces, a byte order mark) around
+/// it, or an error if it's longer than `limit` or holds nothing but whitespace.
+pub async fn get_public_ip(client: &reqwest::Client, url: &str, limit: usize) -> Result<String, BoxError> {
+    let response = client.get(url)
+        .send()
+        .await?;
+
+    let body = read_limited(response, limit).await?;
+    let body = body.trim_matches(|c: char| c.is_whitespace() || c == '\u{feff}');
+    // Pointing the records at an empty address would take them down, so it's never an answer
+    if body.is_empty() {
+        return Err(format!("{} answered with an empty body", url).into());
+    }
+    Ok(body.to_string())
+}
+
+#[async_trait]
+impl IpSource for HttpIpSource {
+    async fn public_ip(&self) -> Result<String, BoxError> {
+        let body = get_public_ip(&self.client, &self.url, self.limit).await?;
+        self.response.extract(&self.url, body)
+    }
+}
+
+/// Asks several sources in turn, until one answers with an IP address.
+pub struct FallbackIpSource {
+    sources: Vec<Box<dyn IpSource>>,
+}
+
+impl FallbackIpSource {
+    pub fn new(sources: Vec<Box<dyn IpSource>>) -> FallbackIpSource {
+        FallbackIpSource { sources }
+    }
+}
+
+#[async_trait]
+impl IpSource for FallbackIpSource {
+    /// An answer that isn't an IP address counts as a failure too, so the next source gets a go.
+    async fn public_ip(&self) -> Result<String, BoxError> {
+        let mut errors = Vec::new();
+        for source in &self.sources {
+            match source.public_ip().await {
+                Ok(ip) if ip.trim().parse::<IpAddr>().is_ok() => return Ok(ip),
+                Ok(ip) => errors.push(format!("{:?} is not an IP address", ip.trim())),
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+        Err(format!("every IP endpoint failed: {}", errors.join("; ")).into())
+    }
+}
+
+/// Reads the address a local network interface sends from, for records pointing at it rather
+/// than at the public IP.
+pub struct InterfaceIpSource {
+    interface: String,
+    ipv6: bool,
+}
+
+impl InterfaceIpSource {
+    /// # Arguments
+    /// * `interface` - The name of the interface, e.g. `eth0`.
+    /// * `ipv6` - Whether its IPv6 address is read instead of its IPv4 one.
+    pub fn new(interface: impl Into<String>, ipv6: bool) -> InterfaceIpSource {
+        InterfaceIpSource { interface: interface.into(), ipv6 }
+    }
+}
+
+#[async_trait]
+impl IpSource for InterfaceIpSource {
+    /// Connects a UDP socket bound to the interface towards a public address, which sends
+    /// nothing but makes the kernel pick the address the interface would send from.
+    #[cfg(target_os = "linux")]
+    async fn public_ip(&self) -> Result<String, BoxError> {
+        let (bind, probe) = if self.ipv6 { ("[::]:0", "[2606:4700:4700::1111]:53") } else { ("0.0.0.0:0", "1.1.1.1:53") };
+        let family = if self.ipv6 { "IPv6" } else { "IPv4" };
+        let unavailable = |err: std::io::Error| format!("interface {} has no {} address: {}", self.interface, family, err);
+
+        let socket = tokio::net::UdpSocket::bind(bind).await?;
+        socket.bind_device(Some(self.interface.as_bytes())).map_err(unavailable)?;
+        socket.connect(probe).await.map_err(unavailable)?;
+        Ok(socket.local_addr()?.ip().to_string())
+    }
+
+    // Rejected by the config loader on other platforms
+    #[cfg(not(target_os = "linux"))]
+    async fn public_ip(&self) -> Result<String, BoxError> {
+        Err(format!("can't read the address of interface {}: only supported on Linux", self.interface).into())
+    }
+}
+
+/// Asks another source again when it fails, as `IP_RETRIES` says.
+pub struct RetryingIpSource {
+    source: Box<dyn IpSource>,
+    retry: RetryPolicy,
+}
+
+impl RetryingIpSource {
+    pub fn new(source: Box<dyn IpSource>, retry: RetryPolicy) -> RetryingIpSource {
+        RetryingIpSource { source, retry }
+    }
+}
+
+#[async_trait]
+impl IpSource for RetryingIpSource {
+    async fn public_ip(&self) -> Result<String, BoxError> {
+        self.retry.run("fetch the public IP", || self.source.public_ip(), |_, wait| Some(wait)).await
+    }
+}
+
+/// Answers with a given address without asking anything, for `IP_OVERRIDE`.
+pub struct FixedIpSource {
+    ip: IpAddr,
+}
+
+impl FixedIpSource {
+    pub fn new(ip: IpAddr) -> FixedIpSource {
+        FixedIpSource { ip }
+    }
+}
+
+#[async_trait]
+impl IpSource for FixedIpSource {
+    async fn public_ip(&self) -> Result<String, BoxError> {
+        Ok(self.ip.to_string())
+    }
+}
+
+/// Finds why `ip` can't be reached from the internet, which makes it useless in a DNS record.
+///
+/// # Returns
+/// The kind of address, e.g. `carrier-grade NAT (100.64.0.0/10)`, or `None` for a public one.
+pub fn non_routable(ip: IpAddr) -> Option<&'static str> {
+    match ip {
+        // The ISP shares the address between its customers, and nothing is forwarded back
+        IpAddr::V4(ip) if ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64 => Some("carrier-grade NAT (100.64.0.0/10)"),
+        IpAddr::V4(ip) if ip.is_private() => Some("private"),
+        IpAddr::V4(ip) if ip.is_link_local() => Some("link-local"),
+        IpAddr::V6(ip) if ip.segments()[0] & 0xfe00 == 0xfc00 => Some("unique local"),
+        IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80 => Some("link-local"),
+        ip if ip.is_loopback() => Some("loopback"),
+        _ => None,
+    }
+}
+
+/// Builds IPv6 record contents from the delegated prefix of the public address and a fixed
+/// interface identifier, so the host's own address rotating within the prefix changes nothing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ipv6Prefix {
+    /// How many leading bits of the public address are the delegated prefix.
+    pub length: u8,
+    /// The interface identifier the record points at, e.g. `::1`. Bits inside the prefix are
+    /// ignored.
+    pub suffix: Ipv6Addr,
+}
+
+impl Ipv6Prefix {
+    /// The delegated prefix of `ip`, with every bit after it cleared.
+    pub fn prefix(&self, ip: Ipv6Addr) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(ip) & self.mask())
+    }
+
+    /// The address within the prefix of `ip` that ends in the configured suffix.
+    pub fn apply(&self, ip: Ipv6Addr) -> Ipv6Addr {
+        Ipv6Addr::from((u128::from(ip) & self.mask()) | (u128::from(self.suffix) & !self.mask()))
+    }
+
+    fn mask(&self) -> u128 {
+        u128::MAX.checked_shl(128 - u32::from(self.length)).unwrap_or(0)
+    }
+}
+
+/// The last fetched public IP, shared between the cron tick and any other reader.
+///
+/// Readers within the TTL reuse the cached value instead of querying the source again, so
+/// auxiliary consumers can't hammer the IP provider. The tick always fetches a fresh value.
+#[derive(Clone, Default)]
+pub struct IpCache {
+    cached: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+impl IpCache {
+    /// Returns the cached IP if it's younger than `ttl`, fetching it otherwise.
+    ///
+    /// # Arguments
+    /// * `source` - Where to fetch the IP from when the cache is stale.
+    /// * `ttl` - How old a cached value may be.
+    pub async fn get(&self, source: &dyn IpSource, ttl: Duration) -> Result<String, BoxError> {
+        // Holding the lock while fetching makes concurrent readers wait for one fetch
+        let mut cached = self.cached.lock().await;
+        if let Some((ip, fetched_at)) = cached.as_ref() {
+            if fetched_at.elapsed() < ttl {
+                return Ok(ip.clone());
+            }
+        }
+
+        let ip = source.public_ip().await?;
+        *cached = Some((ip.clone(), Instant::now()));
+        Ok(ip)
+    }
+
+    /// Fetches the IP regardless of the cache, and caches the result.
+    pub async fn refresh(&self, source: &dyn IpSource) -> Result<String, BoxError> {
+        let ip = source.public_ip().await?;
+        *self.cached.lock().await = Some((ip.clone(), Instant::now()));
+        Ok(ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn ip_server(expected_hits: u64) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("203.0.113.7\n"))
+            .expect(expected_hits)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    /// Tests that a second read within the TTL reuses the cached IP.
+    #[tokio::test]
+    async fn test_cache_reuses_fresh_ip() {
+        let server = ip_server(1).await;
+        let source = HttpIpSource::new(server.uri(), reqwest::Client::new());
+        let cache = IpCache::default();
+
+        assert_eq!(cache.get(&source, Duration::from_secs(30)).await.unwrap(), "203.0.113.7");
+        assert_eq!(cache.get(&source, Duration::from_secs(30)).await.unwrap(), "203.0.113.7");
+    }
+
+    /// Tests that an endpoint answering with more than the limit fails the lookup with a clear
+    /// error, and that a larger limit accepts it.
+    #[tokio::test]
+    async fn test_rejects_oversized_answer() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!("203.0.113.7\n{}", " ".repeat(DEFAULT_IP_RESPONSE_LIMIT))))
+            .mount(&server)
+            .await;
+
+        let err = HttpIpSource::new(server.uri(), reqwest::Client::new()).public_ip().await.unwrap_err();
+        assert_eq!(err.to_string(), format!("{}/ answered with a body over the 4096 byte limit", server.uri()));
+
+        let source = HttpIpSource::new(server.uri(), reqwest::Client::new()).with_limit(2 * DEFAULT_IP_RESPONSE_LIMIT);
+        assert_eq!(source.public_ip().await.unwrap(), "203.0.113.7");
+    }
+
+    /// Tests that an empty or whitespace-only answer fails the lookup instead of passing on an
+    /// empty address.
+    #[tokio::test]
+    async fn test_rejects_empty_answer() {
+        for body in ["", " \r\n\t"] {
+            let server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .mount(&server)
+                .await;
+
+            let err = HttpIpSource::new(server.uri(), reqwest::Client::new()).public_ip().await.unwrap_err();
+            assert_eq!(err.to_string(), format!("{} answered with an empty body", server.uri()));
+        }
+    }
+
+    /// Tests that the whitespace around a plain-text answer is trimmed, whatever its line ending,
+    /// and that an answer with whitespace inside is rejected.
+    #[tokio::test]
+    async fn test_trims_plain_answer() {
+        let server = MockServer::start().await;
+        for (route, body) in [("crlf", "1.2.3.4\r\n"), ("spaces", "  1.2.3.4 \t"), ("bom", "\u{feff}2001:db8::1\n"), ("embedded", "1.2.3.4 5.6.7.8\n")] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", route)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .mount(&server)
+                .await;
+        }
+        let source = |route: &str| HttpIpSource::new(format!("{}/{}", server.uri(), route), reqwest::Client::new());
+
+        assert_eq!(source("crlf").public_ip().await.unwrap(), "1.2.3.4");
+        assert_eq!(source("spaces").public_ip().await.unwrap(), "1.2.3.4");
+        assert_eq!(source("bom").public_ip().await.unwrap(), "2001:db8::1");
+        let err = source("embedded").public_ip().await.unwrap_err();
+        assert_eq!(err.to_string(), format!("{}/embedded answered with \"1.2.3.4 5.6.7.8\", which is not an IP address", server.uri()));
+    }
+
+    /// Tests that a plain-text endpoint and a JSON one answer with the same address, and that a
+    /// JSON answer without a well-formed address is rejected.
+    #[tokio::test]
+    async fn test_json_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/plain"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1.2.3.4\n"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ip":"1.2.3.4","country":"NL"}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/nested"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"data":{"ip":"not-an-ip"}}"#))
+            .mount(&server)
+            .await;
+        let source = |route: &str, response: IpResponse| HttpIpSource::new(format!("{}/{}", server.uri(), route), reqwest::Client::new()).with_response(response);
+
+        let plain = source("plain", IpResponse::Plain).public_ip().await.unwrap();
+        let json = source("json", IpResponse::Json("ip".to_string())).public_ip().await.unwrap();
+        assert_eq!((plain.as_str(), json.as_str()), ("1.2.3.4", "1.2.3.4"));
+
+        let err = source("json", IpResponse::Json("address".to_string())).public_ip().await.unwrap_err();
+        assert_eq!(err.to_string(), format!("{}/json answered without a address field", server.uri()));
+        let err = source("nested", IpResponse::Json("data.ip".to_string())).public_ip().await.unwrap_err();
+        assert_eq!(err.to_string(), format!("{}/nested answered with \"not-an-ip\" in data.ip, which is not an IP address", server.uri()));
+        assert!(source("plain", IpResponse::Json("ip".to_string())).public_ip().await.is_err());
+    }
+
+    /// Tests that the next source is asked when one fails or answers with something else than an
+    /// address, and that every failure is reported when none answers.
+    #[tokio::test]
+    async fn test_fallback_chain() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("<html>maintenance</html>"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ip":"1.2.3.4"}"#))
+            .mount(&server)
+            .await;
+        let source = |route: &str| Box::new(HttpIpSource::new(format!("{}/{}", server.uri(), route), reqwest::Client::new())) as Box<dyn IpSource>;
+
+        let chain = FallbackIpSource::new(vec![source("down"), Box::new(HttpIpSource::new(format!("{}/json", server.uri()), reqwest::Client::new()).with_response(IpResponse::Json("ip".to_string())))]);
+        assert_eq!(chain.public_ip().await.unwrap(), "1.2.3.4");
+
+        let err = FallbackIpSource::new(vec![source("down"), source("json")]).public_ip().await.unwrap_err();
+        assert_eq!(err.to_string(), r#"every IP endpoint failed: "<html>maintenance</html>" is not an IP address; "{\"ip\":\"1.2.3.4\"}" is not an IP address"#);
+    }
+
+    /// Tests that a failing lookup is attempted once more than `IP_RETRIES`, and that a retry
+    /// that succeeds answers.
+    #[tokio::test]
+    async fn test_retries_failed_lookup() {
+        for retries in [0, 2] {
+            let server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(""))
+                .expect(u64::from(retries) + 1)
+                .mount(&server)
+                .await;
+            let source = RetryingIpSource::new(Box::new(HttpIpSource::new(server.uri(), reqwest::Client::new())), RetryPolicy { retries, delay: Duration::ZERO });
+
+            assert!(source.public_ip().await.is_err());
+        }
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(""))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("203.0.113.7"))
+            .mount(&server)
+            .await;
+        let source = RetryingIpSource::new(Box::new(HttpIpSource::new(server.uri(), reqwest::Client::new())), RetryPolicy { retries: 1, delay: Duration::ZERO });
+        assert_eq!(source.public_ip().await.unwrap(), "203.0.113.7");
+    }
+
+    /// Tests that the prefix is cut at its length, and composed with the suffix.
+    #[test]
+    fn test_ipv6_prefix() {
+        let prefix = Ipv6Prefix { length: 64, suffix: "::1".parse().unwrap() };
+        let ip: Ipv6Addr = "2001:db8:1234:5678:a1b2:c3d4:e5f6:789a".parse().unwrap();
+
+        assert_eq!(prefix.prefix(ip), "2001:db8:1234:5678::".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(prefix.apply(ip), "2001:db8:1234:5678::1".parse::<Ipv6Addr>().unwrap());
+
+        let prefix = Ipv6Prefix { length: 56, suffix: "ffff:0:0:ab::1".parse().unwrap() };
+        assert_eq!(prefix.prefix(ip), "2001:db8:1234:5600::".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(prefix.apply(ip), "2001:db8:1234:56ab::1".parse::<Ipv6Addr>().unwrap());
+    }
+
+    /// Tests that the whole of 100.64.0.0/10 is taken for carrier-grade NAT, and nothing around it.
+    #[test]
+    fn test_non_routable_cgnat() {
+        let kind = |ip: &str| non_routable(ip.parse().unwrap());
+
+        assert_eq!(kind("100.64.0.0"), Some("carrier-grade NAT (100.64.0.0/10)"));
+        assert_eq!(kind("100.100.12.34"), Some("carrier-grade NAT (100.64.0.0/10)"));
+        assert_eq!(kind("100.127.255.255"), Some("carrier-grade NAT (100.64.0.0/10)"));
+        assert_eq!(kind("100.63.255.255"), None);
+        assert_eq!(kind("100.128.0.0"), None);
+        assert_eq!(kind("192.168.1.10"), Some("private"));
+        assert_eq!(kind("fd00::1"), Some("unique local"));
+        assert_eq!(kind("2001:db8::1"), None);
+    }
+
+    /// Tests that an interface that doesn't exist is reported by name.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_missing_interface() {
+        let err = InterfaceIpSource::new("skysync-none0", false).public_ip().await.unwrap_err();
+
+        assert!(err.to_string().starts_with("interface skysync-none0 has no IPv4 address: "), "{}", err);
+    }
+
+    /// Tests that refreshing always queries the source.
+    #[tokio::test]
+    async fn test_refresh_bypasses_cache() {
+        let server = ip_server(2).await;
+        let source = HttpIpSource::new(server.uri(), reqwest::Client::new());
+        let cache = IpCache::default();
+
+        cache.refresh(&source).await.unwrap();
+        cache.refresh(&source).await.unwrap();
+        cache.get(&source, Duration::from_secs(30)).await.unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+//! `last-run.txt`, the time of the latest successful tick, for external monitors (e.g. a `monit`
+//! rule on its age) and the downtime check at startup.
+use crate::config::Config;
+use crate::error::BoxError;
+use chrono::{DateTime, FixedOffset};
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, read_to_string, rename, write};
+
+/// The name of the file, kept next to `state.json`.
+pub const FILE_NAME: &str = "last-run.txt";
+
+/// How many ticks may be missed before a start reports downtime, when `STALE_AFTER_INTERVALS`
+/// isn't set.
+pub const DEFAULT_STALE_AFTER_INTERVALS: u32 = 3;
+
+/// The file holding when the latest successful tick ran, as a single RFC 3339 line.
+#[derive(Clone)]
+pub struct LastRun {
+    path: PathBuf,
+}
+
+impl LastRun {
+    pub fn new(path: impl Into<PathBuf>) -> LastRun {
+        LastRun { path: path.into() }
+    }
+
+    /// The default location, `last-run.txt` next to the log file.
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir().expect("Failed to get data directory").join("SkySync").join(FILE_NAME)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the stored time. A missing or unreadable file has none.
+    pub async fn load(&self) -> Option<DateTime<FixedOffset>> {
+        let data = read_to_string(&self.path).await.ok()?;
+        DateTime::parse_from_rfc3339(data.trim()).ok()
+    }
+
+    /// Replaces the stored time with `at`.
+    ///
+    /// The time is written next to the file first and then moved over it, so a monitor never
+    /// reads it half-written.
+    pub async fn record(&self, at: DateTime<FixedOffset>) -> Result<(), BoxError> {
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent).await?;
+        }
+
+        let temp = self.path.with_extension("txt.tmp");
+        write(&temp, format!("{}\n", at.to_rfc3339())).await?;
+        rename(&temp, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// How long the daemon went without a successful tick, if that's longer than expected.
+///
+/// # Arguments
+/// * `config` - The configuration, whose schedule or interval sets when ticks were due.
+/// * `last_run` - When the latest successful tick before this start ran.
+/// * `now` - When this start happened.
+///
+/// # Returns
+/// The time since `last_run`, once more than `STALE_AFTER_INTERVALS` ticks were missed, or `None`
+/// when fewer were or the check is disabled.
+pub fn downtime(config: &Config, last_run: DateTime<FixedOffset>, now: DateTime<FixedOffset>) -> Option<chrono::Duration> {
+    if config.stale_after_intervals == 0 {
+        return None;
+    }
+    // Schedules don't fire at even intervals, so count the ticks it would have run instead
+    let mut due = last_run;
+    for _ in 0..config.stale_after_intervals {
+        due = match &config.cron_schedule {
+            Some(schedule) => schedule.next_after(due, config.timezone)?,
+            None => due + chrono::Duration::from_std(config.cron_interval).ok()?,
+        };
+    }
+    (now > due).then(|| now - last_run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::tests::test_config;
+    use crate::timezone::Timezone;
+    use std::time::Duration;
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    /// Tests that the recorded time reads back, and that a missing file has none.
+    #[tokio::test]
+    async fn test_record_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let last_run = LastRun::new(dir.path().join("nested").join(FILE_NAME));
+        assert_eq!(last_run.load().await, None);
+
+        last_run.record(at("2026-10-14T09:30:00-03:00")).await.unwrap();
+        assert_eq!(last_run.load().await, Some(at("2026-10-14T09:30:00-03:00")));
+        assert_eq!(std::fs::read_to_string(last_run.path()).unwrap(), "2026-10-14T09:30:00-03:00\n");
+        assert!(!last_run.path().with_extension("txt.tmp").exists());
+    }
+
+    /// Tests that downtime is only reported past the allowed number of missed intervals, or
+    /// scheduled ticks, and never once disabled.
+    #[test]
+    fn test_downtime() {
+        let config = Config { cron_interval: Duration::from_secs(600), stale_after_intervals: 3, ..test_config() };
+        let last = at("2026-10-14T09:00:00Z");
+
+        assert_eq!(downtime(&config, last, at("2026-10-14T09:25:00Z")), None);
+        assert_eq!(downtime(&config, last, at("2026-10-14T09:45:00Z")), Some(chrono::Duration::minutes(45)));
+
+        let scheduled = Config {
+            cron_schedule: Some("0 9 * * *".parse().unwrap()),
+            timezone: Timezone::Named(chrono_tz::UTC),
+            ..config.clone()
+        };
+        assert_eq!(downtime(&scheduled, last, at("2026-10-16T10:00:00Z")), None);
+        assert!(downtime(&scheduled, last, at("2026-10-17T10:00:00Z")).is_some());
+
+        let disabled = Config { stale_after_intervals: 0, ..config };
+        assert_eq!(downtime(&disabled, last, at("2027-01-01T00:00:00Z")), None);
+    }
+}
@@ -0,0 +1,35 @@
+//! SkySync keeps DNS records pointed at the public IP address of the machine it runs on.
+//!
+//! The binary is a thin loop around [`Syncer::run_once`]; everything it uses is exposed here so
+//! the same cycle can be embedded or driven from tests with custom providers and notifiers.
+extern crate dotenv;
+pub mod cli;
+pub mod config;
+pub mod console;
+pub mod daemon;
+pub mod encryption;
+pub mod error;
+pub mod geo;
+pub mod history;
+pub mod hook;
+pub mod http;
+pub mod ip;
+pub mod last_run;
+pub mod log;
+pub mod metrics;
+pub mod notifier;
+pub mod provider;
+pub mod resolver;
+pub mod retry;
+pub mod scaffold;
+pub mod setup;
+pub mod schedule;
+pub mod server;
+mod services;
+pub mod status_file;
+pub mod sync;
+pub mod syslog;
+pub mod timezone;
+pub mod update;
+
+pub use crate::sync::{run_once, RecordOutcome, State, Syncer, TickOutcome, TickSummary};
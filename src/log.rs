@@ -0,0 +1,496 @@
+use crate::console;
+#[cfg(unix)]
+use crate::syslog::Syslog;
+use chrono::{DateTime, Local};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use tokio::fs::{create_dir_all, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+/// How many log lines `GET /logs` keeps when `LOG_BUFFER_LENGTH` isn't set.
+pub const DEFAULT_LOG_BUFFER_LENGTH: usize = 200;
+
+/// How long a log entry may get when `LOG_MAX_ENTRY_BYTES` isn't set, enough for any message
+/// but a whole error page pasted into one.
+pub const DEFAULT_LOG_MAX_ENTRY_BYTES: usize = 16 * 1024;
+
+/// How long an entry may get before it's truncated, from `LOG_MAX_ENTRY_BYTES`. Zero is no limit.
+static MAX_ENTRY_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_LOG_MAX_ENTRY_BYTES);
+
+/// Whether `debug_log` messages are written, set by `--verbose`.
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Where `write_log` appends once `LOG_PATH` was applied with `set_log_path`.
+static LOG_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Whether messages are appended to the log file, turned off by `LOG_TARGET=syslog`.
+static LOG_TO_FILE: AtomicBool = AtomicBool::new(true);
+
+/// Collapses repeated entries in the log file once `LOG_DEDUP` turned it on with `set_dedup`.
+static DEDUP: Mutex<Option<Dedup>> = Mutex::new(None);
+
+/// The system logger messages are also sent to, once `set_log_target` connected to it.
+#[cfg(unix)]
+static SYSLOG: OnceLock<Syslog> = OnceLock::new();
+
+tokio::task_local! {
+    /// The profile the current task runs, which its messages are tagged with, set by `in_profile`.
+    static PROFILE: String;
+}
+
+/// Where log messages go, from `LOG_TARGET`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogTarget {
+    /// Appended to `LOG_PATH`.
+    #[default]
+    File,
+    /// Sent to the system logger, e.g. syslog or journald, instead of the file.
+    Syslog,
+    /// Both appended to the file and sent to the system logger.
+    Both,
+}
+
+impl LogTarget {
+    /// The name used for this target in `LOG_TARGET`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogTarget::File => "file",
+            LogTarget::Syslog => "syslog",
+            LogTarget::Both => "both",
+        }
+    }
+
+    /// Whether messages are appended to the log file.
+    pub fn to_file(self) -> bool {
+        self != LogTarget::Syslog
+    }
+}
+
+/// How important a log message is, as the system logger is told.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    /// `debug_log`, only written with `--verbose`.
+    Debug,
+    /// `write_log`, the routine record of what the daemon did.
+    Info,
+    /// `report`, what is also printed to stderr: failures, retries and warnings.
+    Warning,
+}
+
+impl Level {
+    /// The syslog severity of the level, from RFC 5424.
+    pub fn severity(self) -> u8 {
+        match self {
+            Level::Debug => 7,
+            Level::Info => 6,
+            Level::Warning => 4,
+        }
+    }
+}
+
+/// The latest log lines, kept in memory so they can be read without access to `log.txt`.
+/// The oldest lines are dropped first.
+#[derive(Clone)]
+pub struct LogBuffer {
+    inner: Arc<Mutex<Lines>>,
+}
+
+struct Lines {
+    limit: usize,
+    lines: VecDeque<String>,
+}
+
+impl LogBuffer {
+    /// Creates a buffer keeping at most `limit` lines.
+    pub fn new(limit: usize) -> LogBuffer {
+        LogBuffer { inner: Arc::new(Mutex::new(Lines { limit, lines: VecDeque::new() })) }
+    }
+
+    /// The buffer `write_log` feeds.
+    pub fn global() -> &'static LogBuffer {
+        static GLOBAL: OnceLock<LogBuffer> = OnceLock::new();
+        GLOBAL.get_or_init(|| LogBuffer::new(DEFAULT_LOG_BUFFER_LENGTH))
+    }
+
+    /// Changes how many lines are kept, dropping the oldest ones if there are too many.
+    pub fn set_limit(&self, limit: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.limit = limit;
+        let excess = inner.lines.len().saturating_sub(limit);
+        inner.lines.drain(..excess);
+    }
+
+    /// Appends every line of `message`, the way it's written to `log.txt`.
+    pub fn push(&self, message: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        for line in message.lines().filter(|line| !line.is_empty()) {
+            if inner.lines.len() == inner.limit {
+                if inner.limit == 0 {
+                    return;
+                }
+                inner.lines.pop_front();
+            }
+            inner.lines.push_back(line.to_string());
+        }
+    }
+
+    /// The kept lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.inner.lock().unwrap().lines.iter().cloned().collect()
+    }
+}
+
+/// The log file used when `LOG_PATH` isn't set, `log.txt` in the platform data directory.
+pub fn default_log_path() -> PathBuf {
+    dirs::data_dir().expect("Failed to get data directory").join("SkySync").join("log.txt")
+}
+
+/// The file `write_log` appends to.
+pub fn log_path() -> PathBuf {
+    LOG_PATH.read().unwrap().clone().unwrap_or_else(default_log_path)
+}
+
+/// Makes `write_log` append to `path` from now on, creating its parent directories.
+///
+/// # Returns
+/// An error, and the previous file kept, if `path` can't be created or written to.
+pub fn set_log_path(path: &Path) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    *LOG_PATH.write().unwrap() = Some(path.to_path_buf());
+    Ok(())
+}
+
+/// Sends log messages where `LOG_TARGET` says from now on, connecting to the system logger if
+/// it's one of them.
+///
+/// # Returns
+/// An error, and the messages still only written to the file, if the system logger can't be
+/// reached.
+pub fn set_log_target(target: LogTarget) -> std::io::Result<()> {
+    if target != LogTarget::File {
+        connect_syslog()?;
+    }
+    LOG_TO_FILE.store(target.to_file(), Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect_syslog() -> std::io::Result<()> {
+    if SYSLOG.get().is_none() {
+        // Another thread connecting at the same time is as good
+        let _ = SYSLOG.set(Syslog::connect()?);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn connect_syslog() -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "the system logger is only available on Unix"))
+}
+
+/// Runs `future` with every message it logs or prints tagged with `profile`, or as-is without one.
+///
+/// Tasks spawned by `future` aren't tagged, they have to be run in the profile themselves.
+pub async fn in_profile<F: Future>(profile: Option<String>, future: F) -> F::Output {
+    match profile {
+        Some(profile) => PROFILE.scope(profile, future).await,
+        None => future.await,
+    }
+}
+
+/// Prefixes every non-empty line of `message` with the profile of the current task, e.g.
+/// `[home] Updated home.example.com`. Outside a profile the message is kept as-is.
+pub fn tag(message: &str) -> Cow<'_, str> {
+    PROFILE.try_with(|profile| {
+        let tagged: String = message.split_inclusive('\n').map(|line| match line.trim().is_empty() {
+            true => line.to_string(),
+            false => format!("[{}] {}", profile, line),
+        }).collect();
+        Cow::Owned(tagged)
+    }).unwrap_or(Cow::Borrowed(message))
+}
+
+/// Writes a log message to a log file.
+///
+/// # Arguments
+/// * `message` - A string slice that holds the message to be logged.
+pub async fn write_log(message: &str) {
+    record(Level::Info, &log_path(), &truncate_entry(message, max_entry_bytes())).await;
+}
+
+/// Truncates entries longer than `max_bytes` from now on, or none when it's zero.
+pub fn set_max_entry_bytes(max_bytes: usize) {
+    MAX_ENTRY_BYTES.store(max_bytes, Ordering::Relaxed);
+}
+
+fn max_entry_bytes() -> usize {
+    MAX_ENTRY_BYTES.load(Ordering::Relaxed)
+}
+
+/// Cuts `message` down to `max_bytes`, not counting a trailing newline, which is kept, and marks
+/// how much was cut.
+///
+/// # Returns
+/// `message` itself when it fits or `max_bytes` is zero, or else its start followed by
+/// `…[truncated N bytes]`.
+pub fn truncate_entry(message: &str, max_bytes: usize) -> Cow<'_, str> {
+    let body = message.strip_suffix('\n').unwrap_or(message);
+    if max_bytes == 0 || body.len() <= max_bytes {
+        return Cow::Borrowed(message);
+    }
+
+    let mut end = max_bytes;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    let newline = &message[body.len()..];
+    Cow::Owned(format!("{}…[truncated {} bytes]{}", &body[..end], body.len() - end, newline))
+}
+
+/// Keeps a message in the buffer, and writes it to the file and the system logger, as
+/// `LOG_TARGET` says.
+async fn record(level: Level, log_file: &Path, message: &str) {
+    let message = &*tag(message);
+    LogBuffer::global().push(message);
+    if LOG_TO_FILE.load(Ordering::Relaxed) {
+        let entry = match DEDUP.lock().unwrap().as_mut() {
+            Some(dedup) => dedup.entry(message, Local::now()).map(Cow::Owned),
+            None => Some(Cow::Borrowed(message)),
+        };
+        if let Some(entry) = entry {
+            append_log(log_file, &entry).await;
+        }
+    }
+    #[cfg(unix)]
+    if let Some(syslog) = SYSLOG.get() {
+        syslog.send(level, message);
+    }
+    #[cfg(not(unix))]
+    let _ = level;
+}
+
+/// Collapses runs of identical entries in the log file from now on, or stops doing so.
+pub fn set_dedup(enabled: bool) {
+    let mut dedup = DEDUP.lock().unwrap();
+    match enabled {
+        true => {
+            dedup.get_or_insert_with(Dedup::default);
+        }
+        false => *dedup = None,
+    }
+}
+
+/// Collapses runs of entries that only differ in their timestamps, for `LOG_DEDUP`, so a long
+/// stretch of unchanged ticks takes two lines of the log file instead of one entry per tick.
+#[derive(Debug, Default)]
+pub struct Dedup {
+    /// The latest entry that was written, with its timestamps masked.
+    last: Option<String>,
+    /// Whether that entry ended its line.
+    ended_line: bool,
+    /// How many entries since were the same, and when the latest of them came.
+    repeated: u32,
+    repeated_at: Option<DateTime<Local>>,
+}
+
+impl Dedup {
+    /// What to append to the file for `message`.
+    ///
+    /// # Arguments
+    /// * `message` - The entry, as it would be appended.
+    /// * `now` - When it was logged.
+    ///
+    /// # Returns
+    /// Nothing while `message` repeats the latest entry written. Otherwise the message, after a
+    /// line saying how often and until when the latest entry repeated, if it did.
+    pub fn entry(&mut self, message: &str, now: DateTime<Local>) -> Option<String> {
+        let key = mask_timestamps(message);
+        if self.last.as_ref() == Some(&key) {
+            self.repeated += 1;
+            self.repeated_at = Some(now);
+            return None;
+        }
+
+        let summary = self.summary().unwrap_or_default();
+        self.last = Some(key);
+        self.ended_line = message.ends_with('\n');
+        Some(format!("{}{}", summary, message))
+    }
+
+    /// The line ending the current run of repeats, if there is one, which starts a new run.
+    fn summary(&mut self) -> Option<String> {
+        let at = self.repeated_at.take()?;
+        let repeated = std::mem::take(&mut self.repeated);
+        let newline = if self.ended_line { "" } else { "\n" };
+        Some(format!("{}(last entry repeated {} time(s), last at {})\n", newline, repeated, at.format("%Y-%m-%d %H:%M:%S")))
+    }
+}
+
+/// `message` with every timestamp in it replaced by `<time>`, so entries of different ticks compare equal.
+fn mask_timestamps(message: &str) -> String {
+    message.split_inclusive(char::is_whitespace).map(|word| {
+        let token = word.trim_end().trim_matches(|c: char| matches!(c, '(' | ')' | ',' | ':' | '"'));
+        match DateTime::parse_from_rfc3339(token).is_ok() {
+            true => word.replacen(token, "<time>", 1),
+            false => word.to_string(),
+        }
+    }).collect()
+}
+
+/// Turns the debug messages of `debug_log` on or off.
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+/// Whether debug messages are written.
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Prints a message to stderr, unless `--quiet`, and writes it to the log file either way.
+///
+/// # Arguments
+/// * `message` - The message, without a trailing newline.
+pub async fn report(message: &str) {
+    report_to(std::io::stderr(), console::quiet(), &log_path(), Level::Warning, message).await;
+}
+
+async fn report_to(console: impl Write, quiet: bool, log_file: &Path, level: Level, message: &str) {
+    // Debug messages carry the raw responses, which are kept whole for debugging
+    let message = match level {
+        Level::Debug => Cow::Borrowed(message),
+        _ => truncate_entry(message, max_entry_bytes()),
+    };
+    console::emit(console, quiet, &message);
+    record(level, log_file, &format!("{}\n", message)).await;
+}
+
+/// Writes a debug message to stderr and the log file, but only with `--verbose`.
+///
+/// # Arguments
+/// * `message` - The message, without a trailing newline.
+pub async fn debug_log(message: &str) {
+    if !verbose() {
+        return;
+    }
+    report_to(std::io::stderr(), console::quiet(), &log_path(), Level::Debug, &format!("[debug] {}", message)).await;
+}
+
+/// Appends a log message to the file at `log_file`, creating it and its directory if needed.
+///
+/// # Arguments
+/// * `log_file` - The file to append to.
+/// * `message` - A string slice that holds the message to be logged.
+pub async fn append_log(log_file: &Path, message: &str) {
+    if let Some(log_path) = log_file.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        create_dir_all(log_path).await.expect("Failed to create log directory");
+    }
+
+    if !log_file.exists() {
+        File::create(log_file).await.expect("Failed to create log file");
+    }
+
+    let mut write_log = OpenOptions::new()
+        .append(true)
+        .open(log_file)
+        .await.unwrap();
+
+    write_log.write_all(message.as_bytes()).await.expect("Failed to write to log file");
+    // Tokio hands writes to a background thread, so make sure the message is on disk before
+    // the file is dropped; otherwise a shutdown right after could lose it
+    write_log.flush().await.expect("Failed to flush log file");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that messages are appended to a custom file, whose directories are created first.
+    #[tokio::test]
+    async fn test_append_to_custom_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("var").join("log").join("skysync.log");
+
+        append_log(&path, "first\n").await;
+        append_log(&path, "second\n").await;
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+    }
+
+    /// Tests that an oversized entry is cut at the limit with a marker, keeping its newline, and
+    /// that an entry within it is left untouched.
+    #[test]
+    fn test_truncate_entry() {
+        let page = format!("Unexpected Cloudflare response (HTTP 502): {}\n", "<html>".repeat(100));
+
+        let truncated = truncate_entry(&page, 50);
+        assert_eq!(truncated, format!("{}…[truncated {} bytes]\n", &page[..50], page.len() - 51));
+        assert!(matches!(truncate_entry("Updated home.example.com\n", 50), Cow::Borrowed("Updated home.example.com\n")));
+        assert!(matches!(truncate_entry(&page, 0), Cow::Borrowed(_)));
+        // Never in the middle of a character
+        assert_eq!(truncate_entry("ééé", 3), "é…[truncated 4 bytes]");
+    }
+
+    /// Tests that entries only differing in their timestamps are collapsed into one, and that
+    /// the next different entry ends the run with how often and until when it repeated.
+    #[test]
+    fn test_dedup_collapses_repeats() {
+        use chrono::TimeZone;
+        let at = |minute| Local.with_ymd_and_hms(2026, 10, 14, 9, minute, 0).unwrap();
+        let tick = |minute: u32| format!("Running cron job at 2026-10-14T09:{:02}:00.5+00:00\nhome.example.com: Public IP is already up to date: 2.2.2.2\n", minute);
+        let mut dedup = Dedup::default();
+
+        assert_eq!(dedup.entry(&tick(0), at(0)), Some(tick(0)));
+        assert_eq!(dedup.entry(&tick(30), at(30)), None);
+        assert_eq!(dedup.entry(&tick(31), at(31)), None);
+        let changed = "home.example.com: Public IP has changed from 2.2.2.2 to: 3.3.3.3\n";
+        assert_eq!(dedup.entry(changed, at(32)), Some(format!("(last entry repeated 2 time(s), last at 2026-10-14 09:31:00)\n{}", changed)));
+        // A different entry starts over, and a run of one is written as it is
+        assert_eq!(dedup.entry(&tick(33), at(33)), Some(tick(33)));
+        assert_eq!(dedup.entry("Updated\n", at(34)), Some("Updated\n".to_string()));
+    }
+
+    /// Tests that the messages of a profile are tagged line by line, and others left alone.
+    #[tokio::test]
+    async fn test_tag_in_profile() {
+        let tagged = in_profile(Some("home".to_string()), async { tag("Running cron job\n\nUpdated home.example.com\n").to_string() }).await;
+
+        assert_eq!(tagged, "[home] Running cron job\n\n[home] Updated home.example.com\n");
+        assert!(matches!(tag("Updated home.example.com\n"), Cow::Borrowed(_)));
+        assert_eq!(in_profile(None, async { tag("Checking").to_string() }).await, "Checking");
+    }
+
+    /// Tests that a quiet report prints nothing, and still reaches the log file.
+    #[tokio::test]
+    async fn test_quiet_report_still_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        let mut console = Vec::new();
+
+        report_to(&mut console, true, &path, Level::Warning, "Failed to save state.json").await;
+        assert!(console.is_empty());
+        report_to(&mut console, false, &path, Level::Warning, "Configuration reloaded").await;
+        assert_eq!(String::from_utf8(console).unwrap(), "Configuration reloaded\n");
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Failed to save state.json\nConfiguration reloaded\n");
+    }
+
+    /// Tests that a path that can't be written to is rejected and the previous file kept.
+    #[test]
+    fn test_set_log_path_rejects_unwritable() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not-a-directory");
+        std::fs::write(&file, "").unwrap();
+
+        assert!(set_log_path(&file.join("log.txt")).is_err());
+        assert_ne!(log_path(), file.join("log.txt"));
+    }
+}
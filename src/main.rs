@@ -1,151 +1,367 @@
-extern crate dotenv;
-mod services;
-
-use crate::services::cloudflare::service::{dns_records, Root};
-use crate::services::discord::webhooks::send_webhook_message;
-use dotenv::dotenv;
-use tokio::fs::{create_dir_all, File, OpenOptions};
-use tokio::io::AsyncWriteExt;
-use tokio::runtime::Handle;
-use tokio::time::{interval, Duration};
-
-/// Fetches the public IP address of the current machine.
-///
-/// # Returns
-/// A `String` containing the public IP address.
-async fn get_public_ip() -> String {
-    let client = reqwest::Client::new();
-    let response = client.get("https://ipv4.icanhazip.com")
-        .send()
-        .await
-        .expect("Failed to send request");
-
-    let data = response.text().await.expect("Failed to get response");
-    data
-}
-
-/// Writes a log message to a log file.
-///
-/// # Arguments
-/// * `message` - A string slice that holds the message to be logged.
-async fn write_log(message: &str) {
-    let log_path = dirs::data_dir().expect("Failed to get data directory").join("SkySync");
-    let log_file = log_path.join("log.txt");
-    create_dir_all(&log_path).await.expect("Failed to create log directory");
-
-    if !log_file.exists() {
-        File::create(&log_file).await.expect("Failed to create log file");
-    }
-
-    let mut write_log = OpenOptions::new()
-        .write(true)
-        .open(&log_file)
-        .await.unwrap();
-
-    write_log.write_all(message.as_bytes()).await.expect("Failed to write to log file");
-}
+use clap::Parser;
+use std::io::IsTerminal;
+use skysync::cli::{self, Cli, Command, Exit};
+use skysync::config::{default_config_file, Config, ConfigLoader, Mode, ProviderKind};
+use skysync::console::{self, Tone};
+use skysync::daemon;
+use skysync::error::BoxError;
+use skysync::history::{self, History};
+use skysync::http;
+use skysync::log::{self, report, write_log};
+use skysync::notifier::{self, EventStatus, UpdateEvent};
+use skysync::provider::{self, CloudflareProvider, DnsProvider, DnsRecord, GandiProvider, PorkbunProvider};
+use skysync::scaffold;
+use skysync::setup;
+use skysync::{sync, State, Syncer};
 
 /// Main function that initializes the environment and starts the cron job.
 ///
 /// This function sets up a repeating timer to check and update the public IP address
-/// and DNS records at regular intervals.
+/// and DNS records at regular intervals, until asked to stop.
 #[tokio::main]
 async fn main() {
-    dotenv().ok();
-
-    // Get a handle to the Tokio runtime
-    let handle = Handle::current();
-
-    // Get the duration from the .env
-    let env_duration = std::env::var("CRON_INTERVAL").expect("Expected a cron interval in the environment");
-    let interval_duration = Duration::from_secs(env_duration.parse::<u64>().expect("Failed to parse cron interval"));
-    let mut last_public_ip: Option<String> = None;
-
-    // Spawn a new task that sets up a repeating timer and runs cron_init
-    tokio::spawn(async move {
-        let mut interval = interval(interval_duration);
-
-        loop {
-            interval.tick().await; // Wait for the next tick
-
-            let mut msg: String = String::new();
-            let start_msg = format!(
-                "Running cron job at {:?}\nNext run at {:?}",
-                chrono::Local::now(),
-                chrono::Local::now() + interval_duration
-            );
-            msg.push_str(&start_msg);
-            println!("{}", start_msg);
-
-            let dns_name = std::env::var("CF_DNS_NAME").expect("Expected a DNS name in the environment");
-
-            // Hold in memory the last public IP to compare on the next iteration
-            let my_public_ip = get_public_ip().await.replace("\n", "");
-
-            // Compare the current public IP with the last one stored
-            if let Some(last_ip) = &last_public_ip {
-                if my_public_ip != *last_ip {
-                    // Update the last public IP
-                    last_public_ip = Some(my_public_ip.clone());
-                }
+    let cli = Cli::parse();
+    console::init(cli.no_color);
+    console::set_quiet(cli.quiet);
+    log::set_verbose(cli.verbose);
 
-                // If the ip is unchanged, return the function early
-                println!("Public IP has not changed: {}", my_public_ip);
-                msg.push_str(&format!("\nPublic IP has not changed: {}", my_public_ip));
-                write_log(&msg).await;
-                continue;
+    match cli.command {
+        Some(Command::Init { path, interactive, force }) => {
+            let answers = if interactive {
+                match scaffold::prompt(std::io::stdin().lock(), std::io::stdout()) {
+                    Ok(answers) => answers,
+                    Err(err) => {
+                        eprintln!("Failed to read the answers: {}", err);
+                        std::process::exit(1);
+                    }
+                }
             } else {
-                // Update the last public IP
-                last_public_ip = Some(my_public_ip.clone());
-                println!("Public IP has changed to: {}", my_public_ip);
-                msg.push_str(&format!("\nPublic IP has changed to: {}", my_public_ip));
+                Vec::new()
+            };
+            let contents = scaffold::render(&answers);
+            match path {
+                Some(path) => match scaffold::export(&path, &contents, force) {
+                    Ok(()) => println!("Wrote {}", path.display()),
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                        eprintln!("{} already exists, pass --force to replace it", path.display());
+                        std::process::exit(1);
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to write {}: {}", path.display(), err);
+                        std::process::exit(1);
+                    }
+                },
+                None => print!("{}", contents),
             }
-
-            let record = match dns_records(None).await {
-                Ok(Root { result, .. }) => {
-                    result.into_iter().find(|x| x.name == dns_name).unwrap_or_else(|| panic!("Failed to find DNS record"))
-                }
-                _ => {
-                    panic!("Failed to fetch DNS records");
+            return;
+        }
+        Some(Command::Setup { path, force }) => {
+            if !std::io::stdin().is_terminal() {
+                eprintln!("skysync setup asks its questions on a terminal, and none is attached. Pass the settings as flags \
+                    (see `skysync --help`) or environment variables instead, or write a file to fill in with `skysync init`");
+                std::process::exit(Exit::Config.code());
+            }
+            if path.exists() && !force {
+                eprintln!("{} already exists, pass --force to replace it", path.display());
+                std::process::exit(Exit::Config.code());
+            }
+            let mut prompt = setup::LinePrompt::new(std::io::stdin().lock(), std::io::stdout(), true);
+            let contents = match setup::run(&mut prompt, &setup::LiveCheck, &path).await {
+                Ok(contents) => contents,
+                Err(err) => {
+                    eprintln!("Setup stopped: {}", err);
+                    std::process::exit(Exit::Config.code());
                 }
             };
+            match scaffold::export(&path, &contents, force) {
+                Ok(()) => println!("{}", console::paint(Tone::Success, &format!("Wrote {}, start SkySync with `skysync --config {}`", path.display(), path.display()))),
+                Err(err) => {
+                    eprintln!("Failed to write {}: {}", path.display(), err);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Service) => {
+            // The service control manager runs its own runtime on a dedicated thread
+            #[cfg(all(windows, feature = "windows-service"))]
+            {
+                // The service moves to the executable's directory before reading it
+                let config_file = cli.config.map(|path| std::path::absolute(&path).unwrap_or(path));
+                if let Err(err) = tokio::task::block_in_place(|| daemon::run_as_service(config_file)) {
+                    eprintln!("Failed to start the Windows service: {}", err);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            #[cfg(not(all(windows, feature = "windows-service")))]
+            {
+                eprintln!("SkySync was built without the `windows-service` feature");
+                std::process::exit(1);
+            }
+        }
+        // Needs the configuration, so handled once it's loaded
+        Some(Command::Diff | Command::History | Command::PrintIp | Command::Records { .. } | Command::TestNotify) | None => {}
+    }
 
-            if record.content == my_public_ip {
-                println!("Public IP is already up to date: {}", my_public_ip);
-                msg.push_str(&format!("\nPublic IP is already up to date: {}", my_public_ip));
-                write_log(&msg).await;
-                continue;
-            }
-
-            // Update the DNS record with the new public IP
-            let update = crate::services::cloudflare::service::update_dns_records(
-                &record.id,
-                // Update this as needed
-                crate::services::cloudflare::service::DnsType::A,
-                &dns_name,
-                &my_public_ip,
-                1,
-                false,
-            ).await;
-
-            if update.success {
-                send_webhook_message(
-                    &format!("O IP público do domínio {} foi atualizado com sucesso!", dns_name),
-                    Option::from(false),
-                ).await;
-            } else {
-                send_webhook_message(
-                    &format!("Falha ao atualizar o IP público do domínio {}!\n\n```{}```", dns_name, update.errors[0]),
-                    Option::from(true),
-                ).await;
+    let config_file = match cli.config {
+        Some(path) if !path.is_file() => {
+            eprintln!("Config file {} doesn't exist", path.display());
+            std::process::exit(Exit::Config.code());
+        }
+        Some(path) => Some(path),
+        None => default_config_file(),
+    };
+    let source = match &config_file {
+        Some(path) => format!("Reading settings from {}", path.display()),
+        None => "No config file found, reading settings from .env and the environment".to_string(),
+    };
+
+    let has_config_file = config_file.is_some();
+    let loader = match config_file {
+        Some(path) => ConfigLoader::new(".env").with_toml(path),
+        None => ConfigLoader::new(".env"),
+    }.with_overrides(cli.settings.vars());
+    if cli.validate_config {
+        std::process::exit(validate_config(&loader, &source).code());
+    }
+    // Nothing is logged before the log file is open, so errors up to then are printed even when quiet
+    let profiles = match loader.load_profiles() {
+        Ok(profiles) => profiles,
+        Err(err) => {
+            eprintln!("{}", console::paint(Tone::Error, &err.to_string()));
+            if !has_config_file && std::io::stdin().is_terminal() {
+                eprintln!("No config file was found, run `skysync setup` to be walked through writing one");
+            }
+            std::process::exit(Exit::Config.code());
+        }
+    };
+    // The one-off commands and the process-wide settings follow the first profile
+    let config = profiles[0].clone();
+    if config.log_target.to_file() {
+        if let Err(err) = log::set_log_path(&config.log_path) {
+            let msg = format!("Can't write the log file {}: {}", config.log_path.display(), err);
+            eprintln!("{}", console::paint(Tone::Error, &msg));
+            std::process::exit(Exit::Config.code());
+        }
+    }
+    log::set_max_entry_bytes(config.log_max_entry_bytes);
+    log::set_dedup(config.log_dedup);
+    if let Err(err) = log::set_log_target(config.log_target) {
+        let msg = format!("Can't reach the system logger: {}", err);
+        eprintln!("{}", console::paint(Tone::Error, &msg));
+        std::process::exit(Exit::Config.code());
+    }
+    // With PROFILES, every profile keeps its own history
+    if cli.command == Some(Command::History) {
+        let history = History::new(config.profile_path(History::default_path()), 0);
+        match history.load_all().await {
+            Ok(entries) if entries.is_empty() => println!("No IP changes recorded yet"),
+            Ok(entries) => print!("{}", history::render_table(&entries)),
+            Err(err) => {
+                eprintln!("Failed to read {}: {}", history.path().display(), err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // Only the address goes to stdout, for scripts
+    if cli.command == Some(Command::PrintIp) {
+        match Syncer::from_config(&config).public_ip().await {
+            Ok(ip) => println!("{}", ip),
+            Err(err) => {
+                eprintln!("{}", console::paint(Tone::Error, &err.to_string()));
+                std::process::exit(Exit::IpFetch.code());
+            }
+        }
+        return;
+    }
+
+    console::set_quiet(cli.quiet || config.quiet);
+    console::out(&source);
+    write_log(&format!("{}\n", source)).await;
+    for profile in &profiles {
+        log::in_profile(profile.profile.clone(), async {
+            for warning in profile.warnings() {
+                report(&format!("Warning: {}", warning)).await;
+            }
+        }).await;
+    }
+
+    if let Some(Command::Records { record_type }) = cli.command {
+        match list_records(&config, record_type.as_deref()).await {
+            Ok(records) if records.is_empty() => println!("No records found"),
+            Ok(records) => print!("{}", provider::render_table(&records)),
+            Err(err) => {
+                eprintln!("Failed to list records: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if cli.command == Some(Command::Diff) {
+        if config.mode == Mode::Monitor {
+            eprintln!("MODE=monitor doesn't manage any record, so there's nothing to compare");
+            std::process::exit(1);
+        }
+        match Syncer::from_config(&config).diff(&config).await {
+            Ok(diffs) => {
+                for diff in &diffs {
+                    let tone = match diff.detected {
+                        Err(_) => Tone::Error,
+                        Ok(_) if diff.would_change() => Tone::Success,
+                        Ok(_) => Tone::Unchanged,
+                    };
+                    println!("{}", console::paint(tone, &diff.to_string()));
+                }
+                println!("{} of {} records would change", diffs.iter().filter(|diff| diff.would_change()).count(), diffs.len());
+            }
+            Err(err) => {
+                eprintln!("Failed to compare the records: {}", err);
+                std::process::exit(1);
             }
+        }
+        return;
+    }
+
+    if cli.command == Some(Command::TestNotify) {
+        if !test_notify(&config).await {
+            std::process::exit(1);
+        }
+        return;
+    }
 
-            write_log(&msg).await;
+    if cli.once {
+        let syncer = Syncer::from_config(&config).with_force(cli.force);
+        // Carries on from the daemon's state, so the debounce, the circuit breaker and the
+        // notification throttle count this run like any other tick
+        let state_path = config.profile_path(State::default_path());
+        let mut state = match State::load(&state_path, config.state_key.as_ref()).await {
+            Ok(state) => state,
+            Err(err) => {
+                report(&format!("Failed to read {}, starting fresh: {}", state_path.display(), err)).await;
+                State::default()
+            }
+        };
+        let result = syncer.run_once(&config, &mut state).await;
+        if let Err(err) = state.save(&state_path, config.state_key.as_ref()).await {
+            report(&format!("Failed to save {}: {}", state_path.display(), err)).await;
         }
-    });
+        match &result {
+            Ok(summary) => {
+                console::out(&console::summary(summary));
+                write_log(&format!("{}\n", summary)).await;
+            }
+            Err(err) => {
+                console::err(&console::paint(Tone::Error, &err.to_string()));
+                write_log(&format!("{}\n", err)).await;
+            }
+        }
+        // The last line is for schedulers and scripts, so it's never colored
+        console::out(&cli::result_line(&result));
+        std::process::exit(Exit::of(&result).code());
+    }
+
+    if let Err(exit) = daemon::run(loader, profiles, cli.check_first, daemon::shutdown_signal()).await {
+        std::process::exit(exit.code());
+    }
+}
 
-    // Prevent main from exiting immediately
-    handle.spawn_blocking(|| {
-        loop {}
-    }).await.unwrap();
-}
\ No newline at end of file
+/// Validates the settings the daemon would start with, for `skysync --validate-config`.
+///
+/// # Arguments
+/// * `loader` - Where the settings are read from.
+/// * `source` - Which files those are, as printed on startup.
+///
+/// # Returns
+/// `Exit::Config` if any problem was found, `Exit::Success` otherwise.
+fn validate_config(loader: &ConfigLoader, source: &str) -> Exit {
+    println!("{}", source);
+    let problems = loader.problems();
+    for problem in &problems {
+        eprintln!("{}", console::paint(Tone::Error, &problem.to_string()));
+    }
+    if !problems.is_empty() {
+        eprintln!("Found {} problem(s) in the configuration", problems.len());
+        return Exit::Config;
+    }
+
+    for config in loader.load_profiles().unwrap_or_default() {
+        let prefix = config.profile.as_ref().map(|profile| format!("[{}] ", profile)).unwrap_or_default();
+        for warning in config.warnings() {
+            println!("{}", console::paint(Tone::Unchanged, &format!("{}Warning: {}", prefix, warning)));
+        }
+    }
+    println!("{}", console::paint(Tone::Success, "The configuration is valid"));
+    Exit::Success
+}
+
+/// Sends a sample event through every configured notifier, for `skysync test-notify`.
+///
+/// Each notifier is reported on its own line, so one failing doesn't hide how the others went.
+///
+/// # Returns
+/// Whether every notifier delivered the event.
+async fn test_notify(config: &Config) -> bool {
+    let client = match http::client(config.proxy.as_ref(), &config.pool) {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("Failed to build the HTTP client: {}", err);
+            return false;
+        }
+    };
+    let notifiers = notifier::all_from_config(config, &client);
+    if notifiers.is_empty() {
+        eprintln!("No notifiers are configured");
+        return false;
+    }
+
+    // Documentation addresses, so the sample can't be mistaken for a real change
+    let domain = config.dns_names().into_iter().next().unwrap_or_else(|| "home.example.com".to_string());
+    let event = UpdateEvent::new(&domain, Some("192.0.2.1".to_string()), "198.51.100.1", EventStatus::Success)
+        .in_timezone(config.timezone)
+        .as_test();
+
+    let mut delivered = true;
+    for (name, result) in notifier::deliver_each(&notifiers, &event, config.notify_retry).await {
+        match result {
+            Ok(()) => println!("{}", console::paint(Tone::Success, &format!("{}: delivered", name))),
+            Err(err) => {
+                println!("{}", console::paint(Tone::Error, &format!("{}: failed: {}", name, err)));
+                delivered = false;
+            }
+        }
+    }
+    delivered
+}
+
+/// Lists the records of every configured zone, for `skysync records`.
+///
+/// # Arguments
+/// * `config` - The configuration with the zones and credentials.
+/// * `record_type` - Only list records of this type, instead of every type.
+async fn list_records(config: &Config, record_type: Option<&str>) -> Result<Vec<DnsRecord>, BoxError> {
+    let client = http::client(config.proxy.as_ref(), &config.pool)?;
+    match (config.provider, record_type) {
+        (ProviderKind::Cloudflare, _) => {
+            let provider = CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), client).with_record_type(record_type)?;
+            provider.list_records().await
+        }
+        // The others are asked for one record type at a time, the configured one by default
+        (ProviderKind::Gandi, Some(record_type)) => match &config.gandi {
+            Some(gandi) => GandiProvider::new(gandi.clone(), config.zones.clone(), client).with_record_type(record_type).list_records().await,
+            None => Ok(Vec::new()),
+        },
+        (ProviderKind::Porkbun, Some(record_type)) => match &config.porkbun {
+            Some(porkbun) => PorkbunProvider::new(porkbun.clone(), config.zones.clone(), client).with_record_type(record_type).list_records().await,
+            None => Ok(Vec::new()),
+        },
+        #[cfg(feature = "route53")]
+        (ProviderKind::Route53, Some(record_type)) => match &config.route53 {
+            Some(route53) => provider::Route53Provider::new(route53.clone(), config.zones.clone()).with_record_type(record_type).list_records().await,
+            None => Ok(Vec::new()),
+        },
+        _ => sync::dns_provider(config, &client).list_records().await,
+    }
+}
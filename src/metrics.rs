@@ -0,0 +1,169 @@
+//! Timings of the stages of a tick, served by `GET /metrics` in the Prometheus text format. They
+//! tell whether a slow tick spent its time detecting the IP or talking to the DNS provider.
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The upper bounds, in seconds, of the histogram buckets every stage is counted in.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// A stage of a tick whose latency is recorded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    /// Asking an IP source for the public IP.
+    IpFetch,
+    /// Listing the records of the DNS provider.
+    List,
+    /// Writing a record, or a batch of them, to the DNS provider.
+    Update,
+}
+
+impl Stage {
+    const ALL: [Stage; 3] = [Stage::IpFetch, Stage::List, Stage::Update];
+
+    /// The name of the histogram, without its `_bucket`, `_sum` or `_count` suffix.
+    fn metric(&self) -> &'static str {
+        match self {
+            Stage::IpFetch => "skysync_ip_fetch_seconds",
+            Stage::List => "skysync_dns_list_seconds",
+            Stage::Update => "skysync_dns_update_seconds",
+        }
+    }
+
+    fn help(&self) -> &'static str {
+        match self {
+            Stage::IpFetch => "Time spent asking an IP source for the public IP.",
+            Stage::List => "Time spent listing the records of the DNS provider.",
+            Stage::Update => "Time spent writing a record, or a batch of them, to the DNS provider.",
+        }
+    }
+
+    fn index(&self) -> usize {
+        Stage::ALL.iter().position(|stage| stage == self).unwrap_or_default()
+    }
+}
+
+/// How the observations of one stage are distributed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// How many observations fell in each bucket of `BUCKETS`, not counting the lower ones.
+    buckets: [u64; BUCKETS.len()],
+    /// The total of every observation, in seconds.
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Timings {
+    stages: [Histogram; Stage::ALL.len()],
+    /// How long the latest tick took, from start to finish.
+    tick: Option<Duration>,
+}
+
+/// The timings recorded since the start.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<Timings>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// The metrics ticks record their timings in.
+    pub fn global() -> &'static Metrics {
+        static GLOBAL: OnceLock<Metrics> = OnceLock::new();
+        GLOBAL.get_or_init(Metrics::new)
+    }
+
+    /// Records that `stage` took `elapsed`.
+    pub fn observe(&self, stage: Stage, elapsed: Duration) {
+        self.inner.lock().unwrap().stages[stage.index()].observe(elapsed);
+    }
+
+    /// Records how long the latest tick took.
+    pub fn set_tick_duration(&self, elapsed: Duration) {
+        self.inner.lock().unwrap().tick = Some(elapsed);
+    }
+
+    /// The observations of `stage` so far.
+    pub fn histogram(&self, stage: Stage) -> Histogram {
+        self.inner.lock().unwrap().stages[stage.index()]
+    }
+
+    /// Formats the metrics in the Prometheus text format. The tick gauge is left out until a
+    /// tick has finished.
+    pub fn render(&self) -> String {
+        let timings = self.inner.lock().unwrap();
+        let mut text = String::new();
+        for stage in Stage::ALL {
+            let histogram = &timings.stages[stage.index()];
+            let name = stage.metric();
+            let _ = writeln!(text, "# HELP {} {}\n# TYPE {} histogram", name, stage.help(), name);
+            // Prometheus buckets are cumulative, each one counting everything below its bound
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+            }
+            let _ = writeln!(text, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+            let _ = writeln!(text, "{}_sum {}\n{}_count {}", name, histogram.sum, name, histogram.count);
+        }
+        if let Some(tick) = timings.tick {
+            let name = "skysync_tick_duration_seconds";
+            let _ = writeln!(text, "# HELP {} How long the latest tick took.\n# TYPE {} gauge\n{} {}", name, name, name, tick.as_secs_f64());
+        }
+        text
+    }
+}
+
+/// Awaits `future`, recording how long it took as `stage` in the global metrics.
+pub async fn timed<T>(stage: Stage, future: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let output = future.await;
+    Metrics::global().observe(stage, started.elapsed());
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that observations land in cumulative buckets, and that the tick gauge only shows
+    /// up once a tick finished.
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.observe(Stage::IpFetch, Duration::from_millis(40));
+        metrics.observe(Stage::IpFetch, Duration::from_millis(300));
+        metrics.observe(Stage::IpFetch, Duration::from_secs(30));
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE skysync_ip_fetch_seconds histogram\n"), "{}", text);
+        assert!(text.contains("skysync_ip_fetch_seconds_bucket{le=\"0.025\"} 0\n"), "{}", text);
+        assert!(text.contains("skysync_ip_fetch_seconds_bucket{le=\"0.05\"} 1\n"), "{}", text);
+        assert!(text.contains("skysync_ip_fetch_seconds_bucket{le=\"0.5\"} 2\n"), "{}", text);
+        assert!(text.contains("skysync_ip_fetch_seconds_bucket{le=\"10\"} 2\n"), "{}", text);
+        assert!(text.contains("skysync_ip_fetch_seconds_bucket{le=\"+Inf\"} 3\n"), "{}", text);
+        assert!(text.contains("skysync_ip_fetch_seconds_count 3\n"), "{}", text);
+        assert!(text.contains("skysync_dns_update_seconds_count 0\n"), "{}", text);
+        assert!(!text.contains("skysync_tick_duration_seconds"), "{}", text);
+
+        metrics.set_tick_duration(Duration::from_millis(1500));
+        assert!(metrics.render().ends_with("skysync_tick_duration_seconds 1.5\n"));
+    }
+}
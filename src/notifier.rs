@@ -0,0 +1,845 @@
+use crate::config::{Config, Dispatch, NotifierKind};
+use crate::error::BoxError;
+use crate::geo::GeoInfo;
+use crate::http;
+use crate::log::report;
+use crate::retry::RetryPolicy;
+use async_trait::async_trait;
+use crate::timezone::Timezone;
+use chrono::{DateTime, FixedOffset, Local};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+#[cfg(feature = "desktop")]
+pub use crate::services::desktop::notification::DesktopNotifier;
+pub use crate::services::discord::webhooks::DiscordNotifier;
+#[cfg(feature = "email")]
+pub use crate::services::email::smtp::EmailNotifier;
+pub use crate::services::generic::webhook::GenericWebhookNotifier;
+#[cfg(feature = "pushover")]
+pub use crate::services::pushover::api::PushoverNotifier;
+pub use crate::services::slack::webhook::SlackNotifier;
+#[cfg(feature = "sns")]
+pub use crate::services::sns::api::SnsNotifier;
+#[cfg(feature = "teams")]
+pub use crate::services::teams::webhook::TeamsNotifier;
+
+/// How many notifications may wait in the `NotifyQueue` before the oldest is dropped.
+pub const QUEUE_CAPACITY: usize = 64;
+
+/// A notification the service refused with `429 Too Many Requests`.
+#[derive(Debug)]
+pub struct RateLimited {
+    /// How long the service asked to wait, if it said.
+    pub after: Option<Duration>,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.after {
+            Some(after) => write!(f, "rate limited, retry after {:?}", after),
+            None => write!(f, "rate limited"),
+        }
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// A notification that can't be delivered until the notifier is reconfigured, e.g. to a
+/// webhook that was deleted. It isn't retried, and nothing more is logged about it.
+#[derive(Debug)]
+pub struct Disabled {
+    /// Why the notifier is disabled, e.g. `the Discord webhook is invalid or was deleted (HTTP 404)`.
+    pub reason: String,
+}
+
+impl fmt::Display for Disabled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "disabled until reconfigured: {}", self.reason)
+    }
+}
+
+impl std::error::Error for Disabled {}
+
+/// Turns an error status of a webhook response into an error.
+///
+/// # Returns
+/// A `RateLimited` error for `429`, carrying Discord's `X-RateLimit-Reset-After` or the standard
+/// `Retry-After` (both in seconds), or the status error for anything else unsuccessful.
+pub(crate) fn check_response(response: reqwest::Response) -> Result<(), BoxError> {
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(Box::new(RateLimited { after: http::retry_after(response.headers()) }));
+    }
+
+    response.error_for_status()?;
+    Ok(())
+}
+
+/// The outcome being reported to the notifiers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventStatus {
+    Success,
+    Failure(String),
+    /// Ticks have kept failing for `minutes`, past one of the `ESCALATE_AFTER` thresholds.
+    Escalated { error: String, minutes: i64 },
+    /// The records are in sync again after an escalated outage of `minutes`.
+    Resolved { minutes: i64 },
+    /// The public IP changed in `MODE=monitor`, which leaves the records alone.
+    Changed,
+    /// The first tick after a start brought the records in line with the public IP, `updated` of
+    /// them by writing them. Sent in place of one `Success` per record.
+    Reconciled { updated: usize, current: usize, failed: usize },
+    /// The daemon started after going `minutes` without a successful tick, more than
+    /// `STALE_AFTER_INTERVALS` allows.
+    Downtime { minutes: i64 },
+    /// The record had been deleted since it was last seen pointing at `old_ip`, and `SELF_HEAL`
+    /// created it again.
+    Recreated,
+    /// The record was found pointing at `old_ip`, neither the address it was last seen at nor the
+    /// public IP, so it was changed by hand. Sent with `FLAG_MANUAL_DRIFT` before it's pointed
+    /// back at `new_ip`.
+    Drifted,
+    /// The IP changed `changes` times in the last `minutes`, and is now `new_ip`. Sent with
+    /// `NOTIFY_RATE_WINDOW` in place of the updates that were held back.
+    Throttled { changes: u32, minutes: i64 },
+    /// Ticks have failed `failures` times in a row, reaching `FAILURE_ALERT_THRESHOLD`.
+    Degraded { error: String, failures: u32 },
+    /// A tick succeeded again after `failures` failed in a row, past `FAILURE_ALERT_THRESHOLD`.
+    Recovered { failures: u32 },
+    /// The provider failed `failures` ticks in a row, reaching `CIRCUIT_BREAKER_THRESHOLD`, and
+    /// is left alone for `minutes`.
+    CircuitOpen { error: String, failures: u32, minutes: i64 },
+    /// The daemon started as `version`, with the settings summed up in `settings`, and
+    /// `NOTIFY_ON_START`.
+    Started { version: String, settings: String },
+    /// The daemon was asked to stop after running for `minutes`, with `NOTIFY_ON_STOP`.
+    Stopped { minutes: i64 },
+}
+
+impl EventStatus {
+    /// Short lowercase name of the status, as used in templates.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventStatus::Success => "success",
+            EventStatus::Failure(_) => "failure",
+            EventStatus::Escalated { .. } => "escalated",
+            EventStatus::Resolved { .. } => "resolved",
+            EventStatus::Changed => "changed",
+            EventStatus::Reconciled { .. } => "reconciled",
+            EventStatus::Downtime { .. } => "downtime",
+            EventStatus::Recreated => "recreated",
+            EventStatus::Drifted => "drifted",
+            EventStatus::Throttled { .. } => "throttled",
+            EventStatus::Degraded { .. } => "degraded",
+            EventStatus::Recovered { .. } => "recovered",
+            EventStatus::CircuitOpen { .. } => "circuit_open",
+            EventStatus::Started { .. } => "started",
+            EventStatus::Stopped { .. } => "stopped",
+        }
+    }
+
+    /// The error being reported, if any.
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            EventStatus::Failure(error)
+            | EventStatus::Escalated { error, .. }
+            | EventStatus::Degraded { error, .. }
+            | EventStatus::CircuitOpen { error, .. } => Some(error),
+            EventStatus::Success | EventStatus::Resolved { .. } | EventStatus::Changed | EventStatus::Reconciled { .. }
+            | EventStatus::Downtime { .. } | EventStatus::Recreated | EventStatus::Drifted | EventStatus::Throttled { .. }
+            | EventStatus::Recovered { .. }
+            | EventStatus::Started { .. } | EventStatus::Stopped { .. } => None,
+        }
+    }
+}
+
+/// How a startup reconciliation went, e.g. `3 records reconciled: 2 updated, 1 already current`.
+pub fn reconciled(updated: usize, current: usize, failed: usize) -> String {
+    let total = updated + current + failed;
+    let mut line = format!("{} record{} reconciled: {} updated, {} already current", total, if total == 1 { "" } else { "s" }, updated, current);
+    if failed > 0 {
+        line.push_str(&format!(", {} failed", failed));
+    }
+    line
+}
+
+/// How often the IP changed while update notifications were held back, e.g.
+/// `IP changed 5 times in the last hour`.
+pub fn throttled(changes: u32, minutes: i64) -> String {
+    let window = match minutes {
+        1 => "minute".to_string(),
+        60 => "hour".to_string(),
+        minutes if minutes % 60 == 0 => format!("{} hours", minutes / 60),
+        minutes => format!("{} minutes", minutes),
+    };
+    format!("IP changed {} time{} in the last {}", changes, if changes == 1 { "" } else { "s" }, window)
+}
+
+/// How long the daemon was down, e.g. `SkySync was down for 95 minutes before this start`.
+pub fn downtime(minutes: i64) -> String {
+    format!("SkySync was down for {} minute{} before this start", minutes, if minutes == 1 { "" } else { "s" })
+}
+
+/// Which daemon started, e.g. `SkySync 0.1.0 started`.
+pub fn started(version: &str) -> String {
+    format!("SkySync {} started", version)
+}
+
+/// How long the daemon ran before it stopped, e.g. `SkySync stopped after 95 minutes`.
+pub fn stopped(minutes: i64) -> String {
+    format!("SkySync stopped after {} minute{}", minutes, if minutes == 1 { "" } else { "s" })
+}
+
+/// How long ticks have been failing, e.g. `degraded after 5 failed ticks in a row`.
+pub fn degraded(failures: u32) -> String {
+    format!("degraded after {} failed tick{} in a row", failures, if failures == 1 { "" } else { "s" })
+}
+
+/// Why the provider is left alone, e.g. `stopped calling the DNS provider for 10 minutes after 5
+/// failed ticks in a row`.
+pub fn circuit_open(failures: u32, minutes: i64) -> String {
+    format!(
+        "stopped calling the DNS provider for {} minute{} after {} failed tick{} in a row",
+        minutes, if minutes == 1 { "" } else { "s" }, failures, if failures == 1 { "" } else { "s" },
+    )
+}
+
+/// How long ticks were failing before one succeeded, e.g. `recovered after 5 failed ticks`.
+pub fn recovered(failures: u32) -> String {
+    format!("recovered after {} failed tick{}", failures, if failures == 1 { "" } else { "s" })
+}
+
+/// `error` as a Markdown code block, for the notifiers whose messages render one.
+///
+/// Backticks inside are followed by a zero-width space, so an error quoting a fence can't end the
+/// block early, and errors longer than `limit` characters are cut short to keep the message
+/// within the service's size limit.
+///
+/// # Arguments
+/// * `error` - The error, as the notifier got it.
+/// * `limit` - How many characters of it may be kept.
+pub(crate) fn code_block(error: &str, limit: usize) -> String {
+    let mut kept: String = error.chars().take(limit).collect();
+    if kept.len() < error.len() {
+        kept.push('…');
+    }
+    format!("```\n{}\n```", kept.replace('`', "`\u{200b}"))
+}
+
+/// A DNS update attempt, as seen by the notifiers.
+#[derive(Clone, Debug)]
+pub struct UpdateEvent {
+    pub domain: String,
+    pub old_ip: Option<String>,
+    pub new_ip: String,
+    pub status: EventStatus,
+    pub timestamp: DateTime<FixedOffset>,
+    /// Where the new IP is located, when `ENRICH_GEO` is enabled and the lookup succeeded.
+    pub geo: Option<GeoInfo>,
+    /// Why this event is sent to the failover notifier, e.g. `discord notification failed: ...`.
+    pub failover: Option<String>,
+    /// Whether this is a sample event from `skysync test-notify` rather than a real update.
+    pub test: bool,
+    /// Whether Cloudflare proxies the record, so public DNS answers with Cloudflare's addresses
+    /// rather than `new_ip`, the origin the record points at.
+    pub proxied: bool,
+    /// Where the record can be looked at in the DNS provider's console, with
+    /// `INCLUDE_DASHBOARD_LINK` and a provider that has a known URL for it.
+    pub dashboard_link: Option<String>,
+    /// Every record an event coalescing the updates of a tick is about. Empty for an event about
+    /// a single record.
+    pub changes: Vec<RecordChange>,
+    /// The profile the event comes from, when `PROFILES` runs several.
+    pub profile: Option<String>,
+}
+
+/// One record pointed at a new IP, within an event coalescing several.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordChange {
+    pub record: String,
+    pub old_ip: Option<String>,
+    pub new_ip: String,
+}
+
+/// The changes of a coalesced event, one line per record name, e.g.
+/// `home.example.com: A 1.1.1.1 → 2.2.2.2, AAAA 2001:db8::1 → 2001:db8::2`.
+///
+/// The type is told from the address, and a missing old IP (a created record) is shown as `-`.
+pub fn change_lines(changes: &[RecordChange]) -> Vec<String> {
+    let mut lines: Vec<(&str, Vec<String>)> = Vec::new();
+    for change in changes {
+        let kind = match change.new_ip.parse::<std::net::IpAddr>() {
+            Ok(std::net::IpAddr::V4(_)) => "A ",
+            Ok(std::net::IpAddr::V6(_)) => "AAAA ",
+            Err(_) => "",
+        };
+        let part = format!("{}{} → {}", kind, change.old_ip.as_deref().unwrap_or("-"), change.new_ip);
+        match lines.iter_mut().find(|(record, _)| *record == change.record) {
+            Some((_, parts)) => parts.push(part),
+            None => lines.push((&change.record, vec![part])),
+        }
+    }
+    lines.into_iter().map(|(record, parts)| format!("{}: {}", record, parts.join(", "))).collect()
+}
+
+impl UpdateEvent {
+    /// Creates an event stamped with the current local time.
+    pub fn new(domain: &str, old_ip: Option<String>, new_ip: &str, status: EventStatus) -> UpdateEvent {
+        UpdateEvent {
+            domain: domain.to_string(),
+            old_ip,
+            new_ip: new_ip.to_string(),
+            status,
+            timestamp: Local::now().fixed_offset(),
+            geo: None,
+            failover: None,
+            test: false,
+            proxied: false,
+            dashboard_link: None,
+            changes: Vec::new(),
+            profile: None,
+        }
+    }
+
+    /// One event for the updates of several records, which every notifier sends as a single
+    /// message listing them.
+    ///
+    /// # Arguments
+    /// * `events` - The `Success` events of each record, in the order they're listed.
+    ///
+    /// # Returns
+    /// The event for every record at once, named after all of them, or the only event itself.
+    pub fn coalesce(events: Vec<UpdateEvent>) -> Option<UpdateEvent> {
+        if events.len() < 2 {
+            return events.into_iter().next();
+        }
+        let distinct = |values: Vec<&str>| {
+            let mut unique: Vec<&str> = Vec::new();
+            for value in values {
+                if !unique.contains(&value) {
+                    unique.push(value);
+                }
+            }
+            unique.join(", ")
+        };
+        let domain = distinct(events.iter().map(|event| event.domain.as_str()).collect());
+        let new_ip = distinct(events.iter().map(|event| event.new_ip.as_str()).collect());
+        let first = &events[0];
+
+        let mut coalesced = UpdateEvent::new(&domain, None, &new_ip, first.status.clone());
+        coalesced.timestamp = first.timestamp;
+        coalesced.geo = first.geo.clone();
+        coalesced.proxied = events.iter().any(|event| event.proxied);
+        // Records of the same zone share their link, records of several have none in common
+        coalesced.dashboard_link = first.dashboard_link.clone()
+            .filter(|link| events.iter().all(|event| event.dashboard_link.as_ref() == Some(link)));
+        coalesced.changes = events.iter()
+            .map(|event| RecordChange { record: event.domain.clone(), old_ip: event.old_ip.clone(), new_ip: event.new_ip.clone() })
+            .collect();
+        Some(coalesced)
+    }
+
+    /// Shows the timestamp in the configured `TIMEZONE`.
+    pub fn in_timezone(mut self, timezone: Timezone) -> UpdateEvent {
+        self.timestamp = timezone.convert(&self.timestamp);
+        self
+    }
+
+    /// Attaches the location of the new IP.
+    pub fn with_geo(mut self, geo: Option<GeoInfo>) -> UpdateEvent {
+        self.geo = geo;
+        self
+    }
+
+    /// Marks the event as re-sent because delivering it to `primary` failed with `error`.
+    pub fn failed_over(mut self, primary: &str, error: &BoxError) -> UpdateEvent {
+        self.failover = Some(format!("{} notification failed: {}", primary, error));
+        self
+    }
+
+    /// Marks the event as being about a proxied record, so the messages explain why resolvers
+    /// don't show `new_ip`.
+    pub fn with_proxied(mut self, proxied: bool) -> UpdateEvent {
+        self.proxied = proxied;
+        self
+    }
+
+    /// Attaches the link to the record in the DNS provider's console.
+    pub fn with_dashboard_link(mut self, link: Option<String>) -> UpdateEvent {
+        self.dashboard_link = link;
+        self
+    }
+
+    /// Tags the event with the profile it comes from, which every message shows in front of the
+    /// domain, e.g. `[home] home.example.com`.
+    pub fn in_profile(mut self, profile: Option<&str>) -> UpdateEvent {
+        if let Some(profile) = profile {
+            self.domain = format!("[{}] {}", profile, self.domain);
+            self.profile = Some(profile.to_string());
+        }
+        self
+    }
+
+    /// Marks the event as a sample, so the messages say they're only a test.
+    pub fn as_test(mut self) -> UpdateEvent {
+        self.test = true;
+        self
+    }
+}
+
+/// A destination that is told about DNS updates.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name used when logging delivery problems.
+    fn name(&self) -> &'static str;
+
+    /// Delivers a single event.
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError>;
+}
+
+/// Builds the notifiers selected by `NOTIFIER`.
+///
+/// # Arguments
+/// * `config` - The current configuration. Settings for the selected notifiers are already validated.
+/// * `client` - The HTTP client the webhook notifiers send with.
+///
+/// # Returns
+/// One notifier per selected kind, in the configured order.
+pub fn from_config(config: &Config, client: &reqwest::Client) -> Vec<Box<dyn Notifier>> {
+    config.notifiers.iter().filter_map(|kind| build(*kind, config, client)).collect()
+}
+
+/// Builds the notifiers of every record that names its own in `ZONES`.
+///
+/// # Returns
+/// The notifiers of each such record, by record name, in the configured order.
+pub fn routes_from_config(config: &Config, client: &reqwest::Client) -> HashMap<String, Vec<Box<dyn Notifier>>> {
+    config.routes.iter()
+        .map(|(name, kinds)| (name.clone(), kinds.iter().filter_map(|kind| build(*kind, config, client)).collect()))
+        .collect()
+}
+
+/// Builds every notifier the configuration can send to, each once: those of `NOTIFIER`, the
+/// failover and the ones records name in `ZONES`.
+pub fn all_from_config(config: &Config, client: &reqwest::Client) -> Vec<Box<dyn Notifier>> {
+    let mut kinds: Vec<NotifierKind> = Vec::new();
+    for kind in config.notifiers.iter().chain(&config.failover).chain(config.routes.values().flatten()) {
+        if !kinds.contains(kind) {
+            kinds.push(*kind);
+        }
+    }
+    kinds.into_iter().filter_map(|kind| build(kind, config, client)).collect()
+}
+
+/// Builds the notifier selected by `FAILOVER_NOTIFIER`, if any.
+pub fn failover_from_config(config: &Config, client: &reqwest::Client) -> Option<Box<dyn Notifier>> {
+    build(config.failover?, config, client)
+}
+
+/// Builds one notifier from its already validated settings.
+fn build(kind: NotifierKind, config: &Config, client: &reqwest::Client) -> Option<Box<dyn Notifier>> {
+    match kind {
+        // Left out without a webhook, which `Config::warnings` reports
+        NotifierKind::Discord if config.discord.webhook_url.is_empty() => None,
+        NotifierKind::Discord => Some(Box::new(DiscordNotifier::new(config.discord.clone(), client.clone()))),
+        NotifierKind::Generic => config.generic.clone().map(|generic| Box::new(GenericWebhookNotifier::new(generic, client.clone())) as Box<dyn Notifier>),
+        NotifierKind::Slack => config.slack.clone().map(|slack| Box::new(SlackNotifier::new(slack, client.clone())) as Box<dyn Notifier>),
+        #[cfg(feature = "desktop")]
+        NotifierKind::Desktop => Some(Box::new(DesktopNotifier)),
+        // Rejected by the config loader when the feature is disabled
+        #[cfg(not(feature = "desktop"))]
+        NotifierKind::Desktop => None,
+        #[cfg(feature = "email")]
+        NotifierKind::Email => config.email.clone().map(|email| Box::new(EmailNotifier::new(email)) as Box<dyn Notifier>),
+        #[cfg(not(feature = "email"))]
+        NotifierKind::Email => None,
+        #[cfg(feature = "pushover")]
+        NotifierKind::Pushover => config.pushover.clone().map(|pushover| Box::new(PushoverNotifier::new(pushover, client.clone())) as Box<dyn Notifier>),
+        #[cfg(not(feature = "pushover"))]
+        NotifierKind::Pushover => None,
+        #[cfg(feature = "teams")]
+        NotifierKind::Teams => config.teams.clone().map(|teams| Box::new(TeamsNotifier::new(teams, client.clone())) as Box<dyn Notifier>),
+        #[cfg(not(feature = "teams"))]
+        NotifierKind::Teams => None,
+        #[cfg(feature = "sns")]
+        NotifierKind::Sns => config.sns.clone().map(|sns| Box::new(SnsNotifier::new(sns)) as Box<dyn Notifier>),
+        #[cfg(not(feature = "sns"))]
+        NotifierKind::Sns => None,
+    }
+}
+
+/// Delivers an event through one notifier, retrying as `NOTIFY_RETRIES` says.
+///
+/// Retries wait as long as the policy says, or as long as a rate-limited service asked (up to a
+/// minute). A `Disabled` notifier isn't retried.
+///
+/// # Arguments
+/// * `notifier` - The notifier to deliver to.
+/// * `event` - The event to deliver.
+/// * `retry` - How often and how patiently failed deliveries are retried.
+///
+/// # Returns
+/// The error of the last attempt, if none succeeded.
+pub async fn deliver(notifier: &dyn Notifier, event: &UpdateEvent, retry: RetryPolicy) -> Result<(), BoxError> {
+    let what = format!("deliver {} notification", notifier.name());
+    retry.run(&what, || notifier.notify(event), |err: &BoxError, wait| match err.downcast_ref::<RateLimited>() {
+        Some(RateLimited { after: Some(after) }) => Some((*after).min(http::MAX_RETRY_AFTER)),
+        _ if err.is::<Disabled>() => None,
+        _ => Some(wait),
+    }).await
+}
+
+/// Delivers an event to every notifier on its own, with the usual retries, for `skysync test-notify`.
+///
+/// # Returns
+/// The name of each notifier with how delivering to it went, in order.
+pub async fn deliver_each(notifiers: &[Box<dyn Notifier>], event: &UpdateEvent, retry: RetryPolicy) -> Vec<(&'static str, Result<(), BoxError>)> {
+    let deliveries = notifiers.iter().map(|notifier| async move {
+        (notifier.name(), deliver(notifier.as_ref(), event, retry).await)
+    });
+    futures::future::join_all(deliveries).await
+}
+
+/// Sends an event through every notifier, logging the ones that still fail after retrying.
+///
+/// # Arguments
+/// * `notifiers` - The notifiers to deliver to.
+/// * `failover` - Sent the event, marked with the failure, for every notifier that failed.
+/// * `event` - The event to deliver.
+/// * `mode` - Whether the notifiers are tried all at once or in order, from `NOTIFIER_DISPATCH`.
+/// * `retry` - How often and how patiently each delivery is retried.
+///
+/// # Returns
+/// How many of `notifiers` failed. Those skipped after a success don't count.
+pub async fn dispatch(notifiers: &[Box<dyn Notifier>], failover: Option<&dyn Notifier>, event: &UpdateEvent, mode: Dispatch, retry: RetryPolicy) -> usize {
+    match mode {
+        Dispatch::Parallel => {
+            let deliveries = notifiers.iter().map(|notifier| deliver_or_fail_over(notifier.as_ref(), failover, event, retry));
+            futures::future::join_all(deliveries).await.into_iter().filter(|delivered| !delivered).count()
+        }
+        Dispatch::Sequential { stop_on_first_success } => {
+            let mut failed = 0;
+            for notifier in notifiers {
+                if !deliver_or_fail_over(notifier.as_ref(), failover, event, retry).await {
+                    failed += 1;
+                } else if stop_on_first_success {
+                    break;
+                }
+            }
+            failed
+        }
+    }
+}
+
+/// Delivers an event through one notifier, and through `failover` should it give up.
+///
+/// # Returns
+/// Whether `notifier` delivered the event.
+async fn deliver_or_fail_over(notifier: &dyn Notifier, failover: Option<&dyn Notifier>, event: &UpdateEvent, retry: RetryPolicy) -> bool {
+    let Err(err) = deliver(notifier, event, retry).await else {
+        return true;
+    };
+    // A disabled notifier said why once, when it was disabled
+    if !err.is::<Disabled>() {
+        log_failure(notifier.name(), &err, retry).await;
+    }
+
+    if let Some(failover) = failover {
+        let event = event.clone().failed_over(notifier.name(), &err);
+        if let Err(err) = deliver(failover, &event, retry).await {
+            log_failure(failover.name(), &err, retry).await;
+        }
+    }
+    false
+}
+
+/// An event waiting in the `NotifyQueue`, with the notifiers it goes to.
+struct Delivery {
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    failover: Option<Arc<dyn Notifier>>,
+    event: UpdateEvent,
+    mode: Dispatch,
+    retry: RetryPolicy,
+}
+
+struct QueueState {
+    pending: Mutex<VecDeque<Delivery>>,
+    capacity: usize,
+    /// Deliveries pushed out by newer ones since the last were reported.
+    dropped: AtomicUsize,
+    closed: AtomicBool,
+    wake: Notify,
+}
+
+/// Delivers events on a task of its own, in the order they're pushed, so a tick never waits on
+/// a slow notifier.
+///
+/// At most `capacity` events wait; pushing another drops the oldest, which is reported once the
+/// task gets to it.
+#[derive(Clone)]
+pub struct NotifyQueue {
+    state: Arc<QueueState>,
+}
+
+impl NotifyQueue {
+    /// Starts the delivery task.
+    ///
+    /// # Returns
+    /// The queue, and the delivery task, which ends once the queue was closed and drained.
+    pub fn spawn(capacity: usize) -> (NotifyQueue, JoinHandle<()>) {
+        let queue = NotifyQueue {
+            state: Arc::new(QueueState {
+                pending: Mutex::new(VecDeque::new()),
+                capacity: capacity.max(1),
+                dropped: AtomicUsize::new(0),
+                closed: AtomicBool::new(false),
+                wake: Notify::new(),
+            }),
+        };
+        let worker = tokio::spawn(queue.clone().drain());
+        (queue, worker)
+    }
+
+    /// Queues `event` for `notifiers`, as `dispatch` would deliver it.
+    pub fn push(&self, notifiers: Arc<Vec<Box<dyn Notifier>>>, failover: Option<Arc<dyn Notifier>>, event: UpdateEvent, mode: Dispatch, retry: RetryPolicy) {
+        let mut pending = self.state.pending.lock().unwrap();
+        if pending.len() >= self.state.capacity {
+            pending.pop_front();
+            self.state.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+        pending.push_back(Delivery { notifiers, failover, event, mode, retry });
+        drop(pending);
+        self.state.wake.notify_one();
+    }
+
+    /// How many events are waiting to be delivered, not counting the one being delivered.
+    pub fn len(&self) -> usize {
+        self.state.pending.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stops taking new events once the waiting ones are delivered, which ends the delivery task.
+    pub fn close(&self) {
+        self.state.closed.store(true, Ordering::SeqCst);
+        self.state.wake.notify_one();
+    }
+
+    async fn drain(self) {
+        loop {
+            let dropped = self.state.dropped.swap(0, Ordering::SeqCst);
+            if dropped > 0 {
+                report(&format!("Dropped {} notification(s), more than {} were waiting to be delivered", dropped, self.state.capacity)).await;
+            }
+            let next = self.state.pending.lock().unwrap().pop_front();
+            match next {
+                Some(delivery) => {
+                    dispatch(&delivery.notifiers, delivery.failover.as_deref(), &delivery.event, delivery.mode, delivery.retry).await;
+                }
+                None if self.state.closed.load(Ordering::SeqCst) => return,
+                None => self.state.wake.notified().await,
+            }
+        }
+    }
+}
+
+async fn log_failure(name: &str, err: &BoxError, retry: RetryPolicy) {
+    let msg = format!("Failed to deliver {} notification after {} attempts: {}", name, retry.attempts(), err);
+    report(&msg).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::tests::RecordingNotifier;
+
+    /// A notifier whose deliveries always fail.
+    struct FailingNotifier;
+
+    #[async_trait]
+    impl Notifier for FailingNotifier {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn notify(&self, _event: &UpdateEvent) -> Result<(), BoxError> {
+            Err("unreachable".into())
+        }
+    }
+
+    /// A notifier that only delivers once as many others are delivering at the same time.
+    struct BarrierNotifier(Arc<tokio::sync::Barrier>);
+
+    #[async_trait]
+    impl Notifier for BarrierNotifier {
+        fn name(&self) -> &'static str {
+            "barrier"
+        }
+
+        async fn notify(&self, _event: &UpdateEvent) -> Result<(), BoxError> {
+            self.0.wait().await;
+            Ok(())
+        }
+    }
+
+    /// A notifier whose deliveries always fail, counting how often it's tried.
+    struct CountingNotifier(Arc<std::sync::atomic::AtomicU32>);
+
+    #[async_trait]
+    impl Notifier for CountingNotifier {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn notify(&self, _event: &UpdateEvent) -> Result<(), BoxError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Err("unreachable".into())
+        }
+    }
+
+    /// The default number of retries, without waiting in between.
+    const INSTANT: RetryPolicy = RetryPolicy { retries: 3, delay: Duration::ZERO };
+
+    fn event() -> UpdateEvent {
+        UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Success)
+    }
+
+    /// Tests that a delivery is attempted once more than `NOTIFY_RETRIES`, whatever the others do.
+    #[tokio::test]
+    async fn test_deliver_uses_configured_attempts() {
+        for retries in [0, 1, 5] {
+            let attempts = Arc::new(std::sync::atomic::AtomicU32::new(0));
+            let policy = RetryPolicy { retries, delay: Duration::ZERO };
+
+            assert!(deliver(&CountingNotifier(attempts.clone()), &event(), policy).await.is_err());
+            assert_eq!(attempts.load(Ordering::SeqCst), retries + 1);
+        }
+    }
+
+    /// Tests that a primary failing all its retries causes exactly one failover delivery.
+    #[tokio::test]
+    async fn test_failover_after_primary_fails() {
+        let failover = RecordingNotifier::default();
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(FailingNotifier)];
+
+        let failed = dispatch(&notifiers, Some(&failover), &event(), Dispatch::default(), INSTANT).await;
+
+        assert_eq!(failed, 1);
+        let events = failover.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].domain, "home.example.com");
+        assert_eq!(events[0].failover.as_deref(), Some("failing notification failed: unreachable"));
+    }
+
+    /// Tests that parallel dispatch delivers to every notifier at once, which notifiers that each
+    /// wait for the other can only get through together.
+    #[tokio::test]
+    async fn test_parallel_dispatch() {
+        let barrier = Arc::new(tokio::sync::Barrier::new(2));
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(BarrierNotifier(barrier.clone())), Box::new(BarrierNotifier(barrier))];
+
+        let event = event();
+        let dispatched = dispatch(&notifiers, None, &event, Dispatch::Parallel, INSTANT);
+        assert_eq!(tokio::time::timeout(Duration::from_secs(5), dispatched).await.unwrap(), 0);
+    }
+
+    /// Tests that sequential dispatch goes through the notifiers in order, and with
+    /// `STOP_ON_FIRST_SUCCESS` skips the ones after the first that delivers.
+    #[tokio::test]
+    async fn test_sequential_dispatch() {
+        for stop_on_first_success in [false, true] {
+            let (primary, secondary) = (RecordingNotifier::default(), RecordingNotifier::default());
+            let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(FailingNotifier), Box::new(primary.clone()), Box::new(secondary.clone())];
+
+            let failed = dispatch(&notifiers, None, &event(), Dispatch::Sequential { stop_on_first_success }, INSTANT).await;
+
+            assert_eq!(failed, 1);
+            assert_eq!(primary.events.lock().unwrap().len(), 1);
+            assert_eq!(secondary.events.lock().unwrap().len(), usize::from(!stop_on_first_success));
+        }
+    }
+
+    /// Tests that every notifier is tried and reported on its own, even after another failed.
+    #[tokio::test]
+    async fn test_deliver_each_reports_independently() {
+        let recording = RecordingNotifier::default();
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(FailingNotifier), Box::new(recording.clone())];
+
+        let results = deliver_each(&notifiers, &event().as_test(), INSTANT).await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "failing");
+        assert_eq!(results[0].1.as_ref().unwrap_err().to_string(), "unreachable");
+        assert!(results[1].1.is_ok());
+        assert!(recording.events.lock().unwrap()[0].test);
+    }
+
+    /// Tests that an error can't close its code block early, and that a long one is cut short.
+    #[test]
+    fn test_code_block() {
+        assert_eq!(code_block("rejected", 10), "```\nrejected\n```");
+        assert_eq!(code_block("bad ```json```", 20), "```\nbad `\u{200b}`\u{200b}`\u{200b}json`\u{200b}`\u{200b}`\u{200b}\n```");
+        assert_eq!(code_block("áéíóú", 3), "```\náéí…\n```");
+        assert_eq!(code_block("áéí", 3), "```\náéí\n```");
+    }
+
+    /// Tests that coalesced updates list each record once, with every address it changed.
+    #[test]
+    fn test_coalesce() {
+        let update = |domain: &str, old: Option<&str>, new: &str| UpdateEvent::new(domain, old.map(str::to_string), new, EventStatus::Success);
+        assert!(UpdateEvent::coalesce(Vec::new()).is_none());
+        assert!(UpdateEvent::coalesce(vec![update("home.example.com", None, "2.2.2.2")]).unwrap().changes.is_empty());
+
+        let event = UpdateEvent::coalesce(vec![
+            update("home.example.com", Some("1.1.1.1"), "2.2.2.2"),
+            update("home.example.com", Some("2001:db8::1"), "2001:db8::2"),
+            update("www.example.com", None, "2.2.2.2"),
+        ]).unwrap();
+
+        assert_eq!(event.domain, "home.example.com, www.example.com");
+        assert_eq!(event.new_ip, "2.2.2.2, 2001:db8::2");
+        assert_eq!(change_lines(&event.changes), vec![
+            "home.example.com: A 1.1.1.1 → 2.2.2.2, AAAA 2001:db8::1 → 2001:db8::2",
+            "www.example.com: A - → 2.2.2.2",
+        ]);
+    }
+
+    /// Tests that the failover stays quiet while the primary delivers.
+    #[tokio::test]
+    async fn test_no_failover_when_primary_delivers() {
+        let primary = RecordingNotifier::default();
+        let failover = RecordingNotifier::default();
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(primary.clone())];
+
+        assert_eq!(dispatch(&notifiers, Some(&failover), &event(), Dispatch::default(), INSTANT).await, 0);
+        assert_eq!(primary.events.lock().unwrap().len(), 1);
+        assert!(failover.events.lock().unwrap().is_empty());
+    }
+
+    /// Tests that a full queue drops its oldest event, and that closing it delivers the rest in order.
+    #[tokio::test]
+    async fn test_queue_drops_oldest_and_drains() {
+        let recording = RecordingNotifier::default();
+        let notifiers: Arc<Vec<Box<dyn Notifier>>> = Arc::new(vec![Box::new(recording.clone())]);
+        let (queue, worker) = NotifyQueue::spawn(2);
+
+        // The test runtime is single-threaded, so nothing is delivered until it's awaited
+        for domain in ["a.example.com", "b.example.com", "c.example.com"] {
+            queue.push(notifiers.clone(), None, UpdateEvent::new(domain, None, "2.2.2.2", EventStatus::Success), Dispatch::default(), INSTANT);
+        }
+        assert_eq!(queue.len(), 2);
+        queue.close();
+        worker.await.unwrap();
+
+        let domains: Vec<String> = recording.events.lock().unwrap().iter().map(|event| event.domain.clone()).collect();
+        assert_eq!(domains, vec!["b.example.com", "c.example.com"]);
+        assert!(queue.is_empty());
+    }
+}
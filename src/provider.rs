@@ -0,0 +1,232 @@
+use crate::console::table;
+use crate::error::{BoxError, SkySyncError};
+use async_trait::async_trait;
+use std::net::IpAddr;
+
+pub use crate::services::cloudflare::provider::CloudflareProvider;
+pub use crate::services::gandi::provider::GandiProvider;
+pub use crate::services::porkbun::provider::{PorkbunIpSource, PorkbunProvider};
+#[cfg(feature = "route53")]
+pub use crate::services::route53::provider::Route53Provider;
+
+/// A DNS record as seen by SkySync, independent of the provider that hosts it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DnsRecord {
+    pub id: String,
+    /// The zone the record lives in, as the provider identifies it.
+    pub zone_id: String,
+    /// The domain of the zone, e.g. `example.com`. Empty when the provider doesn't say.
+    pub zone_name: String,
+    /// The full name of the record, e.g. `example.com` for the apex or `*.example.com`.
+    pub name: String,
+    pub r#type: String,
+    pub content: String,
+    pub ttl: i64,
+    pub proxied: bool,
+    pub comment: Option<String>,
+    pub tags: Vec<String>,
+    /// The priority of an MX record. SRV records carry theirs with the rest of their fields.
+    pub priority: Option<u16>,
+}
+
+impl DnsRecord {
+    /// Whether this is the record configured as `name`.
+    ///
+    /// Names are compared case-insensitively and without a trailing dot. `@` stands for the zone
+    /// apex, and a name that doesn't end in the zone's domain is relative to it, so `home` is
+    /// `home.example.com` and `*` is `*.example.com`. Anything else must be the record's full
+    /// name. A wildcard only ever matches the wildcard record itself, never the names it covers.
+    pub fn matches(&self, name: &str) -> bool {
+        normalize(&self.name) == self.qualify(name)
+    }
+
+    /// Whether the record already points at `ip`, see `same_address`.
+    pub fn points_at(&self, ip: &str) -> bool {
+        same_address(&self.content, ip)
+    }
+
+    /// The full name of this record, as providers expect it on writes, even if it was built
+    /// with `@` or a name relative to its zone.
+    pub fn fqdn(&self) -> String {
+        self.qualify(&self.name)
+    }
+
+    /// Expands `@` and names relative to the zone of this record into full, normalized names.
+    fn qualify(&self, name: &str) -> String {
+        let (name, zone) = (normalize(name), normalize(&self.zone_name));
+        if name == "@" {
+            zone
+        } else if zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone)) {
+            name
+        } else {
+            format!("{}.{}", name, zone)
+        }
+    }
+}
+
+/// Whether a record content and an address are the same. Addresses are compared parsed, since
+/// IPv6 ones are written in several ways: `2001:db8::1` is `2001:0DB8:0:0:0:0:0:1`. Contents that
+/// aren't addresses are compared as they're written.
+pub fn same_address(content: &str, ip: &str) -> bool {
+    match (content.trim().parse::<IpAddr>(), ip.trim().parse::<IpAddr>()) {
+        (Ok(content), Ok(ip)) => content == ip,
+        _ => content == ip,
+    }
+}
+
+/// Lowercases a name and strips its trailing dot.
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// A DNS host whose records SkySync keeps in sync.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Whether `record` differs from the configuration in anything besides its content, e.g. the
+    /// proxied state Cloudflare records are kept at. Such a record is written even when it
+    /// already points at the public IP, and the write brings it in line.
+    fn drifted(&self, _record: &DnsRecord) -> bool {
+        false
+    }
+
+    /// Where `record` can be looked at in the provider's web console, for notifications with
+    /// `INCLUDE_DASHBOARD_LINK`. `None` when the provider has no known URL for it.
+    fn dashboard_url(&self, _record: &DnsRecord) -> Option<String> {
+        None
+    }
+
+    /// Checks the credentials for what listing the records doesn't show, e.g. that a token
+    /// hasn't expired. Most providers have nothing more to check.
+    async fn check_credentials(&self) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    /// Lists the records of every zone managed by this provider.
+    async fn list_records(&self) -> Result<Vec<DnsRecord>, BoxError>;
+
+    /// Looks up the record named `name` of type `record_type`, e.g. `A`.
+    ///
+    /// # Returns
+    /// The first such record, or `None` when the provider answered but holds no such record.
+    /// Only a failure to list the records is an error.
+    async fn find_record(&self, name: &str, record_type: &str) -> Result<Option<DnsRecord>, SkySyncError> {
+        let records = self.list_records().await.map_err(SkySyncError::Provider)?;
+        Ok(records.into_iter().find(|record| record.matches(name) && record.r#type.eq_ignore_ascii_case(record_type)))
+    }
+
+    /// Points an existing record at new content, leaving its other fields alone.
+    async fn update_record(&self, record: &DnsRecord, content: &str) -> Result<(), BoxError>;
+
+    /// Points an existing record at new content like `update_record`, and replaces its comment
+    /// with `comment` on providers that keep one. Others only update the content.
+    async fn update_record_with_comment(&self, record: &DnsRecord, content: &str, _comment: &str) -> Result<(), BoxError> {
+        self.update_record(record, content).await
+    }
+
+    /// Points several existing records at new content, for providers that can write them in
+    /// fewer requests than one each.
+    ///
+    /// # Returns
+    /// The result of each update, in the order given, or `None` when the provider has no such
+    /// request and the records are left for `update_record`.
+    async fn update_records(&self, _updates: &[(&DnsRecord, &str)]) -> Option<Vec<Result<(), BoxError>>> {
+        None
+    }
+
+    /// Writes every field of a record, replacing whatever the provider currently stores.
+    async fn replace_record(&self, record: &DnsRecord) -> Result<(), BoxError> {
+        self.update_record(record, &record.content).await
+    }
+
+    /// Creates a new record.
+    async fn create_record(&self, name: &str, content: &str) -> Result<(), BoxError>;
+}
+
+/// Formats records as a plain-text table, in the order given.
+pub fn render_table(records: &[DnsRecord]) -> String {
+    let rows: Vec<[String; 5]> = records.iter().map(|record| [
+        record.name.clone(),
+        record.r#type.clone(),
+        record.content.clone(),
+        // Cloudflare's "automatic" TTL
+        if record.ttl == 1 { "auto".to_string() } else { record.ttl.to_string() },
+        if record.proxied { "yes" } else { "no" }.to_string(),
+    ]).collect();
+
+    table(["NAME", "TYPE", "CONTENT", "TTL", "PROXIED"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::tests::{record, MockProvider};
+
+    /// Tests that configured names match Cloudflare's full names, including the apex and a wildcard.
+    #[test]
+    fn test_matches() {
+        let apex = record("example.com", "1.1.1.1");
+        let wildcard = record("*.example.com", "1.1.1.1");
+        let home = record("home.example.com", "1.1.1.1");
+
+        for name in ["example.com", "@", "Example.COM."] {
+            assert!(apex.matches(name), "{}", name);
+            assert!(!wildcard.matches(name) && !home.matches(name), "{}", name);
+        }
+        for name in ["*.example.com", "*"] {
+            assert!(wildcard.matches(name), "{}", name);
+            assert!(!apex.matches(name) && !home.matches(name), "{}", name);
+        }
+        for name in ["home.example.com", "home", "HOME.example.com."] {
+            assert!(home.matches(name), "{}", name);
+            assert!(!apex.matches(name) && !wildcard.matches(name), "{}", name);
+        }
+        assert!(!home.matches("home.example.org"));
+        assert!(!record("a.home.example.com", "1.1.1.1").matches("*.home.example.com"));
+    }
+
+    /// Tests that looking up a missing record answers `None`, and a present one the record.
+    #[tokio::test]
+    async fn test_find_record() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+
+        assert_eq!(provider.find_record("home.example.com", "a").await.unwrap().map(|record| record.content), Some("1.1.1.1".to_string()));
+        assert!(provider.find_record("www.example.com", "A").await.unwrap().is_none());
+        assert!(provider.find_record("home.example.com", "AAAA").await.unwrap().is_none());
+    }
+
+    /// Tests that records built with `@` or a relative name still have their full name.
+    #[test]
+    fn test_fqdn() {
+        assert_eq!(record("@", "1.1.1.1").fqdn(), "example.com");
+        assert_eq!(record("home", "1.1.1.1").fqdn(), "home.example.com");
+        assert_eq!(record("Home.Example.com.", "1.1.1.1").fqdn(), "home.example.com");
+    }
+
+    /// Tests that contents are compared as addresses, so differently written IPv6 addresses match.
+    #[test]
+    fn test_same_address() {
+        assert!(same_address("2001:0DB8:0000:0000:0000:0000:0000:0001", "2001:db8::1"));
+        assert!(same_address("1.1.1.1", "1.1.1.1"));
+        assert!(!same_address("2001:db8::1", "2001:db8::2"));
+        assert!(same_address("home.example.net", "home.example.net"));
+        assert!(!same_address("home.example.net", "1.1.1.1"));
+    }
+
+    /// Tests that records are laid out in aligned columns, with the automatic TTL spelled out.
+    #[test]
+    fn test_render_table() {
+        let records = vec![
+            record("example.com", "1.1.1.1"),
+            DnsRecord { r#type: "AAAA".to_string(), ttl: 300, proxied: true, ..record("home.example.com", "2001:db8::1") },
+        ];
+
+        assert_eq!(render_table(&records), "\
+NAME              TYPE  CONTENT      TTL   PROXIED
+example.com       A     1.1.1.1      auto  no
+home.example.com  AAAA  2001:db8::1  300   yes
+");
+    }
+}
@@ -0,0 +1,372 @@
+//! Resolves hostnames through chosen DNS servers instead of the system resolver, so
+//! split-horizon or VPN DNS can't hand the IP lookup an internal address.
+//!
+//! The queries are made by hickory-resolver with EDNS, so large answers fit in one datagram, and
+//! truncated ones are asked for again over TCP.
+#[cfg(feature = "hickory-dns")]
+use crate::error::BoxError;
+#[cfg(feature = "hickory-dns")]
+use crate::http::AddressFamily;
+#[cfg(feature = "hickory-dns")]
+use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts, ServerOrderingStrategy};
+#[cfg(feature = "hickory-dns")]
+use hickory_resolver::error::ResolveErrorKind;
+#[cfg(feature = "hickory-dns")]
+use hickory_resolver::proto::rr::RecordType;
+#[cfg(feature = "hickory-dns")]
+use hickory_resolver::TokioAsyncResolver;
+#[cfg(feature = "hickory-dns")]
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(feature = "hickory-dns")]
+use std::time::Duration;
+
+/// The port `RESOLVER` uses when it's given without one.
+pub const DEFAULT_PORT: u16 = 53;
+
+/// The servers `COMPARE_MODE=resolve` asks when `RESOLVER` isn't set, run by different operators
+/// so one being down doesn't stop the comparison.
+pub const PUBLIC_RESOLVERS: [SocketAddr; 2] = [
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), DEFAULT_PORT),
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), DEFAULT_PORT),
+];
+
+/// How long a query waits for a server before trying the next one.
+#[cfg(feature = "hickory-dns")]
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves hostnames by querying chosen DNS servers in order, moving on to the next one when a
+/// server fails or doesn't answer.
+#[cfg(feature = "hickory-dns")]
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    servers: Vec<SocketAddr>,
+    /// When set, hostnames only resolve to addresses of this family.
+    family: Option<AddressFamily>,
+    inner: TokioAsyncResolver,
+}
+
+#[cfg(feature = "hickory-dns")]
+impl Resolver {
+    pub fn new(servers: &[SocketAddr]) -> Resolver {
+        Resolver { servers: servers.to_vec(), family: None, inner: connect(servers, false) }
+    }
+
+    /// Only resolves hostnames to addresses of `family`, when given, for connections pinned to it.
+    pub fn with_family(mut self, family: Option<AddressFamily>) -> Resolver {
+        self.family = family;
+        self
+    }
+
+    /// Only accepts answers that DNSSEC validates, when `validate` is set, so a forged answer
+    /// can't pass for the record. Names in unsigned zones then fail to resolve.
+    pub fn with_dnssec(mut self, validate: bool) -> Resolver {
+        self.inner = connect(&self.servers, validate);
+        self
+    }
+
+    /// Looks up the addresses of `host`.
+    ///
+    /// # Arguments
+    /// * `host` - The hostname to resolve.
+    ///
+    /// # Returns
+    /// The IPv4 addresses of `host`, or its IPv6 addresses when it has no IPv4 ones.
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, BoxError> {
+        let mut addrs = self.query(host, RecordType::A).await?;
+        if addrs.is_empty() {
+            addrs = self.query(host, RecordType::AAAA).await?;
+        }
+        if addrs.is_empty() {
+            return Err(format!("{} has no A or AAAA records on {}", host, self.servers()).into());
+        }
+        Ok(addrs)
+    }
+
+    /// Looks up the addresses of one type `host` has.
+    ///
+    /// # Arguments
+    /// * `host` - The hostname to resolve.
+    /// * `ipv6` - Whether to ask for its AAAA records instead of its A records.
+    ///
+    /// # Returns
+    /// The addresses, which may be none.
+    pub async fn lookup_type(&self, host: &str, ipv6: bool) -> Result<Vec<IpAddr>, BoxError> {
+        self.query(host, if ipv6 { RecordType::AAAA } else { RecordType::A }).await
+    }
+
+    /// Asks for the `record_type` records of `host`, as a fully qualified name so no search
+    /// domain is tried.
+    async fn query(&self, host: &str, record_type: RecordType) -> Result<Vec<IpAddr>, BoxError> {
+        let name = format!("{}.", host.trim_end_matches('.'));
+        match self.inner.lookup(name, record_type).await {
+            Ok(lookup) => Ok(lookup.iter().filter_map(|data| data.ip_addr()).collect()),
+            Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(Vec::new()),
+            Err(err) => Err(format!("{} couldn't resolve {}: {}", self.servers(), host, err).into()),
+        }
+    }
+
+    /// The servers asked, for error messages.
+    fn servers(&self) -> String {
+        self.servers.iter().map(SocketAddr::to_string).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Builds a resolver asking `servers` one at a time, in the order given, over UDP and then TCP.
+#[cfg(feature = "hickory-dns")]
+fn connect(servers: &[SocketAddr], validate: bool) -> TokioAsyncResolver {
+    let mut config = ResolverConfig::new();
+    for &server in servers {
+        config.add_name_server(NameServerConfig::new(server, Protocol::Udp));
+        config.add_name_server(NameServerConfig::new(server, Protocol::Tcp));
+    }
+
+    let mut options = ResolverOpts::default();
+    options.timeout = TIMEOUT;
+    options.edns0 = true;
+    options.validate = validate;
+    options.num_concurrent_reqs = 1;
+    options.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+    // Only the chosen servers are asked, not /etc/hosts
+    options.use_hosts_file = false;
+    TokioAsyncResolver::tokio(config, options)
+}
+
+#[cfg(feature = "hickory-dns")]
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs = match resolver.family {
+                Some(family) => {
+                    let addrs = resolver.lookup_type(&host, family == AddressFamily::V6).await?;
+                    if addrs.is_empty() {
+                        return Err(format!("{} has no {} address on {}", host, family.as_str(), resolver.servers()).into());
+                    }
+                    addrs
+                }
+                None => resolver.lookup(&host).await?,
+            };
+            // reqwest fills in the port of the URL
+            let addrs: Addrs = Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(all(test, feature = "hickory-dns"))]
+pub(crate) mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+    use tokio::sync::mpsc;
+
+    /// The largest query the test servers read.
+    const MAX_QUERY: usize = 512;
+
+    const TYPE_A: u16 = 1;
+    const TYPE_AAAA: u16 = 28;
+
+    /// Returns the offset just past the name starting at `offset`.
+    fn skip_name(packet: &[u8], mut offset: usize) -> Option<usize> {
+        loop {
+            let len = *packet.get(offset)? as usize;
+            match len {
+                0 => return Some(offset + 1),
+                _ if len & 0xc0 == 0xc0 => return Some(offset + 2),
+                _ => offset += len + 1,
+            }
+        }
+    }
+
+    /// Builds the answer to `query`: `ipv4` for A queries, and `ipv6` for AAAA queries when given.
+    fn answer(query: &[u8], ipv4: Ipv4Addr, ipv6: Option<Ipv6Addr>) -> Vec<u8> {
+        let question_end = skip_name(query, 12).unwrap() + 4;
+        let qtype = u16::from_be_bytes([query[question_end - 4], query[question_end - 3]]);
+
+        let mut answer = query[..question_end].to_vec();
+        // A response, recursion available, one answer if the type is known
+        answer[2] = 0x81;
+        answer[3] = 0x80;
+        // The EDNS record of the query isn't echoed back
+        answer[10] = 0;
+        answer[11] = 0;
+        match (qtype, ipv6) {
+            (TYPE_A, _) => {
+                answer[7] = 1;
+                answer.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x04]);
+                answer.extend_from_slice(&ipv4.octets());
+            }
+            (TYPE_AAAA, Some(ipv6)) => {
+                answer[7] = 1;
+                answer.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x10]);
+                answer.extend_from_slice(&ipv6.octets());
+            }
+            _ => answer[7] = 0,
+        }
+        answer
+    }
+
+    /// Starts a DNS server on localhost answering every A query with `addr`.
+    pub(crate) async fn dns_server(addr: Ipv4Addr) -> SocketAddr {
+        dual_stack_dns_server(addr, None).await
+    }
+
+    /// Starts a DNS server on localhost answering every A query with `ipv4`, and every AAAA
+    /// query with `ipv6` when given.
+    pub(crate) async fn dual_stack_dns_server(ipv4: Ipv4Addr, ipv6: Option<Ipv6Addr>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_QUERY];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                socket.send_to(&answer(&buf[..len], ipv4, ipv6), peer).await.unwrap();
+            }
+        });
+        local
+    }
+
+    /// Starts a DNS server on localhost answering every A query with `addr`, and sending each
+    /// query it gets to the returned channel.
+    async fn recording_dns_server(addr: Ipv4Addr) -> (SocketAddr, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local = socket.local_addr().unwrap();
+        let (queries, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_QUERY];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let _ = queries.send(buf[..len].to_vec());
+                socket.send_to(&answer(&buf[..len], addr, None), peer).await.unwrap();
+            }
+        });
+        (local, received)
+    }
+
+    /// Starts a DNS server on localhost failing every query with SERVFAIL.
+    async fn failing_dns_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_QUERY];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let mut answer = answer(&buf[..len], Ipv4Addr::UNSPECIFIED, None);
+                answer[3] = 0x82;
+                answer[7] = 0;
+                answer.truncate(skip_name(&answer, 12).unwrap() + 4);
+                socket.send_to(&answer, peer).await.unwrap();
+            }
+        });
+        local
+    }
+
+    /// Starts a DNS server on localhost whose UDP answers are all truncated, and which answers
+    /// every A query with `addr` over TCP.
+    async fn truncating_dns_server(addr: Ipv4Addr) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let socket = UdpSocket::bind(local).await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_QUERY];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let mut answer = answer(&buf[..len], addr, None);
+                // Truncated, with no records
+                answer[2] |= 0x02;
+                answer[7] = 0;
+                answer.truncate(skip_name(&answer, 12).unwrap() + 4);
+                socket.send_to(&answer, peer).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    // Each message over TCP is prefixed with its length
+                    let mut len = [0u8; 2];
+                    while stream.read_exact(&mut len).await.is_ok() {
+                        let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+                        stream.read_exact(&mut query).await.unwrap();
+                        let answer = answer(&query, addr, None);
+                        stream.write_all(&(answer.len() as u16).to_be_bytes()).await.unwrap();
+                        stream.write_all(&answer).await.unwrap();
+                    }
+                });
+            }
+        });
+        local
+    }
+
+    /// Tests that hostnames resolve to what the chosen server answers.
+    #[tokio::test]
+    async fn test_lookup_uses_server() {
+        let server = dns_server(Ipv4Addr::new(203, 0, 113, 7)).await;
+
+        let addrs = Resolver::new(&[server]).lookup("ip.skysync.test").await.unwrap();
+
+        assert_eq!(addrs, vec![IpAddr::from([203, 0, 113, 7])]);
+    }
+
+    /// Tests that hostnames only resolve to addresses of the family they're pinned to.
+    #[tokio::test]
+    async fn test_lookup_type_picks_family() {
+        let server = dual_stack_dns_server(Ipv4Addr::new(203, 0, 113, 7), Some(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7))).await;
+        let resolver = Resolver::new(&[server]);
+
+        assert_eq!(resolver.lookup_type("ip.skysync.test", true).await.unwrap(), vec![IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 7])]);
+        assert_eq!(resolver.lookup_type("ip.skysync.test", false).await.unwrap(), vec![IpAddr::from([203, 0, 113, 7])]);
+    }
+
+    /// Tests that a hostname without addresses is an error rather than an empty answer.
+    #[tokio::test]
+    async fn test_lookup_without_records_fails() {
+        let server = dns_server(Ipv4Addr::new(203, 0, 113, 7)).await;
+        let resolver = Resolver::new(&[server]);
+
+        assert!(resolver.lookup_type("ip.skysync.test", true).await.unwrap().is_empty());
+        assert!(resolver.with_family(Some(AddressFamily::V6)).resolve("ip.skysync.test".parse().unwrap()).await.is_err());
+    }
+
+    /// Tests that a truncated answer is asked for again over TCP.
+    #[tokio::test]
+    async fn test_truncated_answer_retries_over_tcp() {
+        let server = truncating_dns_server(Ipv4Addr::new(203, 0, 113, 9)).await;
+
+        let addrs = Resolver::new(&[server]).lookup("ip.skysync.test").await.unwrap();
+
+        assert_eq!(addrs, vec![IpAddr::from([203, 0, 113, 9])]);
+    }
+
+    /// Tests that a server failing the query is skipped for the next one.
+    #[tokio::test]
+    async fn test_lookup_moves_on_to_next_server() {
+        let failing = failing_dns_server().await;
+        let server = dns_server(Ipv4Addr::new(203, 0, 113, 7)).await;
+
+        let addrs = Resolver::new(&[failing, server]).lookup("ip.skysync.test").await.unwrap();
+
+        assert_eq!(addrs, vec![IpAddr::from([203, 0, 113, 7])]);
+    }
+
+    /// Tests that queries advertise EDNS, so servers can answer with more than 512 bytes.
+    #[tokio::test]
+    async fn test_queries_use_edns() {
+        let (server, mut queries) = recording_dns_server(Ipv4Addr::new(203, 0, 113, 7)).await;
+
+        Resolver::new(&[server]).lookup_type("ip.skysync.test", false).await.unwrap();
+
+        let query = queries.recv().await.unwrap();
+        let question_end = skip_name(&query, 12).unwrap() + 4;
+        assert_eq!(u16::from_be_bytes([query[10], query[11]]), 1);
+        // An OPT record, on the root name
+        assert_eq!(query[question_end..question_end + 3], [0x00, 0x00, 0x29]);
+    }
+
+    /// Tests that validating resolvers refuse an answer that isn't signed.
+    #[tokio::test]
+    async fn test_dnssec_rejects_unsigned_answer() {
+        let server = dns_server(Ipv4Addr::new(203, 0, 113, 7)).await;
+
+        assert!(Resolver::new(&[server]).with_dnssec(true).lookup("ip.skysync.test").await.is_err());
+        assert!(Resolver::new(&[server]).with_dnssec(false).lookup("ip.skysync.test").await.is_ok());
+    }
+}
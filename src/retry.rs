@@ -0,0 +1,94 @@
+//! How failed requests are retried. Each subsystem has its own policy, as the right budget differs:
+//! the IP lookup is cheap (`IP_RETRIES`), Cloudflare rate limits (`CF_RETRIES`) and notifications
+//! are best-effort (`NOTIFY_RETRIES`).
+use crate::log::report;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// How many times a failed request is retried, and how long it waits in between.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times a failed attempt is retried, after the first one.
+    pub retries: u32,
+    /// The wait before the first retry, doubled after every one.
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    /// How many attempts are made in all.
+    pub fn attempts(&self) -> u32 {
+        self.retries.saturating_add(1)
+    }
+
+    /// The wait before retry number `retry`, counted from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.delay.saturating_mul(1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX))
+    }
+
+    /// Makes attempts until one succeeds or the retries run out, logging every retry.
+    ///
+    /// # Arguments
+    /// * `what` - What is attempted, for the log, e.g. `deliver discord notification`.
+    /// * `attempt` - Makes one attempt.
+    /// * `retry_in` - Given an error and the wait the policy would have, how long to wait before
+    ///   the next attempt, or `None` to give up on the error right away.
+    ///
+    /// # Returns
+    /// The result of the first successful attempt, or the error of the last one.
+    pub async fn run<T, E, F>(&self, what: &str, mut attempt: impl FnMut() -> F, mut retry_in: impl FnMut(&E, Duration) -> Option<Duration>) -> Result<T, E>
+    where
+        E: Display,
+        F: Future<Output = Result<T, E>>,
+    {
+        let mut retry = 0;
+        loop {
+            let err = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let wait = if retry < self.retries { retry_in(&err, self.delay(retry + 1)) } else { None };
+            let Some(wait) = wait else {
+                return Err(err);
+            };
+
+            retry += 1;
+            report(&format!("Failed to {} (attempt {} of {}), retrying in {:?}: {}", what, retry, self.attempts(), wait, err)).await;
+            sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Tests that the wait doubles after every retry, without overflowing.
+    #[test]
+    fn test_delay_doubles() {
+        let policy = RetryPolicy { retries: 3, delay: Duration::from_millis(500) };
+
+        assert_eq!((policy.delay(1), policy.delay(2), policy.delay(3)), (Duration::from_millis(500), Duration::from_secs(1), Duration::from_secs(2)));
+        assert_eq!(policy.attempts(), 4);
+        assert_eq!(RetryPolicy { retries: 0, delay: Duration::MAX }.delay(40), Duration::MAX);
+    }
+
+    /// Tests that attempts stop at the policy's count, or as soon as an error isn't retried.
+    #[tokio::test]
+    async fn test_run_stops_after_attempts() {
+        let policy = RetryPolicy { retries: 2, delay: Duration::ZERO };
+        let count = AtomicU32::new(0);
+        let fail = || async { Err::<(), String>(format!("attempt {}", count.fetch_add(1, Ordering::SeqCst) + 1)) };
+
+        assert_eq!(policy.run("connect", fail, |_, wait| Some(wait)).await, Err("attempt 3".to_string()));
+        assert_eq!(count.swap(0, Ordering::SeqCst), 3);
+
+        assert_eq!(policy.run("connect", fail, |_, _| None).await, Err("attempt 1".to_string()));
+        assert_eq!(count.swap(0, Ordering::SeqCst), 1);
+
+        let flaky = || async { if count.fetch_add(1, Ordering::SeqCst) < 1 { Err("down".to_string()) } else { Ok(7) } };
+        assert_eq!(policy.run("connect", flaky, |_, wait| Some(wait)).await, Ok(7));
+    }
+}
@@ -0,0 +1,116 @@
+//! The commented `skysync.toml` template `skysync init` writes.
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Every setting with its default and a one-line description. The essentials are set to
+/// placeholders, everything else is commented out.
+pub const TEMPLATE: &str = include_str!("skysync.toml");
+
+/// The settings `skysync init --interactive` asks for, with their prompts.
+pub const ESSENTIALS: [(&str, &str); 5] = [
+    ("cf_zone_id", "Cloudflare zone ID"),
+    ("cf_dns_name", "Record name (e.g. home.example.com)"),
+    ("cf_api_key", "Cloudflare API key"),
+    ("cf_email", "Cloudflare account email"),
+    ("discord_webhook_id", "Discord webhook URL (optional)"),
+];
+
+/// Fills in the template.
+///
+/// # Arguments
+/// * `answers` - Values of top-level keys. Empty values keep the placeholder, and commented-out
+///   keys are uncommented where they first appear.
+///
+/// # Returns
+/// The template with those keys set.
+pub fn render(answers: &[(&str, String)]) -> String {
+    let mut rendered = String::with_capacity(TEMPLATE.len());
+    let mut filled: Vec<&str> = Vec::new();
+    for line in TEMPLATE.lines() {
+        let setting = line.strip_prefix("# ").unwrap_or(line);
+        let answer = answers.iter().find(|(key, value)| !value.is_empty() && !filled.contains(key) && setting.starts_with(&format!("{} = ", key)));
+        match answer {
+            Some((key, value)) => {
+                rendered.push_str(&format!("{} = {}", key, toml::Value::String(value.clone())));
+                filled.push(key);
+            }
+            None => rendered.push_str(line),
+        }
+        rendered.push('\n');
+    }
+    rendered
+}
+
+/// Asks for each of the `ESSENTIALS`, one line at a time.
+///
+/// # Arguments
+/// * `input` - Where the answers are read from, usually stdin.
+/// * `output` - Where the prompts are written, usually stdout.
+///
+/// # Returns
+/// The answers, trimmed. Skipped prompts and an early end of input leave them empty.
+pub fn prompt(mut input: impl BufRead, mut output: impl Write) -> io::Result<Vec<(&'static str, String)>> {
+    let mut answers = Vec::new();
+    for (key, question) in ESSENTIALS {
+        write!(output, "{}: ", question)?;
+        output.flush()?;
+        let mut answer = String::new();
+        input.read_line(&mut answer)?;
+        answers.push((key, answer.trim().to_string()));
+    }
+    Ok(answers)
+}
+
+/// Writes `contents` to `path`, refusing to replace an existing file unless `force` is set.
+pub fn export(path: &Path, contents: &str, force: bool) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigLoader;
+    use std::collections::HashMap;
+
+    /// Loads `contents` as the only source of settings.
+    fn load(dir: &tempfile::TempDir, contents: &str) -> crate::config::Config {
+        let path = dir.path().join("skysync.toml");
+        export(&path, contents, true).unwrap();
+        ConfigLoader::with_env(dir.path().join(".env"), HashMap::new()).with_toml(&path).load().unwrap()
+    }
+
+    /// Tests that the template, as written and with prompted answers, loads back cleanly.
+    #[test]
+    fn test_template_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = load(&dir, TEMPLATE);
+        assert_eq!(config.cron_interval, std::time::Duration::from_millis(1_800_000));
+
+        let input = "abc123\nvpn.example.com\n\"quoted\" key\nme@example.com\n\n";
+        let answers = prompt(input.as_bytes(), io::sink()).unwrap();
+        let config = load(&dir, &render(&answers));
+
+        assert_eq!(config.zones[0].zone_id, "abc123");
+        assert_eq!(config.zones[0].records, vec!["vpn.example.com".to_string()]);
+        assert_eq!(config.cloudflare.api_key, "\"quoted\" key");
+    }
+
+    /// Tests that an existing file is only replaced with `force`.
+    #[test]
+    fn test_export_refuses_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("skysync.toml");
+        std::fs::write(&path, "mode = \"monitor\"\n").unwrap();
+
+        assert_eq!(export(&path, TEMPLATE, false).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        export(&path, TEMPLATE, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), TEMPLATE);
+    }
+}
@@ -0,0 +1,403 @@
+use crate::timezone::Timezone;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How much each wait between ticks may deviate from the base interval.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Jitter {
+    /// Always wait exactly the base interval.
+    #[default]
+    None,
+    /// Deviate by up to this percentage of the base interval, in either direction.
+    Percent(u8),
+    /// Deviate by up to this fixed amount, in either direction.
+    Absolute(Duration),
+}
+
+impl Jitter {
+    /// Picks the wait before the next tick.
+    ///
+    /// # Arguments
+    /// * `base` - The configured interval.
+    /// * `rng` - The source of randomness.
+    ///
+    /// # Returns
+    /// A `Duration` within `base ± max`, never below zero.
+    pub fn apply(&self, base: Duration, rng: &mut impl Rng) -> Duration {
+        let max = match *self {
+            Jitter::None => return base,
+            Jitter::Percent(percent) => base.mul_f64(f64::from(percent) / 100.0),
+            Jitter::Absolute(max) => max,
+        };
+
+        let max_ms = max.as_millis() as i64;
+        if max_ms == 0 {
+            return base;
+        }
+
+        let offset = rng.gen_range(-max_ms..=max_ms);
+        let wait = base.as_millis() as i64 + offset;
+        Duration::from_millis(wait.max(0) as u64)
+    }
+}
+
+/// Error returned when an `INTERVAL_JITTER` value can't be parsed.
+#[derive(Debug, PartialEq)]
+pub struct ParseJitterError(String);
+
+impl fmt::Display for ParseJitterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Jitter {
+    type Err = ParseJitterError;
+
+    /// Parses either a percentage (`"10%"`) or an absolute maximum in milliseconds (`"5000"`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some(percent) = s.strip_suffix('%') {
+            return match percent.trim().parse::<u8>() {
+                Ok(0) => Ok(Jitter::None),
+                Ok(percent) if percent <= 100 => Ok(Jitter::Percent(percent)),
+                _ => Err(ParseJitterError("expected a percentage between 0% and 100%".to_string())),
+            };
+        }
+
+        match s.parse::<u64>() {
+            Ok(0) => Ok(Jitter::None),
+            Ok(ms) => Ok(Jitter::Absolute(Duration::from_millis(ms))),
+            Err(_) => Err(ParseJitterError("expected a percentage like \"10%\" or milliseconds".to_string())),
+        }
+    }
+}
+
+/// The hours of the day during which DNS changes may be written, in the configured timezone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UpdateWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl UpdateWindow {
+    /// Whether `time` falls inside the window, from `start` up to but excluding `end`. A window
+    /// that ends before it starts spans midnight.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl fmt::Display for UpdateWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+/// Error returned when an `UPDATE_WINDOW` value can't be parsed.
+#[derive(Debug, PartialEq)]
+pub struct ParseUpdateWindowError(String);
+
+impl fmt::Display for ParseUpdateWindowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for UpdateWindow {
+    type Err = ParseUpdateWindowError;
+
+    /// Parses a `"HH:MM-HH:MM"` range, e.g. `"22:00-06:00"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || ParseUpdateWindowError("expected a range of 24-hour times like \"22:00-06:00\"".to_string());
+        let (start, end) = s.split_once('-').ok_or_else(expected)?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| expected())?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| expected())?;
+
+        if start == end {
+            return Err(ParseUpdateWindowError("the window must not start and end at the same time".to_string()));
+        }
+        Ok(UpdateWindow { start, end })
+    }
+}
+
+/// When ticks fire, as a five-field cron expression in the configured timezone: minute, hour,
+/// day of the month, month and day of the week.
+///
+/// The fields are read by the `cron` crate, and take `*`, numbers, ranges (`1-5`), steps (`*/15`,
+/// `8-18/2`) and lists of those (`0,30`). Months and days of the week may also be named (`jan`,
+/// `mon-fri`), and Sunday is either 0 or 7. As in Vixie cron, a day matches when either day field
+/// does if both are set, and when both do if either starts with `*`, so `*/2 * mon` is the
+/// Mondays on odd days.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CronSchedule {
+    expression: String,
+    /// The schedules a time has to match one of: the expression itself, or one per day field
+    /// when both are set.
+    schedules: Vec<cron::Schedule>,
+}
+
+impl CronSchedule {
+    /// The first time after `after` the schedule fires, in the naive time of its timezone.
+    ///
+    /// The schedules are searched in UTC, which has no DST changes, so they see every wall-clock
+    /// time exactly once.
+    fn next_naive(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        self.schedules.iter()
+            .filter_map(|schedule| schedule.after(&after.and_utc()).next())
+            .min()
+            .map(|time| time.naive_utc())
+    }
+
+    /// The first time after `after` the schedule fires.
+    ///
+    /// # Arguments
+    /// * `after` - The moment to search from, usually now.
+    /// * `timezone` - The zone the expression is read in. Times skipped by a DST change are
+    ///   passed over.
+    ///
+    /// # Returns
+    /// The next fire time, or `None` if the expression never fires.
+    pub fn next_after(&self, after: DateTime<FixedOffset>, timezone: Timezone) -> Option<DateTime<FixedOffset>> {
+        let mut naive = timezone.convert(&after).naive_local();
+        loop {
+            naive = self.next_naive(naive)?;
+            if let Some(time) = timezone.localize(naive).filter(|time| *time > after) {
+                return Some(time);
+            }
+        }
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+/// Error returned when a `CRON_SCHEDULE` value can't be parsed.
+#[derive(Debug, PartialEq)]
+pub struct ParseCronScheduleError(String);
+
+impl fmt::Display for ParseCronScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Names the numbered days of a day of week field, since the `cron` crate counts them from 1 on
+/// Sunday rather than from 0.
+fn weekday_names(field: &str) -> String {
+    let name = |day: &str| match day.parse::<usize>() {
+        Ok(day) if day <= 7 => WEEKDAYS[day % 7].to_string(),
+        _ => day.to_string(),
+    };
+    field.split(',').map(|part| {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (part, None),
+        };
+        let named = match range.split_once('-') {
+            // The crate's week ends on Saturday, so a range up to Sunday as 7 wraps around
+            Some((start, "7")) if step.is_none() => format!("{}-sat,sun", name(start)),
+            Some((start, end)) => format!("{}-{}", name(start), name(end)),
+            None => name(range),
+        };
+        match step {
+            Some(step) => format!("{}/{}", named, step),
+            None => named,
+        }
+    }).collect::<Vec<_>>().join(",")
+}
+
+impl FromStr for CronSchedule {
+    type Err = ParseCronScheduleError;
+
+    /// Parses a five-field cron expression like `"*/15 8-18 * * mon-fri"`, or one of `@hourly`,
+    /// `@daily`, `@weekly`, `@monthly` and `@yearly`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expression = s.trim();
+        let expressions = match expression.to_ascii_lowercase().as_str() {
+            "@midnight" => vec!["@daily".to_string()],
+            "@annually" => vec!["@yearly".to_string()],
+            shorthand if shorthand.starts_with('@') => vec![shorthand.to_string()],
+            _ => {
+                let fields: Vec<&str> = expression.split_whitespace().collect();
+                let [minutes, hours, days, months, weekdays] = fields[..] else {
+                    return Err(ParseCronScheduleError(format!(
+                        "expected five fields (minute, hour, day of month, month, day of week), got {}", fields.len(),
+                    )));
+                };
+                // The crate adds a leading seconds field, and wants both day fields to match
+                let weekdays = weekday_names(weekdays);
+                match days.starts_with('*') || weekdays.starts_with('*') {
+                    true => vec![format!("0 {} {} {} {} {}", minutes, hours, days, months, weekdays)],
+                    false => vec![
+                        format!("0 {} {} {} {} *", minutes, hours, days, months),
+                        format!("0 {} {} * {} {}", minutes, hours, months, weekdays),
+                    ],
+                }
+            }
+        };
+
+        let schedule = CronSchedule {
+            expression: expression.to_string(),
+            schedules: expressions.iter()
+                .map(|expression| expression.parse::<cron::Schedule>())
+                .collect::<Result<_, _>>()
+                .map_err(|err| ParseCronScheduleError(format!("invalid cron expression {:?}: {}", expression, err)))?,
+        };
+
+        let reference = NaiveDate::from_ymd_opt(2000, 1, 1).and_then(|date| date.and_hms_opt(0, 0, 0)).unwrap_or_default();
+        if schedule.next_naive(reference).is_none() {
+            return Err(ParseCronScheduleError(format!("{:?} never fires", expression)));
+        }
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Tests that percentage jitter stays within the configured bounds.
+    #[test]
+    fn test_percent_jitter_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let base = Duration::from_secs(100);
+
+        for _ in 0..1000 {
+            let wait = Jitter::Percent(10).apply(base, &mut rng);
+            assert!(wait >= Duration::from_secs(90) && wait <= Duration::from_secs(110), "{:?}", wait);
+        }
+    }
+
+    /// Tests that absolute jitter stays within the configured bounds and never goes negative.
+    #[test]
+    fn test_absolute_jitter_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let base = Duration::from_millis(500);
+
+        for _ in 0..1000 {
+            let wait = Jitter::Absolute(Duration::from_secs(1)).apply(base, &mut rng);
+            assert!(wait <= Duration::from_millis(1500), "{:?}", wait);
+        }
+    }
+
+    /// Tests that no jitter keeps the base interval.
+    #[test]
+    fn test_no_jitter() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(Jitter::None.apply(Duration::from_secs(30), &mut rng), Duration::from_secs(30));
+    }
+
+    /// Tests parsing of the supported formats.
+    #[test]
+    fn test_parse_jitter() {
+        assert_eq!("10%".parse(), Ok(Jitter::Percent(10)));
+        assert_eq!("2500".parse(), Ok(Jitter::Absolute(Duration::from_millis(2500))));
+        assert_eq!("0".parse(), Ok(Jitter::None));
+        assert!("150%".parse::<Jitter>().is_err());
+        assert!("lots".parse::<Jitter>().is_err());
+    }
+
+    /// Fires `expression` `count` times in a row from `start`, in UTC.
+    fn fire_times(expression: &str, start: &str, count: usize) -> Vec<String> {
+        let schedule: CronSchedule = expression.parse().unwrap();
+        let utc: Timezone = "UTC".parse().unwrap();
+        let mut time = DateTime::parse_from_rfc3339(start).unwrap();
+        (0..count).map(|_| {
+            time = schedule.next_after(time, utc).unwrap();
+            time.format("%a %Y-%m-%d %H:%M").to_string()
+        }).collect()
+    }
+
+    /// Tests the fire times of weekday office hours, stepping over the weekend.
+    #[test]
+    fn test_cron_fire_times() {
+        assert_eq!(fire_times("0 9,18 * * mon-fri", "2024-07-04T17:59:30Z", 5), vec![
+            "Thu 2024-07-04 18:00", "Fri 2024-07-05 09:00", "Fri 2024-07-05 18:00", "Mon 2024-07-08 09:00", "Mon 2024-07-08 18:00",
+        ]);
+        assert_eq!(fire_times("*/20 22-23 * * *", "2024-12-31T22:30:00Z", 4), vec![
+            "Tue 2024-12-31 22:40", "Tue 2024-12-31 23:00", "Tue 2024-12-31 23:20", "Tue 2024-12-31 23:40",
+        ]);
+        // Either day field matches when both are set, and Sunday may be 7
+        assert_eq!(fire_times("0 0 13 * 7", "2024-09-01T00:00:00Z", 3), vec![
+            "Sun 2024-09-08 00:00", "Fri 2024-09-13 00:00", "Sun 2024-09-15 00:00",
+        ]);
+        // A day field starting with a star narrows the other one instead
+        assert_eq!(fire_times("0 0 */2 * mon", "2024-09-01T00:00:00Z", 3), vec![
+            "Mon 2024-09-09 00:00", "Mon 2024-09-23 00:00", "Mon 2024-10-07 00:00",
+        ]);
+        assert_eq!(fire_times("0 0 13 * */3", "2024-09-01T00:00:00Z", 2), vec![
+            "Sun 2024-10-13 00:00", "Wed 2024-11-13 00:00",
+        ]);
+        // Numbered days count from Sunday as 0, and a range may end on Sunday as 7
+        assert_eq!(fire_times("0 12 * * 1", "2024-09-05T13:00:00Z", 1), vec!["Mon 2024-09-09 12:00"]);
+        assert_eq!(fire_times("0 12 * * 5-7", "2024-09-05T13:00:00Z", 4), vec![
+            "Fri 2024-09-06 12:00", "Sat 2024-09-07 12:00", "Sun 2024-09-08 12:00", "Fri 2024-09-13 12:00",
+        ]);
+        assert_eq!(fire_times("@yearly", "2024-02-29T12:00:00Z", 1), vec!["Wed 2025-01-01 00:00"]);
+        assert_eq!(fire_times("0 12 29 feb *", "2024-03-01T00:00:00Z", 1), vec!["Tue 2028-02-29 12:00"]);
+    }
+
+    /// Tests that fire times are read in the configured timezone.
+    #[test]
+    fn test_cron_in_timezone() {
+        let schedule: CronSchedule = "30 8 * * *".parse().unwrap();
+        let timezone: Timezone = "America/Sao_Paulo".parse().unwrap();
+        let after = DateTime::parse_from_rfc3339("2024-07-01T12:00:00Z").unwrap();
+
+        assert_eq!(schedule.next_after(after, timezone).unwrap().to_rfc3339(), "2024-07-02T08:30:00-03:00");
+    }
+
+    /// Tests that malformed expressions and ones that never fire are rejected.
+    #[test]
+    fn test_parse_cron_schedule() {
+        assert!("* * * *".parse::<CronSchedule>().is_err());
+        assert!("60 * * * *".parse::<CronSchedule>().is_err());
+        assert!("*/0 * * * *".parse::<CronSchedule>().is_err());
+        assert!("0 18-9 * * *".parse::<CronSchedule>().is_err());
+        assert!("0 0 * * funday".parse::<CronSchedule>().is_err());
+        assert!("0 0 30 feb *".parse::<CronSchedule>().is_err());
+        assert_eq!(" 0 9 * * Mon ".parse::<CronSchedule>().unwrap().to_string(), "0 9 * * Mon");
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    /// Tests that windows within a day and windows spanning midnight include the right times.
+    #[test]
+    fn test_update_window_contains() {
+        let day: UpdateWindow = "09:00-17:00".parse().unwrap();
+        assert!(day.contains(at("09:00")) && day.contains(at("12:30")));
+        assert!(!day.contains(at("17:00")) && !day.contains(at("08:59")));
+
+        let night: UpdateWindow = "22:00-06:00".parse().unwrap();
+        assert!(night.contains(at("22:00")) && night.contains(at("23:59")) && night.contains(at("00:00")) && night.contains(at("05:59")));
+        assert!(!night.contains(at("06:00")) && !night.contains(at("12:00")) && !night.contains(at("21:59")));
+        assert_eq!(night.to_string(), "22:00-06:00");
+    }
+
+    /// Tests that malformed and empty windows are rejected.
+    #[test]
+    fn test_parse_update_window() {
+        assert_eq!(" 22:00 - 06:00 ".parse(), Ok(UpdateWindow { start: at("22:00"), end: at("06:00") }));
+        assert!("22:00".parse::<UpdateWindow>().is_err());
+        assert!("25:00-06:00".parse::<UpdateWindow>().is_err());
+        assert!("10:00-10:00".parse::<UpdateWindow>().is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use crate::config::TriggerConfig;
+use crate::history::{History, HistoryEntry};
+use crate::log::{debug_log, LogBuffer};
+use crate::metrics::Metrics;
+use crate::sync::{Circuit, State};
+use axum::extract;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+
+/// How many of the latest IP changes `GET /status` includes.
+pub const RECENT_CHANGES: usize = 10;
+
+/// The page served at `/`, which renders `GET /status` and refetches it every few seconds.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// A managed record, as listed by `GET /status`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RecordStatus {
+    pub name: String,
+    pub content: String,
+    /// Whether the record is disabled in `ZONES`, and left alone by ticks.
+    pub disabled: bool,
+}
+
+/// The body of `GET /status`.
+#[derive(Serialize, Debug)]
+pub struct StatusReport {
+    pub current_ip: Option<String>,
+    pub last_check: Option<DateTime<FixedOffset>>,
+    pub last_change: Option<DateTime<FixedOffset>>,
+    pub failure_streak: u32,
+    /// Every failed tick since the state was first saved.
+    pub total_failures: u64,
+    pub records: Vec<RecordStatus>,
+    /// Whether the circuit breaker lets ticks reach the provider.
+    pub circuit: Circuit,
+    /// The latest IP changes, oldest first.
+    pub history: Vec<HistoryEntry>,
+}
+
+impl StatusReport {
+    /// Builds the report from the published state and the latest entries of the history.
+    pub fn new(state: &State, mut history: Vec<HistoryEntry>) -> StatusReport {
+        history.drain(..history.len().saturating_sub(RECENT_CHANGES));
+        StatusReport {
+            current_ip: state.current_ip.clone(),
+            last_check: state.last_check,
+            last_change: state.last_change,
+            failure_streak: state.failure_streak,
+            total_failures: state.total_failures,
+            records: state.records.iter()
+                .map(|(name, content)| RecordStatus {
+                    name: name.clone(),
+                    content: content.clone(),
+                    disabled: state.record_health.get(name).is_some_and(|health| health.disabled),
+                })
+                .collect(),
+            circuit: state.circuit.clone(),
+            history,
+        }
+    }
+}
+
+/// Wakes the cron loop for `POST /trigger`, at most once every `min_interval`.
+#[derive(Clone)]
+pub struct Trigger {
+    config: TriggerConfig,
+    wake: Arc<Notify>,
+    /// When the latest trigger was accepted.
+    last: Arc<Mutex<Option<Instant>>>,
+}
+
+/// Why a trigger was turned away.
+#[derive(Debug, PartialEq)]
+enum Rejection {
+    /// The secret is missing or wrong.
+    Unauthorized,
+    /// The previous trigger was accepted less than `min_interval` ago, retry in this many seconds.
+    TooSoon(u64),
+}
+
+impl Trigger {
+    /// # Arguments
+    /// * `config` - The secret callers have to send, and how often they may.
+    /// * `wake` - Notified for every accepted trigger, the cron loop starts a check on it.
+    pub fn new(config: TriggerConfig, wake: Arc<Notify>) -> Trigger {
+        Trigger { config, wake, last: Arc::new(Mutex::new(None)) }
+    }
+
+    /// Wakes the loop for a trigger sent with the `Authorization` header `authorization`, unless
+    /// it doesn't carry the secret or comes too soon after the previous one.
+    fn accept(&self, authorization: Option<&str>, now: Instant) -> Result<(), Rejection> {
+        let given = authorization.and_then(|value| value.strip_prefix("Bearer ")).unwrap_or_default();
+        if !same_secret(given.trim().as_bytes(), self.config.secret.as_bytes()) {
+            return Err(Rejection::Unauthorized);
+        }
+
+        let mut last = self.last.lock().unwrap();
+        if let Some(next) = last.map(|last| last + self.config.min_interval).filter(|next| *next > now) {
+            return Err(Rejection::TooSoon((next - now).as_secs_f64().ceil() as u64));
+        }
+        *last = Some(now);
+        // Triggers arriving during a check leave one pending, rather than queueing a check each
+        self.wake.notify_one();
+        Ok(())
+    }
+}
+
+/// Compares a given secret with the expected one, taking as long however much of it matched.
+fn same_secret(given: &[u8], secret: &[u8]) -> bool {
+    given.len() == secret.len() && given.iter().zip(secret).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// What the route handlers read from.
+#[derive(Clone)]
+struct Shared {
+    status: watch::Receiver<State>,
+    history: History,
+    logs: LogBuffer,
+    trigger: Option<Trigger>,
+}
+
+/// The routes of the HTTP server.
+///
+/// # Arguments
+/// * `status` - The state the cron loop publishes after every tick.
+/// * `history` - The change history the syncer appends to.
+/// * `logs` - The latest log lines, served at `/logs`.
+/// * `trigger` - When set, `POST /trigger` starts a check with it.
+pub fn router(status: watch::Receiver<State>, history: History, logs: LogBuffer, trigger: Option<Trigger>) -> Router {
+    let router = Router::new()
+        .route("/", get(dashboard))
+        .route("/status", get(status_report))
+        .route("/logs", get(recent_logs))
+        .route("/metrics", get(metrics));
+    let router = match trigger {
+        Some(_) => router.route("/trigger", post(trigger_check)),
+        None => router,
+    };
+    router.with_state(Shared { status, history, logs, trigger })
+}
+
+/// Serves the HTTP routes on `listener` until the task is dropped.
+///
+/// # Arguments
+/// * `listener` - The socket bound to `HTTP_BIND`.
+/// * `status` - The state the cron loop publishes after every tick.
+/// * `history` - The change history the syncer appends to.
+/// * `logs` - The latest log lines, served at `/logs`.
+/// * `trigger` - When set, `POST /trigger` starts a check with it.
+pub async fn serve(listener: TcpListener, status: watch::Receiver<State>, history: History, logs: LogBuffer, trigger: Option<Trigger>) -> std::io::Result<()> {
+    axum::serve(listener, router(status, history, logs, trigger)).await
+}
+
+async fn dashboard() -> Html<&'static str> {
+    Html(DASHBOARD)
+}
+
+async fn status_report(extract::State(shared): extract::State<Shared>) -> Json<StatusReport> {
+    // An unreadable history shouldn't take the rest of the report down with it
+    let history = shared.history.load_latest(RECENT_CHANGES).await.unwrap_or_default();
+    let state = shared.status.borrow().clone();
+    Json(StatusReport::new(&state, history))
+}
+
+/// Starts a check right away for a caller with the secret: `202 Accepted` once the loop was
+/// woken, `401` without the secret and `429` with `Retry-After` when the previous one was too recent.
+async fn trigger_check(extract::State(shared): extract::State<Shared>, headers: HeaderMap) -> Response {
+    let Some(trigger) = &shared.trigger else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let authorization = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+    match trigger.accept(authorization, Instant::now()) {
+        Ok(()) => (StatusCode::ACCEPTED, "Check triggered\n").into_response(),
+        Err(Rejection::Unauthorized) => {
+            debug_log("Rejected POST /trigger without the secret").await;
+            (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer".to_string())], "Missing or wrong secret\n").into_response()
+        }
+        Err(Rejection::TooSoon(secs)) => {
+            debug_log(&format!("Rejected POST /trigger, the previous one was accepted less than {}s ago", secs)).await;
+            (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, secs.to_string())], format!("Triggered too recently, retry in {}s\n", secs)).into_response()
+        }
+    }
+}
+
+/// The tick timings, in the Prometheus text format.
+async fn metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], Metrics::global().render())
+}
+
+/// The latest log lines as plain text, oldest first, the way they appear in `log.txt`.
+async fn recent_logs(extract::State(shared): extract::State<Shared>) -> String {
+    let mut text = shared.logs.lines().join("\n");
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::RecordHealth;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    /// Tests that `/status` reports the published state as JSON.
+    #[tokio::test]
+    async fn test_status_json() {
+        let checked = FixedOffset::east_opt(0).unwrap().with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let state = State {
+            current_ip: Some("2.2.2.2".to_string()),
+            last_check: Some(checked),
+            last_change: Some(checked),
+            failure_streak: 1,
+            total_failures: 7,
+            records: [
+                ("a.example.com".to_string(), "2.2.2.2".to_string()),
+                ("b.example.com".to_string(), "1.1.1.1".to_string()),
+            ].into(),
+            record_health: [("b.example.com".to_string(), RecordHealth { disabled: true, ..RecordHealth::default() })].into(),
+            ..State::default()
+        };
+        let (_status_tx, status_rx) = watch::channel(state);
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("history.json"), 50);
+        history.append(Some("1.1.1.1"), "2.2.2.2").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, status_rx, history.clone(), LogBuffer::new(10), None));
+
+        let body: serde_json::Value = reqwest::get(format!("http://{}/status", addr)).await.unwrap()
+            .error_for_status().unwrap()
+            .json().await.unwrap();
+        server.abort();
+
+        let changed = history.load().await.unwrap()[0].timestamp;
+        assert_eq!(body, json!({
+            "current_ip": "2.2.2.2",
+            "last_check": "2024-01-01T12:00:00Z",
+            "last_change": "2024-01-01T12:00:00Z",
+            "failure_streak": 1,
+            "total_failures": 7,
+            "records": [
+                { "name": "a.example.com", "content": "2.2.2.2", "disabled": false },
+                { "name": "b.example.com", "content": "1.1.1.1", "disabled": true }
+            ],
+            "circuit": { "state": "closed", "failures": 0, "retry_at": null },
+            "history": [
+                { "timestamp": changed, "old_ip": "1.1.1.1", "new_ip": "2.2.2.2" }
+            ]
+        }));
+    }
+
+    /// Tests that the dashboard is served at `/` and only keeps the latest changes.
+    #[tokio::test]
+    async fn test_dashboard_and_recent_changes() {
+        let html = router_response("/").await;
+        assert!(html.contains("fetch(\"/status\")"), "{}", html);
+
+        let history: Vec<HistoryEntry> = (0..RECENT_CHANGES + 5)
+            .map(|i| HistoryEntry { timestamp: chrono::Local::now(), old_ip: None, new_ip: format!("10.0.0.{}", i) })
+            .collect();
+        let report = StatusReport::new(&State::default(), history);
+        assert_eq!(report.history.len(), RECENT_CHANGES);
+        assert_eq!(report.history[0].new_ip, "10.0.0.5");
+    }
+
+    /// Tests that `/metrics` serves the timings in the Prometheus text format.
+    #[tokio::test]
+    async fn test_metrics() {
+        let body = router_response("/metrics").await;
+
+        for stage in ["skysync_ip_fetch_seconds", "skysync_dns_list_seconds", "skysync_dns_update_seconds"] {
+            assert!(body.contains(&format!("# TYPE {} histogram\n", stage)), "{}", body);
+            assert!(body.contains(&format!("{}_bucket{{le=\"+Inf\"}}", stage)), "{}", body);
+        }
+    }
+
+    /// Tests that `/logs` only returns the latest lines once more than the limit were logged.
+    #[tokio::test]
+    async fn test_logs_keeps_latest_lines() {
+        let logs = LogBuffer::new(5);
+        for i in 0..8 {
+            logs.push(&format!("line {}\n", i));
+        }
+
+        let body = router_response_with("/logs", logs).await;
+
+        assert_eq!(body, "line 3\nline 4\nline 5\nline 6\nline 7\n");
+    }
+
+    /// Tests that a trigger is accepted again once `min_interval` has passed since the last one.
+    #[test]
+    fn test_trigger_rate_limit() {
+        let trigger = Trigger::new(TriggerConfig { secret: "s3cret".to_string(), min_interval: std::time::Duration::from_secs(30) }, Arc::new(Notify::new()));
+        let start = Instant::now();
+
+        assert_eq!(trigger.accept(Some("Bearer s3cret"), start), Ok(()));
+        assert_eq!(trigger.accept(Some("Bearer s3cret"), start + std::time::Duration::from_millis(29_500)), Err(Rejection::TooSoon(1)));
+        assert_eq!(trigger.accept(Some("Bearer s3cre"), start + std::time::Duration::from_secs(31)), Err(Rejection::Unauthorized));
+        assert_eq!(trigger.accept(Some("Bearer s3cret"), start + std::time::Duration::from_secs(30)), Ok(()));
+    }
+
+    async fn router_response(path: &str) -> String {
+        router_response_with(path, LogBuffer::new(10)).await
+    }
+
+    async fn router_response_with(path: &str, logs: LogBuffer) -> String {
+        let (_status_tx, status_rx) = watch::channel(State::default());
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, status_rx, History::new(dir.path().join("history.json"), 50), logs, None));
+
+        let body = reqwest::get(format!("http://{}{}", addr, path)).await.unwrap()
+            .error_for_status().unwrap()
+            .text().await.unwrap();
+        server.abort();
+        body
+    }
+}
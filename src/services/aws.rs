@@ -0,0 +1,40 @@
+//! The AWS SDK configuration shared by the Route53 provider and the SNS notifier.
+use crate::config::AwsCredentials;
+use crate::error::BoxError;
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_config::{imds, BehaviorVersion, Region, SdkConfig};
+use aws_credential_types::Credentials;
+
+/// Loads the configuration an SDK client is built from.
+///
+/// The configured keys are used as they are. Without them, the standard AWS credential chain
+/// looks in the environment, the shared `~/.aws` files, web identity tokens, ECS and finally the
+/// EC2 instance profile, and refreshes what it finds before it expires.
+///
+/// # Arguments
+/// * `configured` - The keys from `AWS_ACCESS_KEY_ID` and friends, if set.
+/// * `metadata_url` - Base URL of the EC2 instance metadata service, without a trailing slash.
+/// * `endpoint_url` - Base URL of the API the client sends its requests to.
+/// * `region` - The region requests are signed for.
+pub(crate) async fn sdk_config(configured: Option<&AwsCredentials>, metadata_url: &str, endpoint_url: &str, region: &str) -> Result<SdkConfig, BoxError> {
+    let region = Region::new(region.to_string());
+    let loader = aws_config::defaults(BehaviorVersion::latest())
+        .region(region.clone())
+        .endpoint_url(endpoint_url);
+
+    let loader = match configured {
+        Some(keys) => loader.credentials_provider(Credentials::new(
+            &keys.access_key_id,
+            &keys.secret_access_key,
+            keys.session_token.clone(),
+            None,
+            "skysync",
+        )),
+        None => {
+            let imds = imds::Client::builder().endpoint(metadata_url)?.build();
+            let chain = DefaultCredentialsChain::builder().region(region).imds_client(imds).build().await;
+            loader.credentials_provider(chain)
+        }
+    };
+    Ok(loader.load().await)
+}
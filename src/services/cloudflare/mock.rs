@@ -0,0 +1,131 @@
+//! A stand-in for the Cloudflare API, so the service and provider can be tested without live
+//! credentials.
+use crate::config::CloudflareConfig;
+use serde_json::{json, Value};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// A wiremock server answering in the shape of the Cloudflare API.
+pub(crate) struct MockCloudflare {
+    pub server: MockServer,
+}
+
+impl MockCloudflare {
+    pub(crate) async fn start() -> MockCloudflare {
+        MockCloudflare { server: MockServer::start().await }
+    }
+
+    /// Credentials pointing at this server instead of Cloudflare.
+    pub(crate) fn config(&self) -> CloudflareConfig {
+        CloudflareConfig {
+            api_key: "key".to_string(),
+            email: "me@example.com".to_string(),
+            api_token: None,
+            api_url: self.server.uri(),
+            stamp_comment: false,
+            srv: None,
+            https: crate::config::HttpsConfig::default(),
+            record_type: None,
+            // Error pages are answered once, so the tests can count the requests
+            retry: crate::retry::RetryPolicy { retries: 0, delay: std::time::Duration::from_millis(1) },
+            response_limit: crate::config::DEFAULT_CF_RESPONSE_LIMIT,
+            mx_priority: None,
+            proxied: None,
+            ttl: None,
+        }
+    }
+
+    /// Answers every `verb` request to `route` with `response`, expecting exactly `times` of them.
+    pub(crate) async fn respond(&self, verb: &str, route: &str, response: ResponseTemplate, times: u64) {
+        Mock::given(method(verb))
+            .and(path(route))
+            .respond_with(response)
+            .expect(times)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Lists `records` in `zone_id`.
+    pub(crate) async fn list(&self, zone_id: &str, records: Vec<Value>) {
+        Mock::given(method("GET"))
+            .and(path(format!("/zones/{}/dns_records", zone_id)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(listing(records)))
+            .mount(&self.server)
+            .await;
+    }
+}
+
+/// A record in `zone_id`, as Cloudflare returns it. The zone's domain is the last two labels of `name`.
+pub(crate) fn record(zone_id: &str, id: &str, name: &str, content: &str) -> Value {
+    let labels: Vec<&str> = name.rsplitn(3, '.').collect();
+    let zone_name = format!("{}.{}", labels[1], labels[0]);
+    json!({
+        "id": id,
+        "zone_id": zone_id,
+        "zone_name": zone_name,
+        "name": name,
+        "type": "A",
+        "content": content,
+        "proxiable": true,
+        "proxied": false,
+        "ttl": 1,
+        "meta": { "auto_added": false, "managed_by_apps": false, "managed_by_argo_tunnel": false },
+        "comment": null,
+        "tags": [],
+        "created_on": "2024-01-01T00:00:00Z",
+        "modified_on": "2024-01-01T00:00:00Z",
+        "comment_modified_on": null
+    })
+}
+
+/// The body of a successful list request.
+pub(crate) fn listing(records: Vec<Value>) -> Value {
+    let count = records.len();
+    json!({
+        "result": records,
+        "success": true,
+        "errors": [],
+        "messages": [],
+        "result_info": { "page": 1, "per_page": 100, "count": count, "total_count": count, "total_pages": 1 }
+    })
+}
+
+/// A successful create or update of `record`.
+pub(crate) fn written(record: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "result": record,
+        "success": true,
+        "errors": [],
+        "messages": []
+    }))
+}
+
+/// A successful batch that patched `records`.
+pub(crate) fn batched(records: Vec<Value>) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "result": { "deletes": [], "patches": records, "puts": [], "posts": [] },
+        "success": true,
+        "errors": [],
+        "messages": []
+    }))
+}
+
+/// A failure Cloudflare reports with a single error.
+pub(crate) fn failure(status: u16, code: i64, message: &str) -> ResponseTemplate {
+    ResponseTemplate::new(status).set_body_json(json!({
+        "result": null,
+        "success": false,
+        "errors": [{ "code": code, "message": message }],
+        "messages": []
+    }))
+}
+
+/// The answer to a client over Cloudflare's rate limit.
+pub(crate) fn rate_limited() -> ResponseTemplate {
+    failure(429, 971, "Please wait and consider throttling your request speed").insert_header("Retry-After", "60")
+}
+
+/// A 5xx error page from in front of the API, which isn't JSON at all.
+pub(crate) fn bad_gateway() -> ResponseTemplate {
+    ResponseTemplate::new(502).set_body_string("<html><body>502 Bad Gateway</body></html>")
+}
@@ -1 +1,4 @@
-pub(crate) mod service;
\ No newline at end of file
+pub(crate) mod provider;
+pub(crate) mod service;
+#[cfg(test)]
+pub(crate) mod mock;
\ No newline at end of file
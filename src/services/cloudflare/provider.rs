@@ -0,0 +1,1008 @@
+use crate::config::{CloudflareConfig, HttpsConfig, ZoneConfig};
+use crate::error::{BoxError, SkySyncError};
+use crate::log::{debug_log, report};
+use crate::provider::{DnsProvider, DnsRecord};
+use crate::services::cloudflare::service::{
+    all_dns_records, batch_update_dns_records, create_dns_record, format_svc_params, parse_svc_params, patch_dns_record, update_dns_records, BatchPatch,
+    verify_token, DnsType, HttpsData, PatchBody, RecordBody, RecordData, SrvData, Struct,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+
+/// The priority MX records are created with, unless `CF_MX_PRIORITY` is set.
+const CREATED_MX_PRIORITY: u16 = 10;
+
+/// Keeps records hosted on Cloudflare in sync, across any number of zones.
+pub struct CloudflareProvider {
+    config: CloudflareConfig,
+    zones: Vec<ZoneConfig>,
+    client: reqwest::Client,
+    /// The `data` of the HTTPS records last listed, by ID, so their other parameters survive an
+    /// update of the IP hint.
+    https: Mutex<HashMap<String, HttpsData>>,
+}
+
+impl CloudflareProvider {
+    pub fn new(config: CloudflareConfig, zones: Vec<ZoneConfig>, client: reqwest::Client) -> CloudflareProvider {
+        CloudflareProvider { config, zones, client, https: Mutex::new(HashMap::new()) }
+    }
+
+    /// Lists records of `record_type` only, or of every type when `None`, instead of following
+    /// `CF_RECORD_TYPE`.
+    ///
+    /// # Returns
+    /// An error if `record_type` isn't one of the types SkySync knows.
+    pub fn with_record_type(mut self, record_type: Option<&str>) -> Result<CloudflareProvider, BoxError> {
+        self.config.record_type = match record_type {
+            Some(name) => Some(DnsType::from_name(&name.to_ascii_uppercase())
+                .ok_or_else(|| format!("Unknown record type {}, expected one of: A, AAAA, CNAME, HTTPS, TXT, SRV, MX", name))?),
+            None => None,
+        };
+        Ok(self)
+    }
+
+    /// Lists the records of one zone, of `dns_type` only or of every type when `None`.
+    async fn list_zone(&self, zone_id: &str, dns_type: Option<DnsType>) -> Result<Vec<DnsRecord>, BoxError> {
+        let result = all_dns_records(&self.client, &self.config, zone_id, dns_type)
+            .await
+            .map_err(|err| format!("zone {}: {}", zone_id, err))?;
+        let mut unknown: Vec<String> = result.iter().flat_map(|record| record.extra.keys().cloned()).collect();
+        unknown.sort();
+        unknown.dedup();
+        if !unknown.is_empty() {
+            debug_log(&format!("Cloudflare listed fields SkySync doesn't know in zone {}, ignoring them: {}", zone_id, unknown.join(", "))).await;
+        }
+
+        let mut https = self.https.lock().unwrap();
+        Ok(result.into_iter().map(|mut record| {
+            if record.zone_id.is_empty() {
+                record.zone_id = zone_id.to_string();
+            }
+            if let Some(data) = listed_https(&record) {
+                https.insert(record.id.clone(), data);
+            }
+            DnsRecord::from(record)
+        }).collect())
+    }
+
+    /// Builds the `PATCH` body pointing `record` at `content`, already encoded for its type.
+    /// `CF_PROXIED` and `CF_TTL` are sent along, so every write also brings them in line.
+    fn update_body<'a>(&'a self, record: &DnsRecord, content: &'a str, stamped: Option<&'a str>) -> Result<PatchBody<'a>, BoxError> {
+        let data = self.record_data(record, content)?;
+        let priority = priority(&self.config, record)?;
+        Ok(PatchBody { ttl: self.config.ttl, proxied: proxied(&self.config, record), ..patch_body(content, stamped, data, priority) })
+    }
+
+    /// Points `record` at `content`, with `comment` replacing its comment when given.
+    async fn patch(&self, record: &DnsRecord, content: &str, comment: Option<&str>) -> Result<(), BoxError> {
+        let content = record_content(&record.r#type, content)?;
+
+        let body = self.update_body(record, &content, comment)?;
+        patch_dns_record(&self.client, &self.config, &record.zone_id, &record.id, &body)
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    /// The structured fields to write for `record` pointed at `content`, for the types that have
+    /// them.
+    ///
+    /// # Returns
+    /// `None` for types written with `content`, or an error if the fields can't be written.
+    fn record_data<'a>(&'a self, record: &DnsRecord, content: &str) -> Result<Option<RecordData<'a>>, BoxError> {
+        if DnsType::from_name(&record.r#type) != Some(DnsType::HTTPS) {
+            return Ok(srv_data(&self.config, record)?.map(RecordData::Srv));
+        }
+        let listed = self.https.lock().unwrap().get(&record.id).cloned();
+        Ok(Some(RecordData::Https(https_data(&self.config.https, listed.as_ref(), &record.name, content)?)))
+    }
+
+    /// Updates the records of one zone with a single batch request.
+    ///
+    /// # Returns
+    /// The result of each update, or an error if the batch was rejected as a whole, in which case
+    /// none of the records changed.
+    async fn batch_update(&self, zone_id: &str, updates: &[(&DnsRecord, &str)]) -> Result<Vec<Result<(), BoxError>>, BoxError> {
+        let now = Utc::now();
+        let prepared = updates.iter()
+            .map(|(record, content)| Ok((record_content(&record.r#type, content)?, self.config.stamp_comment.then(|| stamp(content, now)))))
+            .collect::<Result<Vec<(String, Option<String>)>, BoxError>>()?;
+        let changes = updates.iter().zip(&prepared)
+            .map(|((record, _), (content, stamped))| Ok(BatchPatch { id: &record.id, body: self.update_body(record, content, stamped.as_deref())? }))
+            .collect::<Result<Vec<BatchPatch>, BoxError>>()?;
+
+        let response = batch_update_dns_records(&self.client, &self.config, zone_id, &changes)
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok(updates.iter().map(|(record, _)| {
+            if response.result.patches.iter().any(|patched| patched.id == record.id) {
+                Ok(())
+            } else {
+                Err(format!("{} was left out of the batch response", record.name).into())
+            }
+        }).collect())
+    }
+}
+
+impl From<Struct> for DnsRecord {
+    fn from(record: Struct) -> DnsRecord {
+        let content = match record.r#type.as_str() {
+            "TXT" => decode_txt(&record.content),
+            "HTTPS" => https_hint(&record).unwrap_or_else(|| record.content.clone()),
+            _ => record.content.clone(),
+        };
+        DnsRecord {
+            id: record.id,
+            zone_id: record.zone_id,
+            zone_name: record.zone_name,
+            name: record.name,
+            content,
+            r#type: record.r#type,
+            ttl: record.ttl,
+            proxied: record.proxied,
+            comment: record.comment,
+            tags: record.tags,
+            priority: record.priority,
+        }
+    }
+}
+
+/// The most a single string of a TXT record can hold, per RFC 1035.
+const TXT_CHUNK: usize = 255;
+
+/// Writes a TXT value as quoted strings of at most 255 bytes each, with quotes and backslashes
+/// escaped. Cloudflare joins them back into one value when answering queries.
+///
+/// # Returns
+/// An error if the value has control characters, which TXT records can't carry.
+fn encode_txt(value: &str) -> Result<String, BoxError> {
+    if let Some(control) = value.chars().find(|c| c.is_control()) {
+        return Err(format!("TXT values can't contain the control character {:?}", control).into());
+    }
+
+    let mut chunks = vec![String::new()];
+    for c in value.chars() {
+        if chunks.last().is_some_and(|chunk| chunk.len() + c.len_utf8() > TXT_CHUNK) {
+            chunks.push(String::new());
+        }
+        chunks.last_mut().unwrap().push(c);
+    }
+    let quoted: Vec<String> = chunks.iter()
+        .map(|chunk| format!("\"{}\"", chunk.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    Ok(quoted.join(" "))
+}
+
+/// Reads a TXT value back from its quoted strings, so it compares equal to what was written.
+/// Content that isn't quoted is taken as is.
+fn decode_txt(content: &str) -> String {
+    let content = content.trim();
+    if !content.starts_with('"') {
+        return content.to_string();
+    }
+
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => value.extend(chars.next()),
+            c if quoted => value.push(c),
+            // The whitespace between strings
+            _ => {}
+        }
+    }
+    value
+}
+
+/// The content to write for a record of `record_type`, encoded when it's a TXT record.
+fn record_content(record_type: &str, content: &str) -> Result<String, BoxError> {
+    if record_type == "TXT" {
+        encode_txt(content)
+    } else {
+        Ok(content.to_string())
+    }
+}
+
+/// Returns the comment SkySync writes when `CF_STAMP_COMMENT` is enabled.
+///
+/// # Arguments
+/// * `ip` - The content the record is being pointed at.
+/// * `at` - When the update happens.
+fn stamp(ip: &str, at: DateTime<Utc>) -> String {
+    format!("SkySync: updated to {} on {}", ip, at.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Returns the structured fields to write for SRV records, which have no `content` of their own.
+///
+/// # Returns
+/// `None` for any other record type, or an error if the `CF_SRV_*` settings are missing.
+fn srv_data<'a>(config: &'a CloudflareConfig, record: &DnsRecord) -> Result<Option<SrvData<'a>>, BoxError> {
+    if DnsType::from_name(&record.r#type) != Some(DnsType::SRV) {
+        return Ok(None);
+    }
+    let Some(srv) = &config.srv else {
+        return Err(format!("{} is an SRV record, but CF_SRV_PORT and friends aren't set", record.name).into());
+    };
+
+    Ok(Some(SrvData {
+        service: &srv.service,
+        proto: &srv.proto,
+        priority: srv.priority,
+        weight: srv.weight,
+        port: srv.port,
+        target: &srv.target,
+    }))
+}
+
+/// The `data` of a listed HTTPS record, if it has any Cloudflare documents.
+fn listed_https(record: &Struct) -> Option<HttpsData> {
+    let data = record.data.clone().filter(|_| record.r#type == "HTTPS")?;
+    serde_json::from_value(data).ok()
+}
+
+/// The address an HTTPS record hints at, which is what SkySync keeps it pointed at: the first
+/// `ipv4hint`, or else the first `ipv6hint`.
+fn https_hint(record: &Struct) -> Option<String> {
+    let params = parse_svc_params(&listed_https(record)?.value).ok()?;
+    ["ipv4hint", "ipv6hint"].iter()
+        .find_map(|hint| params.iter().find(|(key, _)| key == hint))
+        .and_then(|(_, value)| value.as_deref())
+        .and_then(|value| value.split(',').next())
+        .map(str::to_string)
+}
+
+/// Returns the `data` to write for an HTTPS record pointed at `content`.
+///
+/// The IP goes in `ipv4hint` or `ipv6hint`, whichever fits it, and the `CF_HTTPS_*` settings
+/// replace the listed fields they cover. Everything else is kept from the record.
+///
+/// # Arguments
+/// * `config` - The `CF_HTTPS_*` settings.
+/// * `listed` - The record's current `data`, `None` when it's being created.
+/// * `name` - The name of the record, for errors.
+/// * `content` - The IP the record is pointed at.
+///
+/// # Returns
+/// An error if `content` isn't an address, or the record is in AliasMode, which has no hints.
+fn https_data(config: &HttpsConfig, listed: Option<&HttpsData>, name: &str, content: &str) -> Result<HttpsData, BoxError> {
+    let hint = match content.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => "ipv4hint",
+        Ok(IpAddr::V6(_)) => "ipv6hint",
+        Err(_) => return Err(format!("{} is an HTTPS record, which needs an address for its hint, not {}", name, content).into()),
+    };
+    let priority = config.priority.or(listed.map(|listed| listed.priority)).unwrap_or(1);
+    if priority == 0 {
+        return Err(format!("{} is an AliasMode HTTPS record, which carries no hints, set CF_HTTPS_PRIORITY", name).into());
+    }
+
+    let mut params = match listed {
+        Some(listed) => parse_svc_params(&listed.value).map_err(|err| format!("{} has parameters SkySync can't read: {}", name, err))?,
+        None => Vec::new(),
+    };
+    for (key, value) in config.params.iter().cloned().chain([(hint.to_string(), Some(content.to_string()))]) {
+        params.retain(|(listed, _)| *listed != key);
+        params.push((key, value));
+    }
+
+    Ok(HttpsData {
+        priority,
+        target: config.target.clone().or(listed.map(|listed| listed.target.clone())).unwrap_or_else(|| ".".to_string()),
+        value: format_svc_params(&params),
+    })
+}
+
+/// Returns the priority to write for a record: `CF_MX_PRIORITY`, or else the one it was listed
+/// with.
+///
+/// # Returns
+/// `None` for types that take no top-level priority, or an error for an MX record that has none.
+fn priority(config: &CloudflareConfig, record: &DnsRecord) -> Result<Option<u16>, BoxError> {
+    if !DnsType::from_name(&record.r#type).is_some_and(DnsType::takes_priority) {
+        return Ok(None);
+    }
+    match config.mx_priority.or(record.priority) {
+        Some(priority) => Ok(Some(priority)),
+        None => Err(format!("{} is an MX record without a priority, set CF_MX_PRIORITY", record.name).into()),
+    }
+}
+
+/// Builds the `PATCH` body for pointing an existing record at new content.
+///
+/// Only the content, or the `data` of SRV and HTTPS records, (and the comment, when stamping, and
+/// the priority of MX records) is sent, so nothing else about the record can be lost.
+fn patch_body<'a>(content: &'a str, stamped: Option<&'a str>, data: Option<RecordData<'a>>, priority: Option<u16>) -> PatchBody<'a> {
+    let content = if data.is_some() { None } else { Some(content) };
+    PatchBody { content, data, ttl: None, proxied: None, comment: stamped, priority }
+}
+
+/// The proxied state `record` should have, from `CF_PROXIED`, for types that can be proxied.
+fn proxied(config: &CloudflareConfig, record: &DnsRecord) -> Option<bool> {
+    config.proxied.filter(|_| DnsType::from_name(&record.r#type).is_some_and(DnsType::proxiable))
+}
+
+/// Builds the `PUT` body that rewrites a record with all of its current fields.
+///
+/// # Arguments
+/// * `record` - The full desired state of the record.
+/// * `name` - The full name of the record, since Cloudflare doesn't take `@` on `PUT`.
+/// * `comment` - The comment to write, usually the existing one.
+/// * `data` - The SRV or HTTPS fields, which replace `content` for those records.
+/// * `priority` - The priority of MX records, `None` for any other type.
+fn replace_body<'a>(record: &'a DnsRecord, name: &'a str, comment: Option<&'a str>, data: Option<RecordData<'a>>, priority: Option<u16>) -> RecordBody<'a> {
+    RecordBody {
+        r#type: DnsType::from_name(&record.r#type).unwrap_or(DnsType::A),
+        name,
+        content: if data.is_some() { None } else { Some(&record.content) },
+        data,
+        priority,
+        ttl: record.ttl,
+        proxied: record.proxied,
+        comment,
+        tags: &record.tags,
+    }
+}
+
+#[async_trait]
+impl DnsProvider for CloudflareProvider {
+    fn name(&self) -> &'static str {
+        "cloudflare"
+    }
+
+    fn drifted(&self, record: &DnsRecord) -> bool {
+        proxied(&self.config, record).is_some_and(|proxied| proxied != record.proxied)
+            || self.config.ttl.is_some_and(|ttl| ttl != record.ttl)
+    }
+
+    /// The DNS records page of the record's zone. The dashboard addresses zones by name under
+    /// an account, which `:account` leaves for it to pick, so the token needs no account access.
+    fn dashboard_url(&self, record: &DnsRecord) -> Option<String> {
+        if record.zone_name.is_empty() {
+            return None;
+        }
+        Some(format!("https://dash.cloudflare.com/?to=/:account/{}/dns/records", record.zone_name))
+    }
+
+    /// Asks Cloudflare whether `CF_API_TOKEN` is still active. Cloudflare doesn't tell a token
+    /// its own permissions, so a token that can read the records but not edit them is only caught
+    /// by its first write. The legacy email and key can't be verified on their own, and are left
+    /// to the listing.
+    async fn check_credentials(&self) -> Result<(), BoxError> {
+        if self.config.api_token.is_none() {
+            return Ok(());
+        }
+        let token = verify_token(&self.client, &self.config)
+            .await
+            .map_err(|err| format!("Cloudflare rejected CF_API_TOKEN, check that it was copied whole: {}", err))?;
+        if token.status != "active" {
+            let expired = token.expires_on.map(|at| format!(" (expires on {})", at)).unwrap_or_default();
+            return Err(format!("CF_API_TOKEN is {}{}, create a new one with the Zone / DNS / Edit permission", token.status, expired).into());
+        }
+        Ok(())
+    }
+
+    async fn list_records(&self) -> Result<Vec<DnsRecord>, BoxError> {
+        let mut records = Vec::new();
+        for zone in &self.zones {
+            records.extend(self.list_zone(&zone.zone_id, self.config.record_type).await?);
+        }
+        Ok(records)
+    }
+
+    /// Only lists the zone `name` is configured in, and only records of `record_type` when it's
+    /// a type SkySync knows.
+    async fn find_record(&self, name: &str, record_type: &str) -> Result<Option<DnsRecord>, SkySyncError> {
+        let zones: Vec<&ZoneConfig> = match self.zones.iter().find(|zone| zone.records.iter().any(|record| record == name)) {
+            Some(zone) => vec![zone],
+            None => self.zones.iter().collect(),
+        };
+        let dns_type = DnsType::from_name(&record_type.to_ascii_uppercase());
+
+        for zone in zones {
+            let records = self.list_zone(&zone.zone_id, dns_type).await.map_err(SkySyncError::Provider)?;
+            if let Some(record) = records.into_iter().find(|record| record.matches(name) && record.r#type.eq_ignore_ascii_case(record_type)) {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn update_record(&self, record: &DnsRecord, content: &str) -> Result<(), BoxError> {
+        let stamped = self.config.stamp_comment.then(|| stamp(content, Utc::now()));
+        self.patch(record, content, stamped.as_deref()).await
+    }
+
+    async fn update_record_with_comment(&self, record: &DnsRecord, content: &str, comment: &str) -> Result<(), BoxError> {
+        self.patch(record, content, Some(comment)).await
+    }
+
+    /// Zones with more than one record to update are written with one batch request each, and
+    /// record by record should the batch be rejected.
+    async fn update_records(&self, updates: &[(&DnsRecord, &str)]) -> Option<Vec<Result<(), BoxError>>> {
+        let mut zones: Vec<&str> = Vec::new();
+        for (record, _) in updates {
+            if !zones.contains(&record.zone_id.as_str()) {
+                zones.push(&record.zone_id);
+            }
+        }
+
+        let mut results: Vec<Option<Result<(), BoxError>>> = updates.iter().map(|_| None).collect();
+        for zone_id in zones {
+            let indices: Vec<usize> = (0..updates.len()).filter(|&i| updates[i].0.zone_id == zone_id).collect();
+            if indices.len() > 1 {
+                let batch: Vec<(&DnsRecord, &str)> = indices.iter().map(|&i| updates[i]).collect();
+                match self.batch_update(zone_id, &batch).await {
+                    Ok(written) => {
+                        for (i, result) in indices.into_iter().zip(written) {
+                            results[i] = Some(result);
+                        }
+                        continue;
+                    }
+                    Err(err) => report(&format!("Batch update of zone {} failed, updating its records one by one: {}", zone_id, err)).await,
+                }
+            }
+            for i in indices {
+                results[i] = Some(self.update_record(updates[i].0, updates[i].1).await);
+            }
+        }
+        // Every update belongs to exactly one zone, so each has its result
+        Some(results.into_iter().flatten().collect())
+    }
+
+    async fn replace_record(&self, record: &DnsRecord) -> Result<(), BoxError> {
+        let stamped = self.config.stamp_comment.then(|| stamp(&record.content, Utc::now()));
+        let comment = stamped.as_deref().or(record.comment.as_deref());
+
+        let data = self.record_data(record, &record.content)?;
+        let priority = priority(&self.config, record)?;
+        let name = record.fqdn();
+        let record = DnsRecord { content: record_content(&record.r#type, &record.content)?, ..record.clone() };
+
+        update_dns_records(&self.client, &self.config, &record.zone_id, &record.id, &replace_body(&record, &name, comment, data, priority))
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    async fn create_record(&self, name: &str, content: &str) -> Result<(), BoxError> {
+        let stamped = self.config.stamp_comment.then(|| stamp(content, Utc::now()));
+        // Records with their own source may be given an IPv6 address without CF_RECORD_TYPE
+        let r#type = self.config.record_type
+            .unwrap_or(if content.parse::<Ipv6Addr>().is_ok() { DnsType::AAAA } else { DnsType::A });
+        let data = match r#type {
+            DnsType::HTTPS => Some(RecordData::Https(https_data(&self.config.https, None, name, content)?)),
+            _ => None,
+        };
+        let content = if r#type == DnsType::TXT { encode_txt(content)? } else { content.to_string() };
+        let body = RecordBody {
+            r#type,
+            name,
+            content: if data.is_some() { None } else { Some(&content) },
+            data,
+            priority: r#type.takes_priority().then(|| self.config.mx_priority.unwrap_or(CREATED_MX_PRIORITY)),
+            ttl: self.config.ttl.unwrap_or(1),
+            proxied: self.config.proxied.is_some_and(|proxied| proxied && r#type.proxiable()),
+            comment: stamped.as_deref(),
+            tags: &[],
+        };
+
+        let Some(zone) = self.zones.iter().find(|zone| zone.records.iter().any(|record| record == name)) else {
+            return Err(format!("{} isn't listed in any configured zone", name).into());
+        };
+
+        create_dns_record(&self.client, &self.config, &zone.zone_id, &body).await.map_err(|err| err.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{SrvConfig, DEFAULT_CF_API_URL, DEFAULT_CF_RESPONSE_LIMIT, DEFAULT_CF_RETRIES, DEFAULT_CF_RETRY_DELAY};
+    use crate::retry::RetryPolicy;
+    use crate::sync::tests::{test_config, RecordingNotifier, StaticIp};
+    use crate::sync::{Syncer, State, TickOutcome};
+    use serde_json::json;
+    use crate::services::cloudflare::mock::{self, MockCloudflare};
+    use wiremock::matchers::{body_json, body_partial_json, method, path, query_param};
+    use wiremock::Mock;
+
+    /// Mounts the list and update endpoints of a zone holding a single stale record.
+    async fn mock_zone(cloudflare: &MockCloudflare, zone_id: &str, id: &str, name: &str) {
+        cloudflare.list(zone_id, vec![mock::record(zone_id, id, name, "1.1.1.1")]).await;
+        Mock::given(method("PATCH"))
+            .and(path(format!("/zones/{}/dns_records/{}", zone_id, id)))
+            .and(body_json(json!({ "content": "2.2.2.2" })))
+            .respond_with(mock::written(mock::record(zone_id, id, name, "2.2.2.2")))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+    }
+
+    /// A record as returned by the Cloudflare list endpoint.
+    fn listed_record() -> DnsRecord {
+        let record: Struct = serde_json::from_value(json!({
+            "id": "abc",
+            "zone_id": "zone",
+            "zone_name": "example.com",
+            "name": "home.example.com",
+            "type": "A",
+            "content": "1.1.1.1",
+            "proxiable": true,
+            "proxied": true,
+            "ttl": 1,
+            "meta": { "auto_added": false, "managed_by_apps": false, "managed_by_argo_tunnel": false },
+            "comment": "my home router",
+            "tags": ["home", "owner:me"],
+            "created_on": "2024-01-01T00:00:00Z",
+            "modified_on": "2024-01-01T00:00:00Z",
+            "comment_modified_on": null
+        })).unwrap();
+        DnsRecord::from(record)
+    }
+
+    /// Tests that the PATCH body carries only the new content.
+    #[test]
+    fn test_patch_body_only_content() {
+        let body = serde_json::to_value(patch_body("2.2.2.2", None, None, None)).unwrap();
+        assert_eq!(body, json!({ "content": "2.2.2.2" }));
+    }
+
+    /// Tests that stamping adds just the comment to the PATCH body.
+    #[test]
+    fn test_patch_body_stamps_comment() {
+        let at = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678Z").unwrap().to_utc();
+        let stamped = stamp("2.2.2.2", at);
+        let body = serde_json::to_value(patch_body("2.2.2.2", Some(&stamped), None, None)).unwrap();
+
+        assert_eq!(body, json!({ "content": "2.2.2.2", "comment": "SkySync: updated to 2.2.2.2 on 2024-01-02T03:04:05Z" }));
+    }
+
+    /// Tests that updates only send a comment when `CF_STAMP_COMMENT` is enabled.
+    #[tokio::test]
+    async fn test_update_stamps_comment_when_enabled() {
+        for stamp_comment in [false, true] {
+            let cloudflare = MockCloudflare::start().await;
+            cloudflare.respond("PATCH", "/zones/zone/dns_records/abc", mock::written(mock::record("zone", "abc", "home.example.com", "2.2.2.2")), 1).await;
+
+            let mut config = test_config();
+            config.cloudflare.api_url = cloudflare.server.uri();
+            config.cloudflare.stamp_comment = stamp_comment;
+            let provider = CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), reqwest::Client::new());
+
+            let record = DnsRecord { id: "abc".to_string(), ..listed_record() };
+            provider.update_record(&record, "2.2.2.2").await.unwrap();
+
+            let requests = cloudflare.server.received_requests().await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+            match body.get("comment") {
+                Some(comment) if stamp_comment => assert!(comment.as_str().unwrap().starts_with("SkySync: updated to 2.2.2.2 on "), "{}", comment),
+                comment => assert_eq!(comment, None, "stamp_comment: {}", stamp_comment),
+            }
+        }
+    }
+
+    /// Tests that comment, tags and the proxied flag survive a round-trip replacement.
+    #[test]
+    fn test_replace_body_preserves_fields() {
+        let record = DnsRecord { content: "2.2.2.2".to_string(), ..listed_record() };
+        let body = serde_json::to_value(replace_body(&record, &record.name, record.comment.as_deref(), None, None)).unwrap();
+
+        assert_eq!(body["content"], "2.2.2.2");
+        assert_eq!(body["comment"], "my home router");
+        assert_eq!(body["tags"], json!(["home", "owner:me"]));
+        assert_eq!(body["proxied"], true);
+    }
+
+    /// Tests that MX records are written with their listed priority, or `CF_MX_PRIORITY` when
+    /// set, and that no other type is sent one.
+    #[test]
+    fn test_mx_body_includes_priority() {
+        let mut config = test_config().cloudflare;
+        let record = DnsRecord { r#type: "MX".to_string(), content: "mail.example.com".to_string(), priority: Some(20), ..listed_record() };
+
+        let listed = priority(&config, &record).unwrap();
+        let body = serde_json::to_value(replace_body(&record, &record.name, None, None, listed)).unwrap();
+        assert_eq!((&body["type"], &body["content"], &body["priority"]), (&json!("MX"), &json!("mail.example.com"), &json!(20)));
+
+        config.mx_priority = Some(5);
+        let body = serde_json::to_value(patch_body("mail.example.com", None, None, priority(&config, &record).unwrap())).unwrap();
+        assert_eq!(body, json!({ "content": "mail.example.com", "priority": 5 }));
+
+        let a_record = DnsRecord { priority: Some(20), ..listed_record() };
+        assert_eq!(priority(&config, &a_record).unwrap(), None);
+        config.mx_priority = None;
+        assert!(priority(&config, &DnsRecord { priority: None, ..record }).is_err());
+    }
+
+    /// Tests that SRV records are written with Cloudflare's structured `data` instead of `content`.
+    #[test]
+    fn test_srv_body_uses_data() {
+        let config = CloudflareConfig {
+            api_key: "key".to_string(),
+            email: "me@example.com".to_string(),
+            api_token: None,
+            api_url: DEFAULT_CF_API_URL.to_string(),
+            stamp_comment: false,
+            srv: Some(SrvConfig {
+                service: "_sip".to_string(),
+                proto: "_udp".to_string(),
+                priority: 10,
+                weight: 5,
+                port: 5060,
+                target: "home.example.com".to_string(),
+            }),
+            https: HttpsConfig::default(),
+            record_type: None,
+            retry: RetryPolicy { retries: DEFAULT_CF_RETRIES, delay: DEFAULT_CF_RETRY_DELAY },
+            response_limit: DEFAULT_CF_RESPONSE_LIMIT,
+            mx_priority: None,
+            proxied: None,
+            ttl: None,
+        };
+        let record = DnsRecord { name: "_sip._udp.example.com".to_string(), r#type: "SRV".to_string(), ..listed_record() };
+        let data = json!({
+            "service": "_sip",
+            "proto": "_udp",
+            "priority": 10,
+            "weight": 5,
+            "port": 5060,
+            "target": "home.example.com"
+        });
+
+        let srv = || srv_data(&config, &record).unwrap().map(RecordData::Srv);
+        let replaced = serde_json::to_value(replace_body(&record, &record.name, None, srv(), None)).unwrap();
+        assert_eq!(replaced["type"], "SRV");
+        assert_eq!(replaced["data"], data);
+        assert!(replaced.get("content").is_none());
+
+        let patched = serde_json::to_value(patch_body("2.2.2.2", None, srv(), None)).unwrap();
+        assert_eq!(patched, json!({ "data": data }));
+
+        let unconfigured = CloudflareConfig { srv: None, ..config };
+        assert!(srv_data(&unconfigured, &record).is_err());
+    }
+
+    /// Tests that an HTTPS record is listed as the address it hints at, and updated with its
+    /// structured `data`, keeping the parameters that aren't configured.
+    #[tokio::test]
+    async fn test_updates_https_record() {
+        let cloudflare = MockCloudflare::start().await;
+        let mut listed = mock::record("zone", "abc", "home.example.com", r#"1 . alpn="h2" ipv4hint="1.1.1.1" ech="AEn+DQ==""#);
+        listed["type"] = json!("HTTPS");
+        listed["data"] = json!({ "priority": 1, "target": ".", "value": r#"alpn="h2" ipv4hint="1.1.1.1" ech="AEn+DQ==""# });
+        cloudflare.list("zone", vec![listed.clone()]).await;
+        Mock::given(method("PATCH"))
+            .and(path("/zones/zone/dns_records/abc"))
+            .and(body_json(json!({
+                "data": { "priority": 1, "target": "svc.example.com", "value": r#"alpn="h2,h3" port="8443" ipv4hint="2.2.2.2" ech="AEn+DQ==""# }
+            })))
+            .respond_with(mock::written(listed))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+        let https = HttpsConfig {
+            priority: None,
+            target: Some("svc.example.com".to_string()),
+            params: parse_svc_params("alpn=h2,h3 port=8443").unwrap(),
+        };
+        let config = CloudflareConfig { https, record_type: Some(DnsType::HTTPS), ..cloudflare.config() };
+        let zones = vec![ZoneConfig { zone_id: "zone".to_string(), records: vec!["home.example.com".to_string()] }];
+        let provider = CloudflareProvider::new(config, zones, reqwest::Client::new());
+
+        let records = provider.list_records().await.unwrap();
+        assert_eq!(records[0].content, "1.1.1.1");
+        provider.update_record(&records[0], "2.2.2.2").await.unwrap();
+
+        let aliased = HttpsData { priority: 0, target: "cdn.example.net".to_string(), value: String::new() };
+        assert!(https_data(&HttpsConfig::default(), Some(&aliased), "home.example.com", "2.2.2.2").is_err());
+        assert_eq!(
+            https_data(&HttpsConfig::default(), None, "home.example.com", "2001:db8::1").unwrap(),
+            HttpsData { priority: 1, target: ".".to_string(), value: r#"ipv6hint="2001:db8::1""#.to_string() }
+        );
+    }
+
+    /// Tests that a TXT value over 255 bytes is split into quoted strings, and reads back whole.
+    #[test]
+    fn test_txt_chunks_long_values() {
+        let value = format!("v=DKIM1; k=rsa; p={}", "A".repeat(300));
+        let encoded = encode_txt(&value).unwrap();
+
+        assert_eq!(encoded, format!("\"{}\" \"{}\"", &value[..TXT_CHUNK], &value[TXT_CHUNK..]));
+        assert_eq!(decode_txt(&encoded), value);
+    }
+
+    /// Tests that quotes and backslashes are escaped, control characters are refused, and plain
+    /// content is read as is.
+    #[test]
+    fn test_txt_escaping() {
+        let encoded = encode_txt(r#"say "hi" \o/"#).unwrap();
+
+        assert_eq!(encoded, r#""say \"hi\" \\o/""#);
+        assert_eq!(decode_txt(&encoded), r#"say "hi" \o/"#);
+        assert!(encode_txt("line\nbreak").is_err());
+        assert_eq!(decode_txt("google-site-verification=abc"), "google-site-verification=abc");
+    }
+
+    /// Tests that TXT updates are sent encoded, and that the listed value is compared decoded.
+    #[tokio::test]
+    async fn test_updates_txt_record() {
+        let value = "x".repeat(300);
+        let cloudflare = MockCloudflare::start().await;
+        Mock::given(method("PATCH"))
+            .and(path("/zones/zone/dns_records/abc"))
+            .and(body_json(json!({ "content": format!("\"{}\" \"{}\"", "x".repeat(255), "x".repeat(45)) })))
+            .respond_with(mock::written(mock::record("zone", "abc", "home.example.com", "2.2.2.2")))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+        let provider = CloudflareProvider::new(cloudflare.config(), Vec::new(), reqwest::Client::new());
+
+        let record = DnsRecord { r#type: "TXT".to_string(), ..listed_record() };
+        provider.update_record(&record, &value).await.unwrap();
+
+        let mut listed = mock::record("zone", "abc", "home.example.com", &encode_txt(&value).unwrap());
+        listed["type"] = json!("TXT");
+        let record = DnsRecord::from(serde_json::from_value::<Struct>(listed).unwrap());
+        assert_eq!(record.content, value);
+    }
+
+    /// Tests that a proxied record has its origin updated with a body that leaves the proxied
+    /// flag alone, and that the notification says it's proxied.
+    #[tokio::test]
+    async fn test_updates_proxied_origin() {
+        let cloudflare = MockCloudflare::start().await;
+        let mut proxied = mock::record("zone", "abc", "home.example.com", "1.1.1.1");
+        proxied["proxied"] = json!(true);
+        cloudflare.list("zone", vec![proxied.clone()]).await;
+        proxied["content"] = json!("2.2.2.2");
+        Mock::given(method("PATCH"))
+            .and(path("/zones/zone/dns_records/abc"))
+            .and(body_json(json!({ "content": "2.2.2.2" })))
+            .respond_with(mock::written(proxied))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+
+        let mut config = test_config();
+        config.cloudflare.api_url = cloudflare.server.uri();
+        let provider = CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), reqwest::Client::new());
+        let notifier = RecordingNotifier::default();
+        let syncer = Syncer::new(Box::new(provider), Box::new(StaticIp("2.2.2.2")), vec![Box::new(notifier.clone())]);
+        let summary = syncer.run_once(&config, &mut State::default()).await.unwrap();
+
+        assert_eq!(summary.records[0].result.as_ref().unwrap(), &TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        assert!(notifier.events.lock().unwrap()[0].proxied);
+    }
+
+    /// Tests that stale records sharing a zone are updated with one batch request, and record by
+    /// record when Cloudflare rejects the batch.
+    #[tokio::test]
+    async fn test_batches_updates_per_zone() {
+        for rejected in [false, true] {
+            let cloudflare = MockCloudflare::start().await;
+            cloudflare.list("zone", vec![
+                mock::record("zone", "abc", "home.example.com", "1.1.1.1"),
+                mock::record("zone", "def", "www.example.com", "1.1.1.1"),
+            ]).await;
+            let updated = vec![mock::record("zone", "abc", "home.example.com", "2.2.2.2"), mock::record("zone", "def", "www.example.com", "2.2.2.2")];
+            Mock::given(method("POST"))
+                .and(path("/zones/zone/dns_records/batch"))
+                .and(body_json(json!({ "patches": [{ "id": "abc", "content": "2.2.2.2" }, { "id": "def", "content": "2.2.2.2" }] })))
+                .respond_with(if rejected { mock::failure(400, 1004, "DNS Validation Error") } else { mock::batched(updated.clone()) })
+                .expect(1)
+                .mount(&cloudflare.server)
+                .await;
+            for record in updated {
+                let id = record["id"].as_str().unwrap().to_string();
+                cloudflare.respond("PATCH", &format!("/zones/zone/dns_records/{}", id), mock::written(record), u64::from(rejected)).await;
+            }
+
+            let mut config = test_config();
+            config.zones = vec![ZoneConfig { zone_id: "zone".to_string(), records: vec!["home.example.com".to_string(), "www.example.com".to_string()] }];
+            config.cloudflare.api_url = cloudflare.server.uri();
+            let provider = CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), reqwest::Client::new());
+            let syncer = Syncer::new(Box::new(provider), Box::new(StaticIp("2.2.2.2")), vec![Box::new(RecordingNotifier::default())]);
+            let summary = syncer.run_once(&config, &mut State::default()).await.unwrap();
+
+            let updated = TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() };
+            assert!(summary.records.iter().all(|record| record.result.as_ref().unwrap() == &updated), "{}", summary);
+        }
+    }
+
+    /// Tests that a record whose proxied state or TTL differs from `CF_PROXIED` and `CF_TTL` is
+    /// rewritten with them even though it already points at the public IP, and then left alone.
+    #[tokio::test]
+    async fn test_enforces_proxied_and_ttl() {
+        let cloudflare = MockCloudflare::start().await;
+        cloudflare.list("zone", vec![mock::record("zone", "abc", "home.example.com", "2.2.2.2")]).await;
+        Mock::given(method("PATCH"))
+            .and(path("/zones/zone/dns_records/abc"))
+            .and(body_json(json!({ "content": "2.2.2.2", "proxied": true, "ttl": 300 })))
+            .respond_with(mock::written(mock::record("zone", "abc", "home.example.com", "2.2.2.2")))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+
+        let mut config = test_config();
+        config.cloudflare = CloudflareConfig { proxied: Some(true), ttl: Some(300), ..cloudflare.config() };
+        let provider = CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), reqwest::Client::new());
+        let notifier = RecordingNotifier::default();
+        let syncer = Syncer::new(Box::new(provider), Box::new(StaticIp("2.2.2.2")), vec![Box::new(notifier.clone())]);
+        let summary = syncer.run_once(&config, &mut State::default()).await.unwrap();
+
+        assert_eq!(summary.records[0].result.as_ref().unwrap(), &TickOutcome::Reconfigured { ip: "2.2.2.2".to_string() });
+        assert!(notifier.events.lock().unwrap().is_empty());
+
+        let provider = CloudflareProvider::new(cloudflare.config(), Vec::new(), reqwest::Client::new());
+        let enforced = CloudflareProvider::new(CloudflareConfig { proxied: Some(true), ttl: Some(300), ..cloudflare.config() }, Vec::new(), reqwest::Client::new());
+        let record = DnsRecord { proxied: true, ttl: 300, ..listed_record() };
+        assert!(!provider.drifted(&listed_record()));
+        assert!(enforced.drifted(&listed_record()));
+        assert!(!enforced.drifted(&record));
+        // TXT records can't be proxied, so only their TTL is compared
+        assert!(!enforced.drifted(&DnsRecord { r#type: "TXT".to_string(), proxied: false, ..record }));
+    }
+
+    /// Tests that `CF_RECORD_TYPE` filters the listing and is the type of created records.
+    #[tokio::test]
+    async fn test_record_type_filters_and_creates() {
+        let cloudflare = MockCloudflare::start().await;
+        Mock::given(method("GET"))
+            .and(path("/zones/zone/dns_records"))
+            .and(query_param("type", "AAAA"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(mock::listing(Vec::new())))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/zones/zone/dns_records"))
+            .and(body_partial_json(json!({ "type": "AAAA", "name": "home.example.com", "content": "2001:db8::1" })))
+            .respond_with(mock::written(mock::record("zone", "new", "home.example.com", "2001:db8::1")))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+
+        let config = CloudflareConfig { record_type: Some(DnsType::AAAA), ..cloudflare.config() };
+        let zones = vec![ZoneConfig { zone_id: "zone".to_string(), records: vec!["home.example.com".to_string()] }];
+        let provider = CloudflareProvider::new(config, zones, reqwest::Client::new());
+
+        assert!(provider.list_records().await.unwrap().is_empty());
+        provider.create_record("home.example.com", "2001:db8::1").await.unwrap();
+    }
+
+    /// Tests that an apex record configured as `@` is found under its zone name, updated, and
+    /// rewritten with its full name.
+    #[tokio::test]
+    async fn test_updates_apex_record() {
+        let cloudflare = MockCloudflare::start().await;
+        cloudflare.list("zone", vec![mock::record("zone", "apex", "example.com", "1.1.1.1")]).await;
+        cloudflare.respond("PATCH", "/zones/zone/dns_records/apex", mock::written(mock::record("zone", "apex", "example.com", "2.2.2.2")), 1).await;
+        Mock::given(method("PUT"))
+            .and(path("/zones/zone/dns_records/apex"))
+            .and(body_partial_json(json!({ "name": "example.com" })))
+            .respond_with(mock::written(mock::record("zone", "apex", "example.com", "2.2.2.2")))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+
+        let mut config = test_config();
+        config.zones = vec![ZoneConfig { zone_id: "zone".to_string(), records: vec!["@".to_string()] }];
+        config.cloudflare.api_url = cloudflare.server.uri();
+
+        let provider = CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), reqwest::Client::new());
+        let syncer = Syncer::new(Box::new(provider), Box::new(StaticIp("2.2.2.2")), Vec::new());
+        let outcome = &syncer.run_once(&config, &mut State::default()).await.unwrap().records[0];
+        assert_eq!(outcome.result.as_ref().unwrap(), &TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+
+        let provider = CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), reqwest::Client::new());
+        let record = DnsRecord { id: "apex".to_string(), name: "@".to_string(), ..listed_record() };
+        provider.replace_record(&record).await.unwrap();
+    }
+
+    /// Tests that records in different zones are each listed and updated through their own zone.
+    #[tokio::test]
+    async fn test_updates_records_across_zones() {
+        let cloudflare = MockCloudflare::start().await;
+        mock_zone(&cloudflare, "z1", "r1", "home.example.com").await;
+        mock_zone(&cloudflare, "z2", "r2", "home.example.org").await;
+
+        let mut config = test_config();
+        config.zones = vec![
+            ZoneConfig { zone_id: "z1".to_string(), records: vec!["home.example.com".to_string()] },
+            ZoneConfig { zone_id: "z2".to_string(), records: vec!["home.example.org".to_string()] },
+        ];
+        config.cloudflare.api_url = cloudflare.server.uri();
+
+        let provider = CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), reqwest::Client::new());
+        let syncer = Syncer::new(Box::new(provider), Box::new(StaticIp("2.2.2.2")), Vec::new());
+        let summary = syncer.run_once(&config, &mut State::default()).await.unwrap();
+
+        assert_eq!(summary.records.len(), 2);
+        for outcome in &summary.records {
+            assert!(matches!(outcome.result, Ok(TickOutcome::Updated { .. })), "{}", outcome);
+        }
+    }
+
+    /// Tests that looking a record up only lists the zone it's configured in, and that a missing
+    /// record is `None` rather than an error.
+    #[tokio::test]
+    async fn test_find_record() {
+        let cloudflare = MockCloudflare::start().await;
+        cloudflare.list("other", vec![mock::record("other", "def", "www.example.org", "1.1.1.1")]).await;
+        Mock::given(method("GET"))
+            .and(path("/zones/zone/dns_records"))
+            .and(query_param("type", "A"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(mock::listing(vec![mock::record("zone", "abc", "home.example.com", "1.1.1.1")])))
+            .mount(&cloudflare.server)
+            .await;
+
+        let mut config = test_config();
+        config.cloudflare.api_url = cloudflare.server.uri();
+        let zones = vec![
+            ZoneConfig { zone_id: "other".to_string(), records: vec!["www.example.org".to_string()] },
+            ZoneConfig { zone_id: "zone".to_string(), records: vec!["home.example.com".to_string(), "www.example.com".to_string()] },
+        ];
+        let provider = CloudflareProvider::new(config.cloudflare, zones, reqwest::Client::new());
+
+        let found = provider.find_record("home.example.com", "A").await.unwrap().unwrap();
+        assert_eq!((found.id.as_str(), found.zone_id.as_str()), ("abc", "zone"));
+        assert!(provider.find_record("www.example.com", "A").await.unwrap().is_none());
+
+        let requests = cloudflare.server.received_requests().await.unwrap();
+        assert!(requests.iter().all(|request| request.url.path() == "/zones/zone/dns_records"), "{:?}", requests);
+    }
+
+    /// Tests that the dashboard link of a record opens the records of its zone, and that a record
+    /// without a zone name has none.
+    #[test]
+    fn test_dashboard_url() {
+        let provider = CloudflareProvider::new(test_config().cloudflare, Vec::new(), reqwest::Client::new());
+
+        let link = provider.dashboard_url(&listed_record());
+        assert_eq!(link.as_deref(), Some("https://dash.cloudflare.com/?to=/:account/example.com/dns/records"));
+        assert_eq!(provider.dashboard_url(&DnsRecord { zone_name: String::new(), ..listed_record() }), None);
+    }
+
+    /// Tests that an API token is sent as `Bearer` and verified at startup, that a disabled or
+    /// unknown one fails the check, and that the legacy email and key are left to the listing.
+    #[tokio::test]
+    async fn test_check_credentials() {
+        let verification = |status: &str| wiremock::ResponseTemplate::new(200).set_body_json(json!({
+            "result": { "id": "token", "status": status, "expires_on": "2026-10-01T00:00:00Z" },
+            "success": true,
+            "errors": [],
+            "messages": [{ "code": 10000, "message": "This API Token is valid and active", "type": null }]
+        }));
+        let responses = [
+            (verification("active"), None),
+            (verification("expired"), Some("CF_API_TOKEN is expired (expires on 2026-10-01T00:00:00Z)")),
+            (mock::failure(401, 1000, "Invalid API Token"), Some("Cloudflare rejected CF_API_TOKEN, check that it was copied whole: Cloudflare reported a failure (HTTP 401): Invalid API Token (code 1000)")),
+        ];
+        for (response, expected) in responses {
+            let cloudflare = MockCloudflare::start().await;
+            Mock::given(method("GET"))
+                .and(path("/user/tokens/verify"))
+                .and(wiremock::matchers::header("Authorization", "Bearer s3cret"))
+                .respond_with(response)
+                .expect(1)
+                .mount(&cloudflare.server)
+                .await;
+            let config = CloudflareConfig { api_token: Some("s3cret".to_string()), ..cloudflare.config() };
+
+            let checked = CloudflareProvider::new(config, Vec::new(), reqwest::Client::new()).check_credentials().await;
+            match expected {
+                None => checked.unwrap(),
+                Some(expected) => assert!(checked.as_ref().unwrap_err().to_string().starts_with(expected), "{:?}", checked),
+            }
+        }
+
+        let cloudflare = MockCloudflare::start().await;
+        CloudflareProvider::new(cloudflare.config(), Vec::new(), reqwest::Client::new()).check_credentials().await.unwrap();
+        assert!(cloudflare.server.received_requests().await.unwrap().is_empty());
+    }
+}
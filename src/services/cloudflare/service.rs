@@ -1,10 +1,17 @@
-use dotenv::dotenv;
+use crate::config::CloudflareConfig;
+use crate::error::BoxError;
+use crate::http::{self, StatusClass, StatusError};
+use crate::log::{self, debug_log, report};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use tokio::time::sleep;
 
 /// Represents the type of DNS record.
-#[derive(Serialize, Deserialize, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub(crate) enum DnsType {
     A,
     AAAA,
@@ -12,10 +19,211 @@ pub(crate) enum DnsType {
     HTTPS,
     TXT,
     SRV,
+    MX,
+}
+
+impl DnsType {
+    /// Parses the type name Cloudflare lists records with, e.g. `"AAAA"`.
+    pub(crate) fn from_name(name: &str) -> Option<DnsType> {
+        serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+    }
+
+    /// Whether records of this type are written with a top-level `priority`. SRV records send
+    /// theirs in `data`.
+    pub(crate) fn takes_priority(self) -> bool {
+        self == DnsType::MX
+    }
+
+    /// Whether Cloudflare can proxy records of this type.
+    pub(crate) fn proxiable(self) -> bool {
+        matches!(self, DnsType::A | DnsType::AAAA | DnsType::CNAME)
+    }
+}
+
+/// An entry of the `errors` list of a Cloudflare response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct ApiError {
+    pub code: i64,
+    pub message: String,
+}
+
+/// The fields every Cloudflare response has, whatever its `result` looks like.
+#[derive(Deserialize, Debug)]
+struct Envelope {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiError>,
+    /// Notices Cloudflare attaches even to successful responses, e.g. that a setting was ignored.
+    #[serde(default)]
+    messages: Vec<serde_json::Value>,
+}
+
+/// A Cloudflare response that didn't carry what was asked for.
+#[derive(Debug, PartialEq)]
+pub(crate) enum CloudflareError {
+    /// Cloudflare reported the request as failed, e.g. bad credentials or a rate limit.
+    Api { status: u16, errors: Vec<ApiError> },
+    /// The body isn't a Cloudflare API response at all, e.g. an error page from a proxy.
+    Unexpected { status: u16, body: String },
+}
+
+impl fmt::Display for CloudflareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloudflareError::Api { status, errors } if errors.is_empty() => {
+                write!(f, "Cloudflare reported a failure without details (HTTP {})", status)
+            }
+            CloudflareError::Api { status, errors } => {
+                let errors: Vec<String> = errors.iter().map(|err| format!("{} (code {})", err.message, err.code)).collect();
+                write!(f, "Cloudflare reported a failure (HTTP {}): {}", status, errors.join(", "))
+            }
+            CloudflareError::Unexpected { status, body } => write!(f, "Unexpected Cloudflare response (HTTP {}): {}", status, body),
+        }
+    }
+}
+
+impl Error for CloudflareError {}
+
+/// Reads a successful Cloudflare response body as `T`.
+///
+/// # Arguments
+/// * `status` - The HTTP status of the response.
+/// * `body` - The raw body.
+///
+/// # Returns
+/// The parsed body, or a `CloudflareError` with Cloudflare's errors or, when the body isn't the
+/// expected shape, the body itself.
+pub(crate) fn parse_response<T: DeserializeOwned>(status: u16, body: &str) -> std::result::Result<T, CloudflareError> {
+    let unexpected = |reason: String| CloudflareError::Unexpected { status, body: format!("{} ({})", body.trim(), reason) };
+
+    match serde_json::from_str::<Envelope>(body) {
+        // Nothing to tell why, so the body is the only clue
+        Ok(envelope) if !envelope.success && envelope.errors.is_empty() => Err(unexpected("success is false but no errors were given".to_string())),
+        Ok(envelope) if !envelope.success => Err(CloudflareError::Api { status, errors: envelope.errors }),
+        Ok(_) => serde_json::from_str(body).map_err(|err| unexpected(err.to_string())),
+        Err(err) => Err(unexpected(err.to_string())),
+    }
+}
+
+/// The `messages` of a successful response body, which are logged as warnings.
+///
+/// # Returns
+/// Each message as Cloudflare wrote it, with its code when it has one.
+pub(crate) fn warnings(body: &str) -> Vec<String> {
+    let Ok(envelope) = serde_json::from_str::<Envelope>(body) else {
+        return Vec::new();
+    };
+    envelope.messages.iter().filter(|_| envelope.success).map(|message| {
+        match (message.get("message").and_then(|text| text.as_str()), message.get("code")) {
+            (Some(text), Some(code)) => format!("{} (code {})", text, code),
+            (Some(text), None) => text.to_string(),
+            _ => message.as_str().map_or_else(|| message.to_string(), str::to_string),
+        }
+    }).collect()
+}
+
+/// Headers whose values are masked in the debug log.
+const SECRET_HEADERS: [&str; 2] = ["x-auth-key", "authorization"];
+
+/// Sends a request with the Cloudflare credentials, logging it and the raw response with
+/// `--verbose`.
+///
+/// # Returns
+/// The HTTP status and body of a successful response, or a `StatusError` carrying Cloudflare's
+/// errors for a failed one.
+async fn send(config: &CloudflareConfig, request: reqwest::RequestBuilder) -> std::result::Result<(u16, String), BoxError> {
+    let request = match &config.api_token {
+        Some(token) => request.bearer_auth(token),
+        None => request.header("X-Auth-Email", &config.email).header("X-Auth-Key", &config.api_key),
+    };
+    let (client, request) = request.build_split();
+    let request = request?;
+    if log::verbose() {
+        debug_log(&describe_request(&request)).await;
+    }
+
+    let response = client.execute(request).await?;
+    let status = response.status().as_u16();
+    let response = match http::classify_response(response).await {
+        Ok(response) => response,
+        Err(err) => {
+            debug_log(&format!("Cloudflare answered HTTP {}: {}", status, redact_body(config, err.detail.trim()))).await;
+            let explained = parse_response::<serde_json::Value>(status, &err.detail).err();
+            return Err(match explained {
+                Some(explained) => err.explained(explained),
+                None => err,
+            }.into());
+        }
+    };
+    let data = http::read_limited(response, config.response_limit).await?;
+    debug_log(&format!("Cloudflare answered HTTP {}: {}", status, redact_body(config, data.trim()))).await;
+    Ok((status, data))
+}
+
+/// Sends a request and reads its response as `T`, retrying up to `CF_RETRIES` times while its
+/// status is `5xx` or `429`, with a wait of `CF_RETRY_DELAY` doubled after every retry, or as
+/// long as a rate limit asks (up to a minute). Other failures aren't retried, and the error page
+/// itself is never logged, so unlike `RetryPolicy::run` the retries are counted here.
+///
+/// # Returns
+/// The parsed body, or the error of the last attempt.
+async fn request<T: DeserializeOwned>(config: &CloudflareConfig, mut request: reqwest::RequestBuilder) -> std::result::Result<T, Box<dyn Error>> {
+    let mut attempt = 0;
+    loop {
+        // Every body is JSON, so the request can always be cloned
+        let retry = request.try_clone().filter(|_| attempt < config.retry.retries);
+        let err = match send(config, request).await {
+            // A successful status can still carry a failure, which is the same on every attempt
+            Ok((status, data)) => {
+                let parsed = parse_response(status, &data)?;
+                for warning in warnings(&data) {
+                    report(&format!("Cloudflare warned: {}", warning)).await;
+                }
+                return Ok(parsed);
+            }
+            Err(err) => err,
+        };
+        let (Some(&StatusError { status, class, .. }), Some(next)) = (err.downcast_ref::<StatusError>().filter(|failed| failed.is_retryable()), retry) else {
+            return Err(err);
+        };
+
+        attempt += 1;
+        let delay = match class {
+            StatusClass::RateLimited { retry_after: Some(after) } => after.min(http::MAX_RETRY_AFTER),
+            _ => config.retry.delay(attempt),
+        };
+        report(&format!("Cloudflare answered HTTP {}, retrying in {:?} (retry {} of {})", status, delay, attempt, config.retry.retries)).await;
+        sleep(delay).await;
+        request = next;
+    }
+}
+
+/// Masks the credentials in a response body before it's logged, should an error page echo them.
+fn redact_body(config: &CloudflareConfig, body: &str) -> String {
+    let mut body = body.to_string();
+    for secret in [Some(&config.api_key), Some(&config.email), config.api_token.as_ref()].into_iter().flatten() {
+        if !secret.is_empty() {
+            body = body.replace(secret.as_str(), "***");
+        }
+    }
+    body
+}
+
+/// Describes a request for the debug log, with its credentials masked.
+fn describe_request(request: &reqwest::Request) -> String {
+    let headers: Vec<String> = request.headers().iter()
+        .map(|(name, value)| {
+            let value = if SECRET_HEADERS.contains(&name.as_str()) { "***" } else { value.to_str().unwrap_or("<binary>") };
+            format!("{}: {}", name, value)
+        })
+        .collect();
+    let body = request.body().and_then(|body| body.as_bytes()).map(String::from_utf8_lossy).unwrap_or_default();
+    format!("Cloudflare request: {} {} [{}] {}", request.method(), request.url(), headers.join(", "), body)
 }
 
 /// Contains information about the result of a DNS query.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 pub(crate) struct ResultInfo {
     pub page: i64,
     pub per_page: i64,
@@ -25,7 +233,8 @@ pub(crate) struct ResultInfo {
 }
 
 /// Metadata associated with a DNS record.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 pub(crate) struct Meta {
     pub auto_added: bool,
     pub managed_by_apps: bool,
@@ -33,23 +242,46 @@ pub(crate) struct Meta {
 }
 
 /// Represents a DNS record.
+///
+/// Only what SkySync can't do without is required. The rest defaults when Cloudflare leaves it
+/// out, and fields it adds later end up in `extra` instead of failing the listing.
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct Struct {
     pub id: String,
+    /// Left out by newer versions of the API, and then filled in from the zone that was listed.
+    #[serde(default)]
     pub zone_id: String,
+    #[serde(default)]
     pub zone_name: String,
     pub name: String,
     pub r#type: String,
     pub content: String,
+    #[serde(default)]
     pub proxiable: bool,
+    #[serde(default)]
     pub proxied: bool,
     pub ttl: i64,
+    #[serde(default)]
     pub meta: Meta,
+    #[serde(default)]
     pub comment: Option<String>,
+    #[serde(default)]
     pub tags: Vec<String>,
+    /// Only listed for MX, SRV and URI records.
+    #[serde(default)]
+    pub priority: Option<u16>,
+    /// The structured fields of SRV and HTTPS records, whose `content` is only a rendering of them.
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+    #[serde(default)]
     pub created_on: String,
+    #[serde(default)]
     pub modified_on: String,
+    #[serde(default)]
     pub comment_modified_on: Option<String>,
+    /// Whatever else Cloudflare lists, by field name.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 /// Root structure for the DNS records response.
@@ -57,20 +289,36 @@ pub(crate) struct Struct {
 pub(crate) struct Root {
     pub result: Vec<Struct>,
     pub success: bool,
-    pub errors: Vec<String>,
-    pub messages: Vec<String>,
+    #[serde(default)]
+    pub errors: Vec<ApiError>,
+    #[serde(default)]
+    pub messages: Vec<serde_json::Value>,
+    /// Missing when the records fit on one page, which then is the last one.
+    #[serde(default)]
     pub result_info: ResultInfo,
 }
 
-/// Fetches DNS records from Cloudflare.
+/// How many records are asked for per page when listing, Cloudflare's own default.
+pub(crate) const PER_PAGE: i64 = 100;
+
+/// Fetches one page of DNS records from Cloudflare.
 ///
 /// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `config` - The Cloudflare credentials.
+/// * `zone_id` - The zone to list.
 /// * `dns_type` - An optional `DnsType` to filter the DNS records.
+/// * `page` - The page to fetch, starting at 1, of `PER_PAGE` records each.
 ///
 /// # Returns
 /// A `Root` structure containing the DNS records.
-pub(crate) async fn dns_records(dns_type: Option<DnsType>) -> std::result::Result<Root, Box<dyn Error>> {
-    dotenv().ok();
+pub(crate) async fn dns_records(
+    client: &reqwest::Client,
+    config: &CloudflareConfig,
+    zone_id: &str,
+    dns_type: Option<DnsType>,
+    page: i64,
+) -> std::result::Result<Root, Box<dyn Error>> {
     let dns_type = match dns_type {
         Some(dns_type) => match dns_type {
             DnsType::A => "A",
@@ -78,55 +326,101 @@ pub(crate) async fn dns_records(dns_type: Option<DnsType>) -> std::result::Resul
             DnsType::CNAME => "CNAME",
             DnsType::HTTPS => "HTTPS",
             DnsType::TXT => "TXT",
-            DnsType::SRV => "SRV"
+            DnsType::SRV => "SRV",
+            DnsType::MX => "MX"
         },
         None => ""
     };
 
-    let cf_zone_id = env::var("CF_ZONE_ID")?;
-    let uri = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records?type={}", cf_zone_id, dns_type);
-
-    let cf_api_key = env::var("CF_API_KEY")?;
-    let cf_email = env::var("CF_EMAIL")?;
+    let uri = format!("{}/zones/{}/dns_records?type={}&page={}&per_page={}", config.api_url, zone_id, dns_type, page, PER_PAGE);
 
-    let client = reqwest::Client::new();
-    let response = client.get(&uri)
-        .header("X-Auth-Email", cf_email)
-        .header("X-Auth-Key", cf_api_key)
-        .send()
-        .await?;
+    request(config, client.get(&uri)).await
+}
 
-    let data = response.text().await?;
-    let root: Root = serde_json::from_str(&data)?;
-    Ok(root)
+/// Fetches every page of DNS records from Cloudflare.
+///
+/// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `config` - The Cloudflare credentials.
+/// * `zone_id` - The zone to list.
+/// * `dns_type` - An optional `DnsType` to filter the DNS records.
+///
+/// # Returns
+/// The records of all pages, in the order Cloudflare listed them.
+pub(crate) async fn all_dns_records(
+    client: &reqwest::Client,
+    config: &CloudflareConfig,
+    zone_id: &str,
+    dns_type: Option<DnsType>,
+) -> std::result::Result<Vec<Struct>, Box<dyn Error>> {
+    let mut records = Vec::new();
+    let mut page = 1;
+    loop {
+        let root = dns_records(client, config, zone_id, dns_type, page).await?;
+        records.extend(root.result);
+        // An empty zone has no pages at all
+        if page >= root.result_info.total_pages {
+            return Ok(records);
+        }
+        page += 1;
+    }
 }
 
 /// Metadata associated with a DNS record (alternative structure).
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
 pub(crate) struct Meta1 {
     pub auto_added: bool,
     pub managed_by_apps: bool,
     pub managed_by_argo_tunnel: bool,
 }
 
-/// Represents a DNS record (alternative structure).
+/// Represents a DNS record (alternative structure), as lenient as `Struct`.
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Result {
     pub id: String,
+    #[serde(default)]
     pub zone_id: String,
+    #[serde(default)]
     pub zone_name: String,
     pub name: String,
     #[serde(rename = "type")]
     pub r#type: String,
     pub content: String,
+    #[serde(default)]
     pub proxiable: bool,
+    #[serde(default)]
     pub proxied: bool,
     pub ttl: i64,
+    #[serde(default)]
     pub meta: Meta1,
+    #[serde(default)]
     pub comment: Option<String>,
+    #[serde(default)]
     pub tags: Vec<String>,
+    #[serde(default)]
     pub created_on: String,
+    #[serde(default)]
     pub modified_on: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// The body of `GET /user/tokens/verify`.
+#[derive(Deserialize, Debug)]
+pub(crate) struct TokenVerification {
+    pub result: TokenStatus,
+}
+
+/// What Cloudflare says of the API token a request was sent with.
+#[derive(Deserialize, Debug, PartialEq)]
+pub(crate) struct TokenStatus {
+    pub id: String,
+    /// `active`, or `disabled` or `expired` for a token that no longer works.
+    pub status: String,
+    /// When the token stops working, if it does.
+    #[serde(default)]
+    pub expires_on: Option<String>,
 }
 
 /// Response structure for updating DNS records.
@@ -134,87 +428,624 @@ pub(crate) struct Result {
 pub(crate) struct UpdateResponse {
     pub result: Result,
     pub success: bool,
-    pub errors: Vec<String>,
-    pub messages: Vec<String>,
+    #[serde(default)]
+    pub errors: Vec<ApiError>,
+    #[serde(default)]
+    pub messages: Vec<serde_json::Value>,
+}
+
+/// The `data` object of an SRV record, as Cloudflare expects it.
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct SrvData<'a> {
+    pub service: &'a str,
+    pub proto: &'a str,
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: &'a str,
+}
+
+/// The `data` object of an HTTPS record, as Cloudflare lists and expects it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct HttpsData {
+    /// 0 for AliasMode, otherwise the preference among the records of the name.
+    pub priority: u16,
+    /// The host the service is reached at, `.` for the record's own name.
+    pub target: String,
+    /// The service parameters as in a zone file, e.g. `alpn="h2,h3" ipv4hint="192.0.2.1"`.
+    #[serde(default)]
+    pub value: String,
 }
 
-/// Updates a DNS record in Cloudflare.
+/// The structured fields of the record types that are written with `data` instead of `content`.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum RecordData<'a> {
+    Srv(SrvData<'a>),
+    Https(HttpsData),
+}
+
+/// The service parameters Cloudflare accepts on HTTPS records, in the order of their key numbers,
+/// which is the order they're written in.
+pub(crate) const SVC_PARAM_KEYS: [&str; 7] = ["mandatory", "alpn", "no-default-alpn", "port", "ipv4hint", "ech", "ipv6hint"];
+
+/// A service parameter of an HTTPS record: its key, and its value unless it's `no-default-alpn`.
+pub(crate) type SvcParam = (String, Option<String>);
+
+/// Reads service parameters written as in a zone file, e.g. `alpn="h2,h3" port=8443`.
+///
+/// # Returns
+/// The parameters in the order they're given, with their keys in lowercase, or why one of them
+/// isn't something Cloudflare accepts.
+pub(crate) fn parse_svc_params(value: &str) -> std::result::Result<Vec<SvcParam>, String> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(params);
+        }
+
+        let key: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=' && !c.is_whitespace())).collect::<String>().to_ascii_lowercase();
+        let value = match chars.next_if_eq(&'=') {
+            Some(_) => {
+                let quoted = chars.next_if_eq(&'"').is_some();
+                let value: String = std::iter::from_fn(|| chars.next_if(|c| if quoted { *c != '"' } else { !c.is_whitespace() })).collect();
+                if quoted && chars.next().is_none() {
+                    return Err(format!("the value of {} is missing its closing quote", key));
+                }
+                Some(value)
+            }
+            None => None,
+        };
+
+        match (key.as_str(), value.as_deref()) {
+            ("no-default-alpn", None) => {}
+            ("no-default-alpn", Some(_)) => return Err("no-default-alpn takes no value".to_string()),
+            ("port", Some(port)) if port.parse::<u16>().is_err() => return Err(format!("{} isn't a port number", port)),
+            (key, value) if SVC_PARAM_KEYS.contains(&key) => {
+                if value.is_none_or(str::is_empty) {
+                    return Err(format!("{} needs a value", key));
+                }
+            }
+            (key, _) => return Err(format!("unknown parameter {}, expected one of: {}", key, SVC_PARAM_KEYS.join(", "))),
+        }
+        params.push((key, value));
+    }
+}
+
+/// Writes service parameters the way Cloudflare lists them, sorted by key number.
+pub(crate) fn format_svc_params(params: &[SvcParam]) -> String {
+    let mut params = params.to_vec();
+    params.sort_by_key(|(key, _)| SVC_PARAM_KEYS.iter().position(|known| known == key));
+    params.iter()
+        .map(|(key, value)| match value {
+            Some(value) => format!("{}=\"{}\"", key, value),
+            None => key.clone(),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Body sent when creating or replacing a DNS record.
+///
+/// A `PUT` replaces every field that isn't sent, so `comment` and `tags` must be included to
+/// survive an update, and MX records need their `priority`. SRV and HTTPS records send `data`
+/// instead of `content`.
+#[derive(Serialize, Debug)]
+pub(crate) struct RecordBody<'a> {
+    pub r#type: DnsType,
+    pub name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<RecordData<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    pub ttl: i64,
+    pub proxied: bool,
+    pub comment: Option<&'a str>,
+    pub tags: &'a [String],
+}
+
+/// Body sent when only some fields of a DNS record change. Fields left as `None` keep their
+/// current value in Cloudflare.
+#[derive(Serialize, Debug)]
+pub(crate) struct PatchBody<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<RecordData<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+}
+
+/// Updates a DNS record in Cloudflare by replacing it entirely.
 ///
 /// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `config` - The Cloudflare credentials.
+/// * `zone_id` - The zone holding the record.
 /// * `id` - The ID of the DNS record to update.
-/// * `dns_type` - The type of DNS record.
-/// * `name` - The name of the DNS record.
-/// * `content` - The content of the DNS record.
-/// * `ttl` - The TTL (Time To Live) of the DNS record.
-/// * `proxied` - Whether the DNS record is proxied.
+/// * `body` - The full new state of the record.
 ///
 /// # Returns
 /// An `UpdateResponse` structure containing the result of the update operation.
 pub(crate) async fn update_dns_records(
+    client: &reqwest::Client,
+    config: &CloudflareConfig,
+    zone_id: &str,
+    id: &str,
+    body: &RecordBody<'_>,
+) -> std::result::Result<UpdateResponse, Box<dyn Error>> {
+    let uri = format!("{}/zones/{}/dns_records/{}", config.api_url, zone_id, id);
+
+    request(config, client.put(&uri).json(body)).await
+}
+
+/// Partially updates a DNS record in Cloudflare, leaving fields that aren't sent untouched.
+///
+/// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `config` - The Cloudflare credentials.
+/// * `zone_id` - The zone holding the record.
+/// * `id` - The ID of the DNS record to update.
+/// * `body` - The fields to change.
+///
+/// # Returns
+/// An `UpdateResponse` structure containing the updated record.
+pub(crate) async fn patch_dns_record(
+    client: &reqwest::Client,
+    config: &CloudflareConfig,
+    zone_id: &str,
     id: &str,
-    dns_type: DnsType,
-    name: &str,
-    content: &str,
-    ttl: i64,
-    proxied: bool,
-) -> UpdateResponse {
-    dotenv().ok();
-    let uri = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}", env::var("CF_ZONE_ID").expect("Expected a Cloudflare zone id in the environment"), id);
-
-    let cf_api_key = env::var("CF_API_KEY").expect("Expected a Cloudflare API key in the environment");
-    let cf_email = env::var("CF_EMAIL").expect("Expected a Cloudflare email in the environment");
-
-    let client = reqwest::Client::new();
-    let response = client.put(&uri)
-        .header("X-Auth-Email", cf_email)
-        .header("X-Auth-Key", cf_api_key)
-        .json(&serde_json::json!({
-            "type": dns_type,
-            "name": name,
-            "content": content,
-            "ttl": ttl,
-            "proxied": proxied
-        }))
-        .send()
-        .await
-        .expect("Failed to send request");
-
-    let data = response.text().await.unwrap();
-    let root: UpdateResponse = serde_json::from_str(&data).unwrap();
-    root
+    body: &PatchBody<'_>,
+) -> std::result::Result<UpdateResponse, Box<dyn Error>> {
+    let uri = format!("{}/zones/{}/dns_records/{}", config.api_url, zone_id, id);
+
+    request(config, client.patch(&uri).json(body)).await
+}
+
+/// One record of a batch, with the fields to change.
+#[derive(Serialize, Debug)]
+pub(crate) struct BatchPatch<'a> {
+    pub id: &'a str,
+    #[serde(flatten)]
+    pub body: PatchBody<'a>,
+}
+
+/// Body sent to the batch endpoint. Only partial updates are ever batched.
+#[derive(Serialize, Debug)]
+pub(crate) struct BatchBody<'a> {
+    pub patches: &'a [BatchPatch<'a>],
+}
+
+/// What a batch changed, by kind of change.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct BatchResult {
+    #[serde(default)]
+    pub patches: Vec<Result>,
+}
+
+/// Response structure for a batch of DNS record changes.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct BatchResponse {
+    pub result: BatchResult,
+    pub success: bool,
+    pub errors: Vec<ApiError>,
+    pub messages: Vec<serde_json::Value>,
+}
+
+/// Partially updates several DNS records of one zone in a single request.
+///
+/// Cloudflare applies a batch as a whole: either every record is changed or none is.
+///
+/// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `config` - The Cloudflare credentials.
+/// * `zone_id` - The zone holding the records.
+/// * `changes` - The records to update, with the fields to change.
+///
+/// # Returns
+/// A `BatchResponse` structure listing every updated record.
+pub(crate) async fn batch_update_dns_records(
+    client: &reqwest::Client,
+    config: &CloudflareConfig,
+    zone_id: &str,
+    changes: &[BatchPatch<'_>],
+) -> std::result::Result<BatchResponse, Box<dyn Error>> {
+    let uri = format!("{}/zones/{}/dns_records/batch", config.api_url, zone_id);
+
+    request(config, client.post(&uri).json(&BatchBody { patches: changes })).await
+}
+
+/// Creates a DNS record in Cloudflare.
+///
+/// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `config` - The Cloudflare credentials.
+/// * `zone_id` - The zone to create the record in.
+/// * `body` - The record to create.
+///
+/// # Returns
+/// An `UpdateResponse` structure containing the created record.
+pub(crate) async fn create_dns_record(
+    client: &reqwest::Client,
+    config: &CloudflareConfig,
+    zone_id: &str,
+    body: &RecordBody<'_>,
+) -> std::result::Result<UpdateResponse, Box<dyn Error>> {
+    let uri = format!("{}/zones/{}/dns_records", config.api_url, zone_id);
+
+    request(config, client.post(&uri).json(body)).await
+}
+
+/// Asks Cloudflare about the API token the requests are sent with. The answer's message only
+/// says the token is valid, so unlike `request` it isn't reported as a warning.
+///
+/// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `config` - The Cloudflare credentials, with `api_token` set.
+///
+/// # Returns
+/// The token's status, or an error if Cloudflare doesn't know the token at all.
+pub(crate) async fn verify_token(client: &reqwest::Client, config: &CloudflareConfig) -> std::result::Result<TokenStatus, BoxError> {
+    let uri = format!("{}/user/tokens/verify", config.api_url);
+
+    let (status, data) = send(config, client.get(&uri)).await?;
+    Ok(parse_response::<TokenVerification>(status, &data)?.result)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::cloudflare::mock::{bad_gateway, batched, failure, listing, rate_limited, record, written, MockCloudflare};
+    use serde_json::json;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, ResponseTemplate};
 
-    /// Tests the `dns_records` function.
+    fn body<'a>(tags: &'a [String]) -> RecordBody<'a> {
+        RecordBody {
+            r#type: DnsType::A,
+            name: "home.example.com",
+            content: Some("2.2.2.2"),
+            data: None,
+            priority: None,
+            ttl: 1,
+            proxied: false,
+            comment: None,
+            tags,
+        }
+    }
+
+    fn patch() -> PatchBody<'static> {
+        PatchBody { content: Some("2.2.2.2"), data: None, ttl: None, proxied: None, comment: None, priority: None }
+    }
+
+    /// The error responses every endpoint is checked against, with the error they should produce.
+    fn failures() -> Vec<(ResponseTemplate, &'static str)> {
+        vec![
+            (failure(403, 10000, "Authentication error"), "Cloudflare reported a failure (HTTP 403): Authentication error (code 10000)"),
+            // `success` is what tells, even when the status doesn't
+            (failure(200, 9103, "Unknown X-Auth-Key or X-Auth-Email"), "Cloudflare reported a failure (HTTP 200): Unknown X-Auth-Key or X-Auth-Email (code 9103)"),
+            (failure(404, 81044, "Record does not exist."), "Cloudflare reported a failure (HTTP 404): Record does not exist. (code 81044)"),
+            (rate_limited(), "Cloudflare reported a failure (HTTP 429): Please wait and consider throttling your request speed (code 971)"),
+            (failure(500, 10001, "Internal error"), "Cloudflare reported a failure (HTTP 500): Internal error (code 10001)"),
+            (bad_gateway(), "Unexpected Cloudflare response (HTTP 502): <html><body>502 Bad Gateway</body></html>"),
+        ]
+    }
+
+    /// Tests that listing returns the zone's records and sends the credentials, type filter and
+    /// accepted encodings.
     #[tokio::test]
     async fn test_dns_records() {
-        let resp = dns_records(Some(DnsType::A)).await.unwrap();
-        println!("{:?}", resp);
-        assert_eq!(resp.success, true);
+        let cloudflare = MockCloudflare::start().await;
+        cloudflare.list("zone", vec![record("zone", "abc", "home.example.com", "1.1.1.1")]).await;
+
+        let resp = dns_records(&reqwest::Client::new(), &cloudflare.config(), "zone", Some(DnsType::A), 1).await.unwrap();
+        assert!(resp.success);
+        assert_eq!(resp.result.len(), 1);
+        assert_eq!(resp.result[0].content, "1.1.1.1");
+
+        let request = &cloudflare.server.received_requests().await.unwrap()[0];
+        assert_eq!(request.url.query(), Some("type=A&page=1&per_page=100"));
+        assert_eq!(request.headers["X-Auth-Email"], "me@example.com");
+        assert_eq!(request.headers["X-Auth-Key"], "key");
+        assert_eq!(request.headers["Accept-Encoding"], "gzip, br, deflate");
+    }
+
+    /// Tests that requests are built on a base with a path of its own, like a gateway's.
+    #[tokio::test]
+    async fn test_api_base_with_path() {
+        let cloudflare = MockCloudflare::start().await;
+        let config = CloudflareConfig { api_url: format!("{}/gateway/client/v4", cloudflare.server.uri()), ..cloudflare.config() };
+        Mock::given(method("GET"))
+            .and(path("/gateway/client/v4/zones/zone/dns_records"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(listing(vec![record("zone", "abc", "home.example.com", "1.1.1.1")])))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+        cloudflare.respond("PATCH", "/gateway/client/v4/zones/zone/dns_records/abc", written(record("zone", "abc", "home.example.com", "2.2.2.2")), 1).await;
+
+        let client = reqwest::Client::new();
+        assert_eq!(dns_records(&client, &config, "zone", None, 1).await.unwrap().result.len(), 1);
+        patch_dns_record(&client, &config, "zone", "abc", &patch()).await.unwrap();
+    }
+
+    /// Tests that every page of a large zone is fetched.
+    #[tokio::test]
+    async fn test_all_dns_records_pages() {
+        let cloudflare = MockCloudflare::start().await;
+        for page in 1..=2 {
+            let mut body = listing(vec![record("zone", &format!("r{}", page), &format!("{}.example.com", page), "1.1.1.1")]);
+            body["result_info"]["page"] = json!(page);
+            body["result_info"]["total_pages"] = json!(2);
+            Mock::given(method("GET"))
+                .and(path("/zones/zone/dns_records"))
+                .and(query_param("page", page.to_string()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body))
+                .expect(1)
+                .mount(&cloudflare.server)
+                .await;
+        }
+
+        let records = all_dns_records(&reqwest::Client::new(), &cloudflare.config(), "zone", None).await.unwrap();
+        let names: Vec<&str> = records.iter().map(|record| record.name.as_str()).collect();
+        assert_eq!(names, vec!["1.example.com", "2.example.com"]);
+    }
+
+    /// Tests that every failure of the list endpoint is reported as an error.
+    #[tokio::test]
+    async fn test_dns_records_failures() {
+        for (response, expected) in failures() {
+            let cloudflare = MockCloudflare::start().await;
+            cloudflare.respond("GET", "/zones/zone/dns_records", response, 1).await;
+
+            let err = dns_records(&reqwest::Client::new(), &cloudflare.config(), "zone", None, 1).await.unwrap_err();
+            assert!(err.to_string().starts_with(expected), "{}", err);
+        }
     }
 
-    /// Tests the `update_dns_records` function.
+    /// Tests that an HTML error page is retried until the API answers, and that other failures
+    /// and the last error page are reported right away.
+    #[tokio::test]
+    async fn test_retries_error_page() {
+        let cloudflare = MockCloudflare::start().await;
+        Mock::given(method("GET"))
+            .and(path("/zones/zone/dns_records"))
+            .respond_with(bad_gateway())
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+        cloudflare.list("zone", vec![record("zone", "abc", "home.example.com", "1.1.1.1")]).await;
+        let mut config = cloudflare.config();
+        config.retry.retries = 2;
+
+        let resp = dns_records(&reqwest::Client::new(), &config, "zone", None, 1).await.unwrap();
+        assert_eq!(resp.result[0].content, "1.1.1.1");
+
+        let cloudflare = MockCloudflare::start().await;
+        cloudflare.respond("GET", "/zones/zone/dns_records", bad_gateway(), 3).await;
+        cloudflare.respond("PATCH", "/zones/zone/dns_records/abc", failure(400, 9005, "Content for A record is invalid."), 1).await;
+        let mut config = cloudflare.config();
+        config.retry.retries = 2;
+
+        let err = dns_records(&reqwest::Client::new(), &config, "zone", None, 1).await.unwrap_err();
+        assert!(err.to_string().starts_with("Unexpected Cloudflare response (HTTP 502): <html>"), "{}", err);
+        patch_dns_record(&reqwest::Client::new(), &config, "zone", "abc", &patch()).await.err().unwrap();
+    }
+
+    /// Tests that API errors are retried by their status: a `5xx` or a `429` is, after the wait
+    /// the rate limit asks for, while a rejected request isn't.
+    #[tokio::test]
+    async fn test_retries_by_status() {
+        for (response, attempts) in [
+            (failure(500, 10001, "Internal error"), 3),
+            (failure(429, 971, "Please wait and consider throttling your request speed").insert_header("Retry-After", "0"), 3),
+            (failure(403, 10000, "Authentication error"), 1),
+            (failure(404, 81044, "Record does not exist."), 1),
+        ] {
+            let cloudflare = MockCloudflare::start().await;
+            cloudflare.respond("PATCH", "/zones/zone/dns_records/abc", response, attempts).await;
+            let mut config = cloudflare.config();
+            config.retry.retries = 2;
+
+            let err = patch_dns_record(&reqwest::Client::new(), &config, "zone", "abc", &patch()).await.err().unwrap();
+            assert!(err.to_string().starts_with("Cloudflare reported a failure"), "{}", err);
+        }
+    }
+
+    /// Tests that credentials echoed back in a response are masked before it's logged.
+    #[test]
+    fn test_redact_body() {
+        let config = CloudflareConfig { api_key: "super-secret-key".to_string(), ..crate::sync::tests::test_config().cloudflare };
+        assert_eq!(redact_body(&config, "<p>denied super-secret-key for me@example.com</p>"), "<p>denied *** for ***</p>");
+    }
+
+    /// Tests that a replacement is sent as a `PUT` of the full record.
     #[tokio::test]
     async fn test_update_dns_records() {
-        let dns_name = match dns_records(None).await.unwrap() {
-            // Filter by name
-            Root { result, .. } => {
-                result.into_iter().find(|x| x.name == "DOMAIN_NAME").unwrap_or_else(|| panic!("Failed to find DNS record"))
-            }
-        };
+        let cloudflare = MockCloudflare::start().await;
+        cloudflare.respond("PUT", "/zones/zone/dns_records/abc", written(record("zone", "abc", "home.example.com", "2.2.2.2")), 1).await;
 
-        let req = update_dns_records(
-            &dns_name.id,
-            DnsType::A,
-            "DOMAIN_NAME",
-            "192.168.15.112",
-            dns_name.ttl,
-            false,
-        ).await;
+        let resp = update_dns_records(&reqwest::Client::new(), &cloudflare.config(), "zone", "abc", &body(&[])).await.unwrap();
+        assert!(resp.success);
+        assert_eq!(resp.result.content, "2.2.2.2");
 
-        assert_eq!(req.success, true);
+        let request = &cloudflare.server.received_requests().await.unwrap()[0];
+        let sent: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(sent, json!({
+            "type": "A",
+            "name": "home.example.com",
+            "content": "2.2.2.2",
+            "ttl": 1,
+            "proxied": false,
+            "comment": null,
+            "tags": []
+        }));
     }
-}
\ No newline at end of file
+
+    /// Tests that every failure of a replacement is reported as an error instead of panicking.
+    #[tokio::test]
+    async fn test_update_dns_records_failures() {
+        for (response, expected) in failures() {
+            let cloudflare = MockCloudflare::start().await;
+            cloudflare.respond("PUT", "/zones/zone/dns_records/abc", response, 1).await;
+
+            let err = update_dns_records(&reqwest::Client::new(), &cloudflare.config(), "zone", "abc", &body(&[])).await.err().unwrap();
+            assert!(err.to_string().starts_with(expected), "{}", err);
+        }
+    }
+
+    /// Tests that a partial update is sent as a `PATCH` of the changed fields.
+    #[tokio::test]
+    async fn test_patch_dns_record() {
+        let cloudflare = MockCloudflare::start().await;
+        cloudflare.respond("PATCH", "/zones/zone/dns_records/abc", written(record("zone", "abc", "home.example.com", "2.2.2.2")), 1).await;
+
+        let resp = patch_dns_record(&reqwest::Client::new(), &cloudflare.config(), "zone", "abc", &patch()).await.unwrap();
+        assert_eq!(resp.result.content, "2.2.2.2");
+
+        let request = &cloudflare.server.received_requests().await.unwrap()[0];
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&request.body).unwrap(), json!({ "content": "2.2.2.2" }));
+    }
+
+    /// Tests that every failure of a partial update is reported as an error.
+    #[tokio::test]
+    async fn test_patch_dns_record_failures() {
+        for (response, expected) in failures() {
+            let cloudflare = MockCloudflare::start().await;
+            cloudflare.respond("PATCH", "/zones/zone/dns_records/abc", response, 1).await;
+
+            let err = patch_dns_record(&reqwest::Client::new(), &cloudflare.config(), "zone", "abc", &patch()).await.err().unwrap();
+            assert!(err.to_string().starts_with(expected), "{}", err);
+        }
+    }
+
+    /// Tests that a batch sends every change in one `POST` and lists the updated records.
+    #[tokio::test]
+    async fn test_batch_update_dns_records() {
+        let cloudflare = MockCloudflare::start().await;
+        let patched = batched(vec![record("zone", "abc", "home.example.com", "2.2.2.2"), record("zone", "def", "www.example.com", "2.2.2.2")]);
+        cloudflare.respond("POST", "/zones/zone/dns_records/batch", patched, 1).await;
+        let changes = [BatchPatch { id: "abc", body: patch() }, BatchPatch { id: "def", body: patch() }];
+
+        let resp = batch_update_dns_records(&reqwest::Client::new(), &cloudflare.config(), "zone", &changes).await.unwrap();
+        let ids: Vec<&str> = resp.result.patches.iter().map(|record| record.id.as_str()).collect();
+        assert_eq!(ids, vec!["abc", "def"]);
+
+        let request = &cloudflare.server.received_requests().await.unwrap()[0];
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&request.body).unwrap(), json!({
+            "patches": [{ "id": "abc", "content": "2.2.2.2" }, { "id": "def", "content": "2.2.2.2" }]
+        }));
+    }
+
+    /// Tests that a record is created with a `POST` to the zone.
+    #[tokio::test]
+    async fn test_create_dns_record() {
+        let cloudflare = MockCloudflare::start().await;
+        cloudflare.respond("POST", "/zones/zone/dns_records", written(record("zone", "new", "home.example.com", "2.2.2.2")), 1).await;
+
+        let resp = create_dns_record(&reqwest::Client::new(), &cloudflare.config(), "zone", &body(&[])).await.unwrap();
+        assert_eq!(resp.result.id, "new");
+    }
+
+    /// Tests that every failure of a creation is reported as an error.
+    #[tokio::test]
+    async fn test_create_dns_record_failures() {
+        for (response, expected) in failures() {
+            let cloudflare = MockCloudflare::start().await;
+            cloudflare.respond("POST", "/zones/zone/dns_records", response, 1).await;
+
+            let err = create_dns_record(&reqwest::Client::new(), &cloudflare.config(), "zone", &body(&[])).await.err().unwrap();
+            assert!(err.to_string().starts_with(expected), "{}", err);
+        }
+    }
+
+    /// Tests that a failure without errors, and bodies in an unexpected shape, are still reported.
+    #[test]
+    fn test_parse_response_failures() {
+        match parse_response::<UpdateResponse>(200, r#"{"success":false,"errors":[],"messages":[],"result":null}"#) {
+            Err(err @ CloudflareError::Unexpected { status: 200, .. }) => assert_eq!(
+                err.to_string(),
+                r#"Unexpected Cloudflare response (HTTP 200): {"success":false,"errors":[],"messages":[],"result":null} (success is false but no errors were given)"#
+            ),
+            other => panic!("expected an unexpected body error, got {:?}", other.err()),
+        }
+        assert_eq!(
+            CloudflareError::Api { status: 500, errors: Vec::new() }.to_string(),
+            "Cloudflare reported a failure without details (HTTP 500)"
+        );
+
+        match parse_response::<UpdateResponse>(200, r#"{"success":true,"errors":[],"messages":[],"result":{}}"#) {
+            Err(CloudflareError::Unexpected { status: 200, body }) => assert!(body.contains("missing field"), "{}", body),
+            other => panic!("expected an unexpected body error, got {:?}", other.err()),
+        }
+    }
+
+    /// Tests that the messages of a successful response are read as warnings, and those of a
+    /// failed one aren't.
+    #[test]
+    fn test_warnings() {
+        let body = r#"{"success":true,"errors":[],"messages":[{"code":1001,"message":"TTL was raised to 60"},"Proxy setting ignored"],"result":{}}"#;
+        assert_eq!(warnings(body), vec!["TTL was raised to 60 (code 1001)", "Proxy setting ignored"]);
+
+        assert!(warnings(r#"{"success":true,"errors":[],"messages":[],"result":{}}"#).is_empty());
+        assert!(warnings(r#"{"success":false,"errors":[],"messages":["ignored"],"result":null}"#).is_empty());
+        assert!(warnings("<html>Bad gateway</html>").is_empty());
+    }
+
+    /// Tests that a record with fields SkySync doesn't know, and without the optional ones, still
+    /// parses, keeping the unknown fields aside.
+    #[test]
+    fn test_lenient_record() {
+        let body = json!({
+            "success": true,
+            "result": [{
+                "id": "id-home",
+                "name": "home.example.com",
+                "type": "A",
+                "content": "1.1.1.1",
+                "proxied": false,
+                "ttl": 1,
+                "settings": { "ipv4_only": true },
+                "private_routing": false
+            }]
+        });
+
+        let root: Root = serde_json::from_value(body).unwrap();
+
+        let record = &root.result[0];
+        assert_eq!((record.id.as_str(), record.content.as_str(), record.zone_id.as_str()), ("id-home", "1.1.1.1", ""));
+        assert_eq!((record.comment.as_deref(), record.comment_modified_on.as_deref(), record.tags.len()), (None, None, 0));
+        assert_eq!(record.extra.get("settings"), Some(&json!({ "ipv4_only": true })));
+        assert_eq!(record.extra.len(), 2);
+        assert_eq!(root.result_info.total_pages, 0);
+    }
+
+    /// Tests that the logged request masks the credentials but keeps everything else.
+    #[test]
+    fn test_describe_request_redacts_credentials() {
+        let request = reqwest::Client::new().put("https://api.cloudflare.com/client/v4/zones/zone/dns_records/id")
+            .header("X-Auth-Email", "me@example.com")
+            .header("X-Auth-Key", "super-secret-key")
+            .header("Authorization", "Bearer super-secret-token")
+            .json(&json!({ "content": "2.2.2.2" }))
+            .build()
+            .unwrap();
+
+        let logged = describe_request(&request);
+
+        assert!(!logged.contains("super-secret"), "{}", logged);
+        assert!(logged.contains("x-auth-key: ***") && logged.contains("authorization: ***"), "{}", logged);
+        assert!(logged.starts_with("Cloudflare request: PUT https://api.cloudflare.com/client/v4/zones/zone/dns_records/id"), "{}", logged);
+        assert!(logged.contains("x-auth-email: me@example.com") && logged.ends_with(r#"{"content":"2.2.2.2"}"#), "{}", logged);
+    }
+}
@@ -0,0 +1 @@
+pub(crate) mod notification;
\ No newline at end of file
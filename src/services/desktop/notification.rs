@@ -0,0 +1,138 @@
+use crate::error::BoxError;
+use crate::log::report;
+use crate::notifier::{change_lines, circuit_open, degraded, downtime, reconciled, recovered, started, stopped, throttled, EventStatus, Notifier, UpdateEvent};
+use async_trait::async_trait;
+use notify_rust::Notification;
+
+/// Raises a native OS notification on the machine SkySync runs on.
+pub struct DesktopNotifier;
+
+/// An error as plain text of the body. Notification servers following the freedesktop spec may
+/// read the body as markup, which elsewhere is shown as-is.
+fn escape(error: &str) -> String {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        error.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    } else {
+        error.to_string()
+    }
+}
+
+/// Builds the notification title and body for an event.
+fn message(event: &UpdateEvent) -> (String, String) {
+    let (summary, body) = match &event.status {
+        EventStatus::Success if !event.changes.is_empty() => ("SkySync".to_string(), change_lines(&event.changes).join("\n")),
+        EventStatus::Success => (
+            "SkySync".to_string(),
+            format!("IP changed to {} ({})", event.new_ip, event.domain),
+        ),
+        EventStatus::Failure(error) => (
+            "SkySync: update failed".to_string(),
+            format!("Failed to point {} at {}: {}", event.domain, event.new_ip, escape(error)),
+        ),
+        EventStatus::Escalated { error, minutes } => (
+            "SkySync: still failing".to_string(),
+            format!("{} has been failing for {} minutes: {}", event.domain, minutes, escape(error)),
+        ),
+        EventStatus::Resolved { minutes } => (
+            "SkySync: resolved".to_string(),
+            format!("{} is back in sync after {} minutes", event.domain, minutes),
+        ),
+        EventStatus::Changed => (
+            "SkySync: IP changed".to_string(),
+            format!("Public IP changed to {} ({} not updated)", event.new_ip, event.domain),
+        ),
+        EventStatus::Reconciled { updated, current, failed } => (
+            "SkySync: records reconciled".to_string(),
+            format!("{}, now pointing at {}", reconciled(*updated, *current, *failed), event.new_ip),
+        ),
+        EventStatus::Downtime { minutes } => (
+            "SkySync: was down".to_string(),
+            format!("{}, {} may have missed changes", downtime(*minutes), event.domain),
+        ),
+        EventStatus::Recreated => (
+            "SkySync: record recreated".to_string(),
+            format!("{} had been deleted and was recreated at {}", event.domain, event.new_ip),
+        ),
+        EventStatus::Drifted => (
+            "SkySync: manual change detected".to_string(),
+            format!("{} was changed by hand to {}, pointing it back at {}", event.domain, event.old_ip.as_deref().unwrap_or("?"), event.new_ip),
+        ),
+        EventStatus::Throttled { changes, minutes } => (
+            "SkySync: IP keeps changing".to_string(),
+            format!("{}, {} now points at {}", throttled(*changes, *minutes), event.domain, event.new_ip),
+        ),
+        EventStatus::Degraded { error, failures } => (
+            "SkySync: degraded".to_string(),
+            format!("{} {}: {}", event.domain, degraded(*failures), escape(error)),
+        ),
+        EventStatus::Recovered { failures } => (
+            "SkySync: recovered".to_string(),
+            format!("{} {}", event.domain, recovered(*failures)),
+        ),
+        EventStatus::CircuitOpen { error, failures, minutes } => (
+            "SkySync: circuit open".to_string(),
+            format!("SkySync {}: {}", circuit_open(*failures, *minutes), escape(error)),
+        ),
+        EventStatus::Started { version, settings } => ("SkySync: started".to_string(), format!("{}: {}", started(version), settings)),
+        EventStatus::Stopped { minutes } => (
+            "SkySync: stopped".to_string(),
+            format!("{}, last at {}", stopped(*minutes), event.new_ip),
+        ),
+    };
+    let summary = if event.test { format!("[Test] {}", summary) } else { summary };
+    let body = if event.proxied { format!("{} (origin of a proxied record)", body) } else { body };
+    match &event.failover {
+        Some(reason) => (summary, format!("{}\n({})", body, reason)),
+        None => (summary, body),
+    }
+}
+
+#[async_trait]
+impl Notifier for DesktopNotifier {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+        let (summary, body) = message(event);
+
+        // Showing a notification talks to the platform synchronously (D-Bus, WinRT, ...)
+        let shown = tokio::task::spawn_blocking(move || {
+            Notification::new()
+                .appname("SkySync")
+                .summary(&summary)
+                .body(&body)
+                .show()
+                .map(|_| ())
+        }).await?;
+
+        // Headless machines have no notification server, which isn't worth failing over
+        if let Err(err) = shown {
+            report(&format!("Warning: could not show desktop notification: {}", err)).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests the notification text for both outcomes.
+    #[test]
+    fn test_message() {
+        let success = UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Success);
+        assert_eq!(message(&success), ("SkySync".to_string(), "IP changed to 2.2.2.2 (home.example.com)".to_string()));
+
+        let failure = UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Failure("denied".to_string()));
+        assert_eq!(message(&failure).1, "Failed to point home.example.com at 2.2.2.2: denied");
+    }
+
+    /// Tests that an error quoting markup isn't read as such by the notification server.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_escapes_error_markup() {
+        let failure = UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Failure("<b>502</b> & more".to_string()));
+        assert_eq!(message(&failure).1, "Failed to point home.example.com at 2.2.2.2: &lt;b&gt;502&lt;/b&gt; &amp; more");
+    }
+}
@@ -1,30 +1,91 @@
-use chrono::Local;
-use dotenv::dotenv;
+use crate::config::{DiscordConfig, NotifyStyle};
+use crate::error::BoxError;
+use crate::geo::GeoInfo;
+use crate::log::report;
+use crate::notifier::{change_lines, check_response, code_block, Disabled, EventStatus, Notifier, UpdateEvent};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
 use rand::seq::SliceRandom;
+use reqwest::StatusCode;
 use serenity::builder::{CreateEmbed, CreateEmbedAuthor, ExecuteWebhook};
-use serenity::http::Http;
-use serenity::model::webhook::Webhook;
 use serenity::model::Color;
-use std::env;
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
 
-pub(crate) async fn send_webhook_message(content: &str, error: Option<bool>) {
-    dotenv().ok();
-    let http = Http::new("");
+/// How many characters of an error are shown, well within the 2000 of a message and the 4096 of
+/// an embed description.
+const ERROR_LIMIT: usize = 1500;
 
-    let webhook_uri = env::var("DISCORD_WEBHOOK_ID").expect("Expected a webhook id in the environment");
-    let webhook_username = env::var("DISCORD_WEBHOOK_USERNAME").expect("Expected a webhook username in the environment");
-    let webhook_avatar = env::var("DISCORD_WEBHOOK_AVATAR").expect("Expected a webhook avatar in the environment");
+/// The webhooks Discord answered `404 Unknown Webhook` or `401 Invalid Webhook Token` for. They
+/// aren't posted to again until `DISCORD_WEBHOOK_ID` names another one, across the notifiers
+/// every tick builds anew.
+fn invalid_webhooks() -> &'static Mutex<HashSet<String>> {
+    static INVALID: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    INVALID.get_or_init(Mutex::default)
+}
+
+/// Why a webhook is disabled, with the status Discord answered when it's just been found invalid.
+fn disabled(status: Option<StatusCode>) -> Disabled {
+    let status = status.map(|status| format!(" (HTTP {})", status.as_u16())).unwrap_or_default();
+    Disabled { reason: format!("the Discord webhook is invalid or was deleted{}", status) }
+}
+
+/// Turns an error status of a Discord response into an error, disabling the webhook for good
+/// when Discord says it doesn't exist or its token is wrong, which no retry would change.
+async fn check_webhook_response(config: &DiscordConfig, response: reqwest::Response) -> Result<(), BoxError> {
+    let status = response.status();
+    if !matches!(status, StatusCode::NOT_FOUND | StatusCode::UNAUTHORIZED) {
+        return check_response(response);
+    }
+
+    if invalid_webhooks().lock().unwrap().insert(config.webhook_url.clone()) {
+        report(&format!("The Discord webhook is invalid or was deleted (HTTP {}), Discord notifications are disabled until DISCORD_WEBHOOK_ID is reconfigured", status.as_u16())).await;
+    }
+    Err(Box::new(disabled(Some(status))))
+}
 
-    let webhook = Webhook::from_url(&http, &webhook_uri).await.expect("Failed to get webhook");
+/// Posts a message to the Discord webhook: an embed, or a plain line when `NOTIFY_STYLE` is
+/// `compact`. A webhook Discord said is invalid isn't posted to, and fails with `Disabled`.
+///
+/// The payload is built with serenity but sent with SkySync's own client, so it goes through the
+/// configured proxy.
+///
+/// # Arguments
+/// * `client` - The HTTP client to send the request with.
+/// * `config` - The webhook to post to.
+/// * `content` - The embed description, or the whole message in compact style.
+/// * `color` - The embed color, a random one when `None`.
+/// * `mention` - Sent as the message content outside the embed, so whoever it names is pinged.
+/// * `geo` - The location of the new IP, added as embed fields. Left out in compact style.
+/// * `timestamp` - When the update happened.
+pub(crate) async fn send_webhook_message(
+    client: &reqwest::Client,
+    config: &DiscordConfig,
+    content: &str,
+    color: Option<Color>,
+    mention: Option<&str>,
+    geo: Option<&GeoInfo>,
+    timestamp: DateTime<FixedOffset>,
+) -> Result<(), BoxError> {
+    if invalid_webhooks().lock().unwrap().contains(&config.webhook_url) {
+        return Err(Box::new(disabled(None)));
+    }
+    let builder = ExecuteWebhook::new()
+        .avatar_url(&config.avatar)
+        .username(&config.username);
+    if config.style == NotifyStyle::Compact {
+        let content = match mention {
+            Some(mention) => format!("{} {}", mention, content),
+            None => content.to_string(),
+        };
+        return check_webhook_response(config, client.post(&config.webhook_url).json(&builder.content(content)).send().await?).await;
+    }
 
-    let embed_author = CreateEmbedAuthor::new("SkySync - Webhook").icon_url(&webhook_avatar);
+    let embed_author = CreateEmbedAuthor::new(&config.embed_author).icon_url(&config.embed_icon);
 
-    // Randomize the color of the embed based
-    let color = if error.unwrap_or(false) {
-        Color::RED
-    } else {
+    let color = color.unwrap_or_else(|| {
         // Select a random color type
-        let colors = vec![
+        let colors = [
             Color::BLITZ_BLUE,
             Color::DARK_PURPLE,
             Color::FOOYOO,
@@ -33,26 +94,350 @@ pub(crate) async fn send_webhook_message(content: &str, error: Option<bool>) {
         ];
         let random_color = colors.choose(&mut rand::thread_rng()).unwrap();
         *random_color
-    };
+    });
 
-    let embed = CreateEmbed::new().title("**New Call! - Webhook**")
+    let mut embed = CreateEmbed::new().title(&config.embed_title)
         .author(embed_author)
         .description(content)
         .color(color)
-        .timestamp(Local::now());
-    let builder = ExecuteWebhook::new()
-        .avatar_url(webhook_avatar)
-        .username(webhook_username)
-        .embed(embed);
-    webhook.execute(&http, false, builder).await.expect("Could not execute webhook.");
+        .timestamp(timestamp);
+    if let Some(geo) = geo {
+        embed = embed
+            .field("País", format!("{} ({})", geo.country, geo.region), true)
+            .field("ISP", &geo.isp, true)
+            .field("ASN", &geo.asn, true);
+    }
+    let mut builder = builder.embed(embed);
+    if let Some(mention) = mention {
+        builder = builder.content(mention);
+    }
+
+    check_webhook_response(config, client.post(&config.webhook_url).json(&builder).send().await?).await
+}
+
+/// Sends update events to the configured Discord webhook.
+pub struct DiscordNotifier {
+    config: DiscordConfig,
+    client: reqwest::Client,
+}
+
+impl DiscordNotifier {
+    pub fn new(config: DiscordConfig, client: reqwest::Client) -> DiscordNotifier {
+        DiscordNotifier { config, client }
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+        // Sent as the failover, so say which notifier couldn't deliver it
+        let failover = match &event.failover {
+            Some(reason) => format!("\n\n*Enviado como alternativa: {}*", reason),
+            None => String::new(),
+        };
+        if self.config.style == NotifyStyle::Compact {
+            let mention = match event.status {
+                EventStatus::Escalated { .. } => self.config.escalation_mention.as_deref(),
+                _ => None,
+            };
+            // In angle brackets, Discord shows the link without a preview of the page
+            let line = match &event.dashboard_link {
+                Some(link) => format!("{} <{}>", compact_line(event), link),
+                None => compact_line(event),
+            };
+            return send_webhook_message(&self.client, &self.config, &line, None, mention, None, event.timestamp).await;
+        }
+
+        let test = if event.test { "**[TESTE]** Mensagem de teste do SkySync, nenhum registro foi alterado.\n\n" } else { "" };
+        let (content, color, mention) = match &event.status {
+            EventStatus::Success => (
+                format!("O IP público do domínio {} foi atualizado com sucesso!{}", event.domain, failover),
+                None,
+                None,
+            ),
+            EventStatus::Failure(error) => (
+                format!("Falha ao atualizar o IP público do domínio {}!\n\n{}{}", event.domain, code_block(error, ERROR_LIMIT), failover),
+                Some(Color::RED),
+                None,
+            ),
+            EventStatus::Escalated { error, minutes } => (
+                format!("**Falha persistente há {} minutos** em {}!\n\n{}{}", minutes, event.domain, code_block(error, ERROR_LIMIT), failover),
+                Some(Color::DARK_RED),
+                self.config.escalation_mention.as_deref(),
+            ),
+            EventStatus::Resolved { minutes } => (
+                format!("Resolvido: {} voltou a ser atualizado após {} minutos de falhas.{}", event.domain, minutes, failover),
+                Some(Color::DARK_GREEN),
+                None,
+            ),
+            EventStatus::Changed => (
+                format!("O IP público mudou para {} (modo monitor, {} não foi alterado).{}", event.new_ip, event.domain, failover),
+                Some(Color::BLUE),
+                None,
+            ),
+            EventStatus::Reconciled { updated, current, failed } => (
+                format!("Registros conferidos ao iniciar ({}): {}.{}", event.domain, reconciled_line(*updated, *current, *failed), failover),
+                None,
+                None,
+            ),
+            EventStatus::Downtime { minutes } => (
+                format!("O SkySync ficou fora do ar por {} minutos antes de iniciar, {} pode ter perdido mudanças.{}", minutes, event.domain, failover),
+                Some(Color::ORANGE),
+                None,
+            ),
+            EventStatus::Recreated => (
+                format!("O registro {} tinha sido apagado e foi recriado apontando para {}.{}", event.domain, event.new_ip, failover),
+                Some(Color::ORANGE),
+                None,
+            ),
+            EventStatus::Drifted => (
+                format!("O registro {} foi alterado manualmente para {} e está sendo reapontado para {}.{}", event.domain, event.old_ip.as_deref().unwrap_or("?"), event.new_ip, failover),
+                Some(Color::ORANGE),
+                None,
+            ),
+            EventStatus::Throttled { changes, minutes } => (
+                format!("O IP de {} mudou {} vezes nos últimos {} minutos e agora é {}.{}", event.domain, changes, minutes, event.new_ip, failover),
+                Some(Color::BLUE),
+                None,
+            ),
+            EventStatus::Degraded { error, failures } => (
+                format!("**Instável:** {} falhou em {} verificações seguidas!\n\n{}{}", event.domain, failures, code_block(error, ERROR_LIMIT), failover),
+                Some(Color::DARK_ORANGE),
+                None,
+            ),
+            EventStatus::Recovered { failures } => (
+                format!("Recuperado: {} voltou a funcionar após {} verificações com falha.{}", event.domain, failures, failover),
+                Some(Color::DARK_GREEN),
+                None,
+            ),
+            EventStatus::CircuitOpen { error, failures, minutes } => (
+                format!(
+                    "**Circuito aberto:** o provedor de DNS falhou em {} verificações seguidas, nenhuma chamada será feita por {} minutos.\n\n{}{}",
+                    failures, minutes, code_block(error, ERROR_LIMIT), failover,
+                ),
+                Some(Color::DARK_ORANGE),
+                None,
+            ),
+            EventStatus::Started { version, settings } => (
+                format!("O SkySync {} iniciou ({}): {}.{}", version, event.domain, settings, failover),
+                None,
+                None,
+            ),
+            EventStatus::Stopped { minutes } => (
+                format!("O SkySync parou após {} minutos, o último IP conhecido era {}.{}", minutes, event.new_ip, failover),
+                None,
+                None,
+            ),
+        };
+
+        // Resolvers show Cloudflare's addresses for proxied records, which looks like a failed update
+        let proxied = if event.proxied {
+            format!("\n\n*Registro com proxy da Cloudflare: o DNS público mostra os IPs da Cloudflare, a origem é {}.*", event.new_ip)
+        } else {
+            String::new()
+        };
+        // A coalesced update lists every record it changed
+        let changes: String = change_lines(&event.changes).iter().map(|line| format!("\n• {}", line)).collect();
+        let changes = if changes.is_empty() { changes } else { format!("\n{}", changes) };
+        let link = match &event.dashboard_link {
+            Some(link) => format!("\n\n[Abrir no painel]({})", link),
+            None => String::new(),
+        };
+        let content = format!("{}{}{}{}{}", test, content, changes, proxied, link);
+        send_webhook_message(&self.client, &self.config, &content, color, mention, event.geo.as_ref(), event.timestamp).await
+    }
+}
+
+/// How a startup reconciliation went, e.g. `3 registros: 2 atualizados, 1 já estavam corretos`.
+fn reconciled_line(updated: usize, current: usize, failed: usize) -> String {
+    let mut line = format!("{} registros: {} atualizados, {} já estavam corretos", updated + current + failed, updated, current);
+    if failed > 0 {
+        line.push_str(&format!(", {} com falha", failed));
+    }
+    line
+}
+
+/// An error as plain text within a line: on one line, with the characters Discord reads as
+/// Markdown escaped.
+fn escape(error: &str) -> String {
+    let mut escaped = String::new();
+    for c in error.chars() {
+        match c {
+            '\\' | '*' | '_' | '~' | '`' | '|' | '>' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The single line sent in compact style, e.g. `home.example.com: 1.1.1.1 → 2.2.2.2`.
+fn compact_line(event: &UpdateEvent) -> String {
+    let old = event.old_ip.as_deref().unwrap_or("?");
+    let line = match &event.status {
+        EventStatus::Success if !event.changes.is_empty() => change_lines(&event.changes).join("; "),
+        EventStatus::Success if event.proxied => format!("{}: {} → {} (origem, com proxy)", event.domain, old, event.new_ip),
+        EventStatus::Success => format!("{}: {} → {}", event.domain, old, event.new_ip),
+        EventStatus::Failure(error) => format!("{}: falha ({})", event.domain, escape(error)),
+        EventStatus::Escalated { error, minutes } => format!("{}: falha há {} minutos ({})", event.domain, minutes, escape(error)),
+        EventStatus::Resolved { minutes } => format!("{}: resolvido após {} minutos", event.domain, minutes),
+        EventStatus::Changed => format!("{}: {} → {} (modo monitor)", event.domain, old, event.new_ip),
+        EventStatus::Reconciled { updated, current, failed } => format!("{}: {}", event.new_ip, reconciled_line(*updated, *current, *failed)),
+        EventStatus::Downtime { minutes } => format!("{}: fora do ar por {} minutos antes de iniciar", event.domain, minutes),
+        EventStatus::Recreated => format!("{}: recriado com {} após ser apagado", event.domain, event.new_ip),
+        EventStatus::Drifted => format!("{}: alterado manualmente, reapontando para {}", event.domain, event.new_ip),
+        EventStatus::Throttled { changes, minutes } => format!("{}: IP mudou {} vezes em {} minutos, agora {}", event.domain, changes, minutes, event.new_ip),
+        EventStatus::Degraded { error, failures } => format!("{}: instável, {} falhas seguidas ({})", event.domain, failures, escape(error)),
+        EventStatus::Recovered { failures } => format!("{}: recuperado após {} falhas", event.domain, failures),
+        EventStatus::CircuitOpen { failures, minutes, .. } => format!("Circuito aberto após {} falhas, pausado por {} minutos", failures, minutes),
+        EventStatus::Started { version, .. } => format!("SkySync {} iniciado ({})", version, event.domain),
+        EventStatus::Stopped { minutes } => format!("SkySync parado após {} minutos, último IP {}", minutes, event.new_ip),
+    };
+    let test = if event.test { "[TESTE] " } else { "" };
+    match &event.failover {
+        Some(reason) => format!("{}{} (alternativa: {})", test, line, reason),
+        None => format!("{}{}", test, line),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ConfigLoader;
+    use crate::config::Dispatch;
+    use crate::notifier::{deliver, dispatch};
+    use crate::retry::RetryPolicy;
+    use crate::sync::tests::{record, test_config, MockProvider, RecordingNotifier, StaticIp};
+    use crate::sync::{State, Syncer, TickOutcome};
+    use serde_json::json;
+    use tokio::time::Duration;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A notifier posting to `server` in `style`.
+    fn notifier(server: &MockServer, style: NotifyStyle) -> DiscordNotifier {
+        DiscordNotifier::new(DiscordConfig {
+            username: "SkySync".to_string(),
+            avatar: String::new(),
+            webhook_url: server.uri(),
+            escalation_mention: None,
+            style,
+            embed_title: "Home IP".to_string(),
+            embed_author: "Homelab".to_string(),
+            embed_icon: "https://example.com/icon.png".to_string(),
+        }, reqwest::Client::new())
+    }
+
+    /// Sends a successful update in `style` and returns the payload Discord received.
+    async fn sent_payload(style: NotifyStyle) -> serde_json::Value {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let event = UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Success);
+
+        notifier(&server, style).notify(&event).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        serde_json::from_slice(&requests[0].body).unwrap()
+    }
+
+    /// Tests that errors in compact lines stay on their line and don't turn into Markdown.
+    #[test]
+    fn test_compact_line_escapes_error() {
+        let status = EventStatus::Failure("bad *value* for `content`\nat line 2".to_string());
+        let event = UpdateEvent::new("home.example.com", None, "2.2.2.2", status);
+        assert_eq!(compact_line(&event), "home.example.com: falha (bad \\*value\\* for \\`content\\` at line 2)");
+    }
+
+    /// Tests that compact style sends a single plain line and no embed.
+    #[tokio::test]
+    async fn test_compact_style_sends_plain_line() {
+        let payload = sent_payload(NotifyStyle::Compact).await;
+
+        assert_eq!(payload["content"], "home.example.com: 1.1.1.1 → 2.2.2.2");
+        assert!(payload["embeds"].as_array().is_none_or(Vec::is_empty), "{}", payload);
+    }
+
+    /// Tests that rich style, the default, sends the message as an embed.
+    #[tokio::test]
+    async fn test_rich_style_sends_embed() {
+        let payload = sent_payload(NotifyStyle::default()).await;
+
+        let embed = &payload["embeds"][0];
+        assert_eq!(payload.get("content"), None);
+        assert!(embed["description"].as_str().unwrap().contains("home.example.com"), "{}", payload);
+        assert_eq!((&embed["title"], &embed["author"]["name"]), (&json!("Home IP"), &json!("Homelab")));
+        assert_eq!(embed["author"]["icon_url"], "https://example.com/icon.png");
+    }
+
+    /// Tests that a rate-limited webhook is retried until Discord accepts it.
+    #[tokio::test]
+    async fn test_retries_rate_limited_webhook() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("X-RateLimit-Reset-After", "0.01"))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = notifier(&server, NotifyStyle::Rich);
+        let event = UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Success);
+
+        deliver(&notifier, &event, RetryPolicy { retries: 3, delay: Duration::from_secs(60) }).await.unwrap();
+    }
+
+    /// Tests that a deleted webhook is tried once, without retries, and then left alone while
+    /// the record updates and the failover go on.
+    #[tokio::test]
+    async fn test_deleted_webhook_disables_notifier() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({ "message": "Unknown Webhook", "code": 10015 })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // A path of its own, so the servers of other tests reusing the port aren't disabled too
+        let mut deleted = notifier(&server, NotifyStyle::Rich);
+        deleted.config.webhook_url = format!("{}/api/webhooks/deleted", server.uri());
+        let discord: Vec<Box<dyn Notifier>> = vec![Box::new(deleted)];
+        let failover = RecordingNotifier::default();
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let retry = RetryPolicy { retries: 3, delay: Duration::from_secs(60) };
+
+        for ip in ["2.2.2.2", "3.3.3.3"] {
+            let event = UpdateEvent::new("home.example.com", None, ip, EventStatus::Success);
+            assert_eq!(dispatch(&discord, Some(&failover), &event, Dispatch::Parallel, retry).await, 1);
+        }
+        let syncer = Syncer::new(Box::new(provider.clone()), Box::new(StaticIp("4.4.4.4")), discord);
+        let summary = syncer.run_once(&test_config(), &mut State::default()).await.unwrap();
+
+        assert!(matches!(summary.records[0].result, Ok(TickOutcome::Updated { .. })), "{:?}", summary.records[0]);
+        assert_eq!(provider.records.lock().unwrap()[0].content, "4.4.4.4");
+        let events = failover.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].failover.as_deref().unwrap().contains("invalid or was deleted (HTTP 404)"), "{:?}", events[0].failover);
+    }
 
     #[tokio::test]
+    #[ignore = "requires a live Discord webhook"]
     async fn test_send_webhook_message() {
-        send_webhook_message("Hello, world!").await;
+        let config = ConfigLoader::new(".env").load().expect("Expected a valid .env for live tests");
+        send_webhook_message(&reqwest::Client::new(), &config.discord, "Hello, world!", None, None, None, config.timezone.now()).await.unwrap();
     }
 }
\ No newline at end of file
@@ -0,0 +1 @@
+pub(crate) mod smtp;
\ No newline at end of file
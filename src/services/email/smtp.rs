@@ -0,0 +1,171 @@
+use crate::config::EmailConfig;
+use crate::error::BoxError;
+use crate::notifier::{change_lines, circuit_open, degraded, downtime, reconciled, recovered, started, stopped, throttled, EventStatus, Notifier, UpdateEvent};
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+/// Port where SMTP servers expect TLS from the first byte instead of `STARTTLS`.
+const IMPLICIT_TLS_PORT: u16 = 465;
+
+/// Emails update events through an SMTP server.
+pub struct EmailNotifier {
+    config: EmailConfig,
+}
+
+impl EmailNotifier {
+    pub fn new(config: EmailConfig) -> EmailNotifier {
+        EmailNotifier { config }
+    }
+
+    /// Builds the email for an event.
+    fn message(&self, event: &UpdateEvent) -> Result<Message, BoxError> {
+        let (subject, outcome) = match &event.status {
+            EventStatus::Success => (format!("SkySync: {} updated to {}", event.domain, event.new_ip), "Updated".to_string()),
+            EventStatus::Failure(error) => (format!("SkySync: failed to update {}", event.domain), format!("Failed: {}", error)),
+            EventStatus::Escalated { error, minutes } => (
+                format!("SkySync [URGENT]: {} still failing after {} minutes", event.domain, minutes),
+                format!("Failing for {} minutes: {}", minutes, error),
+            ),
+            EventStatus::Resolved { minutes } => (
+                format!("SkySync: {} resolved after {} minutes", event.domain, minutes),
+                "Resolved".to_string(),
+            ),
+            EventStatus::Changed => (
+                format!("SkySync: public IP changed to {}", event.new_ip),
+                "Changed (monitor mode, the record wasn't updated)".to_string(),
+            ),
+            EventStatus::Reconciled { updated, current, failed } => (
+                format!("SkySync: {}", reconciled(*updated, *current, *failed)),
+                "Reconciled on startup".to_string(),
+            ),
+            EventStatus::Downtime { minutes } => (
+                format!("SkySync: {}", downtime(*minutes)),
+                format!("Down for {} minutes, longer than expected", minutes),
+            ),
+            EventStatus::Recreated => (
+                format!("SkySync: {} recreated at {}", event.domain, event.new_ip),
+                "Recreated (the record had been deleted)".to_string(),
+            ),
+            EventStatus::Drifted => (
+                format!("SkySync: manual change to {} detected", event.domain),
+                "Changed by hand, pointing it back at the public IP".to_string(),
+            ),
+            EventStatus::Throttled { changes, minutes } => (
+                format!("SkySync: {}, now {}", throttled(*changes, *minutes), event.new_ip),
+                "Updated every time, but notified once for the whole window".to_string(),
+            ),
+            EventStatus::Degraded { error, failures } => (
+                format!("SkySync: {} {}", event.domain, degraded(*failures)),
+                format!("Failed {} ticks in a row: {}", failures, error),
+            ),
+            EventStatus::Recovered { failures } => (
+                format!("SkySync: {} {}", event.domain, recovered(*failures)),
+                "Recovered".to_string(),
+            ),
+            EventStatus::CircuitOpen { error, failures, minutes } => (
+                format!("SkySync {}", circuit_open(*failures, *minutes)),
+                format!("The DNS provider failed {} ticks in a row: {}", failures, error),
+            ),
+            EventStatus::Started { version, settings } => (started(version), format!("Started: {}", settings)),
+            EventStatus::Stopped { minutes } => (stopped(*minutes), format!("Stopped, the last known IP was {}", event.new_ip)),
+        };
+
+        let subject = if event.test { format!("[TEST] {}", subject) } else { subject };
+
+        let mut body = format!(
+            "Record: {}\nOld IP: {}\nNew IP: {}\nTime: {}\nStatus: {}\n",
+            event.domain,
+            event.old_ip.as_deref().unwrap_or("unknown"),
+            event.new_ip,
+            event.timestamp.to_rfc3339(),
+            outcome,
+        );
+        if !event.changes.is_empty() {
+            body.push_str("Changes:\n");
+            for line in change_lines(&event.changes) {
+                body.push_str(&format!("  {}\n", line));
+            }
+        }
+        if let Some(geo) = &event.geo {
+            body.push_str(&format!("Location: {}\n", geo));
+        }
+        if event.proxied {
+            body.push_str("Proxied: yes, public DNS shows Cloudflare's addresses instead of the new IP\n");
+        }
+        if let Some(link) = &event.dashboard_link {
+            body.push_str(&format!("Dashboard: {}\n", link));
+        }
+        if let Some(reason) = &event.failover {
+            body.push_str(&format!("Sent as the failover: {}\n", reason));
+        }
+
+        Ok(Message::builder()
+            .from(self.config.from.parse()?)
+            .to(self.config.to.parse()?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?)
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+        let message = self.message(event)?;
+
+        let builder = if self.config.port == IMPLICIT_TLS_PORT {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&self.config.host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.config.host)?
+        };
+        let mailer = builder
+            .port(self.config.port)
+            .credentials(Credentials::new(self.config.user.clone(), self.config.pass.clone()))
+            .build();
+
+        mailer.send(message).await.map_err(|err| -> BoxError {
+            if err.status().is_some_and(|code| code.to_string() == "535") {
+                format!("SMTP authentication failed for {}: {}", self.config.user, err).into()
+            } else {
+                err.into()
+            }
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notifier() -> EmailNotifier {
+        EmailNotifier::new(EmailConfig {
+            host: "smtp.example.com".to_string(),
+            port: 587,
+            user: "user".to_string(),
+            pass: "pass".to_string(),
+            from: "SkySync <skysync@example.com>".to_string(),
+            to: "me@example.com".to_string(),
+        })
+    }
+
+    /// Tests that the email carries the record, both IPs and the timestamp.
+    #[test]
+    fn test_message_body() {
+        let event = UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Success);
+        let formatted = String::from_utf8(notifier().message(&event).unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("Subject: SkySync: home.example.com updated to 2.2.2.2"));
+        assert!(formatted.contains("To: me@example.com"));
+        assert!(formatted.contains("Record: home.example.com"));
+        assert!(formatted.contains("Old IP: 1.1.1.1"));
+        assert!(formatted.contains("New IP: 2.2.2.2"));
+        assert!(formatted.contains(&format!("Time: {}", event.timestamp.to_rfc3339())));
+    }
+}
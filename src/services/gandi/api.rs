@@ -0,0 +1,157 @@
+use crate::config::GandiConfig;
+use crate::error::BoxError;
+use crate::http::{self, StatusClass, StatusError};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+/// A record set as Gandi lists it: every value of one name and type.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Rrset {
+    pub rrset_name: String,
+    pub rrset_type: String,
+    #[serde(default)]
+    pub rrset_ttl: i64,
+    pub rrset_values: Vec<String>,
+}
+
+/// The body of a create or replace request.
+#[derive(Serialize, Debug)]
+pub(crate) struct RrsetBody<'a> {
+    pub rrset_values: Vec<&'a str>,
+    pub rrset_ttl: i64,
+}
+
+/// An entry of the `errors` list Gandi adds to failed validations.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct FieldError {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+/// The body of a failed Gandi request.
+#[derive(Deserialize, Debug)]
+struct Envelope {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    cause: String,
+    #[serde(default)]
+    errors: Vec<FieldError>,
+}
+
+/// A Gandi response that didn't carry what was asked for.
+#[derive(Debug, PartialEq)]
+pub(crate) enum GandiError {
+    /// Gandi reported the request as failed, e.g. bad credentials or an invalid value.
+    Api { status: u16, message: String, errors: Vec<String> },
+    /// The body isn't a Gandi API response at all, e.g. an error page from a proxy.
+    Unexpected { status: u16, body: String },
+}
+
+impl fmt::Display for GandiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GandiError::Api { status, message, errors } if errors.is_empty() => {
+                write!(f, "Gandi reported a failure (HTTP {}): {}", status, message)
+            }
+            GandiError::Api { status, message, errors } => {
+                write!(f, "Gandi reported a failure (HTTP {}): {} ({})", status, message, errors.join(", "))
+            }
+            GandiError::Unexpected { status, body } => write!(f, "Unexpected Gandi response (HTTP {}): {}", status, body),
+        }
+    }
+}
+
+impl Error for GandiError {}
+
+/// Reads the error envelope of a failed response.
+///
+/// # Arguments
+/// * `status` - The HTTP status of the response.
+/// * `body` - The raw body.
+pub(crate) fn parse_error(status: u16, body: &str) -> GandiError {
+    match serde_json::from_str::<Envelope>(body) {
+        Ok(envelope) if !envelope.message.is_empty() || !envelope.cause.is_empty() => GandiError::Api {
+            status,
+            message: if envelope.message.is_empty() { envelope.cause } else { envelope.message },
+            errors: envelope.errors.into_iter()
+                .map(|err| format!("{}: {}", err.name, err.description))
+                .collect(),
+        },
+        _ => GandiError::Unexpected { status, body: body.trim().to_string() },
+    }
+}
+
+/// The URL of the record set `name`/`rrset_type` in `domain`.
+fn rrset_url(config: &GandiConfig, domain: &str, name: &str, rrset_type: &str) -> String {
+    format!("{}/domains/{}/records/{}/{}", config.api_url, domain, name, rrset_type)
+}
+
+/// The `Authorization` header for the configured credentials.
+fn authorization(config: &GandiConfig) -> String {
+    if config.personal_access_token {
+        format!("Bearer {}", config.token)
+    } else {
+        format!("Apikey {}", config.token)
+    }
+}
+
+/// Sends `request` and returns the body of a successful response.
+///
+/// # Returns
+/// The body, or a `StatusError` explained with Gandi's error envelope for a failed status.
+async fn send(request: reqwest::RequestBuilder, config: &GandiConfig) -> Result<String, BoxError> {
+    let response = request.header("Authorization", authorization(config)).send().await?;
+    match http::classify_response(response).await {
+        Ok(response) => Ok(response.text().await?),
+        Err(err) => {
+            let explained = parse_error(err.status, &err.detail);
+            Err(err.explained(explained).into())
+        }
+    }
+}
+
+/// Reads one record set.
+///
+/// # Arguments
+/// * `domain` - The domain the record set lives in.
+/// * `name` - The name of the record set relative to `domain`, `@` for the apex.
+/// * `rrset_type` - The type of the record set, e.g. `A`.
+///
+/// # Returns
+/// The record set, or `None` if Gandi has no record set of that name and type.
+pub(crate) async fn get_rrset(client: &reqwest::Client, config: &GandiConfig, domain: &str, name: &str, rrset_type: &str) -> Result<Option<Rrset>, BoxError> {
+    let body = match send(client.get(rrset_url(config, domain, name, rrset_type)), config).await {
+        Ok(body) => body,
+        Err(err) if err.downcast_ref::<StatusError>().is_some_and(|err| err.class == StatusClass::NotFound) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok(Some(serde_json::from_str(&body).map_err(|err| GandiError::Unexpected { status: 200, body: format!("{} ({})", body.trim(), err) })?))
+}
+
+/// Replaces every value of one record set.
+///
+/// # Arguments
+/// * `domain` - The domain the record set lives in.
+/// * `name` - The name of the record set relative to `domain`, `@` for the apex.
+/// * `rrset_type` - The type of the record set, e.g. `A`.
+/// * `body` - The new values and TTL.
+pub(crate) async fn put_rrset(client: &reqwest::Client, config: &GandiConfig, domain: &str, name: &str, rrset_type: &str, body: &RrsetBody<'_>) -> Result<(), BoxError> {
+    send(client.put(rrset_url(config, domain, name, rrset_type)).json(body), config).await?;
+    Ok(())
+}
+
+/// Creates a record set that doesn't exist yet.
+///
+/// # Arguments
+/// * `domain` - The domain the record set is created in.
+/// * `name` - The name of the record set relative to `domain`, `@` for the apex.
+/// * `rrset_type` - The type of the record set, e.g. `A`.
+/// * `body` - The values and TTL.
+pub(crate) async fn post_rrset(client: &reqwest::Client, config: &GandiConfig, domain: &str, name: &str, rrset_type: &str, body: &RrsetBody<'_>) -> Result<(), BoxError> {
+    send(client.post(rrset_url(config, domain, name, rrset_type)).json(body), config).await?;
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use crate::config::{GandiConfig, ZoneConfig};
+use crate::error::BoxError;
+use crate::provider::{DnsProvider, DnsRecord};
+use crate::services::gandi::api::{get_rrset, post_rrset, put_rrset, Rrset, RrsetBody};
+use async_trait::async_trait;
+
+/// The TTL records are created with, the lowest Gandi accepts.
+const CREATED_TTL: i64 = 300;
+
+/// Keeps records hosted on Gandi LiveDNS in sync, across any number of domains.
+///
+/// Gandi groups records into record sets of one name and type, with no ID of their own, so each
+/// managed name is read and written as the record set of the configured type.
+pub struct GandiProvider {
+    config: GandiConfig,
+    zones: Vec<ZoneConfig>,
+    client: reqwest::Client,
+}
+
+impl GandiProvider {
+    pub fn new(config: GandiConfig, zones: Vec<ZoneConfig>, client: reqwest::Client) -> GandiProvider {
+        GandiProvider { config, zones, client }
+    }
+
+    /// Lists record sets of `record_type` instead of following `GANDI_RECORD_TYPE`.
+    pub fn with_record_type(mut self, record_type: &str) -> GandiProvider {
+        self.config.record_type = record_type.to_ascii_uppercase();
+        self
+    }
+}
+
+/// The name of `name` relative to `domain`, the way Gandi addresses record sets: `@` for the
+/// apex, `home` for `home.example.com`. Names that are already relative are kept.
+fn relative(name: &str, domain: &str) -> String {
+    let name = name.trim().trim_end_matches('.').to_ascii_lowercase();
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    if name == "@" || name == domain {
+        "@".to_string()
+    } else {
+        name.strip_suffix(&format!(".{}", domain)).unwrap_or(&name).to_string()
+    }
+}
+
+/// Turns a record set into a record. Only its first value is kept, since SkySync points every
+/// record at a single address.
+fn from_rrset(domain: &str, rrset: Rrset) -> DnsRecord {
+    let name = if rrset.rrset_name == "@" { domain.to_string() } else { format!("{}.{}", rrset.rrset_name, domain) };
+    DnsRecord {
+        id: format!("{}/{}", rrset.rrset_name, rrset.rrset_type),
+        zone_id: domain.to_string(),
+        zone_name: domain.to_string(),
+        name,
+        r#type: rrset.rrset_type,
+        content: rrset.rrset_values.into_iter().next().unwrap_or_default(),
+        ttl: rrset.rrset_ttl,
+        proxied: false,
+        comment: None,
+        tags: Vec::new(),
+        priority: None,
+    }
+}
+
+#[async_trait]
+impl DnsProvider for GandiProvider {
+    fn name(&self) -> &'static str {
+        "gandi"
+    }
+
+    async fn list_records(&self) -> Result<Vec<DnsRecord>, BoxError> {
+        let mut records = Vec::new();
+        for zone in &self.zones {
+            for name in &zone.records {
+                let rrset = get_rrset(&self.client, &self.config, &zone.zone_id, &relative(name, &zone.zone_id), &self.config.record_type)
+                    .await
+                    .map_err(|err| format!("domain {}: {}", zone.zone_id, err))?;
+                records.extend(rrset.map(|rrset| from_rrset(&zone.zone_id, rrset)));
+            }
+        }
+        Ok(records)
+    }
+
+    async fn update_record(&self, record: &DnsRecord, content: &str) -> Result<(), BoxError> {
+        let body = RrsetBody { rrset_values: vec![content], rrset_ttl: record.ttl };
+        put_rrset(&self.client, &self.config, &record.zone_id, &relative(&record.fqdn(), &record.zone_id), &record.r#type, &body).await
+    }
+
+    async fn create_record(&self, name: &str, content: &str) -> Result<(), BoxError> {
+        let Some(zone) = self.zones.iter().find(|zone| zone.records.iter().any(|record| record == name)) else {
+            return Err(format!("{} isn't listed in any configured domain", name).into());
+        };
+
+        let body = RrsetBody { rrset_values: vec![content], rrset_ttl: CREATED_TTL };
+        post_rrset(&self.client, &self.config, &zone.zone_id, &relative(name, &zone.zone_id), &self.config.record_type, &body).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A provider for `example.com` talking to `server`.
+    fn provider(server: &MockServer, personal_access_token: bool) -> GandiProvider {
+        let config = GandiConfig {
+            token: "secret".to_string(),
+            personal_access_token,
+            api_url: server.uri(),
+            record_type: "A".to_string(),
+        };
+        let zones = vec![ZoneConfig { zone_id: "example.com".to_string(), records: vec!["home".to_string(), "example.com".to_string()] }];
+        GandiProvider::new(config, zones, reqwest::Client::new())
+    }
+
+    /// Tests that record sets are read into records with their full name, and that a missing
+    /// one is left out rather than failing the listing.
+    #[tokio::test]
+    async fn test_list_records() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/domains/example.com/records/home/A"))
+            .and(header("Authorization", "Apikey secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "rrset_name": "home",
+                "rrset_type": "A",
+                "rrset_ttl": 1800,
+                "rrset_values": ["1.1.1.1"],
+                "rrset_href": "https://api.gandi.net/v5/livedns/domains/example.com/records/home/A"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/domains/example.com/records/@/A"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "code": 404, "message": "Can't find the DNS record @/A in LiveDNS", "object": "dns-record", "cause": "Not Found"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let records = provider(&server, false).list_records().await.unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "home.example.com");
+        assert_eq!((records[0].content.as_str(), records[0].ttl), ("1.1.1.1", 1800));
+        assert!(records[0].matches("home"));
+    }
+
+    /// Tests that an update replaces the values of the record set and keeps its TTL.
+    #[tokio::test]
+    async fn test_update_record() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/domains/example.com/records/home/A"))
+            .and(header("Authorization", "Bearer secret"))
+            .and(body_json(json!({ "rrset_values": ["2.2.2.2"], "rrset_ttl": 1800 })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "message": "DNS Record Created" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let record = DnsRecord {
+            id: "home/A".to_string(),
+            zone_id: "example.com".to_string(),
+            zone_name: "example.com".to_string(),
+            name: "home.example.com".to_string(),
+            r#type: "A".to_string(),
+            content: "1.1.1.1".to_string(),
+            ttl: 1800,
+            proxied: false,
+            comment: None,
+            tags: Vec::new(),
+            priority: None,
+        };
+
+        provider(&server, true).update_record(&record, "2.2.2.2").await.unwrap();
+    }
+
+    /// Tests that Gandi's error envelope is reported with its field errors.
+    #[tokio::test]
+    async fn test_reports_error_envelope() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/domains/example.com/records/@/A"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "code": 400,
+                "message": "Validation error",
+                "object": "dns-record",
+                "cause": "Bad Request",
+                "errors": [{ "location": "body", "name": "rrset_values", "description": "not a valid IPv4 address" }]
+            })))
+            .mount(&server)
+            .await;
+
+        let err = provider(&server, true).create_record("example.com", "2.2.2.2").await.unwrap_err();
+
+        assert_eq!(err.to_string(), "Gandi reported a failure (HTTP 400): Validation error (rrset_values: not a valid IPv4 address)");
+    }
+
+    /// Tests that names are addressed relative to their domain.
+    #[test]
+    fn test_relative() {
+        assert_eq!(relative("example.com", "example.com"), "@");
+        assert_eq!(relative("@", "example.com"), "@");
+        assert_eq!(relative("Home.Example.com.", "example.com"), "home");
+        assert_eq!(relative("*", "example.com"), "*");
+    }
+}
@@ -0,0 +1,216 @@
+use crate::config::GenericWebhookConfig;
+#[cfg(feature = "webhook-signing")]
+use crate::config::{SignatureEncoding, WebhookSigning};
+use crate::error::BoxError;
+use crate::notifier::{change_lines, check_response, Notifier, UpdateEvent};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::Value;
+
+/// Posts a user-defined JSON body to an arbitrary URL.
+pub struct GenericWebhookNotifier {
+    config: GenericWebhookConfig,
+    client: reqwest::Client,
+}
+
+impl GenericWebhookNotifier {
+    pub fn new(config: GenericWebhookConfig, client: reqwest::Client) -> GenericWebhookNotifier {
+        GenericWebhookNotifier { config, client }
+    }
+}
+
+/// Fills the template placeholders in every string of a JSON value.
+///
+/// Substitution happens on the parsed template, so values containing quotes or newlines can't
+/// break the JSON structure.
+///
+/// # Arguments
+/// * `template` - The parsed template.
+/// * `event` - The event providing the values.
+///
+/// # Returns
+/// A copy of the template with `{{domain}}`, `{{old_ip}}`, `{{new_ip}}`, `{{status}}`,
+/// `{{error}}`, `{{timestamp}}`, `{{failover}}` (why the event was sent to the failover notifier,
+/// empty otherwise), `{{test}}` (`true` for `skysync test-notify`, `false` otherwise), `{{proxied}}`
+/// (`true` for records Cloudflare proxies), `{{dashboard_link}}` (the record in the DNS provider's
+/// console with `INCLUDE_DASHBOARD_LINK`, empty otherwise), `{{changes}}` (every record a coalesced update changed,
+/// separated by `; `, empty otherwise), `{{profile}}` (the profile of `PROFILES` the event comes
+/// from, empty otherwise) and, with `ENRICH_GEO`, `{{country}}`, `{{region}}`, `{{isp}}` and `{{asn}}`
+/// replaced.
+pub(crate) fn render_template(template: &Value, event: &UpdateEvent) -> Value {
+    match template {
+        Value::String(s) => {
+            let geo = event.geo.clone().unwrap_or_default();
+
+            Value::String(s
+                .replace("{{domain}}", &event.domain)
+                .replace("{{old_ip}}", event.old_ip.as_deref().unwrap_or(""))
+                .replace("{{new_ip}}", &event.new_ip)
+                .replace("{{status}}", event.status.as_str())
+                .replace("{{error}}", event.status.error().unwrap_or(""))
+                .replace("{{timestamp}}", &event.timestamp.to_rfc3339())
+                .replace("{{failover}}", event.failover.as_deref().unwrap_or(""))
+                .replace("{{test}}", if event.test { "true" } else { "false" })
+                .replace("{{proxied}}", if event.proxied { "true" } else { "false" })
+                .replace("{{dashboard_link}}", event.dashboard_link.as_deref().unwrap_or(""))
+                .replace("{{changes}}", &change_lines(&event.changes).join("; "))
+                .replace("{{profile}}", event.profile.as_deref().unwrap_or(""))
+                .replace("{{country}}", &geo.country)
+                .replace("{{region}}", &geo.region)
+                .replace("{{isp}}", &geo.isp)
+                .replace("{{asn}}", &geo.asn))
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_template(item, event)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(key, value)| (key.clone(), render_template(value, event))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// The signature of `body` with `WEBHOOK_SIGNING_SECRET`, written as `WEBHOOK_SIGNATURE_ENCODING` says.
+#[cfg(feature = "webhook-signing")]
+fn sign(signing: &WebhookSigning, body: &[u8]) -> String {
+    use base64::Engine;
+    use ring::hmac;
+
+    let signature = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, signing.secret.as_bytes()), body);
+    match signing.encoding {
+        SignatureEncoding::Hex => signature.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect(),
+        SignatureEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(signature.as_ref()),
+    }
+}
+
+#[async_trait]
+impl Notifier for GenericWebhookNotifier {
+    fn name(&self) -> &'static str {
+        "generic"
+    }
+
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+        // Serialized up front, so the signature covers the exact bytes sent
+        let body = serde_json::to_vec(&render_template(&self.config.template, event))?;
+
+        let mut request = self.client.post(&self.config.url).header(CONTENT_TYPE, "application/json");
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        #[cfg(feature = "webhook-signing")]
+        if let Some(signing) = &self.config.signing {
+            request = request.header(&signing.header, sign(signing, &body));
+        }
+        let request = request.body(body);
+
+        check_response(request.send().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::EventStatus;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn event() -> UpdateEvent {
+        UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Success)
+    }
+
+    /// Tests that placeholders are substituted inside nested values.
+    #[test]
+    fn test_render_template() {
+        let template = json!({
+            "text": "{{domain}}: {{old_ip}} -> {{new_ip}}",
+            "fields": [{ "status": "{{status}}" }],
+            "count": 1
+        });
+
+        let rendered = render_template(&template, &event());
+        assert_eq!(rendered, json!({
+            "text": "home.example.com: 1.1.1.1 -> 2.2.2.2",
+            "fields": [{ "status": "success" }],
+            "count": 1
+        }));
+    }
+
+    /// Tests that the rendered body and custom headers reach the endpoint.
+    #[tokio::test]
+    async fn test_notify_posts_template() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("Authorization", "Bearer secret"))
+            .and(body_json(json!({ "msg": "2.2.2.2 (success)" })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = GenericWebhookNotifier::new(GenericWebhookConfig {
+            url: server.uri(),
+            template: json!({ "msg": "{{new_ip}} ({{status}})" }),
+            headers: vec![("Authorization".to_string(), "Bearer secret".to_string())],
+            signing: None,
+        }, reqwest::Client::new());
+
+        notifier.notify(&event()).await.unwrap();
+    }
+
+    /// The signing settings of the tests.
+    #[cfg(feature = "webhook-signing")]
+    fn signing(secret: &str, encoding: SignatureEncoding) -> WebhookSigning {
+        WebhookSigning { secret: secret.to_string(), header: "X-Signature".to_string(), encoding }
+    }
+
+    /// Tests that signatures match HMAC-SHA256 computed elsewhere for a known body and secret,
+    /// in both encodings.
+    #[cfg(feature = "webhook-signing")]
+    #[test]
+    fn test_sign_known_vector() {
+        let body = b"The quick brown fox jumps over the lazy dog";
+
+        assert_eq!(sign(&signing("key", SignatureEncoding::Hex), body), "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+        assert_eq!(sign(&signing("key", SignatureEncoding::Base64), body), "97yD9DBThCSxMpjmqm+xQ+9NWaFJRhdZl0edvC0aPNg=");
+    }
+
+    /// Tests that the signature of the body as sent arrives in the configured header.
+    #[cfg(feature = "webhook-signing")]
+    #[tokio::test]
+    async fn test_notify_signs_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("X-Signature", "734c6b700db971e46cbbea5af1bec58d9480a87e5ae1d5b6de1e15344d3670c6"))
+            .and(header("Content-Type", "application/json"))
+            .and(body_json(json!({ "msg": "2.2.2.2 (success)" })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = GenericWebhookNotifier::new(GenericWebhookConfig {
+            url: server.uri(),
+            template: json!({ "msg": "{{new_ip}} ({{status}})" }),
+            headers: Vec::new(),
+            signing: Some(signing("s3cret", SignatureEncoding::Hex)),
+        }, reqwest::Client::new());
+
+        notifier.notify(&event()).await.unwrap();
+    }
+
+    /// Tests that an error status from the endpoint is reported.
+    #[tokio::test]
+    async fn test_notify_reports_error_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let notifier = GenericWebhookNotifier::new(GenericWebhookConfig {
+            url: server.uri(),
+            template: json!({}),
+            headers: Vec::new(),
+            signing: None,
+        }, reqwest::Client::new());
+
+        assert!(notifier.notify(&event()).await.is_err());
+    }
+}
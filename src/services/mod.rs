@@ -1,3 +1,29 @@
+#[cfg(any(feature = "route53", feature = "sns"))]
+pub(crate) mod aws;
 pub(crate) mod cloudflare;
+#[cfg(feature = "desktop")]
+pub(crate) mod desktop;
 pub(crate) mod proxmox;
 pub(crate) mod discord;
+#[cfg(feature = "email")]
+pub(crate) mod email;
+pub(crate) mod gandi;
+pub(crate) mod generic;
+pub(crate) mod porkbun;
+#[cfg(feature = "pushover")]
+pub(crate) mod pushover;
+#[cfg(feature = "route53")]
+pub(crate) mod route53;
+pub(crate) mod slack;
+#[cfg(feature = "sns")]
+pub(crate) mod sns;
+#[cfg(feature = "teams")]
+pub(crate) mod teams;
+#[cfg(all(unix, feature = "systemd"))]
+pub(crate) mod systemd;
+#[cfg(feature = "upnp")]
+pub(crate) mod upnp;
+#[cfg(all(windows, feature = "windows-service"))]
+pub(crate) mod windows;
+#[cfg(any(feature = "upnp", feature = "route53"))]
+pub(crate) mod xml;
@@ -0,0 +1,173 @@
+use crate::config::PorkbunConfig;
+use crate::error::BoxError;
+use crate::http;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+/// A record as Porkbun lists it. Porkbun sends numbers as strings, so they're kept as sent.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct PorkbunRecord {
+    pub id: String,
+    /// The full name, e.g. `home.example.com`, or the domain itself for the apex.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub r#type: String,
+    pub content: String,
+    #[serde(default)]
+    pub ttl: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+/// The API keys, which Porkbun expects in the body of every request rather than a header.
+#[derive(Serialize)]
+pub(crate) struct Credentials<'a> {
+    pub apikey: &'a str,
+    pub secretapikey: &'a str,
+}
+
+impl Credentials<'_> {
+    pub fn of(config: &PorkbunConfig) -> Credentials<'_> {
+        Credentials { apikey: &config.api_key, secretapikey: &config.secret_api_key }
+    }
+}
+
+/// The body of an edit or create request.
+#[derive(Serialize)]
+pub(crate) struct RecordBody<'a> {
+    #[serde(flatten)]
+    pub credentials: Credentials<'a>,
+    /// The name relative to the domain, empty for the apex.
+    pub name: &'a str,
+    #[serde(rename = "type")]
+    pub r#type: &'a str,
+    pub content: &'a str,
+    pub ttl: String,
+}
+
+/// The records of a retrieve response.
+#[derive(Deserialize)]
+struct Records {
+    records: Vec<PorkbunRecord>,
+}
+
+/// The answer of the ping endpoint.
+#[derive(Deserialize)]
+struct Ping {
+    #[serde(rename = "yourIp")]
+    your_ip: String,
+}
+
+/// The fields every Porkbun response carries.
+#[derive(Deserialize, Debug)]
+struct Envelope {
+    status: String,
+    #[serde(default)]
+    message: String,
+}
+
+/// A Porkbun response that didn't carry what was asked for.
+#[derive(Debug, PartialEq)]
+pub(crate) enum PorkbunError {
+    /// Porkbun reported the request as failed, e.g. bad keys or API access not enabled for the domain.
+    Api { status: u16, message: String },
+    /// The body isn't a Porkbun API response at all, e.g. an error page from a proxy.
+    Unexpected { status: u16, body: String },
+}
+
+impl fmt::Display for PorkbunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PorkbunError::Api { status, message } => write!(f, "Porkbun reported a failure (HTTP {}): {}", status, message),
+            PorkbunError::Unexpected { status, body } => write!(f, "Unexpected Porkbun response (HTTP {}): {}", status, body),
+        }
+    }
+}
+
+impl Error for PorkbunError {}
+
+/// Reads a response body, which is a success only when its `status` says so.
+///
+/// # Arguments
+/// * `status` - The HTTP status of the response.
+/// * `body` - The raw body.
+///
+/// # Returns
+/// The body read as `T`, or what Porkbun said went wrong.
+pub(crate) fn parse<T: DeserializeOwned>(status: u16, body: &str) -> Result<T, PorkbunError> {
+    let unexpected = |err: Option<serde_json::Error>| PorkbunError::Unexpected {
+        status,
+        body: match err {
+            Some(err) => format!("{} ({})", body.trim(), err),
+            None => body.trim().to_string(),
+        },
+    };
+    match serde_json::from_str::<Envelope>(body) {
+        Ok(envelope) if envelope.status == "SUCCESS" => serde_json::from_str(body).map_err(|err| unexpected(Some(err))),
+        Ok(envelope) if !envelope.message.is_empty() => Err(PorkbunError::Api { status, message: envelope.message }),
+        _ => Err(unexpected(None)),
+    }
+}
+
+/// POSTs `body` to `url` and reads the response as `T`.
+///
+/// # Returns
+/// The body read as `T`, what Porkbun said went wrong, or a `StatusError` explained with it for a
+/// failed status.
+async fn post<T: DeserializeOwned>(client: &reqwest::Client, url: &str, body: &impl Serialize) -> Result<T, BoxError> {
+    let response = match http::classify_response(client.post(url).json(body).send().await?).await {
+        Ok(response) => response,
+        Err(err) => {
+            return Err(match parse::<Envelope>(err.status, &err.detail) {
+                Err(explained) => err.explained(explained),
+                Ok(_) => err,
+            }.into());
+        }
+    };
+    let status = response.status().as_u16();
+    Ok(parse(status, &response.text().await?)?)
+}
+
+/// Lists every record of a domain.
+///
+/// # Arguments
+/// * `domain` - The domain to list, e.g. `example.com`.
+pub(crate) async fn retrieve(client: &reqwest::Client, config: &PorkbunConfig, domain: &str) -> Result<Vec<PorkbunRecord>, BoxError> {
+    let url = format!("{}/dns/retrieve/{}", config.api_url, domain);
+    let records: Records = post(client, &url, &Credentials::of(config)).await?;
+    Ok(records.records)
+}
+
+/// Replaces the fields of one record.
+///
+/// # Arguments
+/// * `domain` - The domain the record lives in.
+/// * `id` - The ID Porkbun listed the record with.
+/// * `body` - The new fields.
+pub(crate) async fn edit(client: &reqwest::Client, config: &PorkbunConfig, domain: &str, id: &str, body: &RecordBody<'_>) -> Result<(), BoxError> {
+    let url = format!("{}/dns/edit/{}/{}", config.api_url, domain, id);
+    post::<Envelope>(client, &url, body).await?;
+    Ok(())
+}
+
+/// Creates a record that doesn't exist yet.
+///
+/// # Arguments
+/// * `domain` - The domain the record is created in.
+/// * `body` - The fields of the record.
+pub(crate) async fn create(client: &reqwest::Client, config: &PorkbunConfig, domain: &str, body: &RecordBody<'_>) -> Result<(), BoxError> {
+    let url = format!("{}/dns/create/{}", config.api_url, domain);
+    post::<Envelope>(client, &url, body).await?;
+    Ok(())
+}
+
+/// Asks the ping endpoint which address the request came from, which doubles as a check of the keys.
+///
+/// # Arguments
+/// * `url` - The ping endpoint, e.g. `https://api-ipv4.porkbun.com/api/json/v3/ping`.
+pub(crate) async fn ping(client: &reqwest::Client, config: &PorkbunConfig, url: &str) -> Result<String, BoxError> {
+    let ping: Ping = post(client, url, &Credentials::of(config)).await?;
+    Ok(ping.your_ip)
+}
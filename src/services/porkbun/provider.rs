@@ -0,0 +1,264 @@
+use crate::config::{PorkbunConfig, ZoneConfig, DEFAULT_PORKBUN_API_URL};
+use crate::error::BoxError;
+use crate::ip::IpSource;
+use crate::provider::{DnsProvider, DnsRecord};
+use crate::services::porkbun::api::{create, edit, ping, retrieve, Credentials, PorkbunRecord, RecordBody};
+use async_trait::async_trait;
+
+/// The TTL records are created with, the lowest Porkbun accepts.
+const CREATED_TTL: i64 = 600;
+
+/// The ping endpoint of Porkbun's IPv4-only host. The main one answers over IPv6 too, and then
+/// reports an address A records can't point at.
+const IPV4_PING_URL: &str = "https://api-ipv4.porkbun.com/api/json/v3/ping";
+
+/// Keeps records hosted on Porkbun in sync, across any number of domains.
+///
+/// Porkbun lists every record of a domain at once, so each domain is read with a single request
+/// and only the records of the configured type are kept.
+pub struct PorkbunProvider {
+    config: PorkbunConfig,
+    zones: Vec<ZoneConfig>,
+    client: reqwest::Client,
+}
+
+impl PorkbunProvider {
+    pub fn new(config: PorkbunConfig, zones: Vec<ZoneConfig>, client: reqwest::Client) -> PorkbunProvider {
+        PorkbunProvider { config, zones, client }
+    }
+
+    /// Lists records of `record_type` instead of following `PORKBUN_RECORD_TYPE`.
+    pub fn with_record_type(mut self, record_type: &str) -> PorkbunProvider {
+        self.config.record_type = record_type.to_ascii_uppercase();
+        self
+    }
+}
+
+/// The name of `name` relative to `domain`, the way Porkbun writes records: empty for the apex,
+/// `home` for `home.example.com`. Names that are already relative are kept.
+fn relative(name: &str, domain: &str) -> String {
+    let name = name.trim().trim_end_matches('.').to_ascii_lowercase();
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    if name == "@" || name == domain {
+        String::new()
+    } else {
+        name.strip_suffix(&format!(".{}", domain)).unwrap_or(&name).to_string()
+    }
+}
+
+/// Turns a listed record into a record.
+fn from_record(domain: &str, record: PorkbunRecord) -> DnsRecord {
+    DnsRecord {
+        id: record.id,
+        zone_id: domain.to_string(),
+        zone_name: domain.to_string(),
+        name: record.name,
+        r#type: record.r#type,
+        content: record.content,
+        ttl: record.ttl.parse().unwrap_or(CREATED_TTL),
+        proxied: false,
+        comment: record.notes.filter(|notes| !notes.is_empty()),
+        tags: Vec::new(),
+        priority: None,
+    }
+}
+
+#[async_trait]
+impl DnsProvider for PorkbunProvider {
+    fn name(&self) -> &'static str {
+        "porkbun"
+    }
+
+    async fn list_records(&self) -> Result<Vec<DnsRecord>, BoxError> {
+        let mut records = Vec::new();
+        for zone in &self.zones {
+            let listed = retrieve(&self.client, &self.config, &zone.zone_id)
+                .await
+                .map_err(|err| format!("domain {}: {}", zone.zone_id, err))?;
+            records.extend(listed.into_iter()
+                .filter(|record| record.r#type == self.config.record_type)
+                .map(|record| from_record(&zone.zone_id, record)));
+        }
+        Ok(records)
+    }
+
+    async fn update_record(&self, record: &DnsRecord, content: &str) -> Result<(), BoxError> {
+        let name = relative(&record.fqdn(), &record.zone_id);
+        let body = RecordBody {
+            credentials: Credentials::of(&self.config),
+            name: &name,
+            r#type: &record.r#type,
+            content,
+            ttl: record.ttl.to_string(),
+        };
+        edit(&self.client, &self.config, &record.zone_id, &record.id, &body).await
+    }
+
+    async fn create_record(&self, name: &str, content: &str) -> Result<(), BoxError> {
+        let Some(zone) = self.zones.iter().find(|zone| zone.records.iter().any(|record| record == name)) else {
+            return Err(format!("{} isn't listed in any configured domain", name).into());
+        };
+
+        let relative = relative(name, &zone.zone_id);
+        let body = RecordBody {
+            credentials: Credentials::of(&self.config),
+            name: &relative,
+            r#type: &self.config.record_type,
+            content,
+            ttl: CREATED_TTL.to_string(),
+        };
+        create(&self.client, &self.config, &zone.zone_id, &body).await
+    }
+}
+
+/// Reads the public IP from Porkbun's ping endpoint, with the keys of `DNS_PROVIDER=porkbun`.
+pub struct PorkbunIpSource {
+    config: PorkbunConfig,
+    url: String,
+    client: reqwest::Client,
+}
+
+impl PorkbunIpSource {
+    /// Pings the IPv4-only host for A records, unless `PORKBUN_API_URL` points elsewhere.
+    pub fn new(config: PorkbunConfig, client: reqwest::Client) -> PorkbunIpSource {
+        let url = if config.api_url == DEFAULT_PORKBUN_API_URL && config.record_type == "A" {
+            IPV4_PING_URL.to_string()
+        } else {
+            format!("{}/ping", config.api_url)
+        };
+        PorkbunIpSource { config, url, client }
+    }
+}
+
+#[async_trait]
+impl IpSource for PorkbunIpSource {
+    async fn public_ip(&self) -> Result<String, BoxError> {
+        ping(&self.client, &self.config, &self.url).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Porkbun settings talking to `server`.
+    fn config(server: &MockServer) -> PorkbunConfig {
+        PorkbunConfig {
+            api_key: "pk1_key".to_string(),
+            secret_api_key: "sk1_secret".to_string(),
+            api_url: server.uri(),
+            record_type: "A".to_string(),
+        }
+    }
+
+    /// A provider for `example.com` talking to `server`.
+    fn provider(server: &MockServer) -> PorkbunProvider {
+        let zones = vec![ZoneConfig { zone_id: "example.com".to_string(), records: vec!["home".to_string(), "@".to_string()] }];
+        PorkbunProvider::new(config(server), zones, reqwest::Client::new())
+    }
+
+    /// Tests that a domain is retrieved with the keys in the body, and that only records of the
+    /// configured type are kept.
+    #[tokio::test]
+    async fn test_retrieve_records() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/dns/retrieve/example.com"))
+            .and(body_json(json!({ "apikey": "pk1_key", "secretapikey": "sk1_secret" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "status": "SUCCESS",
+                "records": [
+                    { "id": "106926652", "name": "home.example.com", "type": "A", "content": "1.1.1.1", "ttl": "600", "prio": "0", "notes": "" },
+                    { "id": "106926653", "name": "example.com", "type": "MX", "content": "mail.example.com", "ttl": "3600", "prio": "10", "notes": null }
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let records = provider(&server).list_records().await.unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].id.as_str(), records[0].content.as_str(), records[0].ttl), ("106926652", "1.1.1.1", 600));
+        assert_eq!(records[0].comment, None);
+        assert!(records[0].matches("home"));
+    }
+
+    /// Tests that an update edits the record by ID, with its name relative to the domain and its TTL kept.
+    #[tokio::test]
+    async fn test_edit_record() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/dns/edit/example.com/106926652"))
+            .and(body_json(json!({
+                "apikey": "pk1_key",
+                "secretapikey": "sk1_secret",
+                "name": "home",
+                "type": "A",
+                "content": "2.2.2.2",
+                "ttl": "600"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "SUCCESS" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let record = DnsRecord {
+            id: "106926652".to_string(),
+            zone_id: "example.com".to_string(),
+            zone_name: "example.com".to_string(),
+            name: "home.example.com".to_string(),
+            r#type: "A".to_string(),
+            content: "1.1.1.1".to_string(),
+            ttl: 600,
+            proxied: false,
+            comment: None,
+            tags: Vec::new(),
+            priority: None,
+        };
+
+        provider(&server).update_record(&record, "2.2.2.2").await.unwrap();
+    }
+
+    /// Tests that Porkbun's error message is reported, even when it answers with HTTP 200.
+    #[tokio::test]
+    async fn test_reports_error_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/dns/create/example.com"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "ERROR", "message": "Invalid API key. (002)" })))
+            .mount(&server)
+            .await;
+
+        let err = provider(&server).create_record("@", "2.2.2.2").await.unwrap_err();
+
+        assert_eq!(err.to_string(), "Porkbun reported a failure (HTTP 200): Invalid API key. (002)");
+    }
+
+    /// Tests that the ping endpoint's answer is read as the public IP.
+    #[tokio::test]
+    async fn test_ping_ip_source() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/ping"))
+            .and(body_json(json!({ "apikey": "pk1_key", "secretapikey": "sk1_secret" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "SUCCESS", "yourIp": "2.2.2.2" })))
+            .mount(&server)
+            .await;
+
+        let source = PorkbunIpSource::new(config(&server), reqwest::Client::new());
+
+        assert_eq!(source.public_ip().await.unwrap(), "2.2.2.2");
+    }
+
+    /// Tests that names are written relative to their domain.
+    #[test]
+    fn test_relative() {
+        assert_eq!(relative("example.com", "example.com"), "");
+        assert_eq!(relative("@", "example.com"), "");
+        assert_eq!(relative("Home.Example.com.", "example.com"), "home");
+        assert_eq!(relative("*", "example.com"), "*");
+    }
+}
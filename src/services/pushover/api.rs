@@ -0,0 +1,187 @@
+use crate::config::PushoverConfig;
+use crate::error::BoxError;
+use crate::notifier::{change_lines, check_response, circuit_open, degraded, downtime, reconciled, recovered, started, stopped, throttled, EventStatus, Notifier, RateLimited, UpdateEvent};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Priorities Pushover defines, from silent to repeating until acknowledged.
+const LOW_PRIORITY: i8 = -1;
+const NORMAL_PRIORITY: i8 = 0;
+const HIGH_PRIORITY: i8 = 1;
+const EMERGENCY_PRIORITY: i8 = 2;
+
+/// How often, in seconds, an emergency notification repeats until it's acknowledged. Pushover's
+/// minimum is 30.
+const EMERGENCY_RETRY: u32 = 60;
+/// How long, in seconds, an emergency notification keeps repeating. Pushover's maximum is 10800.
+const EMERGENCY_EXPIRE: u32 = 3600;
+
+/// Sends update events as Pushover push notifications.
+pub struct PushoverNotifier {
+    config: PushoverConfig,
+    client: reqwest::Client,
+}
+
+impl PushoverNotifier {
+    pub fn new(config: PushoverConfig, client: reqwest::Client) -> PushoverNotifier {
+        PushoverNotifier { config, client }
+    }
+}
+
+/// Builds the message request for an event.
+///
+/// Failures are sent with high priority, and escalated outages as emergencies that repeat every
+/// minute for an hour until acknowledged.
+///
+/// # Arguments
+/// * `config` - The application token and user key to send with.
+/// * `event` - The event to format.
+pub(crate) fn message(config: &PushoverConfig, event: &UpdateEvent) -> Value {
+    let (title, priority) = match &event.status {
+        EventStatus::Success => (format!("{} updated", event.domain), NORMAL_PRIORITY),
+        EventStatus::Failure(_) => (format!("Failed to update {}", event.domain), HIGH_PRIORITY),
+        EventStatus::Escalated { minutes, .. } => (format!("{} still failing after {} minutes", event.domain, minutes), EMERGENCY_PRIORITY),
+        EventStatus::Resolved { minutes } => (format!("{} resolved after {} minutes", event.domain, minutes), NORMAL_PRIORITY),
+        EventStatus::Changed => (format!("Public IP changed, {} not updated", event.domain), NORMAL_PRIORITY),
+        EventStatus::Reconciled { updated, current, failed } => (reconciled(*updated, *current, *failed), NORMAL_PRIORITY),
+        EventStatus::Downtime { minutes } => (downtime(*minutes), NORMAL_PRIORITY),
+        EventStatus::Recreated => (format!("{} recreated after it was deleted", event.domain), NORMAL_PRIORITY),
+        EventStatus::Drifted => (format!("{} was changed by hand, pointing it back", event.domain), NORMAL_PRIORITY),
+        EventStatus::Throttled { changes, minutes } => (format!("{}, now {}", throttled(*changes, *minutes), event.new_ip), NORMAL_PRIORITY),
+        EventStatus::Degraded { failures, .. } => (format!("{} {}", event.domain, degraded(*failures)), HIGH_PRIORITY),
+        EventStatus::Recovered { failures } => (format!("{} {}", event.domain, recovered(*failures)), NORMAL_PRIORITY),
+        EventStatus::CircuitOpen { failures, minutes, .. } => (format!("SkySync {}", circuit_open(*failures, *minutes)), HIGH_PRIORITY),
+        EventStatus::Started { version, .. } => (started(version), LOW_PRIORITY),
+        EventStatus::Stopped { minutes } => (stopped(*minutes), LOW_PRIORITY),
+    };
+    let title = if event.test { format!("[Test] {}", title) } else { title };
+
+    let mut lines = if event.changes.is_empty() {
+        vec![format!("{} -> {}", event.old_ip.as_deref().unwrap_or("-"), event.new_ip)]
+    } else {
+        change_lines(&event.changes)
+    };
+    if let Some(error) = event.status.error() {
+        lines.push(format!("Error: {}", error));
+    }
+    if let Some(geo) = &event.geo {
+        lines.push(format!("Location: {}", geo));
+    }
+    if event.proxied {
+        lines.push("Proxied by Cloudflare: public DNS shows Cloudflare's addresses".to_string());
+    }
+    if let Some(reason) = &event.failover {
+        lines.push(format!("Sent as the failover: {}", reason));
+    }
+
+    let mut body = json!({
+        "token": config.token,
+        "user": config.user,
+        "title": title,
+        "message": lines.join("\n"),
+        "priority": priority,
+        "timestamp": event.timestamp.timestamp(),
+    });
+    // Pushover shows the link under the message, opened with a tap
+    if let Some(link) = &event.dashboard_link {
+        body["url"] = json!(link);
+        body["url_title"] = json!("Open in the DNS dashboard");
+    }
+    if priority == EMERGENCY_PRIORITY {
+        body["retry"] = json!(EMERGENCY_RETRY);
+        body["expire"] = json!(EMERGENCY_EXPIRE);
+    }
+    body
+}
+
+#[async_trait]
+impl Notifier for PushoverNotifier {
+    fn name(&self) -> &'static str {
+        "pushover"
+    }
+
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+        let response = self.client.post(&self.config.api_url).json(&message(&self.config, event)).send().await?;
+
+        // Over its monthly limit, Pushover says when the limit resets as a Unix timestamp
+        let status = response.status();
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let after = response.headers().get("x-limit-app-reset")
+                .and_then(|reset| reset.to_str().ok()?.trim().parse::<u64>().ok())
+                .map(|reset| Duration::from_secs(reset.saturating_sub(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())));
+            return Err(Box::new(RateLimited { after }));
+        }
+        // Pushover explains rejections like an invalid user key in its `errors` list
+        if status.is_client_error() {
+            let body = response.json::<Value>().await.unwrap_or_default();
+            return Err(format!("Pushover rejected the message ({}): {}", status, body["errors"]).into());
+        }
+        check_response(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn notifier(server: &MockServer) -> PushoverNotifier {
+        let config = PushoverConfig { token: "app-token".to_string(), user: "user-key".to_string(), api_url: server.uri() };
+        PushoverNotifier::new(config, reqwest::Client::new())
+    }
+
+    /// Tests that a failure is posted with the token, user and a high priority.
+    #[tokio::test]
+    async fn test_notify_posts_message() {
+        let event = UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Failure("rejected".to_string()));
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "token": "app-token",
+                "user": "user-key",
+                "title": "Failed to update home.example.com",
+                "message": "1.1.1.1 -> 2.2.2.2\nError: rejected",
+                "priority": 1
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": 1, "request": "id" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        notifier(&server).notify(&event).await.unwrap();
+    }
+
+    /// Tests that escalations are emergencies that repeat, and other events don't.
+    #[test]
+    fn test_escalation_is_emergency() {
+        let config = PushoverConfig { token: "app-token".to_string(), user: "user-key".to_string(), api_url: String::new() };
+        let status = EventStatus::Escalated { error: "rejected".to_string(), minutes: 60 };
+
+        let body = message(&config, &UpdateEvent::new("home.example.com", None, "2.2.2.2", status));
+        assert_eq!((&body["priority"], &body["retry"], &body["expire"]), (&json!(2), &json!(60), &json!(3600)));
+
+        let body = message(&config, &UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Success));
+        assert_eq!(body["priority"], 0);
+        assert!(body.get("retry").is_none());
+    }
+
+    /// Tests that running out of messages waits for the reset Pushover reports.
+    #[tokio::test]
+    async fn test_notify_honors_limit_reset() {
+        let reset = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 30;
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("X-Limit-App-Reset", reset.to_string().as_str()))
+            .mount(&server)
+            .await;
+
+        let event = UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Success);
+        let err = notifier(&server).notify(&event).await.unwrap_err();
+
+        let after = err.downcast_ref::<RateLimited>().unwrap().after.unwrap();
+        assert!(after > Duration::from_secs(25) && after <= Duration::from_secs(30), "{:?}", after);
+    }
+}
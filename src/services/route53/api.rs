@@ -0,0 +1,167 @@
+use crate::config::Route53Config;
+use crate::error::BoxError;
+use crate::services::aws::sdk_config;
+use crate::services::xml::{element, elements};
+use aws_sdk_route53::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_route53::operation::change_resource_record_sets::ChangeResourceRecordSetsError;
+use aws_sdk_route53::types::{self, Change, ChangeAction, ChangeBatch, ResourceRecord, RrType};
+use aws_sdk_route53::Client;
+use std::error::Error;
+use std::fmt;
+
+/// Route53 is a global service, signed for this region whatever the region of the zone.
+const REGION: &str = "us-east-1";
+
+/// A record set as Route53 lists it: every value of one name and type.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ResourceRecordSet {
+    /// The full name, without the trailing dot Route53 adds.
+    pub name: String,
+    pub r#type: String,
+    pub ttl: i64,
+    pub values: Vec<String>,
+}
+
+/// A failure Route53 reported.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Route53Error {
+    pub status: u16,
+    /// The error code, e.g. `InvalidChangeBatch` or `AccessDenied`.
+    pub code: String,
+    pub messages: Vec<String>,
+}
+
+impl fmt::Display for Route53Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Route53 reported a failure (HTTP {}): {}", self.status, self.code)?;
+        if !self.messages.is_empty() {
+            write!(f, ": {}", self.messages.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Route53Error {}
+
+/// Reads the error of a failed response, either an `ErrorResponse` or the `InvalidChangeBatch`
+/// Route53 answers rejected changes with.
+pub(crate) fn parse_error(status: u16, body: &str) -> Route53Error {
+    let code = element(body, "Code")
+        .or_else(|| body.contains("<InvalidChangeBatch").then_some("InvalidChangeBatch"))
+        .unwrap_or("UnknownError");
+    let mut messages: Vec<String> = elements(body, "Message").into_iter()
+        .map(|message| message.trim().to_string())
+        .filter(|message| !message.is_empty())
+        .collect();
+    if messages.is_empty() && element(body, "Code").is_none() {
+        messages.push(body.trim().to_string());
+    }
+    Route53Error { status, code: code.to_string(), messages }
+}
+
+/// Builds the client every request is sent with, signed with the configured keys or the
+/// credentials the standard AWS chain finds.
+pub(crate) async fn client(config: &Route53Config) -> Result<Client, BoxError> {
+    let sdk = sdk_config(config.credentials.as_ref(), &config.metadata_url, &config.api_url, REGION).await?;
+    Ok(Client::new(&sdk))
+}
+
+/// Explains a failed call with what Route53 answered, or with the whole chain of causes when the
+/// request didn't get an answer, e.g. because no credentials were found.
+///
+/// The SDK only reads errors wrapped in an `ErrorResponse`, so the body of any other answer, like
+/// the bare `InvalidChangeBatch` of rejected changes, is read with `parse_error`.
+///
+/// # Arguments
+/// * `err` - The failure of the call.
+/// * `messages` - The messages of the error, when it carries a list of them rather than one.
+fn explain<E>(err: SdkError<E>, messages: Vec<String>) -> BoxError
+where
+    E: ProvideErrorMetadata + Error + 'static,
+{
+    let Some(response) = err.raw_response() else {
+        return DisplayErrorContext(&err).to_string().into();
+    };
+    let status = response.status().as_u16();
+    match err.as_service_error().filter(|service| service.code().is_some()) {
+        Some(service) => {
+            let messages = match messages.is_empty() {
+                true => service.message().map(|message| message.trim().to_string()).into_iter().collect(),
+                false => messages,
+            };
+            Box::new(Route53Error { status, code: service.code().unwrap_or_default().to_string(), messages })
+        }
+        None => {
+            let body = response.body().bytes().map(String::from_utf8_lossy).unwrap_or_default();
+            Box::new(parse_error(status, &body))
+        }
+    }
+}
+
+/// Turns a record set the SDK read into one of ours.
+fn from_sdk(set: &types::ResourceRecordSet) -> ResourceRecordSet {
+    ResourceRecordSet {
+        // Route53 escapes the wildcard label as an octal code
+        name: set.name().trim_end_matches('.').replace("\\052", "*"),
+        r#type: set.r#type().as_str().to_string(),
+        ttl: set.ttl().unwrap_or_default(),
+        values: set.resource_records().iter().map(|record| record.value().trim().to_string()).collect(),
+    }
+}
+
+/// Reads one record set with `ListResourceRecordSets`.
+///
+/// # Arguments
+/// * `zone` - The ID of the hosted zone.
+/// * `name` - The full name of the record set.
+/// * `rrset_type` - The type of the record set, e.g. `A`.
+///
+/// # Returns
+/// The record set, or `None` if the zone has no record set of that name and type.
+pub(crate) async fn get_record_set(client: &Client, zone: &str, name: &str, rrset_type: &str) -> Result<Option<ResourceRecordSet>, BoxError> {
+    let listing = client.list_resource_record_sets()
+        .hosted_zone_id(zone)
+        .start_record_name(name)
+        .start_record_type(RrType::from(rrset_type))
+        .max_items(1)
+        .send()
+        .await
+        .map_err(|err| explain(err, Vec::new()))?;
+
+    // The listing starts at the name asked for, so the first set is a later one if it's missing
+    let wanted = name.trim_end_matches('.');
+    Ok(listing.resource_record_sets().iter().map(from_sdk)
+        .find(|set| set.name.eq_ignore_ascii_case(wanted) && set.r#type == rrset_type))
+}
+
+/// Creates or replaces a record set with `ChangeResourceRecordSets`.
+///
+/// # Arguments
+/// * `zone` - The ID of the hosted zone.
+/// * `set` - The full desired state of the record set.
+pub(crate) async fn upsert_record_set(client: &Client, zone: &str, set: &ResourceRecordSet) -> Result<(), BoxError> {
+    let mut record_set = types::ResourceRecordSet::builder()
+        .name(format!("{}.", set.name))
+        .r#type(RrType::from(set.r#type.as_str()))
+        .ttl(set.ttl);
+    for value in &set.values {
+        record_set = record_set.resource_records(ResourceRecord::builder().value(value).build()?);
+    }
+    let change = Change::builder().action(ChangeAction::Upsert).resource_record_set(record_set.build()?).build()?;
+    let batch = ChangeBatch::builder().comment("Updated by SkySync").changes(change).build()?;
+
+    client.change_resource_record_sets()
+        .hosted_zone_id(zone)
+        .change_batch(batch)
+        .send()
+        .await
+        .map_err(|err| {
+            // Rejected changes come with one message per problem
+            let messages = match err.as_service_error() {
+                Some(ChangeResourceRecordSetsError::InvalidChangeBatch(batch)) => batch.messages().to_vec(),
+                _ => Vec::new(),
+            };
+            explain(err, messages)
+        })?;
+    Ok(())
+}
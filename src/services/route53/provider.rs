@@ -0,0 +1,243 @@
+use crate::config::{Route53Config, ZoneConfig};
+use crate::error::BoxError;
+use crate::provider::{DnsProvider, DnsRecord};
+use crate::services::route53::api::{self, get_record_set, upsert_record_set, ResourceRecordSet};
+use async_trait::async_trait;
+use aws_sdk_route53::Client;
+use tokio::sync::OnceCell;
+
+/// The TTL records are created with.
+const CREATED_TTL: i64 = 300;
+
+/// Keeps records hosted on AWS Route53 in sync, across any number of hosted zones.
+///
+/// Route53 groups records into record sets of one name and type, so each managed name is read
+/// and written as the record set of the configured type. Writes are `UPSERT`s, which replace
+/// every value of the set.
+pub struct Route53Provider {
+    config: Route53Config,
+    zones: Vec<ZoneConfig>,
+    /// The SDK client, built on the first request since finding the credentials takes a
+    /// round trip.
+    client: OnceCell<Client>,
+}
+
+impl Route53Provider {
+    pub fn new(config: Route53Config, zones: Vec<ZoneConfig>) -> Route53Provider {
+        Route53Provider { config, zones, client: OnceCell::new() }
+    }
+
+    /// Lists record sets of `record_type` instead of following `ROUTE53_RECORD_TYPE`.
+    pub fn with_record_type(mut self, record_type: &str) -> Route53Provider {
+        self.config.record_type = record_type.to_ascii_uppercase();
+        self
+    }
+
+    async fn client(&self) -> Result<&Client, BoxError> {
+        self.client.get_or_try_init(|| api::client(&self.config)).await
+    }
+}
+
+/// Turns a record set into a record. Only its first value is kept, since SkySync points every
+/// record at a single address.
+fn from_record_set(zone: &str, set: ResourceRecordSet) -> DnsRecord {
+    DnsRecord {
+        id: format!("{}/{}", set.name, set.r#type),
+        zone_id: zone.to_string(),
+        // Hosted zones are addressed by ID, and the names SkySync is given are already full
+        zone_name: String::new(),
+        name: set.name,
+        r#type: set.r#type,
+        content: set.values.into_iter().next().unwrap_or_default(),
+        ttl: set.ttl,
+        proxied: false,
+        comment: None,
+        tags: Vec::new(),
+        priority: None,
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Route53Provider {
+    fn name(&self) -> &'static str {
+        "route53"
+    }
+
+    /// The record sets of the record's hosted zone in the AWS console.
+    fn dashboard_url(&self, record: &DnsRecord) -> Option<String> {
+        let zone = record.zone_id.trim_start_matches("/hostedzone/");
+        Some(format!("https://console.aws.amazon.com/route53/v2/hostedzones#ListRecordSets/{}", zone))
+    }
+
+    async fn list_records(&self) -> Result<Vec<DnsRecord>, BoxError> {
+        let client = self.client().await?;
+        let mut records = Vec::new();
+        for zone in &self.zones {
+            for name in &zone.records {
+                let set = get_record_set(client, &zone.zone_id, name, &self.config.record_type)
+                    .await
+                    .map_err(|err| format!("hosted zone {}: {}", zone.zone_id, err))?;
+                records.extend(set.map(|set| from_record_set(&zone.zone_id, set)));
+            }
+        }
+        Ok(records)
+    }
+
+    async fn update_record(&self, record: &DnsRecord, content: &str) -> Result<(), BoxError> {
+        let set = ResourceRecordSet {
+            name: record.fqdn(),
+            r#type: record.r#type.clone(),
+            ttl: record.ttl,
+            values: vec![content.to_string()],
+        };
+        upsert_record_set(self.client().await?, &record.zone_id, &set).await
+    }
+
+    async fn create_record(&self, name: &str, content: &str) -> Result<(), BoxError> {
+        let Some(zone) = self.zones.iter().find(|zone| zone.records.iter().any(|record| record == name)) else {
+            return Err(format!("{} isn't listed in any configured hosted zone", name).into());
+        };
+
+        let set = ResourceRecordSet {
+            name: name.trim_end_matches('.').to_string(),
+            r#type: self.config.record_type.clone(),
+            ttl: CREATED_TTL,
+            values: vec![content.to_string()],
+        };
+        upsert_record_set(self.client().await?, &zone.zone_id, &set).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AwsCredentials;
+    use crate::sync::tests::{test_config, StaticIp};
+    use crate::sync::{State, Syncer, TickOutcome};
+    use wiremock::matchers::{body_string_contains, header_exists, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A provider for one hosted zone on `server`, with `credentials` or the instance profile.
+    fn provider(server: &MockServer, credentials: Option<AwsCredentials>) -> Route53Provider {
+        let config = Route53Config {
+            credentials,
+            api_url: server.uri(),
+            metadata_url: server.uri(),
+            record_type: "A".to_string(),
+        };
+        let zones = vec![ZoneConfig { zone_id: "/hostedzone/Z123".to_string(), records: vec!["home.example.com".to_string()] }];
+        Route53Provider::new(config, zones)
+    }
+
+    fn keys() -> AwsCredentials {
+        AwsCredentials { access_key_id: "AKIDEXAMPLE".to_string(), secret_access_key: "secret".to_string(), session_token: None }
+    }
+
+    /// A `ListResourceRecordSets` answer holding `name` pointed at `ip`.
+    fn listing(name: &str, ip: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\"?>\n<ListResourceRecordSetsResponse xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">\
+             <ResourceRecordSets><ResourceRecordSet><Name>{}.</Name><Type>A</Type><TTL>600</TTL>\
+             <ResourceRecords><ResourceRecord><Value>{}</Value></ResourceRecord></ResourceRecords></ResourceRecordSet>\
+             </ResourceRecordSets><IsTruncated>false</IsTruncated><MaxItems>1</MaxItems></ListResourceRecordSetsResponse>",
+            name, ip,
+        )
+    }
+
+    /// Tests a whole tick: the stale record is read, then upserted with the new IP and its TTL.
+    #[tokio::test]
+    async fn test_reads_and_upserts_record() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/2013-04-01/hostedzone/Z123/rrset"))
+            .and(query_param("name", "home.example.com"))
+            .and(query_param("type", "A"))
+            .and(header_exists("authorization"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(listing("home.example.com", "1.1.1.1")))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/2013-04-01/hostedzone/Z123/rrset"))
+            .and(body_string_contains("<Action>UPSERT</Action><ResourceRecordSet><Name>home.example.com.</Name><Type>A</Type><TTL>600</TTL>"))
+            .and(body_string_contains("<Value>2.2.2.2</Value>"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<ChangeResourceRecordSetsResponse><ChangeInfo><Status>PENDING</Status></ChangeInfo></ChangeResourceRecordSetsResponse>"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let syncer = Syncer::new(Box::new(provider(&server, Some(keys()))), Box::new(StaticIp("2.2.2.2")), Vec::new());
+        let summary = syncer.run_once(&test_config(), &mut State::default()).await.unwrap();
+
+        assert!(matches!(&summary.records[0].result, Ok(TickOutcome::Updated { .. })), "{:?}", summary.records[0].result);
+    }
+
+    /// Tests that a listing starting at a later name means the record is missing.
+    #[tokio::test]
+    async fn test_missing_record_set() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(listing("www.example.com", "1.1.1.1")))
+            .mount(&server)
+            .await;
+
+        assert!(provider(&server, Some(keys())).list_records().await.unwrap().is_empty());
+    }
+
+    /// Tests that a rejected change is reported with Route53's messages.
+    #[tokio::test]
+    async fn test_reports_invalid_change_batch() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(
+                "<?xml version=\"1.0\"?>\n<InvalidChangeBatch xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">\
+                 <Messages><Message>RRSet with DNS name home.example.com. is not permitted in zone other.com.</Message></Messages>\
+                 </InvalidChangeBatch>",
+            ))
+            .mount(&server)
+            .await;
+
+        let err = provider(&server, Some(keys())).create_record("home.example.com", "2.2.2.2").await.unwrap_err();
+
+        assert_eq!(err.to_string(), "Route53 reported a failure (HTTP 400): InvalidChangeBatch: RRSet with DNS name home.example.com. is not permitted in zone other.com.");
+    }
+
+    /// Tests that requests are signed with the instance profile credentials when no keys are
+    /// configured.
+    #[tokio::test]
+    async fn test_instance_profile_credentials() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/latest/api/token"))
+            .respond_with(ResponseTemplate::new(200).insert_header("x-aws-ec2-metadata-token-ttl-seconds", "300").set_body_string("imds-token"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/latest/meta-data/iam/security-credentials/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("skysync-role"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/latest/meta-data/iam/security-credentials/skysync-role"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Code": "Success",
+                "AccessKeyId": "ASIAEXAMPLE",
+                "SecretAccessKey": "secret",
+                "Token": "session",
+                "Expiration": "2030-01-01T00:00:00Z"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/2013-04-01/hostedzone/Z123/rrset"))
+            .and(wiremock::matchers::header("x-amz-security-token", "session"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(listing("home.example.com", "1.1.1.1")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let records = provider(&server, None).list_records().await.unwrap();
+
+        assert_eq!(records[0].content, "1.1.1.1");
+        assert!(records[0].matches("home.example.com"));
+    }
+}
@@ -0,0 +1 @@
+pub(crate) mod webhook;
\ No newline at end of file
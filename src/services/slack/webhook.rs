@@ -0,0 +1,228 @@
+use crate::config::SlackConfig;
+use crate::error::BoxError;
+use crate::notifier::{change_lines, check_response, circuit_open, code_block, degraded, downtime, reconciled, recovered, started, stopped, throttled, EventStatus, Notifier, UpdateEvent};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+/// Slack's own colors for good and bad news, shown as the bar left of the attachment.
+const SUCCESS_COLOR: &str = "#2eb886";
+const FAILURE_COLOR: &str = "#a30200";
+/// Darker than a plain failure, so an escalated outage stands out in the channel.
+const ESCALATED_COLOR: &str = "#5c0011";
+/// Neither good nor bad news, for changes `MODE=monitor` only reports.
+const CHANGED_COLOR: &str = "#439fe0";
+
+/// How many characters of an error are shown. Slack rejects section text over 3000 characters,
+/// and escaping can make an error five times longer.
+const ERROR_LIMIT: usize = 500;
+
+/// Posts update events to a Slack incoming webhook, formatted with Block Kit.
+pub struct SlackNotifier {
+    config: SlackConfig,
+    client: reqwest::Client,
+}
+
+impl SlackNotifier {
+    pub fn new(config: SlackConfig, client: reqwest::Client) -> SlackNotifier {
+        SlackNotifier { config, client }
+    }
+}
+
+/// Escapes the characters `mrkdwn` reads as links and mentions, the only escaping Slack has.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// A `mrkdwn` field of a section block, a bold label over its value.
+fn field(label: &str, value: &str) -> Value {
+    json!({ "type": "mrkdwn", "text": format!("*{}*\n{}", label, value) })
+}
+
+/// Builds the webhook payload for an event.
+///
+/// The blocks sit in a colored attachment, green on success and red on failure, and `text` is
+/// the plain fallback Slack shows in notifications. Escalations start with `mention`, when
+/// given, so they ping.
+///
+/// # Arguments
+/// * `event` - The event to format.
+/// * `mention` - The `SLACK_ESCALATION_MENTION` setting.
+pub(crate) fn message(event: &UpdateEvent, mention: Option<&str>) -> Value {
+    let (title, color) = match &event.status {
+        EventStatus::Success => (format!("{} updated", event.domain), SUCCESS_COLOR),
+        EventStatus::Failure(_) => (format!("Failed to update {}", event.domain), FAILURE_COLOR),
+        EventStatus::Escalated { minutes, .. } => (format!("{} still failing after {} minutes", event.domain, minutes), ESCALATED_COLOR),
+        EventStatus::Resolved { minutes } => (format!("{} resolved after {} minutes", event.domain, minutes), SUCCESS_COLOR),
+        EventStatus::Changed => (format!("Public IP changed, {} not updated", event.domain), CHANGED_COLOR),
+        EventStatus::Reconciled { updated, current, failed } => (reconciled(*updated, *current, *failed), SUCCESS_COLOR),
+        EventStatus::Downtime { minutes } => (downtime(*minutes), FAILURE_COLOR),
+        EventStatus::Recreated => (format!("{} recreated after it was deleted", event.domain), SUCCESS_COLOR),
+        EventStatus::Drifted => (format!("{} was changed by hand, pointing it back", event.domain), CHANGED_COLOR),
+        EventStatus::Throttled { changes, minutes } => (format!("{}, now {}", throttled(*changes, *minutes), event.new_ip), CHANGED_COLOR),
+        EventStatus::Degraded { failures, .. } => (format!("{} {}", event.domain, degraded(*failures)), FAILURE_COLOR),
+        EventStatus::Recovered { failures } => (format!("{} {}", event.domain, recovered(*failures)), SUCCESS_COLOR),
+        EventStatus::CircuitOpen { failures, minutes, .. } => (format!("SkySync {}", circuit_open(*failures, *minutes)), FAILURE_COLOR),
+        EventStatus::Started { version, .. } => (started(version), CHANGED_COLOR),
+        EventStatus::Stopped { minutes } => (stopped(*minutes), CHANGED_COLOR),
+    };
+    let title = if event.test { format!("[Test] {}", title) } else { title };
+
+    let mut context = vec![json!({ "type": "mrkdwn", "text": event.timestamp.to_rfc3339() })];
+    if let Some(geo) = &event.geo {
+        context.push(json!({ "type": "mrkdwn", "text": format!("Location: {}", geo) }));
+    }
+    if event.proxied {
+        context.push(json!({ "type": "mrkdwn", "text": "Proxied by Cloudflare: public DNS shows Cloudflare's addresses, not the origin" }));
+    }
+    if let Some(link) = &event.dashboard_link {
+        context.push(json!({ "type": "mrkdwn", "text": format!("<{}|Open in the DNS dashboard>", link) }));
+    }
+
+    let mut blocks = vec![
+        json!({ "type": "header", "text": { "type": "plain_text", "text": title } }),
+        json!({ "type": "context", "elements": context }),
+        json!({
+            "type": "section",
+            "fields": [
+                field("Record", &event.domain),
+                field("Old IP", event.old_ip.as_deref().unwrap_or("-")),
+                field("New IP", &event.new_ip),
+            ]
+        }),
+    ];
+    if !event.changes.is_empty() {
+        blocks.push(json!({ "type": "section", "text": { "type": "mrkdwn", "text": escape(&change_lines(&event.changes).join("\n")) } }));
+    }
+    if let Some(error) = event.status.error() {
+        blocks.push(json!({ "type": "section", "text": { "type": "mrkdwn", "text": escape(&code_block(error, ERROR_LIMIT)) } }));
+    }
+    if let Some(reason) = &event.failover {
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{ "type": "mrkdwn", "text": format!("Sent as the failover: {}", reason) }]
+        }));
+    }
+
+    let text = match (&event.status, mention) {
+        (EventStatus::Escalated { .. }, Some(mention)) => format!("{} {}", mention, title),
+        _ => title,
+    };
+
+    json!({
+        "text": text,
+        "attachments": [{ "color": color, "blocks": blocks }]
+    })
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+        let response = self.client.post(&self.config.webhook_url).json(&message(event, self.config.escalation_mention.as_deref())).send().await?;
+
+        // Slack explains rejections like `invalid_payload` or `no_service` in a plain-text body
+        let status = response.status();
+        if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Slack rejected the message ({}): {}", status, body.trim()).into());
+        }
+        check_response(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn notifier(server: &MockServer) -> SlackNotifier {
+        SlackNotifier::new(SlackConfig { webhook_url: server.uri(), escalation_mention: None }, reqwest::Client::new())
+    }
+
+    /// Tests that a successful update is posted as a green attachment of header, context and fields.
+    #[tokio::test]
+    async fn test_notify_posts_blocks() {
+        let event = UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Success);
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_json(json!({
+                "text": "home.example.com updated",
+                "attachments": [{
+                    "color": "#2eb886",
+                    "blocks": [
+                        { "type": "header", "text": { "type": "plain_text", "text": "home.example.com updated" } },
+                        { "type": "context", "elements": [{ "type": "mrkdwn", "text": event.timestamp.to_rfc3339() }] },
+                        {
+                            "type": "section",
+                            "fields": [
+                                { "type": "mrkdwn", "text": "*Record*\nhome.example.com" },
+                                { "type": "mrkdwn", "text": "*Old IP*\n1.1.1.1" },
+                                { "type": "mrkdwn", "text": "*New IP*\n2.2.2.2" }
+                            ]
+                        }
+                    ]
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        notifier(&server).notify(&event).await.unwrap();
+    }
+
+    /// Tests that escalations stand out and ping the configured mention.
+    #[test]
+    fn test_escalation_mentions() {
+        let status = EventStatus::Escalated { error: "rejected".to_string(), minutes: 60 };
+        let event = UpdateEvent::new("home.example.com", None, "2.2.2.2", status);
+
+        let body = message(&event, Some("<!here>"));
+        assert_eq!(body["text"], "<!here> home.example.com still failing after 60 minutes");
+        assert_eq!(body["attachments"][0]["color"], "#5c0011");
+        assert_eq!(body["attachments"][0]["blocks"][3]["text"]["text"], "```\nrejected\n```");
+        assert_eq!(message(&event, None)["text"], "home.example.com still failing after 60 minutes");
+    }
+
+    /// Tests that an error quoting markup or a fence is escaped, so Slack shows it as it was.
+    #[test]
+    fn test_escapes_error() {
+        let status = EventStatus::Failure("<html>bad & ```broken```</html>".to_string());
+        let body = message(&UpdateEvent::new("home.example.com", None, "2.2.2.2", status), None);
+
+        let text = body["attachments"][0]["blocks"][3]["text"]["text"].as_str().unwrap();
+        assert_eq!(text.replace('\u{200b}', ""), "```\n&lt;html&gt;bad &amp; ```broken```&lt;/html&gt;\n```");
+        assert_eq!(text.matches("```").count(), 2);
+    }
+
+    /// Tests that samples from `skysync test-notify` say they're a test.
+    #[test]
+    fn test_marks_test_events() {
+        let event = UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Success).as_test();
+        assert_eq!(message(&event, None)["text"], "[Test] home.example.com updated");
+    }
+
+    /// Tests that failures are red and carry the error, and that Slack's rejection reason is reported.
+    #[tokio::test]
+    async fn test_notify_reports_invalid_payload() {
+        let event = UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Failure("rejected".to_string()));
+        let body = message(&event, Some("<!here>"));
+        assert_eq!(body["text"], "Failed to update home.example.com");
+        assert_eq!(body["attachments"][0]["color"], "#a30200");
+        assert_eq!(body["attachments"][0]["blocks"][3]["text"]["text"], "```\nrejected\n```");
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("invalid_payload"))
+            .mount(&server)
+            .await;
+
+        let err = notifier(&server).notify(&event).await.unwrap_err();
+        assert!(err.to_string().contains("invalid_payload"), "{}", err);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::config::SnsConfig;
+use crate::error::BoxError;
+use crate::notifier::{change_lines, circuit_open, degraded, downtime, reconciled, recovered, started, stopped, throttled, EventStatus, Notifier, RateLimited, UpdateEvent};
+use crate::services::aws::sdk_config;
+use async_trait::async_trait;
+use aws_sdk_sns::error::{DisplayErrorContext, ProvideErrorMetadata};
+use aws_sdk_sns::Client;
+use serde_json::json;
+use tokio::sync::OnceCell;
+
+/// The longest subject SNS accepts.
+const MAX_SUBJECT: usize = 100;
+
+/// Publishes update events to an AWS SNS topic, for its subscribers (email, SMS, Lambda...) to
+/// pick up.
+pub struct SnsNotifier {
+    config: SnsConfig,
+    /// The SDK client, built on the first message since finding the credentials takes a round
+    /// trip.
+    client: OnceCell<Client>,
+}
+
+impl SnsNotifier {
+    pub fn new(config: SnsConfig) -> SnsNotifier {
+        SnsNotifier { config, client: OnceCell::new() }
+    }
+
+    async fn client(&self) -> Result<&Client, BoxError> {
+        self.client.get_or_try_init(|| async {
+            let sdk = sdk_config(self.config.credentials.as_ref(), &self.config.metadata_url, &self.config.api_url, &self.config.region).await?;
+            Ok::<_, BoxError>(Client::new(&sdk))
+        }).await
+    }
+}
+
+/// The subject of the message about an event.
+///
+/// SNS only takes printable ASCII on a single line of at most 100 characters, so anything else
+/// is replaced and the end cut off.
+pub(crate) fn subject(event: &UpdateEvent) -> String {
+    let subject = match &event.status {
+        EventStatus::Success => format!("{} updated", event.domain),
+        EventStatus::Failure(_) => format!("Failed to update {}", event.domain),
+        EventStatus::Escalated { minutes, .. } => format!("{} still failing after {} minutes", event.domain, minutes),
+        EventStatus::Resolved { minutes } => format!("{} resolved after {} minutes", event.domain, minutes),
+        EventStatus::Changed => format!("Public IP changed, {} not updated", event.domain),
+        EventStatus::Reconciled { updated, current, failed } => reconciled(*updated, *current, *failed),
+        EventStatus::Downtime { minutes } => downtime(*minutes),
+        EventStatus::Recreated => format!("{} recreated after it was deleted", event.domain),
+        EventStatus::Drifted => format!("{} was changed by hand, pointing it back", event.domain),
+        EventStatus::Throttled { changes, minutes } => format!("{}, now {}", throttled(*changes, *minutes), event.new_ip),
+        EventStatus::Degraded { failures, .. } => format!("{} {}", event.domain, degraded(*failures)),
+        EventStatus::Recovered { failures } => format!("{} {}", event.domain, recovered(*failures)),
+        EventStatus::CircuitOpen { failures, minutes, .. } => format!("SkySync {}", circuit_open(*failures, *minutes)),
+        EventStatus::Started { version, .. } => started(version),
+        EventStatus::Stopped { minutes } => stopped(*minutes),
+    };
+    let subject = if event.test { format!("[Test] {}", subject) } else { subject };
+    subject.chars().map(|c| if c.is_ascii_graphic() || c == ' ' { c } else { '?' }).take(MAX_SUBJECT).collect()
+}
+
+/// The message about an event: lines of text, or with `SNS_MESSAGE_FORMAT=json` an object
+/// subscribers can read the IPs and status from.
+pub(crate) fn message(config: &SnsConfig, event: &UpdateEvent) -> String {
+    if config.json {
+        return json!({
+            "domain": event.domain,
+            "old_ip": event.old_ip,
+            "new_ip": event.new_ip,
+            "status": event.status.as_str(),
+            "error": event.status.error(),
+            "timestamp": event.timestamp.to_rfc3339(),
+            "test": event.test,
+        }).to_string();
+    }
+
+    let mut lines = if event.changes.is_empty() {
+        vec![format!("{} -> {}", event.old_ip.as_deref().unwrap_or("-"), event.new_ip)]
+    } else {
+        change_lines(&event.changes)
+    };
+    if let Some(error) = event.status.error() {
+        lines.push(format!("Error: {}", error));
+    }
+    if let Some(geo) = &event.geo {
+        lines.push(format!("Location: {}", geo));
+    }
+    if event.proxied {
+        lines.push("Proxied by Cloudflare: public DNS shows Cloudflare's addresses".to_string());
+    }
+    if let Some(reason) = &event.failover {
+        lines.push(format!("Sent as the failover: {}", reason));
+    }
+    lines.join("\n")
+}
+
+#[async_trait]
+impl Notifier for SnsNotifier {
+    fn name(&self) -> &'static str {
+        "sns"
+    }
+
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+        let published = self.client().await?.publish()
+            .topic_arn(&self.config.topic_arn)
+            .subject(subject(event))
+            .message(message(&self.config, event))
+            .send()
+            .await;
+        let Err(err) = published else {
+            return Ok(());
+        };
+
+        // The request didn't get an answer, e.g. because no credentials were found
+        let Some(status) = err.raw_response().map(|response| response.status().as_u16()) else {
+            return Err(DisplayErrorContext(&err).to_string().into());
+        };
+        let code = err.code().unwrap_or_default();
+        if status == 429 || code == "Throttling" {
+            return Err(Box::new(RateLimited { after: None }));
+        }
+        match err.message() {
+            Some(message) => Err(format!("SNS rejected the message (HTTP {}): {}: {}", status, code, message).into()),
+            None => Err(format!("SNS rejected the message (HTTP {})", status).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AwsCredentials;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate, Request};
+
+    fn config(server: &MockServer, json: bool) -> SnsConfig {
+        SnsConfig {
+            topic_arn: "arn:aws:sns:eu-west-1:123456789012:skysync".to_string(),
+            region: "eu-west-1".to_string(),
+            credentials: Some(AwsCredentials { access_key_id: "AKIDEXAMPLE".to_string(), secret_access_key: "secret".to_string(), session_token: None }),
+            api_url: server.uri(),
+            metadata_url: server.uri(),
+            json,
+        }
+    }
+
+    /// Tests that an event is published to the topic with a request signed for SNS in the
+    /// topic's region.
+    #[tokio::test]
+    async fn test_notify_publishes_to_topic() {
+        let event = UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Failure("rejected".to_string()));
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/"))
+            .and(header("content-type", "application/x-www-form-urlencoded"))
+            .and(body_string_contains("Action=Publish&Version=2010-03-31&TopicArn=arn%3Aaws%3Asns%3Aeu-west-1%3A123456789012%3Askysync"))
+            .and(body_string_contains("Subject=Failed%20to%20update%20home.example.com"))
+            .and(body_string_contains("Message=1.1.1.1%20-%3E%202.2.2.2%0AError%3A%20rejected"))
+            .and(|request: &Request| request.headers.get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/") && value.contains("/eu-west-1/sns/aws4_request")))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<PublishResponse><PublishResult><MessageId>id</MessageId></PublishResult></PublishResponse>"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        SnsNotifier::new(config(&server, false)).notify(&event).await.unwrap();
+    }
+
+    /// Tests that the JSON format carries the event's fields, and that SNS's error is reported.
+    #[tokio::test]
+    async fn test_json_message_and_rejection() {
+        let server = MockServer::start().await;
+        let event = UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Success);
+
+        let body: serde_json::Value = serde_json::from_str(&message(&config(&server, true), &event)).unwrap();
+        assert_eq!((&body["domain"], &body["old_ip"], &body["new_ip"], &body["status"]), (&json!("home.example.com"), &json!("1.1.1.1"), &json!("2.2.2.2"), &json!("success")));
+        assert_eq!(body["error"], json!(null));
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(404).set_body_string(
+                "<ErrorResponse><Error><Type>Sender</Type><Code>NotFound</Code><Message>Topic does not exist</Message></Error></ErrorResponse>",
+            ))
+            .mount(&server)
+            .await;
+        let err = SnsNotifier::new(config(&server, true)).notify(&event).await.unwrap_err();
+        assert_eq!(err.to_string(), "SNS rejected the message (HTTP 404): NotFound: Topic does not exist");
+    }
+
+    /// Tests that subjects are kept to the single ASCII line of 100 characters SNS accepts.
+    #[test]
+    fn test_subject_fits_sns_limits() {
+        let event = UpdateEvent::new(&format!("{}.example.com", "é".repeat(120)), None, "2.2.2.2", EventStatus::Success);
+
+        let subject = subject(&event);
+        assert_eq!(subject.len(), 100);
+        assert!(subject.starts_with("???"), "{}", subject);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::log::report;
+use sd_notify::NotifyState;
+use std::env;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Whether SkySync was started by systemd as a `Type=notify` unit.
+fn enabled() -> bool {
+    env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// Sends `state` to systemd, ignoring it when not running under a notify unit.
+fn send(state: NotifyState) {
+    if enabled() {
+        if let Err(err) = sd_notify::notify(false, &[state]) {
+            let msg = format!("Failed to notify systemd: {}", err);
+            tokio::spawn(async move { report(&msg).await });
+        }
+    }
+}
+
+/// Tells systemd the first tick went through.
+pub(crate) fn ready() {
+    send(NotifyState::Ready);
+}
+
+/// Tells systemd SkySync is shutting down.
+pub(crate) fn stopping() {
+    send(NotifyState::Stopping);
+}
+
+/// How often to ping the watchdog for a given `WatchdogSec`, half of it as systemd recommends.
+fn watchdog_period(usec: u64) -> Duration {
+    Duration::from_micros(usec / 2)
+}
+
+/// Starts pinging the watchdog when the unit has `WatchdogSec` set.
+///
+/// # Returns
+/// The ping task, or `None` when the watchdog is disabled.
+pub(crate) fn spawn_watchdog() -> Option<JoinHandle<()>> {
+    let mut usec = 0;
+    if !enabled() || !sd_notify::watchdog_enabled(false, &mut usec) {
+        return None;
+    }
+
+    let period = watchdog_period(usec);
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            send(NotifyState::Watchdog);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the watchdog is pinged twice per timeout.
+    #[test]
+    fn test_watchdog_period() {
+        assert_eq!(watchdog_period(30_000_000), Duration::from_secs(15));
+    }
+}
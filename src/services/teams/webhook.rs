@@ -0,0 +1,179 @@
+use crate::config::TeamsConfig;
+use crate::error::BoxError;
+use crate::notifier::{change_lines, check_response, circuit_open, degraded, downtime, reconciled, recovered, started, stopped, throttled, EventStatus, Notifier, UpdateEvent};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+/// Colors of the accent line along the top of the card, as the hex Teams expects, without a `#`.
+const SUCCESS_COLOR: &str = "2EB886";
+const FAILURE_COLOR: &str = "A30200";
+/// Darker than a plain failure, so an escalated outage stands out in the channel.
+const ESCALATED_COLOR: &str = "5C0011";
+/// Neither good nor bad news, for changes `MODE=monitor` only reports.
+const CHANGED_COLOR: &str = "439FE0";
+
+/// Posts update events to a Microsoft Teams incoming webhook, as a legacy `MessageCard`.
+pub struct TeamsNotifier {
+    config: TeamsConfig,
+    client: reqwest::Client,
+}
+
+impl TeamsNotifier {
+    pub fn new(config: TeamsConfig, client: reqwest::Client) -> TeamsNotifier {
+        TeamsNotifier { config, client }
+    }
+}
+
+/// A fact of the card section, a label next to its value.
+fn fact(name: &str, value: &str) -> Value {
+    json!({ "name": name, "value": value })
+}
+
+/// Builds the card for an event.
+///
+/// Incoming webhooks only accept cards that carry `@type` and `@context`, and need a `summary`,
+/// the plain text Teams shows in notifications and the activity feed.
+///
+/// # Arguments
+/// * `event` - The event to format.
+pub(crate) fn message(event: &UpdateEvent) -> Value {
+    let (title, color) = match &event.status {
+        EventStatus::Success => (format!("{} updated", event.domain), SUCCESS_COLOR),
+        EventStatus::Failure(_) => (format!("Failed to update {}", event.domain), FAILURE_COLOR),
+        EventStatus::Escalated { minutes, .. } => (format!("{} still failing after {} minutes", event.domain, minutes), ESCALATED_COLOR),
+        EventStatus::Resolved { minutes } => (format!("{} resolved after {} minutes", event.domain, minutes), SUCCESS_COLOR),
+        EventStatus::Changed => (format!("Public IP changed, {} not updated", event.domain), CHANGED_COLOR),
+        EventStatus::Reconciled { updated, current, failed } => (reconciled(*updated, *current, *failed), SUCCESS_COLOR),
+        EventStatus::Downtime { minutes } => (downtime(*minutes), FAILURE_COLOR),
+        EventStatus::Recreated => (format!("{} recreated after it was deleted", event.domain), SUCCESS_COLOR),
+        EventStatus::Drifted => (format!("{} was changed by hand, pointing it back", event.domain), CHANGED_COLOR),
+        EventStatus::Throttled { changes, minutes } => (format!("{}, now {}", throttled(*changes, *minutes), event.new_ip), CHANGED_COLOR),
+        EventStatus::Degraded { failures, .. } => (format!("{} {}", event.domain, degraded(*failures)), FAILURE_COLOR),
+        EventStatus::Recovered { failures } => (format!("{} {}", event.domain, recovered(*failures)), SUCCESS_COLOR),
+        EventStatus::CircuitOpen { failures, minutes, .. } => (format!("SkySync {}", circuit_open(*failures, *minutes)), FAILURE_COLOR),
+        EventStatus::Started { version, .. } => (started(version), CHANGED_COLOR),
+        EventStatus::Stopped { minutes } => (stopped(*minutes), CHANGED_COLOR),
+    };
+    let title = if event.test { format!("[Test] {}", title) } else { title };
+
+    let mut facts = vec![
+        fact("Record", &event.domain),
+        fact("Old IP", event.old_ip.as_deref().unwrap_or("-")),
+        fact("New IP", &event.new_ip),
+        fact("Status", event.status.as_str()),
+    ];
+    if !event.changes.is_empty() {
+        facts.push(fact("Changes", &change_lines(&event.changes).join("; ")));
+    }
+    if let Some(error) = event.status.error() {
+        facts.push(fact("Error", error));
+    }
+    if let Some(geo) = &event.geo {
+        facts.push(fact("Location", &geo.to_string()));
+    }
+    if event.proxied {
+        facts.push(fact("Proxied", "By Cloudflare: public DNS shows Cloudflare's addresses, not the origin"));
+    }
+    if let Some(link) = &event.dashboard_link {
+        facts.push(fact("Dashboard", &format!("[Open in the DNS dashboard]({})", link)));
+    }
+    if let Some(reason) = &event.failover {
+        facts.push(fact("Sent as the failover", reason));
+    }
+
+    json!({
+        "@type": "MessageCard",
+        "@context": "https://schema.org/extensions",
+        "themeColor": color,
+        "summary": title,
+        "title": title,
+        "sections": [{
+            "activitySubtitle": event.timestamp.to_rfc3339(),
+            "facts": facts,
+            "markdown": false
+        }]
+    })
+}
+
+#[async_trait]
+impl Notifier for TeamsNotifier {
+    fn name(&self) -> &'static str {
+        "teams"
+    }
+
+    async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+        let response = self.client.post(&self.config.webhook_url).json(&message(event)).send().await?;
+
+        // Teams explains malformed cards, like one without a summary, in a plain-text body
+        let status = response.status();
+        if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Teams rejected the card ({}): {}", status, body.trim()).into());
+        }
+        check_response(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn notifier(server: &MockServer) -> TeamsNotifier {
+        TeamsNotifier::new(TeamsConfig { webhook_url: server.uri() }, reqwest::Client::new())
+    }
+
+    /// Tests that a successful update is posted as a green card with the fields Teams requires.
+    #[tokio::test]
+    async fn test_notify_posts_card() {
+        let event = UpdateEvent::new("home.example.com", Some("1.1.1.1".to_string()), "2.2.2.2", EventStatus::Success);
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_json(json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "themeColor": "2EB886",
+                "summary": "home.example.com updated",
+                "title": "home.example.com updated",
+                "sections": [{
+                    "activitySubtitle": event.timestamp.to_rfc3339(),
+                    "facts": [
+                        { "name": "Record", "value": "home.example.com" },
+                        { "name": "Old IP", "value": "1.1.1.1" },
+                        { "name": "New IP", "value": "2.2.2.2" },
+                        { "name": "Status", "value": "success" }
+                    ],
+                    "markdown": false
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        notifier(&server).notify(&event).await.unwrap();
+    }
+
+    /// Tests that failures are red and carry the error, and that Teams' rejection reason is reported.
+    #[tokio::test]
+    async fn test_failure_card() {
+        let event = UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Failure("rejected".to_string()));
+        let card = message(&event);
+        assert_eq!(card["themeColor"], "A30200");
+        assert_eq!(card["sections"][0]["facts"][4], json!({ "name": "Error", "value": "rejected" }));
+
+        let escalated = UpdateEvent::new("home.example.com", None, "2.2.2.2", EventStatus::Escalated { error: "rejected".to_string(), minutes: 60 });
+        assert_eq!(message(&escalated)["themeColor"], "5C0011");
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("Summary or Text is required."))
+            .mount(&server)
+            .await;
+
+        let err = notifier(&server).notify(&event).await.unwrap_err();
+        assert!(err.to_string().contains("Summary or Text is required."), "{}", err);
+    }
+}
@@ -0,0 +1,243 @@
+//! Reads the public IP from the WAN side of a UPnP Internet Gateway Device, i.e. the home router,
+//! instead of asking a service on the internet.
+use crate::error::BoxError;
+use crate::ip::IpSource;
+use crate::services::xml::{element, elements};
+use async_trait::async_trait;
+use reqwest::Url;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Where SSDP discovery requests are sent.
+const SSDP_ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900);
+
+/// How long discovery waits for a gateway to answer.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The services of a gateway that know its external address, in order of preference.
+const WAN_SERVICES: [&str; 4] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+    "urn:schemas-upnp-org:service:WANIPConnection",
+];
+
+/// Asks the gateway of the local network for its external IP address over UPnP.
+pub struct UpnpIpSource {
+    client: reqwest::Client,
+}
+
+impl UpnpIpSource {
+    /// Creates the source. `client` should not go through a proxy, since the gateway is only
+    /// reachable from the local network.
+    pub fn new(client: reqwest::Client) -> UpnpIpSource {
+        UpnpIpSource { client }
+    }
+
+    /// Reads the external address from the gateway described at `location`.
+    ///
+    /// # Arguments
+    /// * `location` - The URL of the device description, as announced during discovery.
+    pub(crate) async fn external_ip(&self, location: &str) -> Result<String, BoxError> {
+        let location = Url::parse(location).map_err(|err| format!("the gateway announced an invalid location {}: {}", location, err))?;
+        let description = self.client.get(location.clone())
+            .send().await?
+            .error_for_status()?
+            .text().await?;
+        let (service, control_url) = control_url(&description, &location)?;
+
+        let response = self.client.post(control_url)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header("SOAPAction", format!("\"{}#GetExternalIPAddress\"", service))
+            .body(soap_request(service))
+            .send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            let reason = element(&body, "errorDescription").unwrap_or(status.as_str());
+            return Err(format!("the gateway refused to report its external address: {}", reason).into());
+        }
+        parse_external_ip(&body)
+    }
+}
+
+#[async_trait]
+impl IpSource for UpnpIpSource {
+    async fn public_ip(&self) -> Result<String, BoxError> {
+        let location = discover().await?;
+        self.external_ip(&location).await
+    }
+}
+
+/// Finds the gateway of the local network with an SSDP search.
+///
+/// # Returns
+/// The URL of its device description.
+async fn discover() -> Result<String, BoxError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n",
+        SSDP_ADDR,
+    );
+    socket.send_to(search.as_bytes(), SSDP_ADDR).await?;
+
+    tokio::time::timeout(DISCOVERY_TIMEOUT, async {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            // Other devices on the network may answer too; only a gateway names its description
+            if let Some(location) = parse_location(&String::from_utf8_lossy(&buf[..len])) {
+                return Ok::<_, BoxError>(location);
+            }
+        }
+    }).await.map_err(|_| format!("no UPnP gateway answered within {:?}; is UPnP enabled on the router?", DISCOVERY_TIMEOUT))?
+}
+
+/// The `LOCATION` header of an SSDP answer.
+fn parse_location(answer: &str) -> Option<String> {
+    answer.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|location| !location.is_empty())
+}
+
+/// Finds the WAN connection service in a device description.
+///
+/// # Arguments
+/// * `description` - The device description XML.
+/// * `location` - Where the description was read from, which relative URLs are resolved against.
+///
+/// # Returns
+/// The service type and the URL its actions are posted to.
+fn control_url(description: &str, location: &Url) -> Result<(&'static str, Url), BoxError> {
+    let base = match element(description, "URLBase") {
+        Some(base) => Url::parse(base).unwrap_or_else(|_| location.clone()),
+        None => location.clone(),
+    };
+
+    let services = elements(description, "service");
+    for wanted in WAN_SERVICES {
+        let found = services.iter()
+            .find(|service| element(service, "serviceType") == Some(wanted));
+        if let Some(control) = found.and_then(|service| element(service, "controlURL")) {
+            return Ok((wanted, base.join(control)?));
+        }
+    }
+    Err("the gateway has no WAN connection service, so it can't report its external address".into())
+}
+
+/// The body of a `GetExternalIPAddress` call to `service`.
+fn soap_request(service: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:GetExternalIPAddress xmlns:u=\"{}\"></u:GetExternalIPAddress></s:Body>\
+         </s:Envelope>",
+        service,
+    )
+}
+
+/// Reads the address out of a `GetExternalIPAddress` answer.
+fn parse_external_ip(body: &str) -> Result<String, BoxError> {
+    match element(body, "NewExternalIPAddress") {
+        // Gateways without a WAN connection answer with an empty or unspecified address
+        Some(ip) if !ip.is_empty() && ip != "0.0.0.0" => Ok(ip.to_string()),
+        _ => Err("the gateway has no external address, is it connected?".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+    </serviceList>
+    <deviceList>
+      <device>
+        <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+        <serviceList>
+          <service>
+            <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+            <controlURL>/ctl/IPConn</controlURL>
+          </service>
+        </serviceList>
+      </device>
+    </deviceList>
+  </device>
+</root>"#;
+
+    /// A `GetExternalIPAddress` answer reporting `ip`.
+    fn soap_answer(ip: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\"?>\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body>\
+             <u:GetExternalIPAddressResponse xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">\
+             <NewExternalIPAddress>{}</NewExternalIPAddress>\
+             </u:GetExternalIPAddressResponse></s:Body></s:Envelope>",
+            ip,
+        )
+    }
+
+    /// Tests that the description location is read from a discovery answer.
+    #[test]
+    fn test_parse_location() {
+        let answer = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\nLocation: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+        assert_eq!(parse_location(answer).as_deref(), Some("http://192.168.1.1:5000/rootDesc.xml"));
+        assert_eq!(parse_location("HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n"), None);
+    }
+
+    /// Tests that the WAN service is found among the others and its URL resolved.
+    #[test]
+    fn test_control_url() {
+        let location = Url::parse("http://192.168.1.1:5000/rootDesc.xml").unwrap();
+
+        let (service, url) = control_url(DESCRIPTION, &location).unwrap();
+
+        assert_eq!(service, "urn:schemas-upnp-org:service:WANIPConnection:1");
+        assert_eq!(url.as_str(), "http://192.168.1.1:5000/ctl/IPConn");
+        assert!(control_url("<root><device></device></root>", &location).is_err());
+    }
+
+    /// Tests that the address is read from the answer, and that a disconnected gateway is an error.
+    #[test]
+    fn test_parse_external_ip() {
+        assert_eq!(parse_external_ip(&soap_answer("203.0.113.9")).unwrap(), "203.0.113.9");
+        assert!(parse_external_ip(&soap_answer("")).is_err());
+        assert!(parse_external_ip(&soap_answer("0.0.0.0")).is_err());
+    }
+
+    /// Tests the whole exchange with a stubbed gateway.
+    #[tokio::test]
+    async fn test_external_ip_from_gateway() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rootDesc.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(DESCRIPTION))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/ctl/IPConn"))
+            .and(header("SOAPAction", "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\""))
+            .respond_with(ResponseTemplate::new(200).set_body_string(soap_answer("203.0.113.9")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let source = UpnpIpSource::new(reqwest::Client::new());
+        let ip = source.external_ip(&format!("{}/rootDesc.xml", server.uri())).await.unwrap();
+
+        assert_eq!(ip, "203.0.113.9");
+    }
+}
@@ -0,0 +1,102 @@
+use crate::config::{default_config_file, ConfigLoader};
+use crate::daemon;
+use crate::error::BoxError;
+use crate::log;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use windows_service::service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::{define_windows_service, service_dispatcher};
+
+/// Name the service is registered under with `sc create`.
+const SERVICE_NAME: &str = "SkySync";
+
+/// The `--config` file given on the command line, read once the SCM calls `service_main`.
+static CONFIG_FILE: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Hands the process over to the Windows service control manager.
+///
+/// Only works when started by the SCM, e.g. after
+/// `sc create SkySync binPath= "C:\path\to\skysync.exe service"`. Blocks until the service stops.
+///
+/// # Arguments
+/// * `config_file` - The TOML file to read. Without one, the usual locations are searched.
+pub fn run_as_service(config_file: Option<PathBuf>) -> windows_service::Result<()> {
+    let _ = CONFIG_FILE.set(config_file);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        eprintln!("SkySync service failed: {}", err);
+    }
+}
+
+/// Reports `state` to the SCM.
+fn set_status(handle: &ServiceStatusHandle, state: ServiceState, exit_code: u32) -> windows_service::Result<()> {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+
+    handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::ZERO,
+        process_id: None,
+    })
+}
+
+fn run_service() -> Result<(), BoxError> {
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let stop_tx = Mutex::new(Some(stop_tx));
+
+    let handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            if let Some(stop_tx) = stop_tx.lock().unwrap().take() {
+                let _ = stop_tx.send(());
+            }
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+
+    // Services start in System32, look for `.env` next to the executable instead
+    if let Some(dir) = std::env::current_exe()?.parent() {
+        std::env::set_current_dir(dir)?;
+    }
+
+    let loader = match CONFIG_FILE.get().cloned().flatten().or_else(default_config_file) {
+        Some(path) => ConfigLoader::new(".env").with_toml(path),
+        None => ConfigLoader::new(".env"),
+    };
+    let profiles = match loader.load_profiles() {
+        Ok(profiles) => profiles,
+        Err(err) => {
+            set_status(&handle, ServiceState::Stopped, 1)?;
+            return Err(err.into());
+        }
+    };
+    let log_path = &profiles[0].log_path;
+    if let Err(err) = log::set_log_path(log_path) {
+        set_status(&handle, ServiceState::Stopped, 1)?;
+        return Err(format!("Can't write the log file {}: {}", log_path.display(), err).into());
+    }
+
+    set_status(&handle, ServiceState::Running, 0)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(daemon::run(loader, profiles, false, async {
+        let _ = stop_rx.await;
+    }));
+    set_status(&handle, ServiceState::Stopped, if result.is_ok() { 0 } else { 1 })?;
+    Ok(result?)
+}
@@ -0,0 +1,30 @@
+//! Just enough XML to read the small, flat documents of UPnP gateways and the Route53 API, without
+//! pulling in a full parser.
+
+/// The text of the first `tag` element in `xml`, trimmed.
+pub(crate) fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    elements(xml, tag).first().map(|text| text.trim())
+}
+
+/// The contents of every `tag` element in `xml`. The tag may carry a namespace prefix, as SOAP
+/// answers often do.
+pub(crate) fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else { break };
+        let open = &rest[..end];
+        let name = open.split_whitespace().next().unwrap_or_default();
+        let local = name.rsplit(':').next().unwrap_or_default();
+        if local != tag || open.starts_with('/') || open.ends_with('/') {
+            continue;
+        }
+        let close = format!("</{}>", name);
+        let content = &rest[end + 1..];
+        let Some(stop) = content.find(&close) else { break };
+        found.push(&content[..stop]);
+        rest = &content[stop + close.len()..];
+    }
+    found
+}
@@ -0,0 +1,380 @@
+//! `skysync setup`, a first-run wizard. It asks for the essential settings one step at a time,
+//! checks the provider answers against the provider itself as it goes, and returns a
+//! ready-to-run `skysync.toml`.
+use crate::config::{toml_vars, Config};
+use crate::error::BoxError;
+use crate::http;
+use crate::scaffold;
+use crate::sync;
+use async_trait::async_trait;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// How often the records are checked unless another interval is given, in minutes.
+const DEFAULT_INTERVAL_MINUTES: u64 = 30;
+
+/// How the wizard talks to the user.
+pub trait Prompt {
+    /// Asks `question` and reads the answer, trimmed.
+    fn ask(&mut self, question: &str) -> io::Result<String>;
+
+    /// Like `ask`, but for a secret, which isn't shown as it's typed when read from a terminal.
+    fn ask_secret(&mut self, question: &str) -> io::Result<String>;
+
+    /// Shows a line, e.g. why an answer was turned down.
+    fn say(&mut self, line: &str) -> io::Result<()>;
+}
+
+/// Writes questions to `output` and reads the answers line by line from `input`.
+pub struct LinePrompt<R, W> {
+    input: R,
+    output: W,
+    mask: bool,
+}
+
+impl<R: BufRead, W: Write> LinePrompt<R, W> {
+    /// # Arguments
+    /// * `input` - Where the answers are read from, usually stdin.
+    /// * `output` - Where the questions are written, usually stdout.
+    /// * `mask` - Whether `input` is the terminal, whose echo is turned off while secrets are typed.
+    pub fn new(input: R, output: W, mask: bool) -> LinePrompt<R, W> {
+        LinePrompt { input, output, mask }
+    }
+
+    fn read(&mut self) -> io::Result<String> {
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the answers ended before the setup was done"));
+        }
+        Ok(answer.trim().to_string())
+    }
+}
+
+impl<R: BufRead, W: Write> Prompt for LinePrompt<R, W> {
+    fn ask(&mut self, question: &str) -> io::Result<String> {
+        write!(self.output, "{}: ", question)?;
+        self.output.flush()?;
+        self.read()
+    }
+
+    fn ask_secret(&mut self, question: &str) -> io::Result<String> {
+        if !self.mask {
+            return self.ask(question);
+        }
+        write!(self.output, "{}: ", question)?;
+        self.output.flush()?;
+        set_echo(false);
+        let answer = self.read();
+        set_echo(true);
+        // The Enter that ended the answer wasn't echoed either
+        writeln!(self.output)?;
+        answer
+    }
+
+    fn say(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.output, "{}", line)
+    }
+}
+
+/// Turns the echo of the terminal on stdin on or off. Where `stty` isn't available, e.g. on
+/// Windows, secrets are shown as they're typed.
+fn set_echo(on: bool) {
+    #[cfg(unix)]
+    {
+        let _ = std::process::Command::new("stty").arg(if on { "echo" } else { "-echo" }).stdin(std::process::Stdio::inherit()).status();
+    }
+    #[cfg(not(unix))]
+    let _ = on;
+}
+
+/// Checks the provider answers before the wizard moves on.
+#[async_trait]
+pub trait Verify: Send + Sync {
+    /// Checks that the provider accepts the credentials of `config`, and has every record.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration the answers so far make up.
+    /// * `record_type` - The type of the records, e.g. `A`.
+    async fn provider(&self, config: &Config, record_type: &str) -> Result<(), BoxError>;
+}
+
+/// Asks the provider itself.
+pub struct LiveCheck;
+
+#[async_trait]
+impl Verify for LiveCheck {
+    async fn provider(&self, config: &Config, record_type: &str) -> Result<(), BoxError> {
+        let client = http::client(config.proxy.as_ref(), &config.pool)?;
+        let provider = sync::dns_provider(config, &client);
+        provider.check_credentials().await?;
+        for name in config.dns_names() {
+            if provider.find_record(&name, record_type).await?.is_none() {
+                return Err(format!("there is no {} record named {}, create it first", record_type, name).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The providers that can be chosen, the ones SkySync was built without left out.
+fn providers() -> Vec<&'static str> {
+    let mut providers = vec!["cloudflare", "gandi", "porkbun"];
+    if cfg!(feature = "route53") {
+        providers.push("route53");
+    }
+    providers
+}
+
+/// Asks for one of `options`, again until the answer is one of them.
+fn choose(prompt: &mut impl Prompt, question: &str, options: &[&'static str], default: &'static str) -> io::Result<&'static str> {
+    loop {
+        let answer = prompt.ask(&format!("{} ({}) [{}]", question, options.join(", "), default))?;
+        if answer.is_empty() {
+            return Ok(default);
+        }
+        if let Some(option) = options.iter().find(|option| option.eq_ignore_ascii_case(&answer)) {
+            return Ok(option);
+        }
+        prompt.say(&format!("Expected one of: {}", options.join(", ")))?;
+    }
+}
+
+/// Asks a yes or no question.
+fn confirm(prompt: &mut impl Prompt, question: &str, default: bool) -> io::Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        match prompt.ask(&format!("{} [{}]", question, hint))?.to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => prompt.say("Expected yes or no")?,
+        }
+    }
+}
+
+/// Asks `question` again until it's answered.
+fn required(prompt: &mut impl Prompt, question: &str, secret: bool) -> io::Result<String> {
+    loop {
+        let answer = if secret { prompt.ask_secret(question)? } else { prompt.ask(question)? };
+        if !answer.is_empty() {
+            return Ok(answer);
+        }
+        prompt.say("This one is required")?;
+    }
+}
+
+/// Asks for the credentials, the zone and the records of `provider`.
+///
+/// # Returns
+/// The answers as the settings they stand for, and the type of the records.
+fn provider_answers(prompt: &mut impl Prompt, provider: &'static str) -> io::Result<(Vec<(&'static str, String)>, &'static str)> {
+    let mut answers = vec![("dns_provider", provider.to_string())];
+    let record_type_key = match provider {
+        "gandi" => {
+            answers.push(("gandi_pat", required(prompt, "Gandi personal access token", true)?));
+            answers.push(("gandi_domain", required(prompt, "Domain (e.g. example.com)", false)?));
+            answers.push(("gandi_dns_name", required(prompt, "Records to update, comma-separated (e.g. home)", false)?));
+            "gandi_record_type"
+        }
+        "porkbun" => {
+            answers.push(("porkbun_api_key", required(prompt, "Porkbun API key", true)?));
+            answers.push(("porkbun_secret_api_key", required(prompt, "Porkbun secret API key", true)?));
+            answers.push(("porkbun_domain", required(prompt, "Domain (e.g. example.com)", false)?));
+            answers.push(("porkbun_dns_name", required(prompt, "Records to update, comma-separated (e.g. home)", false)?));
+            "porkbun_record_type"
+        }
+        "route53" => {
+            let access_key_id = prompt.ask("AWS access key ID (empty uses the EC2 instance profile)")?;
+            if !access_key_id.is_empty() {
+                answers.push(("aws_access_key_id", access_key_id));
+                answers.push(("aws_secret_access_key", required(prompt, "AWS secret access key", true)?));
+            }
+            answers.push(("route53_zone_id", required(prompt, "Hosted zone ID", false)?));
+            answers.push(("route53_dns_name", required(prompt, "Records to update, comma-separated full names (e.g. home.example.com)", false)?));
+            "route53_record_type"
+        }
+        _ => {
+            let token = prompt.ask_secret("Cloudflare API token with Zone / DNS / Edit (empty uses the global API key)")?;
+            if token.is_empty() {
+                answers.push(("cf_api_key", required(prompt, "Cloudflare global API key", true)?));
+                answers.push(("cf_email", required(prompt, "Cloudflare account email", false)?));
+            } else {
+                answers.push(("cf_api_token", token));
+            }
+            answers.push(("cf_zone_id", required(prompt, "Zone ID", false)?));
+            answers.push(("cf_dns_name", required(prompt, "Records to update, comma-separated (e.g. home.example.com)", false)?));
+            "cf_record_type"
+        }
+    };
+    let record_type = choose(prompt, "Record type", &["A", "AAAA"], "A")?;
+    answers.push((record_type_key, record_type.to_string()));
+    Ok((answers, record_type))
+}
+
+/// Loads the configuration the answers make up, as `skysync` will once they're written.
+fn draft(path: &Path, answers: &[(&'static str, String)]) -> Result<Config, BoxError> {
+    Ok(Config::from_vars(&toml_vars(path, &scaffold::render(answers))?)?)
+}
+
+/// Walks through the setup: the provider and its records, checked with `verify`, then the check
+/// interval and an optional notifier.
+///
+/// # Arguments
+/// * `prompt` - Where the questions are asked.
+/// * `verify` - What checks the provider answers, `LiveCheck` outside tests.
+/// * `path` - Where the result is going to be written, for error messages.
+///
+/// # Returns
+/// The contents of the config file.
+pub async fn run(prompt: &mut impl Prompt, verify: &dyn Verify, path: &Path) -> Result<String, BoxError> {
+    prompt.say("Setting up SkySync. Press Enter to take the default in brackets.")?;
+    let mut answers: Vec<(&'static str, String)> = Vec::new();
+
+    let provider = choose(prompt, "DNS provider", &providers(), "cloudflare")?;
+    loop {
+        let (step, record_type) = provider_answers(prompt, provider)?;
+        let checked = match draft(path, &step) {
+            Ok(config) => {
+                prompt.say(&format!("Checking the credentials and records with {}...", provider))?;
+                verify.provider(&config, record_type).await
+            }
+            Err(err) => Err(err),
+        };
+        match checked {
+            Ok(()) => {
+                prompt.say("Found every record")?;
+                answers.extend(step);
+                break;
+            }
+            Err(err) => {
+                prompt.say(&format!("That didn't work: {}", err))?;
+                if !confirm(prompt, "Try again?", true)? {
+                    answers.extend(step);
+                    break;
+                }
+            }
+        }
+    }
+
+    loop {
+        let question = format!("How often to check for a new IP, in minutes [{}]", DEFAULT_INTERVAL_MINUTES);
+        let answer = prompt.ask(&question)?;
+        match if answer.is_empty() { Ok(DEFAULT_INTERVAL_MINUTES) } else { answer.parse::<u64>() } {
+            Ok(minutes) if minutes > 0 => {
+                answers.push(("cron_interval", minutes.saturating_mul(60_000).to_string()));
+                break;
+            }
+            _ => prompt.say("Expected a whole number of minutes, 1 or more")?,
+        }
+    }
+
+    loop {
+        let notifier = choose(prompt, "Notifier for IP changes", &["none", "discord", "slack", "generic"], "none")?;
+        let key = match notifier {
+            "discord" => "discord_webhook_id",
+            "slack" => "slack_webhook_url",
+            "generic" => "generic_webhook_url",
+            _ => break,
+        };
+        let step = [("notifier", notifier.to_string()), (key, required(prompt, "Webhook URL", true)?)];
+        match draft(path, &[answers.as_slice(), &step].concat()) {
+            Ok(_) => {
+                answers.extend(step);
+                break;
+            }
+            Err(err) => prompt.say(&format!("That didn't work: {}", err))?,
+        }
+    }
+
+    Ok(scaffold::render(&answers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigLoader, NotifierKind, ProviderKind};
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Answers from a script, and keeps what was said.
+    struct Scripted {
+        answers: VecDeque<&'static str>,
+        said: Vec<String>,
+    }
+
+    impl Prompt for Scripted {
+        fn ask(&mut self, question: &str) -> io::Result<String> {
+            self.said.push(question.to_string());
+            self.answers.pop_front().map(str::to_string).ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+        }
+
+        fn ask_secret(&mut self, question: &str) -> io::Result<String> {
+            self.ask(question)
+        }
+
+        fn say(&mut self, line: &str) -> io::Result<()> {
+            self.said.push(line.to_string());
+            Ok(())
+        }
+    }
+
+    /// Turns down the first check, then accepts, keeping the zones it was asked about.
+    #[derive(Default)]
+    struct Flaky {
+        checked: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Verify for Flaky {
+        async fn provider(&self, config: &Config, record_type: &str) -> Result<(), BoxError> {
+            let mut checked = self.checked.lock().unwrap();
+            checked.push(format!("{} {}", config.zones[0].zone_id, record_type));
+            if checked.len() == 1 {
+                return Err("Invalid API Token".into());
+            }
+            Ok(())
+        }
+    }
+
+    /// Tests that a scripted setup, where the first token is turned down, writes a file the
+    /// loader reads back with every answer.
+    #[tokio::test]
+    async fn test_scripted_answers_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("skysync.toml");
+        let mut prompt = Scripted {
+            answers: VecDeque::from([
+                "", "wrong-token", "abc123", "home.example.com", "", "",
+                "good-token", "abc123", "home.example.com, www.example.com", "aaaa",
+                "0", "5",
+                "discord", "not a url", "discord", "https://discord.com/api/webhooks/1/secret",
+            ]),
+            said: Vec::new(),
+        };
+        let verify = Flaky::default();
+
+        let contents = run(&mut prompt, &verify, &path).await.unwrap();
+        assert_eq!(*verify.checked.lock().unwrap(), vec!["abc123 A", "abc123 AAAA"]);
+        assert!(prompt.said.contains(&"That didn't work: Invalid API Token".to_string()), "{:?}", prompt.said);
+
+        scaffold::export(&path, &contents, false).unwrap();
+        let config = ConfigLoader::with_env(dir.path().join(".env"), HashMap::new()).with_toml(&path).load().unwrap();
+        assert_eq!(config.provider, ProviderKind::Cloudflare);
+        assert_eq!(config.cloudflare.api_token.as_deref(), Some("good-token"));
+        assert_eq!(config.zones[0].zone_id, "abc123");
+        assert_eq!(config.zones[0].records, vec!["home.example.com".to_string(), "www.example.com".to_string()]);
+        assert_eq!(config.cron_interval, Duration::from_secs(300));
+        assert_eq!(config.notifiers, vec![NotifierKind::Discord]);
+        assert_eq!(config.discord.webhook_url, "https://discord.com/api/webhooks/1/secret");
+    }
+
+    /// Tests that answers running out stop the setup instead of writing half a file.
+    #[tokio::test]
+    async fn test_answers_ending_early() {
+        let mut prompt = LinePrompt::new("gandi\ntoken\n".as_bytes(), io::sink(), false);
+
+        let err = run(&mut prompt, &LiveCheck, Path::new("skysync.toml")).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<io::Error>().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,180 @@
+//! `STATUS_FILE`, a JSON snapshot of the latest tick that external tooling can poll instead of
+//! parsing the log or running a subcommand. Unlike `history.json` it only ever holds the latest tick.
+use crate::cli::rank;
+use crate::error::{BoxError, SkySyncError};
+use crate::sync::{State, TickOutcome, TickSummary};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, rename, write};
+
+/// How one managed record ended up in the latest tick.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RecordStatus {
+    pub name: String,
+    /// The outcome, as `skysync --once` names it: `unchanged`, `updated`, `failed`...
+    pub status: &'static str,
+    /// What the record was last seen or written pointing at.
+    pub content: Option<String>,
+    /// Why the record failed, when it did.
+    pub error: Option<String>,
+    /// When the record was last found or brought up to date.
+    pub last_success: Option<DateTime<FixedOffset>>,
+    /// How many ticks in a row failed to update the record.
+    pub failures: u32,
+}
+
+/// The contents of the file.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Snapshot {
+    /// When the tick ran.
+    pub checked_at: DateTime<FixedOffset>,
+    /// The most significant outcome of the tick, a failure over an update over no change,
+    /// `ip_fetch_failed` when the public IP couldn't be read, or `circuit_open` when the tick was
+    /// skipped by the circuit breaker.
+    pub result: &'static str,
+    /// Why the whole tick failed, when it did.
+    pub error: Option<String>,
+    /// The public IP the tick fetched.
+    pub public_ip: Option<String>,
+    /// When a record was last pointed at a new IP.
+    pub last_change: Option<DateTime<FixedOffset>>,
+    pub records: Vec<RecordStatus>,
+}
+
+impl Snapshot {
+    /// Describes a tick.
+    ///
+    /// # Arguments
+    /// * `checked_at` - When the tick ran.
+    /// * `state` - The state the tick left behind.
+    /// * `outcome` - What the tick did.
+    pub fn new(checked_at: DateTime<FixedOffset>, state: &State, outcome: &Result<TickSummary, SkySyncError>) -> Snapshot {
+        let (result, error, records) = match outcome {
+            Ok(summary) => {
+                let records = summary.records.iter().map(|record| {
+                    let health = state.record_health.get(&record.name).cloned().unwrap_or_default();
+                    RecordStatus {
+                        name: record.name.clone(),
+                        status: rank(&record.result).1,
+                        content: state.records.get(&record.name).cloned(),
+                        error: match &record.result {
+                            Ok(TickOutcome::Failed { error }) => Some(error.clone()),
+                            Err(err) => Some(err.to_string()),
+                            Ok(_) => None,
+                        },
+                        last_success: health.last_success,
+                        failures: health.failures,
+                    }
+                }).collect();
+                let result = summary.records.iter().map(|record| rank(&record.result)).max().map_or("unchanged", |(_, result)| result);
+                (result, None, records)
+            }
+            Err(err @ SkySyncError::IpFetch(_)) => ("ip_fetch_failed", Some(err.to_string()), Vec::new()),
+            Err(err @ SkySyncError::CircuitOpen { .. }) => ("circuit_open", Some(err.to_string()), Vec::new()),
+            Err(err) => ("failed", Some(err.to_string()), Vec::new()),
+        };
+
+        Snapshot {
+            checked_at,
+            result,
+            error,
+            public_ip: state.current_ip.clone(),
+            last_change: state.last_change,
+            records,
+        }
+    }
+}
+
+/// The file the snapshot of every tick replaces.
+#[derive(Clone)]
+pub struct StatusFile {
+    path: PathBuf,
+}
+
+impl StatusFile {
+    pub fn new(path: impl Into<PathBuf>) -> StatusFile {
+        StatusFile { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the file with `snapshot`.
+    ///
+    /// The snapshot is written next to the file first and then moved over it, so a reader sees
+    /// either the previous tick or this one, never half of it.
+    pub async fn write(&self, snapshot: &Snapshot) -> Result<(), BoxError> {
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent).await?;
+        }
+
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        write(&temp, serde_json::to_string_pretty(snapshot)?).await?;
+        rename(&temp, &self.path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{RecordHealth, RecordOutcome};
+    use serde_json::json;
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    /// A tick that updated one record and failed another.
+    fn snapshot() -> Snapshot {
+        let mut state = State { current_ip: Some("2.2.2.2".to_string()), last_change: Some(at("2026-10-14T09:00:00Z")), ..State::default() };
+        state.records.insert("home.example.com".to_string(), "2.2.2.2".to_string());
+        state.record_health.insert("home.example.com".to_string(), RecordHealth { last_success: Some(at("2026-10-14T09:00:00Z")), ..RecordHealth::default() });
+        state.record_health.insert("www.example.com".to_string(), RecordHealth { failures: 2, ..RecordHealth::default() });
+        let summary = TickSummary {
+            records: vec![
+                RecordOutcome { name: "home.example.com".to_string(), result: Ok(TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() }) },
+                RecordOutcome { name: "www.example.com".to_string(), result: Ok(TickOutcome::Failed { error: "rejected".to_string() }) },
+            ],
+        };
+        Snapshot::new(at("2026-10-14T09:00:00Z"), &state, &Ok(summary))
+    }
+
+    /// Tests the JSON a tick is written as.
+    #[test]
+    fn test_json_shape() {
+        assert_eq!(serde_json::to_value(snapshot()).unwrap(), json!({
+            "checked_at": "2026-10-14T09:00:00Z",
+            "result": "failed",
+            "error": null,
+            "public_ip": "2.2.2.2",
+            "last_change": "2026-10-14T09:00:00Z",
+            "records": [
+                { "name": "home.example.com", "status": "updated", "content": "2.2.2.2", "error": null, "last_success": "2026-10-14T09:00:00Z", "failures": 0 },
+                { "name": "www.example.com", "status": "failed", "content": null, "error": "rejected", "last_success": null, "failures": 2 }
+            ]
+        }));
+
+        let failed = Snapshot::new(at("2026-10-14T09:00:00Z"), &State::default(), &Err(SkySyncError::IpFetch("timed out".into())));
+        assert_eq!((failed.result, failed.records.len()), ("ip_fetch_failed", 0));
+    }
+
+    /// Tests that a write replaces the previous snapshot whole and leaves no temporary file behind.
+    #[tokio::test]
+    async fn test_atomic_replacement() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = StatusFile::new(dir.path().join("nested").join("status.json"));
+        let read = || serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(file.path()).unwrap()).unwrap();
+
+        file.write(&Snapshot { result: "unchanged", records: Vec::new(), ..snapshot() }).await.unwrap();
+        assert_eq!(read()["result"], "unchanged");
+
+        file.write(&snapshot()).await.unwrap();
+        assert_eq!(read()["records"][0]["status"], "updated");
+        let entries: Vec<_> = std::fs::read_dir(file.path().parent().unwrap()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(entries, vec!["status.json"]);
+    }
+}
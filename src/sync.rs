@@ -0,0 +1,3258 @@
+#[cfg(feature = "hickory-dns")]
+use crate::config::CompareMode;
+use crate::config::{AddressSource, Config, DetectionFailure, Dispatch, DuplicatePolicy, HeartbeatConfig, IpSourceKind, Mode, PoolConfig, ProviderKind, ProxyConfig, StateKey, DEFAULT_NOTIFY_RETRIES, DEFAULT_NOTIFY_RETRY_DELAY};
+use crate::encryption;
+use crate::error::{BoxError, SkySyncError};
+use crate::geo::{GeoInfo, GeoLocator, DEFAULT_GEO_URL};
+use crate::history::History;
+use crate::hook::ChangeHook;
+use crate::http::{self, AddressFamily};
+use crate::ip::{non_routable, FallbackIpSource, FixedIpSource, HttpIpSource, InterfaceIpSource, IpCache, IpSource, RetryingIpSource, DEFAULT_IPV6_URL, DEFAULT_IP_URL};
+use crate::log::{debug_log, report, write_log};
+use crate::metrics::{timed, Metrics, Stage};
+use crate::notifier::{self, EventStatus, Notifier, NotifyQueue, UpdateEvent};
+use crate::provider::{same_address, CloudflareProvider, DnsProvider, DnsRecord, GandiProvider, PorkbunIpSource, PorkbunProvider};
+#[cfg(feature = "hickory-dns")]
+use crate::resolver::{Resolver, PUBLIC_RESOLVERS};
+use crate::retry::RetryPolicy;
+use chrono::{DateTime, FixedOffset, NaiveTime, SecondsFormat, TimeDelta};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::{create_dir_all, read, rename, write};
+use tokio::time::Instant;
+
+/// What `MODE=monitor` reports on when no record is configured.
+pub const MONITORED: &str = "public IP";
+
+/// What a single check/update cycle ended up doing to one record.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TickOutcome {
+    /// The record already points at the current public IP.
+    Unchanged { ip: String },
+    /// The record was pointed from `old` to `new`.
+    Updated { old: String, new: String },
+    /// The record didn't exist and was created with `ip`.
+    Created { ip: String },
+    /// The record already pointed at `ip`, and was rewritten to bring the rest of it, like its
+    /// proxied state or TTL, in line with the configuration.
+    Reconfigured { ip: String },
+    /// A new IP was seen `seen` times in a row, but must be seen `required` times before it's applied.
+    Pending { ip: String, seen: u32, required: u32 },
+    /// A change to `ip` was seen outside `UPDATE_WINDOW` and waits for it to open at `opens`.
+    Deferred { ip: String, opens: NaiveTime },
+    /// `MODE=monitor` saw the public IP change from `old` to `new`, and left the record alone.
+    Detected { old: String, new: String },
+    /// The provider rejected the update or creation.
+    Failed { error: String },
+    /// The record failed `failures` times in a row and is left alone until `retry_at`, with
+    /// `RECORD_RETRY_BACKOFF`, instead of being pointed at `ip` again.
+    BackingOff { ip: String, failures: u32, retry_at: DateTime<FixedOffset> },
+    /// The record has `enabled = false` in `ZONES`, and was left alone.
+    Disabled,
+}
+
+/// The result of a tick for one managed record.
+#[derive(Debug)]
+pub struct RecordOutcome {
+    pub name: String,
+    /// The outcome, or why the record couldn't be synced at all.
+    pub result: Result<TickOutcome, SkySyncError>,
+}
+
+impl RecordOutcome {
+    /// Whether the record now points at the public IP, or will once the change settles.
+    pub fn is_ok(&self) -> bool {
+        !matches!(self.result, Ok(TickOutcome::Failed { .. } | TickOutcome::BackingOff { .. }) | Err(_))
+    }
+}
+
+impl fmt::Display for RecordOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.result {
+            Ok(TickOutcome::Unchanged { ip }) => write!(f, "{}: Public IP is already up to date: {}", self.name, ip),
+            Ok(TickOutcome::Updated { old, new }) => write!(f, "{}: Public IP has changed from {} to: {}", self.name, old, new),
+            Ok(TickOutcome::Created { ip }) => write!(f, "Created DNS record {} pointing to: {}", self.name, ip),
+            Ok(TickOutcome::Reconfigured { ip }) => write!(f, "{}: Brought the record settings in line with the configuration, still pointing to: {}", self.name, ip),
+            Ok(TickOutcome::Pending { ip, seen, required }) => write!(f, "{}: Public IP changed to {}, waiting for it to settle ({}/{})", self.name, ip, seen, required),
+            Ok(TickOutcome::Deferred { ip, opens }) => write!(f, "{}: Public IP changed to {}, deferred until the update window opens at {}", self.name, ip, opens.format("%H:%M")),
+            Ok(TickOutcome::Detected { old, new }) => write!(f, "{}: Public IP has changed from {} to {} (monitor mode, not updated)", self.name, old, new),
+            Ok(TickOutcome::Failed { error }) => write!(f, "Failed to update DNS record {}: {}", self.name, error),
+            Ok(TickOutcome::BackingOff { ip, failures, retry_at }) => {
+                write!(f, "{}: Failed {} time(s) in a row, retrying the update to {} at {}", self.name, failures, ip, retry_at.format("%H:%M:%S"))
+            }
+            Ok(TickOutcome::Disabled) => write!(f, "{}: Disabled, left alone", self.name),
+            Err(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// How one managed record compares with the address a tick would point it at, for `skysync diff`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordDiff {
+    pub name: String,
+    /// The type and content the provider has for the record, or `None` when it's missing.
+    pub current: Option<(String, String)>,
+    /// The address the record would be pointed at, or why it couldn't be read.
+    pub detected: Result<String, String>,
+    /// Whether a missing record would be created, from `CF_CREATE_MISSING`.
+    pub create_missing: bool,
+    /// Whether the record is disabled in `ZONES`, which ticks leave alone.
+    pub disabled: bool,
+}
+
+impl RecordDiff {
+    /// Whether a tick would write the record.
+    pub fn would_change(&self) -> bool {
+        if self.disabled {
+            return false;
+        }
+        match (&self.current, &self.detected) {
+            (Some((_, content)), Ok(ip)) => !same_address(content, ip),
+            (None, Ok(_)) => self.create_missing,
+            (_, Err(_)) => false,
+        }
+    }
+}
+
+/// One line per record, e.g. `home.example.com A: 1.1.1.1 (current) → 2.2.2.2 (detected) [WOULD UPDATE]`.
+impl fmt::Display for RecordDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let current = match &self.current {
+            Some((record_type, content)) => format!("{} {}: {} (current)", self.name, record_type, content),
+            None => format!("{}: missing", self.name),
+        };
+        match &self.detected {
+            Err(err) => write!(f, "{}, detected IP unknown: {} [UNKNOWN]", current, err),
+            Ok(ip) => {
+                let verdict = match &self.current {
+                    _ if self.disabled => "DISABLED",
+                    Some((_, content)) if same_address(content, ip) => "OK",
+                    Some(_) => "WOULD UPDATE",
+                    None if self.create_missing => "WOULD CREATE",
+                    None => "MISSING",
+                };
+                write!(f, "{} → {} (detected) [{}]", current, ip, verdict)
+            }
+        }
+    }
+}
+
+/// What a tick did to every managed record, in the configured order.
+#[derive(Debug)]
+pub struct TickSummary {
+    pub records: Vec<RecordOutcome>,
+}
+
+impl TickSummary {
+    /// The `X of N records in sync` line opening the summary, when there's more than one enabled
+    /// record.
+    pub fn headline(&self) -> Option<String> {
+        let enabled = self.records.iter().filter(|record| !matches!(record.result, Ok(TickOutcome::Disabled))).count();
+        (enabled > 1).then(|| format!("{} of {} records in sync", enabled - self.failed(), enabled))
+    }
+
+    /// Adds a `Disabled` outcome for every disabled record, keeping the configured order.
+    fn with_disabled(mut self, config: &Config) -> TickSummary {
+        if config.disabled.is_empty() || config.mode == Mode::Monitor {
+            return self;
+        }
+        let order = config.configured_names();
+        self.records.extend(config.disabled.iter().map(|name| RecordOutcome { name: name.clone(), result: Ok(TickOutcome::Disabled) }));
+        self.records.sort_by_key(|outcome| order.iter().position(|name| *name == outcome.name));
+        self
+    }
+
+    /// A summary where every record ended up the same way, e.g. when the IP didn't change.
+    fn uniform(names: &[String], outcome: TickOutcome) -> TickSummary {
+        TickSummary {
+            records: names.iter().map(|name| RecordOutcome { name: name.clone(), result: Ok(outcome.clone()) }).collect(),
+        }
+    }
+
+    /// How many records couldn't be synced this tick.
+    pub fn failed(&self) -> usize {
+        self.records.iter().filter(|record| !record.is_ok()).count()
+    }
+}
+
+impl fmt::Display for TickSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(headline) = self.headline() {
+            writeln!(f, "{}", headline)?;
+        }
+        for (i, record) in self.records.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+/// State carried from one tick to the next, and across restarts of the daemon.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// The public IP every record was last confirmed to point at.
+    pub last_public_ip: Option<String>,
+    /// A newly detected IP waiting out the debounce, with how many ticks in a row it was seen.
+    pub pending_ip: Option<(String, u32)>,
+    /// The public IP fetched by the latest tick.
+    pub current_ip: Option<String>,
+    /// When the latest tick started.
+    pub last_check: Option<DateTime<FixedOffset>>,
+    /// When a record was last pointed at a new IP.
+    pub last_change: Option<DateTime<FixedOffset>>,
+    /// How many ticks in a row failed, fully or for some record.
+    pub failure_streak: u32,
+    /// How many ticks failed since the state was first saved.
+    pub total_failures: u64,
+    /// When the current run of failing ticks started, tracked while `ESCALATE_AFTER` is set.
+    pub outage_since: Option<DateTime<FixedOffset>>,
+    /// How many of the `ESCALATE_AFTER` thresholds the current outage has already crossed.
+    pub escalations: usize,
+    /// When the record contents were last read from the provider.
+    pub records_checked: Option<DateTime<FixedOffset>>,
+    /// How many ticks in a row trusted an unchanged public IP instead of reading the records,
+    /// counted towards `DRIFT_CHECK_EVERY`.
+    pub unread_ticks: u32,
+    /// The content of every managed record, as last seen or written.
+    pub records: BTreeMap<String, String>,
+    /// How the updates of every managed record went lately.
+    pub record_health: BTreeMap<String, RecordHealth>,
+    /// The circuit breaker around the provider, with `CIRCUIT_BREAKER_THRESHOLD`.
+    pub circuit: Circuit,
+    /// The update notifications held back, with `NOTIFY_RATE_WINDOW`.
+    pub throttle: NotifyThrottle,
+    /// Geo lookups already made, by IP. Only kept in memory.
+    #[serde(skip)]
+    pub geo_cache: HashMap<String, GeoInfo>,
+}
+
+impl State {
+    /// The default location, `state.json` next to the log file.
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir().expect("Failed to get data directory").join("SkySync").join("state.json")
+    }
+
+    /// Reads the state saved by a previous run. A missing file is a fresh state.
+    ///
+    /// # Arguments
+    /// * `path` - Where the state was saved.
+    /// * `key` - The `SKYSYNC_STATE_KEY` an encrypted file is opened with. A plaintext file is
+    ///   read either way, so turning encryption on keeps the state.
+    pub async fn load(path: &Path, key: Option<&StateKey>) -> Result<State, BoxError> {
+        if !path.exists() {
+            return Ok(State::default());
+        }
+
+        let data = read(path).await?;
+        let data = match (encryption::is_encrypted(&data), key) {
+            (false, _) => data,
+            (true, Some(key)) => encryption::open(key, &data).map_err(|err| format!("{} {}", path.display(), err))?,
+            (true, None) => return Err(format!("{} is encrypted, set SKYSYNC_STATE_KEY to read it", path.display()).into()),
+        };
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// Saves the state for the next run, encrypted with `key` if it's set.
+    ///
+    /// The file is written next to `path` first and then moved over it, so a crash mid-write
+    /// leaves the previous state intact.
+    pub async fn save(&self, path: &Path, key: Option<&StateKey>) -> Result<(), BoxError> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).await?;
+        }
+
+        let json = serde_json::to_string_pretty(self)?;
+        let data = match key {
+            Some(key) => encryption::seal(key, json.as_bytes())?,
+            None => json.into_bytes(),
+        };
+        let temp = path.with_extension("json.tmp");
+        write(&temp, data).await?;
+        rename(&temp, path).await?;
+        Ok(())
+    }
+
+    /// The records still waiting out their `RECORD_RETRY_BACKOFF` at `now`.
+    fn backing_off(&self, now: DateTime<FixedOffset>) -> HashMap<String, RecordHealth> {
+        self.record_health.iter()
+            .filter(|(_, health)| health.retry_at.is_some_and(|retry_at| retry_at > now))
+            .map(|(name, health)| (name.clone(), health.clone()))
+            .collect()
+    }
+
+    /// Updates the health of every record from how the tick at `now` went. Records that are
+    /// waiting, for the debounce, the update window or their backoff, keep theirs.
+    fn track_records(&mut self, config: &Config, summary: &TickSummary, now: DateTime<FixedOffset>) {
+        if config.mode == Mode::Monitor {
+            return;
+        }
+        for record in &summary.records {
+            let health = self.record_health.entry(record.name.clone()).or_default();
+            match &record.result {
+                Ok(TickOutcome::Disabled) => health.disabled = true,
+                Ok(TickOutcome::Pending { .. } | TickOutcome::Deferred { .. } | TickOutcome::BackingOff { .. }) => health.disabled = false,
+                _ if record.is_ok() => *health = RecordHealth { last_success: Some(now), ..RecordHealth::default() },
+                _ => {
+                    health.disabled = false;
+                    health.failures += 1;
+                    health.retry_at = (!config.record_retry_backoff.is_zero())
+                        .then(|| now + TimeDelta::from_std(retry_backoff(config.record_retry_backoff, health.failures)).unwrap_or_default());
+                }
+            }
+        }
+        let names = config.configured_names();
+        self.record_health.retain(|name, _| names.contains(name));
+    }
+
+    /// Counts another observation of a changed IP.
+    ///
+    /// # Arguments
+    /// * `ip` - The newly detected IP.
+    /// * `required` - How many consecutive observations are needed before applying it.
+    ///
+    /// # Returns
+    /// How many times in a row `ip` has been seen, if that's still short of `required`.
+    fn debounce(&mut self, ip: &str, required: u32) -> Option<u32> {
+        let seen = match &self.pending_ip {
+            Some((pending, seen)) if pending == ip => seen + 1,
+            _ => 1,
+        };
+
+        if seen >= required {
+            self.pending_ip = None;
+            None
+        } else {
+            self.pending_ip = Some((ip.to_string(), seen));
+            Some(seen)
+        }
+    }
+}
+
+/// The longest a failing record is left alone, however many times in a row it failed.
+const MAX_RECORD_RETRY_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// How long a record is left alone after failing `failures` times in a row: `backoff` doubled
+/// after every failure but the first, up to `MAX_RECORD_RETRY_BACKOFF`.
+fn retry_backoff(backoff: Duration, failures: u32) -> Duration {
+    backoff.saturating_mul(2u32.saturating_pow(failures.saturating_sub(1))).min(MAX_RECORD_RETRY_BACKOFF)
+}
+
+/// How the updates of one record went lately, so a failing record is retried on its own schedule.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordHealth {
+    /// When a tick last found the record pointing at the public IP, or got it there.
+    pub last_success: Option<DateTime<FixedOffset>>,
+    /// How many ticks in a row failed to update it.
+    pub failures: u32,
+    /// When it's next written, with `RECORD_RETRY_BACKOFF`. Until then it's left alone.
+    pub retry_at: Option<DateTime<FixedOffset>>,
+    /// Whether the record is disabled in `ZONES`. The rest of its health is kept from before.
+    pub disabled: bool,
+}
+
+/// The update notifications of the current `NOTIFY_RATE_WINDOW`, which starts with the latest
+/// one sent.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotifyThrottle {
+    /// When an update was last notified.
+    pub notified_at: Option<DateTime<FixedOffset>>,
+    /// How many changes were held back since, with the address of the latest.
+    pub held: Option<(u32, String)>,
+}
+
+/// What the updates of a tick come to with `NOTIFY_RATE_WINDOW`.
+#[derive(Debug, PartialEq)]
+enum Release {
+    /// Nothing is sent, either because nothing changed or because the window isn't over.
+    Nothing,
+    /// The updates are notified as usual.
+    Updates,
+    /// The changes held back over the last `minutes`, this tick's included, are notified as one.
+    Summary { changes: u32, minutes: i64, ip: String },
+}
+
+impl NotifyThrottle {
+    /// Counts the change of the tick at `now`, if there was one, and decides what's notified.
+    ///
+    /// # Arguments
+    /// * `window` - How long after a notification the next one is held back.
+    /// * `now` - When the tick ran.
+    /// * `changed` - The address the records were pointed at, when the tick updated any.
+    fn release(&mut self, window: Duration, now: DateTime<FixedOffset>, changed: Option<&str>) -> Release {
+        let last = self.notified_at;
+        let open = last.is_none_or(|at| (now - at).to_std().unwrap_or_default() >= window);
+        if !open {
+            if let Some(ip) = changed {
+                let held = self.held.get_or_insert_with(|| (0, String::new()));
+                *held = (held.0 + 1, ip.to_string());
+            }
+            return Release::Nothing;
+        }
+
+        let release = match (self.held.take(), changed) {
+            (None, None) => return Release::Nothing,
+            (None, Some(_)) => Release::Updates,
+            (Some((held, ip)), changed) => Release::Summary {
+                changes: held + u32::from(changed.is_some()),
+                minutes: last.map_or(0, |at| (now - at).num_minutes()),
+                ip: changed.map_or(ip, str::to_string),
+            },
+        };
+        self.notified_at = Some(now);
+        release
+    }
+}
+
+/// Where the circuit breaker around the provider stands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// The provider is called as usual.
+    #[default]
+    Closed,
+    /// The provider failed too often, and ticks are skipped until the cooldown is over.
+    Open,
+    /// The cooldown is over and the provider answered a test listing. The running tick decides
+    /// whether the circuit closes or opens again.
+    HalfOpen,
+}
+
+/// The circuit breaker around the provider, which stops calling it for
+/// `CIRCUIT_BREAKER_COOLDOWN` once it failed `CIRCUIT_BREAKER_THRESHOLD` ticks in a row.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Circuit {
+    pub state: CircuitState,
+    /// How many ticks in a row failed to read or write the records.
+    pub failures: u32,
+    /// When the open circuit lets the provider be tested again.
+    pub retry_at: Option<DateTime<FixedOffset>>,
+}
+
+/// The public IP an IP source answered with, and the address it parses to.
+///
+/// # Returns
+/// `SkySyncError::IpFetch` if the lookup failed, or answered with nothing or with something
+/// other than an address.
+fn detected(fetched: Result<String, BoxError>) -> Result<(String, IpAddr), SkySyncError> {
+    let ip = fetched.map_err(SkySyncError::IpFetch)?.trim().to_string();
+    if ip.is_empty() {
+        return Err(SkySyncError::IpFetch("the IP source answered with nothing".into()));
+    }
+    match ip.parse::<IpAddr>() {
+        Ok(address) => Ok((ip, address)),
+        Err(_) => Err(SkySyncError::IpFetch(format!("{:?} is not an IP address", ip).into())),
+    }
+}
+
+/// Why a tick failed at the provider, if it did, or `None` for a tick that never reached it.
+fn provider_failure(result: &Result<TickSummary, SkySyncError>) -> Option<Option<String>> {
+    match result {
+        Err(err @ SkySyncError::Provider(_)) => Some(Some(err.to_string())),
+        Err(SkySyncError::IpFetch(_) | SkySyncError::CircuitOpen { .. }) => None,
+        Err(SkySyncError::RecordNotFound(_) | SkySyncError::DuplicateRecords { .. }) => Some(None),
+        Ok(summary) => {
+            let failed = summary.records.iter()
+                .find(|record| matches!(record.result, Ok(TickOutcome::Failed { .. }) | Err(SkySyncError::Provider(_))));
+            let reached = summary.records.iter().any(|record| !matches!(record.result, Err(SkySyncError::IpFetch(_))));
+            match failed {
+                Some(record) => Some(Some(record.to_string())),
+                None => reached.then_some(None),
+            }
+        }
+    }
+}
+
+/// The HTTP clients the services of a tick send their requests with, kept by the daemon across
+/// ticks so their connections are too.
+#[derive(Clone)]
+pub struct Clients {
+    /// The client of the provider, the notifiers and the location lookup.
+    client: reqwest::Client,
+    /// The client of the records with their own source, which honors `RESOLVER`.
+    ip_client: reqwest::Client,
+    /// The client of the public IP lookup, pinned to `IP_FAMILY` when it's set.
+    public_client: reqwest::Client,
+    /// The settings the clients were built with.
+    settings: (Option<ProxyConfig>, PoolConfig, Vec<SocketAddr>, Option<AddressFamily>),
+}
+
+impl Clients {
+    /// Builds the clients with the proxy, pool and resolver settings of `config`.
+    pub fn from_config(config: &Config) -> Clients {
+        // Like `reqwest::Client::new`, this only fails without a usable TLS backend
+        let client = http::client(config.proxy.as_ref(), &config.pool).expect("Failed to build the HTTP client");
+        let ip_client = http::ip_client(config.proxy.as_ref(), &config.pool, &config.resolver, None).expect("Failed to build the HTTP client");
+        // Records with their own source pick the endpoint of their family, so only the public IP is pinned
+        let public_client = match config.ip_family {
+            Some(family) => http::ip_client(config.proxy.as_ref(), &config.pool, &config.resolver, Some(family)).expect("Failed to build the HTTP client"),
+            None => ip_client.clone(),
+        };
+        Clients { client, ip_client, public_client, settings: Clients::settings(config) }
+    }
+
+    /// Whether the clients were built with the settings of `config`, so a reload to it can keep them.
+    pub fn fit(&self, config: &Config) -> bool {
+        self.settings == Clients::settings(config)
+    }
+
+    fn settings(config: &Config) -> (Option<ProxyConfig>, PoolConfig, Vec<SocketAddr>, Option<AddressFamily>) {
+        (config.proxy.clone(), config.pool.clone(), config.resolver.clone(), config.ip_family)
+    }
+}
+
+/// The services a tick talks to.
+pub struct Syncer {
+    provider: Box<dyn DnsProvider>,
+    ip_source: Box<dyn IpSource>,
+    /// Sources used instead of `ip_source` for the address of some records, by record name and type.
+    sources: HashMap<(String, String), Box<dyn IpSource>>,
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    /// Notifiers used instead of `notifiers` for the events of some records, by record name.
+    routes: HashMap<String, Arc<Vec<Box<dyn Notifier>>>>,
+    failover: Option<Arc<dyn Notifier>>,
+    /// Whether the notifiers of an event are tried all at once or in order.
+    dispatch: Dispatch,
+    /// How often a delivery that failed is retried.
+    notify_retry: RetryPolicy,
+    /// When set, events are delivered by its task instead of during the tick.
+    queue: Option<NotifyQueue>,
+    hook: Option<ChangeHook>,
+    /// Updates of the running tick waiting to be sent as one, with `COALESCE_NOTIFICATIONS`.
+    coalesced: std::sync::Mutex<Vec<UpdateEvent>>,
+    /// Updates of the running tick the provider already wrote together, by record ID and content.
+    prewritten: std::sync::Mutex<HashMap<(String, String), Result<(), BoxError>>>,
+    /// Records the running tick leaves alone until their backoff is over, by record name.
+    backing_off: std::sync::Mutex<HashMap<String, RecordHealth>>,
+    history: Option<History>,
+    geo: Option<GeoLocator>,
+    ip_cache: Option<IpCache>,
+    /// When set, records are compared by what this server resolves them to.
+    #[cfg(feature = "hickory-dns")]
+    resolver: Option<Resolver>,
+    force: bool,
+    /// The profile every event is tagged with, when `PROFILES` runs several.
+    profile: Option<String>,
+}
+
+impl Syncer {
+    /// Creates a syncer from explicit services, e.g. mocks in tests or custom embeddings.
+    pub fn new(provider: Box<dyn DnsProvider>, ip_source: Box<dyn IpSource>, notifiers: Vec<Box<dyn Notifier>>) -> Syncer {
+        Syncer {
+            provider,
+            ip_source,
+            sources: HashMap::new(),
+            notifiers: Arc::new(notifiers),
+            routes: HashMap::new(),
+            failover: None,
+            dispatch: Dispatch::default(),
+            notify_retry: RetryPolicy { retries: DEFAULT_NOTIFY_RETRIES, delay: DEFAULT_NOTIFY_RETRY_DELAY },
+            queue: None,
+            hook: None,
+            coalesced: std::sync::Mutex::new(Vec::new()),
+            prewritten: std::sync::Mutex::new(HashMap::new()),
+            backing_off: std::sync::Mutex::new(HashMap::new()),
+            history: None,
+            geo: None,
+            ip_cache: None,
+            #[cfg(feature = "hickory-dns")]
+            resolver: None,
+            force: false,
+            profile: None,
+        }
+    }
+
+    /// Creates a syncer with the services selected by the configuration, and clients of its own.
+    pub fn from_config(config: &Config) -> Syncer {
+        Syncer::from_clients(config, &Clients::from_config(config))
+    }
+
+    /// Creates a syncer with the services selected by the configuration, sending their requests
+    /// with `clients`.
+    ///
+    /// Every HTTP request of the tick goes through one client, so they share a proxy and connections.
+    pub fn from_clients(config: &Config, clients: &Clients) -> Syncer {
+        let Clients { client, ip_client, public_client, .. } = clients.clone();
+        let syncer = Syncer::new(
+            dns_provider(config, &client),
+            Box::new(RetryingIpSource::new(ip_source(config, public_client), config.ip_retry)),
+            notifier::from_config(config, &client),
+        ).with_history(History::new(config.profile_path(History::default_path()), config.history_length).with_rotate_size(config.history_rotate_size).with_rotate_keep(config.history_rotate_keep))
+            .with_profile(config.profile.clone())
+            .with_routes(notifier::routes_from_config(config, &client))
+            .with_sources(record_sources(config, ip_client))
+            .with_dispatch(config.notifier_dispatch)
+            .with_notify_retry(config.notify_retry);
+        let syncer = match notifier::failover_from_config(config, &client) {
+            Some(failover) => syncer.with_failover(failover),
+            None => syncer,
+        };
+        let syncer = match &config.on_change {
+            Some(hook) => syncer.with_hook(ChangeHook::new(hook.clone())),
+            None => syncer,
+        };
+        // Resolve is rejected by the config loader when the feature is disabled
+        #[cfg(feature = "hickory-dns")]
+        let syncer = match config.compare_mode {
+            CompareMode::Resolve => {
+                let servers = if config.resolver.is_empty() { &PUBLIC_RESOLVERS[..] } else { &config.resolver[..] };
+                syncer.with_resolver(Resolver::new(servers).with_dnssec(config.compare_dnssec))
+            }
+            CompareMode::Api => syncer,
+        };
+
+        if config.enrich_geo {
+            syncer.with_geo(GeoLocator::new(DEFAULT_GEO_URL, client))
+        } else {
+            syncer
+        }
+    }
+
+    /// Writes every record on the next tick, even the ones already pointing at the public IP.
+    ///
+    /// Skips the last-IP cache and the debounce, for recovering from records edited by hand.
+    pub fn with_force(mut self, force: bool) -> Syncer {
+        self.force = force;
+        self
+    }
+
+    /// Tags every event with `profile`, so notifications say which profile they come from.
+    pub fn with_profile(mut self, profile: Option<String>) -> Syncer {
+        self.profile = profile;
+        self
+    }
+
+    /// Adds the location of the new IP to every notification.
+    pub fn with_geo(mut self, geo: GeoLocator) -> Syncer {
+        self.geo = Some(geo);
+        self
+    }
+
+    /// Compares records by what `resolver` answers for them instead of by their content.
+    ///
+    /// The records are then listed on every tick, since resolvers can lag behind them.
+    #[cfg(feature = "hickory-dns")]
+    pub fn with_resolver(mut self, resolver: Resolver) -> Syncer {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Whether records are compared by what a resolver answers, so they're listed on every tick.
+    fn compares_resolved(&self) -> bool {
+        #[cfg(feature = "hickory-dns")]
+        return self.resolver.is_some();
+        #[cfg(not(feature = "hickory-dns"))]
+        false
+    }
+
+    /// Stores every fetched IP in `cache`, for readers outside the tick.
+    pub fn with_ip_cache(mut self, cache: IpCache) -> Syncer {
+        self.ip_cache = Some(cache);
+        self
+    }
+
+    /// Sends the events of the records in `routes` to their own notifiers instead of the rest.
+    pub fn with_routes(mut self, routes: HashMap<String, Vec<Box<dyn Notifier>>>) -> Syncer {
+        self.routes = routes.into_iter().map(|(name, notifiers)| (name, Arc::new(notifiers))).collect();
+        self
+    }
+
+    /// Reads the address of the records in `sources`, by name and type, from their own source
+    /// instead of the public IP.
+    pub fn with_sources(mut self, sources: HashMap<(String, String), Box<dyn IpSource>>) -> Syncer {
+        self.sources = sources;
+        self
+    }
+
+    /// Tries the notifiers of every event as `dispatch` says, instead of in order.
+    pub fn with_dispatch(mut self, dispatch: Dispatch) -> Syncer {
+        self.dispatch = dispatch;
+        self
+    }
+
+    /// Retries deliveries that failed as `retry` says, instead of `NOTIFY_RETRIES`' defaults.
+    pub fn with_notify_retry(mut self, retry: RetryPolicy) -> Syncer {
+        self.notify_retry = retry;
+        self
+    }
+
+    /// Sends events to `failover` whenever one of the notifiers fails to deliver them.
+    pub fn with_failover(mut self, failover: Box<dyn Notifier>) -> Syncer {
+        self.failover = Some(Arc::from(failover));
+        self
+    }
+
+    /// Runs `hook` for every record pointed at a new IP.
+    pub fn with_hook(mut self, hook: ChangeHook) -> Syncer {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Hands events to `queue` instead of waiting for them to be delivered.
+    pub fn with_queue(mut self, queue: NotifyQueue) -> Syncer {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Records every detected IP change in `history`.
+    pub fn with_history(mut self, history: History) -> Syncer {
+        self.history = Some(history);
+        self
+    }
+
+    /// Runs a single check/update cycle.
+    ///
+    /// Records are written concurrently, at most `update_concurrency` at a time, and a failure
+    /// on one record doesn't stop the others.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration to apply.
+    /// * `state` - State from the previous tick, updated in place.
+    ///
+    /// # Returns
+    /// A `TickSummary` with one outcome per record, or an error if the public IP or the current
+    /// records couldn't be fetched.
+    pub async fn run_once(&self, config: &Config, state: &mut State) -> Result<TickSummary, SkySyncError> {
+        self.run(config, state, false).await
+    }
+
+    /// `run_once`, listing the records once up front and leaving the updates unnotified when
+    /// `batched`.
+    async fn run(&self, config: &Config, state: &mut State, batched: bool) -> Result<TickSummary, SkySyncError> {
+        let started = Instant::now();
+        let now = config.timezone.now();
+        state.last_check = Some(now);
+        if config.circuit_breaker_threshold > 0 {
+            self.test_circuit(config, state, now).await?;
+        }
+
+        *self.backing_off.lock().unwrap() = state.backing_off(now);
+        let result = self.tick(config, state, batched).await.map(|summary| summary.with_disabled(config));
+        self.backing_off.lock().unwrap().clear();
+        if let Ok(summary) = &result {
+            state.track_records(config, summary, now);
+            let updated = summary.records.iter().any(|record| matches!(record.result, Ok(TickOutcome::Updated { .. } | TickOutcome::Created { .. })));
+            if let (Some(heartbeat), true) = (&config.heartbeat, updated) {
+                self.beat(heartbeat, state, now).await;
+            }
+        }
+        self.send_coalesced(config, state, now).await;
+        let notified = config.on_detection_failure == DetectionFailure::Error && matches!(result, Err(SkySyncError::IpFetch(_)));
+        if let (true, Err(err)) = (notified, &result) {
+            let ip = state.current_ip.clone().unwrap_or_else(|| "unknown".to_string());
+            let event = UpdateEvent::new(&config.dns_names().join(", "), None, &ip, EventStatus::Failure(err.to_string()));
+            self.notify(event.in_timezone(config.timezone)).await;
+        }
+        let error = match &result {
+            Ok(summary) => summary.records.iter().find(|record| !record.is_ok()).map(RecordOutcome::to_string),
+            Err(err) => Some(err.to_string()),
+        };
+        self.track_failures(config, state, error.as_deref()).await;
+
+        if !config.escalate_after.is_empty() {
+            self.track_outage(config, state, now, error, result.is_err() && !notified).await;
+        }
+        if config.circuit_breaker_threshold > 0 {
+            self.track_circuit(config, state, now, &result).await;
+        }
+        Metrics::global().set_tick_duration(started.elapsed());
+        result
+    }
+
+    /// Points the `HEARTBEAT_RECORD` at its template filled in for a tick at `now` that updated a
+    /// record. The heartbeat is best-effort: a missing record or a failed write is only logged.
+    async fn beat(&self, heartbeat: &HeartbeatConfig, state: &State, now: DateTime<FixedOffset>) {
+        let content = heartbeat.template
+            .replace("{{timestamp}}", &now.to_utc().to_rfc3339_opts(SecondsFormat::Secs, true))
+            .replace("{{ip}}", state.current_ip.as_deref().unwrap_or(""));
+        let written = match self.provider.find_record(&heartbeat.record, "TXT").await {
+            Ok(Some(record)) if record.content == content => Ok(()),
+            Ok(Some(record)) => timed(Stage::Update, self.provider.update_record(&record, &content)).await.map_err(|err| err.to_string()),
+            Ok(None) => Err(format!("there is no TXT record named {}, create it to publish the heartbeat", heartbeat.record)),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(err) = written {
+            report(&format!("Failed to update the heartbeat record {}: {}", heartbeat.record, err)).await;
+        }
+    }
+
+    /// Skips the tick while the circuit breaker is open, and tests the provider with a listing
+    /// once the cooldown is over, leaving the circuit half-open if it answers.
+    ///
+    /// # Returns
+    /// `SkySyncError::CircuitOpen` if the tick is skipped, without any of the bookkeeping of a
+    /// failed tick.
+    async fn test_circuit(&self, config: &Config, state: &mut State, now: DateTime<FixedOffset>) -> Result<(), SkySyncError> {
+        if state.circuit.state != CircuitState::Open {
+            return Ok(());
+        }
+        if let Some(until) = state.circuit.retry_at.filter(|retry_at| *retry_at > now) {
+            return Err(SkySyncError::CircuitOpen { until });
+        }
+
+        match self.verify().await {
+            Ok(()) => {
+                state.circuit.state = CircuitState::HalfOpen;
+                write_log("The DNS provider answered again, testing it with a tick before closing the circuit").await;
+                Ok(())
+            }
+            Err(err) => {
+                let until = now + TimeDelta::from_std(config.circuit_breaker_cooldown).unwrap_or_default();
+                state.circuit.retry_at = Some(until);
+                report(&format!("The DNS provider still fails, leaving it alone until {}: {}", until.to_rfc3339(), err)).await;
+                Err(SkySyncError::CircuitOpen { until })
+            }
+        }
+    }
+
+    /// Counts the ticks in a row that failed at the provider, opening the circuit breaker once
+    /// they reach `CIRCUIT_BREAKER_THRESHOLD`, or right away when the tick testing a half-open
+    /// circuit fails. Opening it is reported once; a tick that goes through closes it again.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the tick.
+    /// * `state` - State after the tick, with the circuit updated in place.
+    /// * `now` - When the tick started.
+    /// * `result` - How the tick went.
+    async fn track_circuit(&self, config: &Config, state: &mut State, now: DateTime<FixedOffset>, result: &Result<TickSummary, SkySyncError>) {
+        let circuit = &mut state.circuit;
+        let error = match provider_failure(result) {
+            // Without reaching the provider the tick says nothing about it
+            None => return,
+            Some(None) => {
+                if circuit.state == CircuitState::HalfOpen {
+                    write_log("The DNS provider is back, closing the circuit").await;
+                }
+                *circuit = Circuit::default();
+                return;
+            }
+            Some(Some(error)) => error,
+        };
+
+        circuit.failures += 1;
+        let reopened = circuit.state == CircuitState::HalfOpen;
+        if !reopened && circuit.failures < config.circuit_breaker_threshold {
+            return;
+        }
+        let until = now + TimeDelta::from_std(config.circuit_breaker_cooldown).unwrap_or_default();
+        circuit.state = CircuitState::Open;
+        circuit.retry_at = Some(until);
+        if reopened {
+            report(&format!("The DNS provider still fails, leaving it alone until {}: {}", until.to_rfc3339(), error)).await;
+            return;
+        }
+
+        let names = config.dns_names().join(", ");
+        let ip = state.current_ip.clone().unwrap_or_else(|| "unknown".to_string());
+        let status = EventStatus::CircuitOpen {
+            error,
+            failures: state.circuit.failures,
+            minutes: (config.circuit_breaker_cooldown.as_secs() / 60) as i64,
+        };
+        report(&format!("The DNS provider failed {} ticks in a row, leaving it alone until {}", state.circuit.failures, until.to_rfc3339())).await;
+        self.notify(UpdateEvent::new(&names, None, &ip, status).in_timezone(config.timezone)).await;
+    }
+
+    /// Counts the failed ticks, reporting once when the streak reaches `FAILURE_ALERT_THRESHOLD`
+    /// and once when a tick succeeds after it did.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the tick.
+    /// * `state` - State after the tick, with the counters updated in place.
+    /// * `error` - Why the tick failed, if it did.
+    async fn track_failures(&self, config: &Config, state: &mut State, error: Option<&str>) {
+        let threshold = config.failure_alert_threshold;
+        let names = config.dns_names().join(", ");
+        let ip = state.current_ip.clone().unwrap_or_else(|| "unknown".to_string());
+        let event = |status| UpdateEvent::new(&names, None, &ip, status).in_timezone(config.timezone);
+
+        let Some(error) = error else {
+            if threshold > 0 && state.failure_streak >= threshold {
+                self.notify(event(EventStatus::Recovered { failures: state.failure_streak })).await;
+            }
+            state.failure_streak = 0;
+            return;
+        };
+
+        state.failure_streak += 1;
+        state.total_failures += 1;
+        if state.failure_streak == threshold {
+            self.notify(event(EventStatus::Degraded { error: error.to_string(), failures: threshold })).await;
+        }
+    }
+
+    /// Escalates an outage once for every `ESCALATE_AFTER` threshold it crosses, and reports the
+    /// end of an escalated one.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the tick.
+    /// * `state` - State after the tick, with the outage tracking updated in place.
+    /// * `now` - When the tick started.
+    /// * `error` - Why the tick failed, if it did.
+    /// * `aborted` - Whether the tick failed before reaching the records, which then sent nothing.
+    async fn track_outage(&self, config: &Config, state: &mut State, now: DateTime<FixedOffset>, error: Option<String>, aborted: bool) {
+        let names = config.dns_names().join(", ");
+        let ip = state.current_ip.clone().unwrap_or_else(|| "unknown".to_string());
+        let event = |status| UpdateEvent::new(&names, None, &ip, status).in_timezone(config.timezone);
+
+        let Some(error) = error else {
+            if let Some(since) = state.outage_since.take().filter(|_| state.escalations > 0) {
+                self.notify(event(EventStatus::Resolved { minutes: (now - since).num_minutes() })).await;
+            }
+            state.escalations = 0;
+            return;
+        };
+
+        let since = *state.outage_since.get_or_insert(now);
+        // Failed records are reported as they fail, but nothing was said about a tick that couldn't start
+        if aborted && state.failure_streak == 1 {
+            self.notify(event(EventStatus::Failure(error.clone()))).await;
+        }
+
+        let elapsed = (now - since).to_std().unwrap_or_default();
+        let crossed = config.escalate_after.iter().filter(|threshold| elapsed >= **threshold).count();
+        if crossed > state.escalations {
+            state.escalations = crossed;
+            self.notify(event(EventStatus::Escalated { error, minutes: (now - since).num_minutes() })).await;
+        }
+    }
+
+    /// Reports that the daemon went `minutes` without a successful tick before this start.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the first tick.
+    /// * `state` - State loaded from the previous run.
+    /// * `minutes` - How long ago the latest successful tick ran.
+    pub async fn notify_downtime(&self, config: &Config, state: &State, minutes: i64) {
+        let names = config.dns_names().join(", ");
+        let ip = state.current_ip.clone().unwrap_or_else(|| "unknown".to_string());
+        self.notify(UpdateEvent::new(&names, None, &ip, EventStatus::Downtime { minutes }).in_timezone(config.timezone)).await;
+    }
+
+    /// Tells the notifiers that the daemon started or stopped, with the last known public IP.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration the daemon runs with.
+    /// * `state` - The state at that point.
+    /// * `status` - `Started` or `Stopped`.
+    pub async fn notify_lifecycle(&self, config: &Config, state: &State, status: EventStatus) {
+        let names = config.dns_names().join(", ");
+        let ip = state.current_ip.clone().unwrap_or_else(|| "unknown".to_string());
+        self.notify(UpdateEvent::new(&names, None, &ip, status).in_timezone(config.timezone)).await;
+    }
+
+    /// Checks that the provider accepts the credentials and lists the records, without changing
+    /// anything.
+    pub async fn verify(&self) -> Result<(), SkySyncError> {
+        self.provider.check_credentials().await.map_err(SkySyncError::Provider)?;
+        self.list_records().await.map(|_| ()).map_err(SkySyncError::Provider)
+    }
+
+    /// Detects the public IP the way a tick does, with the configured sources, fallbacks and
+    /// override, but without the cache or touching any record, for `skysync print-ip`.
+    ///
+    /// # Returns
+    /// The address, or `SkySyncError::IpFetch` if no source answered with one.
+    pub async fn public_ip(&self) -> Result<String, SkySyncError> {
+        detected(self.ip_source.public_ip().await).map(|(ip, _)| ip)
+    }
+
+    /// Compares every managed record with the address a tick would point it at, without writing
+    /// or notifying anything, for `skysync diff`.
+    ///
+    /// The records are always read, and the saved state, the debounce and the update window are
+    /// left out, so stale records show up even when the next tick would still wait on them.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration naming the records and their sources.
+    ///
+    /// # Returns
+    /// One entry per record, in the configured order, or an error if the records couldn't be
+    /// listed. An address that can't be read only leaves its own records unknown.
+    pub async fn diff(&self, config: &Config) -> Result<Vec<RecordDiff>, SkySyncError> {
+        let records = self.list_records().await.map_err(SkySyncError::Provider)?;
+        let checked = |fetched: Result<String, BoxError>| -> Result<String, String> {
+            let ip = fetched.map_err(|err| err.to_string())?.trim().to_string();
+            match (ip.parse::<IpAddr>(), config.ipv6_prefix) {
+                (Ok(IpAddr::V6(address)), Some(prefix)) => Ok(prefix.apply(address).to_string()),
+                (Ok(_), _) => Ok(ip),
+                (Err(_), _) => Err(format!("{:?} is not an IP address", ip)),
+            }
+        };
+
+        let names = config.configured_names();
+        let public = match names.iter().any(|name| !config.sources.contains_key(name)) {
+            true => Some(checked(self.ip_source.public_ip().await)),
+            false => None,
+        };
+        let mut diffs = Vec::new();
+        for name in names {
+            // A record listing several types gets one entry per type
+            let types: Vec<Option<&str>> = match config.sources.get(&name) {
+                Some(configured) => configured.iter().map(|source| Some(source.record_type.as_str())).collect(),
+                None => vec![None],
+            };
+            for record_type in types {
+                let detected = match record_type.and_then(|record_type| self.sources.get(&(name.clone(), record_type.to_string()))) {
+                    Some(source) => checked(source.public_ip().await),
+                    None if record_type.is_some() => Err("no source was set up".to_string()),
+                    None => public.clone().unwrap_or_else(|| Err("no source was set up".to_string())),
+                };
+                let current = records.iter()
+                    .find(|record| record.matches(&name) && record_type.is_none_or(|record_type| record.r#type.eq_ignore_ascii_case(record_type)))
+                    .map(|record| (record.r#type.clone(), record.content.clone()));
+                let disabled = config.disabled.contains(&name);
+                diffs.push(RecordDiff { name: name.clone(), current, detected, create_missing: config.create_missing, disabled });
+            }
+        }
+        Ok(diffs)
+    }
+
+    /// Runs the first cycle after a start, trusting the records over the saved `state`.
+    ///
+    /// The record contents are always read, so records that already point at the public IP are
+    /// adopted as-is (seeding `last_public_ip`) without an update or a notification, and records
+    /// changed while SkySync was down are caught even if the saved IP matches. With `--force`
+    /// every record is rewritten anyway, and `MODE=monitor` never reads them, so both are a
+    /// plain `run_once`.
+    ///
+    /// Every record is compared against a single listing, and the updates are announced by one
+    /// `Reconciled` event instead of one per record. Failed writes are still notified on their own.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration to apply.
+    /// * `state` - State loaded from the previous run, updated in place.
+    pub async fn reconcile(&self, config: &Config, state: &mut State) -> Result<TickSummary, SkySyncError> {
+        if self.force || config.mode == Mode::Monitor {
+            return self.run_once(config, state).await;
+        }
+        state.last_public_ip = None;
+        let summary = self.run(config, state, true).await?;
+
+        let count = |wanted: fn(&TickOutcome) -> bool| summary.records.iter().filter(|record| record.result.as_ref().is_ok_and(wanted)).count();
+        let updated = count(|outcome| matches!(outcome, TickOutcome::Updated { .. } | TickOutcome::Created { .. } | TickOutcome::Reconfigured { .. }));
+        let current = count(|outcome| matches!(outcome, TickOutcome::Unchanged { .. }));
+        let failed = summary.failed();
+        if updated > 0 {
+            let names = config.dns_names().join(", ");
+            let ip = state.current_ip.clone().unwrap_or_default();
+            let status = EventStatus::Reconciled { updated, current, failed };
+            self.notify(UpdateEvent::new(&names, None, &ip, status).in_timezone(config.timezone)).await;
+        }
+        Ok(summary)
+    }
+
+    /// The cycle behind `run_once`, without the bookkeeping of how it went.
+    async fn tick(&self, config: &Config, state: &mut State, batched: bool) -> Result<TickSummary, SkySyncError> {
+        if config.mode == Mode::Monitor {
+            return self.tick_public(config, state, &config.dns_names(), None).await;
+        }
+        // Without the saved IP to go on, every record has to be read anyway, so they're read once
+        let listing = match batched {
+            true => Some(self.list_records().await.map_err(SkySyncError::Provider)?),
+            false => None,
+        };
+        if config.sources.is_empty() {
+            return self.tick_public(config, state, &config.dns_names(), listing.as_deref()).await;
+        }
+
+        // Records with their own source don't wait for the public IP, so neither can block the other
+        let order = config.dns_names();
+        let (sourced, public): (Vec<String>, Vec<String>) = order.iter().cloned().partition(|name| config.sources.contains_key(name));
+        let mut outcomes = match public.is_empty() {
+            true => Vec::new(),
+            false => match self.tick_public(config, state, &public, listing.as_deref()).await {
+                Ok(summary) => summary.records,
+                Err(err) => public.iter().map(|name| RecordOutcome { name: name.clone(), result: Err(copy_error(&err)) }).collect(),
+            },
+        };
+        outcomes.extend(self.sync_sourced(config, state, &sourced, listing.as_deref()).await);
+        outcomes.sort_by_key(|outcome| order.iter().position(|name| *name == outcome.name));
+        if config.atomic_dual_stack {
+            self.roll_back_half_updates(config, state, &mut outcomes).await;
+        }
+        Ok(TickSummary { records: outcomes })
+    }
+
+    /// Points the records of one family updated this tick back at their previous address when
+    /// records of the other family failed, with `ATOMIC_DUAL_STACK`. Every record rolled back, or
+    /// that couldn't be, is notified and ends up failed.
+    async fn roll_back_half_updates(&self, config: &Config, state: &mut State, outcomes: &mut [RecordOutcome]) {
+        // Records without a source follow the public IP, and with it its family. A name kept for
+        // both types can't tell which of its records an outcome is about, so it's left alone
+        let public = state.current_ip.as_deref().and_then(|ip| ip.parse::<IpAddr>().ok());
+        let family = |name: &str| match config.sources.get(name).map(Vec::as_slice) {
+            Some([source]) if source.record_type == "AAAA" => Some("AAAA"),
+            Some([_]) => Some("A"),
+            Some(_) => None,
+            None => public.map(|ip| if ip.is_ipv6() { "AAAA" } else { "A" }),
+        };
+        let failed: Vec<&str> = outcomes.iter().filter(|outcome| !outcome.is_ok()).filter_map(|outcome| family(&outcome.name)).collect();
+        let (failed_type, updated_type) = match (failed.contains(&"A"), failed.contains(&"AAAA")) {
+            (true, false) => ("A", "AAAA"),
+            (false, true) => ("AAAA", "A"),
+            // Neither family is ahead of the other
+            _ => return,
+        };
+        let updated = |outcome: &RecordOutcome| matches!(outcome.result, Ok(TickOutcome::Updated { .. })) && family(&outcome.name) == Some(updated_type);
+        if !outcomes.iter().any(updated) {
+            return;
+        }
+
+        let listed = self.list_records().await;
+        let mut rolled_back: Vec<String> = Vec::new();
+        for outcome in outcomes.iter_mut().filter(|outcome| updated(outcome)) {
+            let Ok(TickOutcome::Updated { old, new }) = &outcome.result else {
+                continue;
+            };
+            let (old, new) = (old.clone(), new.clone());
+            let found = listed.as_ref().map(|records| records.iter().find(|record| {
+                record.matches(&outcome.name)
+                    && record.r#type.eq_ignore_ascii_case(updated_type)
+                    && record.points_at(&new)
+                    && !rolled_back.contains(&record.id)
+            }));
+            let rolled = match found {
+                Ok(Some(record)) => timed(Stage::Update, self.provider.update_record(record, &old)).await.map(|()| record.id.clone()).map_err(|err| err.to_string()),
+                Ok(None) => Err("the record is gone".to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+            let error = match rolled {
+                Ok(id) => {
+                    rolled_back.push(id);
+                    state.records.insert(outcome.name.clone(), old.clone());
+                    format!("rolled back to {} after the {} records failed to update", old, failed_type)
+                }
+                Err(err) => format!("pointed at {}, but couldn't be rolled back to {} after the {} records failed to update: {}", new, old, failed_type, err),
+            };
+            report(&format!("{}: {}", outcome.name, error)).await;
+
+            // The update it replaces shouldn't be announced anymore
+            self.coalesced.lock().unwrap().retain(|event| event.domain != outcome.name);
+            let event = UpdateEvent::new(&outcome.name, Some(old), &new, EventStatus::Failure(error)).in_timezone(config.timezone);
+            outcome.result = Ok(self.fail(event).await);
+        }
+        if !rolled_back.is_empty() {
+            // The public records may have been rolled back, so they're read again on the next tick
+            state.last_public_ip = None;
+        }
+    }
+
+    /// Syncs `dns_names` with the public IP, comparing them against `listing` when it's given.
+    async fn tick_public(&self, config: &Config, state: &mut State, dns_names: &[String], listing: Option<&[DnsRecord]>) -> Result<TickSummary, SkySyncError> {
+        let fetched = match &self.ip_cache {
+            Some(cache) => timed(Stage::IpFetch, cache.refresh(self.ip_source.as_ref())).await,
+            None => timed(Stage::IpFetch, self.ip_source.public_ip()).await,
+        };
+        let (my_public_ip, address) = match detected(fetched) {
+            Ok(detected) => detected,
+            Err(err) => match (config.on_detection_failure, state.last_public_ip.clone()) {
+                // Nothing is compared against the reused IP, so not a single record is written
+                (DetectionFailure::ReuseLast, Some(last)) => {
+                    report(&format!("{}, reusing the last known IP {}", err, last)).await;
+                    return Ok(TickSummary::uniform(dns_names, TickOutcome::Unchanged { ip: last }));
+                }
+                _ => return Err(err),
+            },
+        };
+        let newly_seen = state.current_ip.as_deref() != Some(my_public_ip.as_str());
+        state.current_ip = Some(my_public_ip.clone());
+
+        if let Some(kind) = non_routable(address) {
+            if let Some(summary) = self.unreachable(config, dns_names, &my_public_ip, kind, newly_seen).await {
+                return Ok(summary);
+            }
+        }
+
+        // With a delegated prefix, records follow the prefix instead of the host's own address
+        let my_public_ip = match (address, config.ipv6_prefix) {
+            (IpAddr::V6(address), Some(prefix)) => prefix.apply(address).to_string(),
+            _ => my_public_ip,
+        };
+
+        if config.mode == Mode::Monitor {
+            return Ok(self.monitor(config, state, my_public_ip).await);
+        }
+
+        // If the IP is unchanged since the last confirmed update, there's nothing to do. That's
+        // only trusted until the records are due a recheck, or every `DRIFT_CHECK_EVERY` ticks,
+        // so ones edited by hand are caught
+        let unchanged = state.last_public_ip.as_deref() == Some(my_public_ip.as_str());
+        let checked_recently = state.records_checked.zip(state.last_check)
+            .is_some_and(|(checked, now)| (now - checked).to_std().unwrap_or_default() < config.record_recheck_interval);
+        let drift_due = config.drift_check_every > 0 && state.unread_ticks.saturating_add(1) >= config.drift_check_every;
+        if !self.force && unchanged && checked_recently && !drift_due && !self.compares_resolved() {
+            // Any candidate change was a flap that has since reverted
+            state.pending_ip = None;
+            state.unread_ticks = state.unread_ticks.saturating_add(1);
+            return Ok(TickSummary::uniform(dns_names, TickOutcome::Unchanged { ip: my_public_ip }));
+        }
+
+        let records = match listing {
+            Some(records) => records.to_vec(),
+            None => self.list_records().await.map_err(SkySyncError::Provider)?,
+        };
+        state.records_checked = state.last_check;
+        state.unread_ticks = 0;
+        let mut managed: Vec<(&str, Option<DnsRecord>)> = Vec::new();
+        let mut ambiguous: Vec<RecordOutcome> = Vec::new();
+        for name in dns_names {
+            match select(&records, name, None, config.duplicate_records) {
+                Ok(found) if found.is_empty() => managed.push((name, None)),
+                Ok(found) => managed.extend(found.into_iter().map(|record| (name.as_str(), Some(record.clone())))),
+                Err(err) => ambiguous.push(RecordOutcome { name: name.clone(), result: Err(err) }),
+            }
+        }
+        // Whatever the rest of the tick does, records that are ambiguous fail on their own
+        let uniform = |outcome: TickOutcome| TickSummary {
+            records: dns_names.iter().map(|name| RecordOutcome {
+                name: name.clone(),
+                result: match ambiguous.iter().find(|ambiguous| ambiguous.name == *name) {
+                    Some(RecordOutcome { result: Err(err), .. }) => Err(copy_error(err)),
+                    _ => Ok(outcome.clone()),
+                },
+            }).collect(),
+        };
+        // A record that was seen before and is missing now was deleted from under SkySync
+        let vanished: HashMap<String, String> = managed.iter()
+            .filter(|(_, record)| config.self_heal && record.is_none())
+            .filter_map(|(name, _)| Some((name.to_string(), state.records.get(*name)?.clone())))
+            .collect();
+        // A record pointing at neither the address it was last seen at nor the public IP was
+        // changed by hand, not left behind by a change of the IP. Keyed by record ID
+        let manual: HashMap<String, String> = managed.iter()
+            .filter_map(|(name, record)| Some((record.as_ref()?, state.records.get(*name)?)))
+            .filter(|(record, known)| !record.points_at(&my_public_ip) && !same_address(&record.content, known))
+            .map(|(record, known)| (record.id.clone(), known.clone()))
+            .collect();
+        state.records = managed.iter()
+            .filter_map(|(name, record)| Some((name.to_string(), record.as_ref()?.content.clone())))
+            // Kept until they're recreated, so a failed recreation is retried on the next tick
+            .chain(vanished.clone())
+            .collect();
+        for record in managed.iter().filter_map(|(_, record)| record.as_ref().filter(|record| record.proxied)) {
+            debug_log(&format!(
+                "{} is proxied: public DNS answers with Cloudflare's addresses, so its origin {} is compared instead",
+                record.name, record.content,
+            )).await;
+        }
+        #[cfg(feature = "hickory-dns")]
+        let managed = match &self.resolver {
+            Some(resolver) => resolved(resolver, managed, &my_public_ip).await,
+            None => managed,
+        };
+
+        let current = |record: &DnsRecord| record.points_at(&my_public_ip) && !self.provider.drifted(record);
+        if !self.force && managed.iter().all(|(_, record)| record.as_ref().is_some_and(current)) {
+            // Confirming the IP would skip listing the records, and with it reporting the duplicates
+            if ambiguous.is_empty() {
+                state.last_public_ip = Some(my_public_ip.clone());
+            }
+            state.pending_ip = None;
+            return Ok(uniform(TickOutcome::Unchanged { ip: my_public_ip }));
+        }
+
+        let stale = managed.iter().find_map(|(_, record)| record.as_ref().filter(|record| !record.points_at(&my_public_ip)));
+        // A record drifting from the confirmed IP isn't an IP flap, so it's fixed right away
+        if stale.is_some() && !self.force && !unchanged {
+            if let Some(seen) = state.debounce(&my_public_ip, config.change_debounce_ticks) {
+                let pending = TickOutcome::Pending { ip: my_public_ip, seen, required: config.change_debounce_ticks };
+                return Ok(uniform(pending));
+            }
+        }
+
+        // Outside the update window nothing is written, and the records stay stale until it opens
+        let missing = (config.create_missing && managed.iter().any(|(_, record)| record.is_none())) || !vanished.is_empty();
+        let drifted = managed.iter().any(|(_, record)| record.as_ref().is_some_and(|record| self.provider.drifted(record)));
+        if let Some(window) = config.update_window.filter(|_| stale.is_some() || missing || drifted || self.force) {
+            let now = state.last_check.unwrap_or_else(|| config.timezone.now());
+            if !window.contains(now.time()) {
+                // The change already settled, so it shouldn't have to wait out the debounce again
+                if stale.is_some() && config.change_debounce_ticks > 1 {
+                    state.pending_ip = Some((my_public_ip.clone(), config.change_debounce_ticks - 1));
+                }
+                return Ok(uniform(TickOutcome::Deferred { ip: my_public_ip, opens: window.start }));
+            }
+        }
+
+        if stale.is_some() || missing {
+            self.record_change(stale.map(|record| record.content.as_str()), &my_public_ip).await;
+        }
+
+        let geo = self.locate(&my_public_ip, state).await;
+        // Providers that can write several records in one request do so up front
+        let updates: Vec<(&DnsRecord, &str)> = managed.iter()
+            .filter(|(name, _)| !self.backing_off.lock().unwrap().contains_key(*name))
+            .filter_map(|(_, record)| record.as_ref())
+            .filter(|record| !record.points_at(&my_public_ip) || self.force || self.provider.drifted(record))
+            // Those are written on their own, with the comment saying why
+            .filter(|record| !(config.flag_manual_drift && manual.contains_key(&record.id)))
+            .map(|record| (record, my_public_ip.as_str()))
+            .collect();
+        if updates.len() > 1 {
+            if let Some(results) = timed(Stage::Update, self.provider.update_records(&updates)).await {
+                let written = updates.iter().map(|(record, content)| (record.id.clone(), content.to_string())).zip(results);
+                self.prewritten.lock().unwrap().extend(written);
+            }
+        }
+        let (ip, geo, vanished, manual) = (&my_public_ip, geo.as_ref(), &vanished, &manual);
+        let mut outcomes: Vec<RecordOutcome> = stream::iter(&managed)
+            .map(|(name, record)| async move {
+                if let Some(old_ip) = vanished.get(*name).filter(|_| record.is_none()) {
+                    return self.recreate(config, name, old_ip, ip, geo).await;
+                }
+                if let Some((record, known)) = record.as_ref().and_then(|record| Some((record, manual.get(&record.id)?))) {
+                    report(&format!(
+                        "Manual change detected: {} was set to {} by hand (last known {}), pointing it back at {}",
+                        name, record.content, known, ip,
+                    )).await;
+                    if config.flag_manual_drift {
+                        return self.reassert(config, name, record, ip, geo).await;
+                    }
+                }
+                self.sync_record(config, name, record.as_ref(), ip, geo, listing.is_some()).await
+            })
+            .buffered(config.update_concurrency)
+            .collect()
+            .await;
+        self.prewritten.lock().unwrap().clear();
+        outcomes.extend(ambiguous);
+        outcomes.sort_by_key(|outcome| dns_names.iter().position(|name| *name == outcome.name));
+
+        for outcome in &outcomes {
+            if let Ok(TickOutcome::Updated { new: ip, .. } | TickOutcome::Created { ip }) = &outcome.result {
+                state.records.insert(outcome.name.clone(), ip.clone());
+                state.last_change = state.last_check;
+            }
+        }
+
+        // Records that failed are retried on the next tick
+        if outcomes.iter().all(RecordOutcome::is_ok) {
+            state.last_public_ip = Some(my_public_ip);
+        }
+        Ok(TickSummary { records: outcomes })
+    }
+
+    /// Points each record in `names` at the address read from its own source. A source that
+    /// can't be read only fails its own record.
+    ///
+    /// These records are written as soon as their address changes: the debounce, the update
+    /// window and the history all follow the public IP.
+    async fn sync_sourced(&self, config: &Config, state: &mut State, names: &[String], listing: Option<&[DnsRecord]>) -> Vec<RecordOutcome> {
+        if names.is_empty() {
+            return Vec::new();
+        }
+        let records = match listing {
+            Some(records) => records.to_vec(),
+            None => match self.list_records().await {
+                Ok(records) => records,
+                Err(err) => {
+                    let err = SkySyncError::Provider(err);
+                    return names.iter().map(|name| RecordOutcome { name: name.clone(), result: Err(copy_error(&err)) }).collect();
+                }
+            },
+        };
+
+        let outcomes: Vec<RecordOutcome> = stream::iter(names)
+            .map(|name| self.sync_sourced_record(config, name, &records, listing.is_some()))
+            .buffered(config.update_concurrency)
+            .collect::<Vec<Vec<RecordOutcome>>>()
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+        for outcome in &outcomes {
+            match &outcome.result {
+                Ok(TickOutcome::Updated { new: ip, .. } | TickOutcome::Created { ip }) => {
+                    state.records.insert(outcome.name.clone(), ip.clone());
+                    state.last_change = state.last_check;
+                }
+                Ok(TickOutcome::Unchanged { ip }) => {
+                    state.records.insert(outcome.name.clone(), ip.clone());
+                }
+                _ => {}
+            }
+        }
+        outcomes
+    }
+
+    /// Reads the address of one record from its source, and points the record at it, or every
+    /// record of its name with `DUPLICATE_RECORDS=update-all`. A name kept for several types
+    /// does so for each of them in turn.
+    async fn sync_sourced_record(&self, config: &Config, dns_name: &str, records: &[DnsRecord], batched: bool) -> Vec<RecordOutcome> {
+        let mut outcomes = Vec::new();
+        for configured in config.sources.get(dns_name).into_iter().flatten() {
+            outcomes.extend(self.sync_sourced_type(config, dns_name, &configured.record_type, records, batched).await);
+        }
+        outcomes
+    }
+
+    /// Points the records of `dns_name` and `record_type` at the address read from their source.
+    async fn sync_sourced_type(&self, config: &Config, dns_name: &str, record_type: &str, records: &[DnsRecord], batched: bool) -> Vec<RecordOutcome> {
+        let fetched = match self.sources.get(&(dns_name.to_string(), record_type.to_string())) {
+            Some(source) => timed(Stage::IpFetch, source.public_ip()).await.map(|ip| ip.trim().to_string()),
+            None => Err(format!("no source was set up for the {} record of {}", record_type, dns_name).into()),
+        };
+        let ip = match fetched {
+            Ok(ip) if ip.parse::<IpAddr>().is_ok() => ip,
+            Ok(ip) => return vec![RecordOutcome { name: dns_name.to_string(), result: Err(SkySyncError::IpFetch(format!("{:?} is not an IP address", ip).into())) }],
+            Err(err) => return vec![RecordOutcome { name: dns_name.to_string(), result: Err(SkySyncError::IpFetch(err)) }],
+        };
+
+        // The same name may have both an A and an AAAA record
+        let found = match select(records, dns_name, Some(record_type), config.duplicate_records) {
+            Ok(found) => found,
+            Err(err) => return vec![RecordOutcome { name: dns_name.to_string(), result: Err(err) }],
+        };
+        if found.is_empty() {
+            return vec![self.sync_record(config, dns_name, None, &ip, None, batched).await];
+        }
+        let mut outcomes = Vec::new();
+        for record in found {
+            outcomes.push(self.sync_record(config, dns_name, Some(record), &ip, None, batched).await);
+        }
+        outcomes
+    }
+
+    /// Points a single record at the public IP, creating it if allowed. When `batched`, a
+    /// successful write is left for the caller to announce.
+    async fn sync_record(&self, config: &Config, dns_name: &str, record: Option<&DnsRecord>, my_public_ip: &str, geo: Option<&GeoInfo>, batched: bool) -> RecordOutcome {
+        let event = |old_ip: Option<String>, status| {
+            UpdateEvent::new(dns_name, old_ip, my_public_ip, status)
+                .with_geo(geo.cloned())
+                .with_proxied(record.is_some_and(|record| record.proxied))
+                .with_dashboard_link(record.and_then(|record| self.dashboard_link(config, record)))
+                .in_timezone(config.timezone)
+        };
+        let writes = match record {
+            None => config.create_missing,
+            Some(record) => !record.points_at(my_public_ip) || self.force || self.provider.drifted(record),
+        };
+        if let Some(outcome) = self.backoff(dns_name, my_public_ip).filter(|_| writes) {
+            return RecordOutcome { name: dns_name.to_string(), result: Ok(outcome) };
+        }
+
+        let result = match record {
+            None if !config.create_missing => Err(SkySyncError::RecordNotFound(dns_name.to_string())),
+            None => Ok(match timed(Stage::Update, self.provider.create_record(dns_name, my_public_ip)).await {
+                Ok(()) => {
+                    self.run_hook(dns_name, None, my_public_ip).await;
+                    if !batched {
+                        self.announce(config, event(None, EventStatus::Success)).await;
+                    }
+                    TickOutcome::Created { ip: my_public_ip.to_string() }
+                }
+                Err(err) => self.fail(event(None, EventStatus::Failure(err.to_string()))).await,
+            }),
+            Some(record) if record.points_at(my_public_ip) && !self.force && !self.provider.drifted(record) => Ok(TickOutcome::Unchanged { ip: my_public_ip.to_string() }),
+            // The IP didn't change, so there's nothing to notify
+            Some(record) if record.points_at(my_public_ip) && !self.force => Ok(match self.update(record, my_public_ip).await {
+                Ok(()) => TickOutcome::Reconfigured { ip: my_public_ip.to_string() },
+                Err(err) => self.fail(event(Some(record.content.clone()), EventStatus::Failure(err.to_string()))).await,
+            }),
+            Some(record) => Ok(match self.update(record, my_public_ip).await {
+                Ok(()) => {
+                    self.run_hook(dns_name, Some(&record.content), my_public_ip).await;
+                    if !batched {
+                        self.announce(config, event(Some(record.content.clone()), EventStatus::Success)).await;
+                    }
+                    TickOutcome::Updated { old: record.content.clone(), new: my_public_ip.to_string() }
+                }
+                Err(err) => self.fail(event(Some(record.content.clone()), EventStatus::Failure(err.to_string()))).await,
+            }),
+        };
+
+        RecordOutcome { name: dns_name.to_string(), result }
+    }
+
+    /// The link to `record` in the provider's console, with `INCLUDE_DASHBOARD_LINK`.
+    fn dashboard_link(&self, config: &Config, record: &DnsRecord) -> Option<String> {
+        config.include_dashboard_link.then(|| self.provider.dashboard_url(record)).flatten()
+    }
+
+    /// Creates a record again after it was deleted while pointing at `old_ip`, with `SELF_HEAL`.
+    /// Unlike a record created with `CF_CREATE_MISSING`, this is always notified, even in the
+    /// first tick after a start.
+    async fn recreate(&self, config: &Config, dns_name: &str, old_ip: &str, my_public_ip: &str, geo: Option<&GeoInfo>) -> RecordOutcome {
+        let event = |status| {
+            UpdateEvent::new(dns_name, Some(old_ip.to_string()), my_public_ip, status)
+                .with_geo(geo.cloned())
+                .in_timezone(config.timezone)
+        };
+
+        if let Some(outcome) = self.backoff(dns_name, my_public_ip) {
+            return RecordOutcome { name: dns_name.to_string(), result: Ok(outcome) };
+        }
+        report(&format!("{} was deleted since it was last seen, recreating it with {}", dns_name, my_public_ip)).await;
+        let outcome = match timed(Stage::Update, self.provider.create_record(dns_name, my_public_ip)).await {
+            Ok(()) => {
+                self.run_hook(dns_name, Some(old_ip), my_public_ip).await;
+                self.notify(event(EventStatus::Recreated)).await;
+                TickOutcome::Created { ip: my_public_ip.to_string() }
+            }
+            Err(err) => self.fail(event(EventStatus::Failure(format!("{} was deleted and couldn't be recreated: {}", dns_name, err)))).await,
+        };
+        RecordOutcome { name: dns_name.to_string(), result: Ok(outcome) }
+    }
+
+    /// Points a record that was changed by hand back at the public IP, with `FLAG_MANUAL_DRIFT`.
+    /// The change is notified before the write, and the record's comment says it was reverted.
+    async fn reassert(&self, config: &Config, dns_name: &str, record: &DnsRecord, my_public_ip: &str, geo: Option<&GeoInfo>) -> RecordOutcome {
+        let event = |status| {
+            UpdateEvent::new(dns_name, Some(record.content.clone()), my_public_ip, status)
+                .with_geo(geo.cloned())
+                .with_proxied(record.proxied)
+                .with_dashboard_link(self.dashboard_link(config, record))
+                .in_timezone(config.timezone)
+        };
+
+        if let Some(outcome) = self.backoff(dns_name, my_public_ip) {
+            return RecordOutcome { name: dns_name.to_string(), result: Ok(outcome) };
+        }
+        self.notify(event(EventStatus::Drifted)).await;
+        let comment = drift_comment(&record.content, my_public_ip, config.timezone.now());
+        let outcome = match timed(Stage::Update, self.provider.update_record_with_comment(record, my_public_ip, &comment)).await {
+            Ok(()) => {
+                self.run_hook(dns_name, Some(&record.content), my_public_ip).await;
+                TickOutcome::Updated { old: record.content.clone(), new: my_public_ip.to_string() }
+            }
+            Err(err) => self.fail(event(EventStatus::Failure(err.to_string()))).await,
+        };
+        RecordOutcome { name: dns_name.to_string(), result: Ok(outcome) }
+    }
+
+    /// What a record still waiting out its `RECORD_RETRY_BACKOFF` ends up as, instead of being
+    /// pointed at `ip` again.
+    fn backoff(&self, dns_name: &str, ip: &str) -> Option<TickOutcome> {
+        let backing_off = self.backing_off.lock().unwrap();
+        let health = backing_off.get(dns_name)?;
+        Some(TickOutcome::BackingOff { ip: ip.to_string(), failures: health.failures, retry_at: health.retry_at? })
+    }
+
+    /// Points `record` at `content`, unless the provider already did with the other updates of the tick.
+    async fn update(&self, record: &DnsRecord, content: &str) -> Result<(), BoxError> {
+        let written = self.prewritten.lock().unwrap().remove(&(record.id.clone(), content.to_string()));
+        match written {
+            Some(result) => result,
+            None => timed(Stage::Update, self.provider.update_record(record, content)).await,
+        }
+    }
+
+    /// Lists the records of the provider, timing it for `GET /metrics`.
+    async fn list_records(&self) -> Result<Vec<DnsRecord>, BoxError> {
+        timed(Stage::List, self.provider.list_records()).await
+    }
+
+    /// Looks up where `ip` is, once per address. A failed lookup only leaves the notifications
+    /// without the location.
+    async fn locate(&self, ip: &str, state: &mut State) -> Option<GeoInfo> {
+        let geo = self.geo.as_ref()?;
+        if let Some(info) = state.geo_cache.get(ip) {
+            return Some(info.clone());
+        }
+
+        match geo.lookup(ip).await {
+            Ok(info) => {
+                state.geo_cache.insert(ip.to_string(), info.clone());
+                Some(info)
+            }
+            Err(err) => {
+                report(&format!("Failed to look up the location of {}: {}", ip, err)).await;
+                None
+            }
+        }
+    }
+
+    /// The rest of the cycle in `MODE=monitor`: a change of the public IP is logged and notified
+    /// for every configured record, without reading or writing any of them.
+    async fn monitor(&self, config: &Config, state: &mut State, my_public_ip: String) -> TickSummary {
+        let mut names = config.dns_names();
+        if names.is_empty() {
+            names.push(MONITORED.to_string());
+        }
+
+        let old = match state.last_public_ip.clone() {
+            Some(old) if old != my_public_ip => old,
+            // The first IP seen is only remembered, there's no change to report yet
+            last => {
+                state.last_public_ip = Some(my_public_ip.clone());
+                if last.is_some() {
+                    state.pending_ip = None;
+                }
+                return TickSummary::uniform(&names, TickOutcome::Unchanged { ip: my_public_ip });
+            }
+        };
+        if let Some(seen) = state.debounce(&my_public_ip, config.change_debounce_ticks) {
+            return TickSummary::uniform(&names, TickOutcome::Pending { ip: my_public_ip, seen, required: config.change_debounce_ticks });
+        }
+
+        self.record_change(Some(&old), &my_public_ip).await;
+        let geo = self.locate(&my_public_ip, state).await;
+        for name in &names {
+            self.run_hook(name, Some(&old), &my_public_ip).await;
+            let event = UpdateEvent::new(name, Some(old.clone()), &my_public_ip, EventStatus::Changed)
+                .with_geo(geo.clone())
+                .in_timezone(config.timezone);
+            self.notify(event).await;
+        }
+
+        state.last_public_ip = Some(my_public_ip.clone());
+        state.last_change = state.last_check;
+        TickSummary::uniform(&names, TickOutcome::Detected { old, new: my_public_ip })
+    }
+
+    /// Warns that the public IP can't be reached from the internet, once for every new such IP,
+    /// and fails every record when `REFUSE_CGNAT` is set.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the tick.
+    /// * `dns_names` - The records following the public IP.
+    /// * `ip` - The public IP just fetched.
+    /// * `kind` - What kind of address it is, e.g. `carrier-grade NAT (100.64.0.0/10)`.
+    /// * `newly_seen` - Whether the previous tick fetched a different IP.
+    ///
+    /// # Returns
+    /// The summary of the refused tick, or `None` if it should go on.
+    async fn unreachable(&self, config: &Config, dns_names: &[String], ip: &str, kind: &str, newly_seen: bool) -> Option<TickSummary> {
+        let warning = format!(
+            "The public IP {} is a {} address, which can't be reached from the internet. DDNS won't make this host reachable; if your ISP uses carrier-grade NAT, ask them for a public IP",
+            ip, kind,
+        );
+        if newly_seen {
+            report(&format!("Warning: {}", warning)).await;
+        }
+        if !config.refuse_cgnat || config.mode == Mode::Monitor {
+            return None;
+        }
+
+        let error = format!("refused to point the record at {}, a {} address (REFUSE_CGNAT is set)", ip, kind);
+        let mut records = Vec::new();
+        for name in dns_names {
+            // Reported once per IP, rather than on every tick it stays the same
+            if newly_seen {
+                let event = UpdateEvent::new(name, None, ip, EventStatus::Failure(format!("{}. {}", error, warning))).in_timezone(config.timezone);
+                self.notify(event).await;
+            }
+            records.push(RecordOutcome { name: name.clone(), result: Ok(TickOutcome::Failed { error: error.clone() }) });
+        }
+        Some(TickSummary { records })
+    }
+
+    /// Appends a detected change to the history. Failing to do so doesn't stop the update.
+    async fn record_change(&self, old_ip: Option<&str>, new_ip: &str) {
+        if let Some(history) = &self.history {
+            if let Err(err) = history.append(old_ip, new_ip).await {
+                report(&format!("Failed to write IP history to {}: {}", history.path().display(), err)).await;
+            }
+        }
+    }
+
+    /// Runs `ON_CHANGE_COMMAND` for a changed record, logging what it printed. Its failure
+    /// doesn't undo or fail the update.
+    async fn run_hook(&self, record: &str, old_ip: Option<&str>, new_ip: &str) {
+        let Some(hook) = &self.hook else {
+            return;
+        };
+        match hook.run(record, old_ip, new_ip).await {
+            Ok(printed) if printed.is_empty() => {}
+            Ok(printed) => write_log(&format!("ON_CHANGE_COMMAND for {}:\n{}\n", record, printed)).await,
+            Err(err) => report(&format!("ON_CHANGE_COMMAND for {} failed: {}", record, err)).await,
+        }
+    }
+
+    /// Notifies a successful update, or keeps it for `send_coalesced` with `COALESCE_NOTIFICATIONS`
+    /// or `NOTIFY_RATE_WINDOW`.
+    async fn announce(&self, config: &Config, event: UpdateEvent) {
+        if config.coalesce_notifications || !config.notify_rate_window.is_zero() {
+            self.coalesced.lock().unwrap().push(event);
+        } else {
+            self.notify(event).await;
+        }
+    }
+
+    /// Sends the updates `announce` kept during the tick as a single event. Records with their own
+    /// notifiers are still notified on their own.
+    ///
+    /// With `NOTIFY_RATE_WINDOW`, they're only sent when the window since the last notification
+    /// is over, and as one summary of every change of the window when some were held back.
+    async fn send_coalesced(&self, config: &Config, state: &mut State, now: DateTime<FixedOffset>) {
+        let mut events = std::mem::take(&mut *self.coalesced.lock().unwrap());
+        // Records are written concurrently, so put them back in the configured order
+        let order = config.dns_names();
+        events.sort_by_key(|event| order.iter().position(|name| *name == event.domain));
+        if !config.notify_rate_window.is_zero() {
+            let changed = events.last().map(|event| event.new_ip.clone());
+            match state.throttle.release(config.notify_rate_window, now, changed.as_deref()) {
+                Release::Updates => {}
+                Release::Nothing => {
+                    if !events.is_empty() {
+                        debug_log(&format!("Holding back the notification of {} update(s), one was sent less than {:?} ago", events.len(), config.notify_rate_window)).await;
+                    }
+                    return;
+                }
+                Release::Summary { changes, minutes, ip } => {
+                    let names = config.dns_names().join(", ");
+                    let event = UpdateEvent::new(&names, None, &ip, EventStatus::Throttled { changes, minutes });
+                    self.notify(event.in_timezone(config.timezone)).await;
+                    return;
+                }
+            }
+            if !config.coalesce_notifications {
+                for event in events {
+                    self.notify(event).await;
+                }
+                return;
+            }
+        }
+        let (routed, shared): (Vec<UpdateEvent>, Vec<UpdateEvent>) = events.into_iter().partition(|event| self.routes.contains_key(&event.domain));
+        for event in routed.into_iter().chain(UpdateEvent::coalesce(shared)) {
+            self.notify(event).await;
+        }
+    }
+
+    async fn notify(&self, event: UpdateEvent) {
+        let notifiers = self.routes.get(&event.domain).unwrap_or(&self.notifiers);
+        let event = event.in_profile(self.profile.as_deref());
+        match &self.queue {
+            Some(queue) => queue.push(notifiers.clone(), self.failover.clone(), event, self.dispatch, self.notify_retry),
+            None => {
+                notifier::dispatch(notifiers, self.failover.as_deref(), &event, self.dispatch, self.notify_retry).await;
+            }
+        }
+    }
+
+    /// Reports a rejected write and builds the matching outcome.
+    async fn fail(&self, event: UpdateEvent) -> TickOutcome {
+        let error = event.status.error().unwrap_or_default().to_string();
+        self.notify(event).await;
+        TickOutcome::Failed { error }
+    }
+}
+
+/// Returns the comment a record changed by hand is stamped with when it's pointed back, with
+/// `FLAG_MANUAL_DRIFT`.
+///
+/// # Arguments
+/// * `found` - What the record was changed to.
+/// * `ip` - What it's pointed back at.
+/// * `at` - When it's pointed back.
+fn drift_comment(found: &str, ip: &str, at: DateTime<FixedOffset>) -> String {
+    format!("SkySync: manual change to {} reverted to {} on {}", found, ip, at.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Runs a single check/update cycle with the services selected by the configuration.
+///
+/// This starts from an empty `State`, so it always compares against the record itself.
+///
+/// # Arguments
+/// * `config` - The configuration to apply.
+///
+/// # Returns
+/// A `TickSummary` with one outcome per record, or an error if the cycle couldn't start.
+pub async fn run_once(config: &Config) -> Result<TickSummary, SkySyncError> {
+    Syncer::from_config(config).run_once(config, &mut State::default()).await
+}
+
+/// Builds the DNS provider selected by `DNS_PROVIDER`.
+///
+/// # Arguments
+/// * `config` - The configuration with the zones and credentials.
+/// * `client` - The client every request to the provider is sent with.
+pub fn dns_provider(config: &Config, client: &reqwest::Client) -> Box<dyn DnsProvider> {
+    match (config.provider, &config.gandi, &config.route53, &config.porkbun) {
+        (ProviderKind::Gandi, Some(gandi), _, _) => Box::new(GandiProvider::new(gandi.clone(), config.zones.clone(), client.clone())),
+        // Rejected by the config loader when the feature is disabled
+        #[cfg(feature = "route53")]
+        (ProviderKind::Route53, _, Some(route53), _) => Box::new(crate::provider::Route53Provider::new(route53.clone(), config.zones.clone())),
+        (ProviderKind::Porkbun, _, _, Some(porkbun)) => Box::new(PorkbunProvider::new(porkbun.clone(), config.zones.clone(), client.clone())),
+        _ => Box::new(CloudflareProvider::new(config.cloudflare.clone(), config.zones.clone(), client.clone())),
+    }
+}
+
+/// Builds the IP source selected by `IP_SOURCE`.
+///
+/// # Arguments
+/// * `config` - The configuration to read the selection from.
+/// * `ip_client` - The client for HTTP lookups, which honors `RESOLVER`.
+fn ip_source(config: &Config, ip_client: reqwest::Client) -> Box<dyn IpSource> {
+    match config.ip_source {
+        IpSourceKind::Http if config.ip_endpoints.is_empty() => {
+            let url = if config.ip_family == Some(AddressFamily::V6) { DEFAULT_IPV6_URL } else { DEFAULT_IP_URL };
+            Box::new(HttpIpSource::new(url, ip_client).with_limit(config.ip_response_limit))
+        }
+        IpSourceKind::Http => Box::new(FallbackIpSource::new(config.ip_endpoints.iter().map(|endpoint| {
+            let source = HttpIpSource::new(&endpoint.url, ip_client.clone()).with_limit(config.ip_response_limit).with_response(endpoint.response.clone());
+            Box::new(source) as Box<dyn IpSource>
+        }).collect())),
+        // The router is on the local network, so a configured proxy could never reach it
+        #[cfg(feature = "upnp")]
+        IpSourceKind::Upnp => {
+            let client = reqwest::Client::builder().no_proxy().build().expect("Failed to build the HTTP client");
+            Box::new(crate::services::upnp::UpnpIpSource::new(client))
+        }
+        // Rejected by the config loader when the feature is disabled
+        #[cfg(not(feature = "upnp"))]
+        IpSourceKind::Upnp => Box::new(HttpIpSource::new(DEFAULT_IP_URL, ip_client).with_limit(config.ip_response_limit)),
+        // Only accepted by the config loader along with the Porkbun keys
+        IpSourceKind::Porkbun => match &config.porkbun {
+            Some(porkbun) => Box::new(PorkbunIpSource::new(porkbun.clone(), ip_client)),
+            None => Box::new(HttpIpSource::new(DEFAULT_IP_URL, ip_client).with_limit(config.ip_response_limit)),
+        },
+        IpSourceKind::Override(ip) => Box::new(FixedIpSource::new(ip)),
+    }
+}
+
+/// Builds the sources of the records that name their own in `ZONES`.
+///
+/// # Arguments
+/// * `config` - The configuration to read the sources from.
+/// * `ip_client` - The client for HTTP lookups, which honors `RESOLVER`.
+fn record_sources(config: &Config, ip_client: reqwest::Client) -> HashMap<(String, String), Box<dyn IpSource>> {
+    let typed = config.sources.iter().flat_map(|(name, sources)| sources.iter().map(move |source| (name, source)));
+    typed.map(|(name, source)| {
+        let built: Box<dyn IpSource> = match &source.address {
+            AddressSource::HttpV4 => Box::new(HttpIpSource::new(DEFAULT_IP_URL, ip_client.clone()).with_limit(config.ip_response_limit)),
+            AddressSource::HttpV6 => Box::new(HttpIpSource::new(DEFAULT_IPV6_URL, ip_client.clone()).with_limit(config.ip_response_limit)),
+            AddressSource::Interface(interface) => Box::new(InterfaceIpSource::new(interface, source.record_type == "AAAA")),
+        };
+        ((name.clone(), source.record_type.clone()), Box::new(RetryingIpSource::new(built, config.ip_retry)) as Box<dyn IpSource>)
+    }).collect()
+}
+
+/// Replaces the content of the address records in `managed` with what `resolver` answers for
+/// their names, so they count as stale until resolvers serve the public IP.
+///
+/// Proxied records resolve to the provider's edge, and other types have no address, so both keep
+/// their content. So does a record whose name can't be resolved, after a warning.
+#[cfg(feature = "hickory-dns")]
+async fn resolved<'a>(resolver: &Resolver, mut managed: Vec<(&'a str, Option<DnsRecord>)>, my_public_ip: &str) -> Vec<(&'a str, Option<DnsRecord>)> {
+    for record in managed.iter_mut().filter_map(|(_, record)| record.as_mut()) {
+        let ipv6 = match record.r#type.as_str() {
+            "A" => false,
+            "AAAA" => true,
+            _ => continue,
+        };
+        if record.proxied {
+            continue;
+        }
+
+        match resolver.lookup_type(&record.fqdn(), ipv6).await {
+            Ok(addrs) => {
+                let addrs: Vec<String> = addrs.iter().map(IpAddr::to_string).collect();
+                match addrs.first() {
+                    // Any of several addresses is as good as the record's own content
+                    Some(_) if addrs.iter().any(|addr| same_address(addr, my_public_ip)) => record.content = my_public_ip.to_string(),
+                    Some(first) => record.content = first.clone(),
+                    None => report(&format!("{} doesn't resolve yet, comparing its content instead", record.fqdn())).await,
+                }
+            }
+            Err(err) => report(&format!("Failed to resolve {}, comparing its content instead: {}", record.fqdn(), err)).await,
+        }
+    }
+    managed
+}
+
+/// The records in `records` that `name` manages, as `policy` picks among several of the same type.
+///
+/// Records of another type than the first match are never counted as duplicates, so without a
+/// `record_type` they're left alone as before.
+///
+/// # Arguments
+/// * `records` - The listing of the provider.
+/// * `name` - The managed name, as configured.
+/// * `record_type` - When set, only records of this type match.
+/// * `policy` - What `DUPLICATE_RECORDS` asks for.
+///
+/// # Returns
+/// The records to sync, none when the name has no record, or an error when several share it
+/// and `policy` refuses to pick.
+fn select<'a>(records: &'a [DnsRecord], name: &str, record_type: Option<&str>, policy: DuplicatePolicy) -> Result<Vec<&'a DnsRecord>, SkySyncError> {
+    let mut matching = records.iter()
+        .filter(|record| record.matches(name) && record_type.is_none_or(|record_type| record.r#type.eq_ignore_ascii_case(record_type)));
+    let Some(first) = matching.next() else {
+        return Ok(Vec::new());
+    };
+    let mut found = vec![first];
+    found.extend(matching.filter(|record| record.r#type.eq_ignore_ascii_case(&first.r#type)));
+
+    match policy {
+        _ if found.len() == 1 => Ok(found),
+        DuplicatePolicy::UpdateAll => Ok(found),
+        DuplicatePolicy::UpdateFirst => Ok(vec![first]),
+        DuplicatePolicy::Error => Err(SkySyncError::DuplicateRecords { name: name.to_string(), count: found.len() }),
+    }
+}
+
+/// A copy of `err` for each of the records it failed, since its cause can't be cloned.
+fn copy_error(err: &SkySyncError) -> SkySyncError {
+    match err {
+        SkySyncError::IpFetch(cause) => SkySyncError::IpFetch(cause.to_string().into()),
+        SkySyncError::Provider(cause) => SkySyncError::Provider(cause.to_string().into()),
+        SkySyncError::RecordNotFound(name) => SkySyncError::RecordNotFound(name.clone()),
+        SkySyncError::DuplicateRecords { name, count } => SkySyncError::DuplicateRecords { name: name.clone(), count: *count },
+        SkySyncError::CircuitOpen { until } => SkySyncError::CircuitOpen { until: *until },
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{RecordSource, Sources, ZoneConfig};
+    use crate::ip::Ipv6Prefix;
+    use crate::provider::DnsRecord;
+    use crate::schedule::UpdateWindow;
+    use async_trait::async_trait;
+    use std::collections::{BTreeSet, HashMap};
+    use std::sync::{Arc, Mutex};
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A configuration managing `home.example.com`, with everything else at its defaults.
+    pub(crate) fn test_config() -> Config {
+        let vars = HashMap::from([
+            ("CRON_INTERVAL".to_string(), "1000".to_string()),
+            ("CF_DNS_NAME".to_string(), "home.example.com".to_string()),
+            ("CF_API_KEY".to_string(), "key".to_string()),
+            ("CF_EMAIL".to_string(), "me@example.com".to_string()),
+            ("CF_ZONE_ID".to_string(), "zone".to_string()),
+        ]);
+        Config::from_vars(&vars).unwrap()
+    }
+
+    pub(crate) fn record(name: &str, content: &str) -> DnsRecord {
+        DnsRecord {
+            id: format!("id-{}", name),
+            zone_id: "zone".to_string(),
+            zone_name: "example.com".to_string(),
+            name: name.to_string(),
+            r#type: "A".to_string(),
+            content: content.to_string(),
+            ttl: 1,
+            proxied: false,
+            comment: None,
+            tags: Vec::new(),
+            priority: None,
+        }
+    }
+
+    /// An in-memory provider that records every write.
+    #[derive(Clone, Default)]
+    pub(crate) struct MockProvider {
+        pub records: Arc<Mutex<Vec<DnsRecord>>>,
+        pub writes: Arc<Mutex<Vec<(String, String)>>>,
+        pub fail_writes: bool,
+        /// Names of records whose writes are rejected, on top of `fail_writes`.
+        pub failing: Vec<String>,
+        /// How many times the records were listed.
+        pub listings: Arc<Mutex<u32>>,
+        /// Whether listing the records fails, as when the provider is down.
+        pub fail_listings: bool,
+    }
+
+    impl MockProvider {
+        pub(crate) fn with_records(records: Vec<DnsRecord>) -> MockProvider {
+            MockProvider { records: Arc::new(Mutex::new(records)), ..MockProvider::default() }
+        }
+    }
+
+    #[async_trait]
+    impl DnsProvider for MockProvider {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        fn dashboard_url(&self, record: &DnsRecord) -> Option<String> {
+            Some(format!("https://dash.example/{}/{}", record.zone_id, record.id))
+        }
+
+        async fn list_records(&self) -> Result<Vec<DnsRecord>, BoxError> {
+            *self.listings.lock().unwrap() += 1;
+            if self.fail_listings {
+                return Err("service unavailable".into());
+            }
+            Ok(self.records.lock().unwrap().clone())
+        }
+
+        async fn update_record(&self, record: &DnsRecord, content: &str) -> Result<(), BoxError> {
+            if self.fail_writes || self.failing.contains(&record.name) {
+                return Err("rejected".into());
+            }
+            self.writes.lock().unwrap().push((record.name.clone(), content.to_string()));
+            for existing in self.records.lock().unwrap().iter_mut().filter(|x| x.id == record.id) {
+                existing.content = content.to_string();
+            }
+            Ok(())
+        }
+
+        async fn update_record_with_comment(&self, record: &DnsRecord, content: &str, comment: &str) -> Result<(), BoxError> {
+            self.update_record(record, content).await?;
+            for existing in self.records.lock().unwrap().iter_mut().filter(|x| x.id == record.id) {
+                existing.comment = Some(comment.to_string());
+            }
+            Ok(())
+        }
+
+        async fn create_record(&self, name: &str, content: &str) -> Result<(), BoxError> {
+            if self.fail_writes || self.failing.iter().any(|failing| failing == name) {
+                return Err("rejected".into());
+            }
+            self.writes.lock().unwrap().push((name.to_string(), content.to_string()));
+            self.records.lock().unwrap().push(record(name, content));
+            Ok(())
+        }
+    }
+
+    /// An IP source that always returns the same address.
+    pub(crate) struct StaticIp(pub &'static str);
+
+    #[async_trait]
+    impl IpSource for StaticIp {
+        async fn public_ip(&self) -> Result<String, BoxError> {
+            Ok(format!("{}\n", self.0))
+        }
+    }
+
+    /// A notifier that keeps every event it receives.
+    #[derive(Clone, Default)]
+    pub(crate) struct RecordingNotifier {
+        pub events: Arc<Mutex<Vec<UpdateEvent>>>,
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        async fn notify(&self, event: &UpdateEvent) -> Result<(), BoxError> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    fn syncer(provider: &MockProvider, ip: &'static str, notifier: &RecordingNotifier) -> Syncer {
+        Syncer::new(Box::new(provider.clone()), Box::new(StaticIp(ip)), vec![Box::new(notifier.clone())])
+    }
+
+    /// The outcome of the only managed record.
+    fn only(summary: TickSummary) -> TickOutcome {
+        assert_eq!(summary.records.len(), 1);
+        summary.records.into_iter().next().unwrap().result.unwrap()
+    }
+
+    /// Tests that the diff marks stale, current and missing records, and writes nothing.
+    #[tokio::test]
+    async fn test_diff() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1"), record("www.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.zones = vec![ZoneConfig {
+            zone_id: "zone".to_string(),
+            records: vec!["home.example.com".to_string(), "www.example.com".to_string(), "new.example.com".to_string()],
+        }];
+
+        let diffs = syncer(&provider, "2.2.2.2\n", &notifier).diff(&config).await.unwrap();
+        let lines: Vec<String> = diffs.iter().map(RecordDiff::to_string).collect();
+        assert_eq!(lines, vec![
+            "home.example.com A: 1.1.1.1 (current) → 2.2.2.2 (detected) [WOULD UPDATE]",
+            "www.example.com A: 2.2.2.2 (current) → 2.2.2.2 (detected) [OK]",
+            "new.example.com: missing → 2.2.2.2 (detected) [MISSING]",
+        ]);
+        assert_eq!(diffs.iter().filter(|diff| diff.would_change()).count(), 1);
+
+        config.create_missing = true;
+        let diffs = syncer(&provider, "not an ip", &notifier).diff(&config).await.unwrap();
+        assert_eq!(diffs[2].to_string(), "new.example.com: missing, detected IP unknown: \"not an ip\" is not an IP address [UNKNOWN]");
+        let diffs = syncer(&provider, "2.2.2.2", &notifier).diff(&config).await.unwrap();
+        assert!(diffs[2].would_change());
+
+        config.disabled = BTreeSet::from(["home.example.com".to_string()]);
+        let diffs = syncer(&provider, "2.2.2.2", &notifier).diff(&config).await.unwrap();
+        assert_eq!(diffs[0].to_string(), "home.example.com A: 1.1.1.1 (current) → 2.2.2.2 (detected) [DISABLED]");
+        assert!(!diffs[0].would_change());
+
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert!(notifier.events.lock().unwrap().is_empty());
+    }
+
+    /// Tests that a stale record is updated and the change is notified.
+    /// Tests that the address of `IP_OVERRIDE` is what the records are pointed at, with nothing
+    /// asked of the IP lookup, whose client here can't reach anything.
+    #[tokio::test]
+    async fn test_ip_override_updates_record() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.ip_source = IpSourceKind::Override("203.0.113.7".parse().unwrap());
+        let unreachable = reqwest::Client::builder().proxy(reqwest::Proxy::all("http://127.0.0.1:9").unwrap()).build().unwrap();
+        let syncer = Syncer::new(Box::new(provider.clone()), ip_source(&config, unreachable), vec![Box::new(notifier.clone())]);
+
+        let outcome = only(syncer.run_once(&config, &mut State::default()).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "203.0.113.7".to_string() });
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "203.0.113.7".to_string())]);
+    }
+
+    /// Tests that a state saved with a key is encrypted on disk and loads back with it only, and
+    /// that a plaintext state is still read once a key is set.
+    #[cfg(feature = "state-encryption")]
+    #[tokio::test]
+    async fn test_encrypted_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let key = StateKey::new("passphrase");
+        let state = State { current_ip: Some("2.2.2.2".to_string()), ..State::default() };
+
+        state.save(&path, None).await.unwrap();
+        assert_eq!(State::load(&path, Some(&key)).await.unwrap().current_ip.as_deref(), Some("2.2.2.2"));
+
+        state.save(&path, Some(&key)).await.unwrap();
+        assert!(!String::from_utf8_lossy(&std::fs::read(&path).unwrap()).contains("2.2.2.2"));
+        assert_eq!(State::load(&path, Some(&key)).await.unwrap().current_ip.as_deref(), Some("2.2.2.2"));
+
+        let err = State::load(&path, Some(&StateKey::new("wrong"))).await.unwrap_err();
+        assert!(err.to_string().ends_with("can't be decrypted: SKYSYNC_STATE_KEY is wrong, or the file is damaged"), "{}", err);
+        let err = State::load(&path, None).await.unwrap_err();
+        assert!(err.to_string().ends_with("is encrypted, set SKYSYNC_STATE_KEY to read it"), "{}", err);
+    }
+
+    /// Tests that `print-ip` reads the address of the configured source, and fails on an answer
+    /// that isn't one, without listing records.
+    #[tokio::test]
+    async fn test_public_ip() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+
+        assert_eq!(syncer(&provider, " 2.2.2.2\n", &notifier).public_ip().await.unwrap(), "2.2.2.2");
+        let err = syncer(&provider, "<html>", &notifier).public_ip().await.unwrap_err();
+        assert!(matches!(err, SkySyncError::IpFetch(_)), "{:?}", err);
+        assert_eq!(crate::cli::Exit::of(&Err(err)), crate::cli::Exit::IpFetch);
+        assert_eq!(*provider.listings.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_run_once_updates_stale_record() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let mut state = State::default();
+
+        let outcome = only(syncer(&provider, "2.2.2.2", &notifier).run_once(&test_config(), &mut state).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+        assert_eq!(state.last_public_ip.as_deref(), Some("2.2.2.2"));
+        let events = notifier.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].status, EventStatus::Success);
+    }
+
+    /// Tests that detected changes end up in the history.
+    #[tokio::test]
+    async fn test_run_once_records_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier).with_history(History::new(dir.path().join("history.json"), 10));
+
+        syncer.run_once(&test_config(), &mut State::default()).await.unwrap();
+
+        let entries = History::new(dir.path().join("history.json"), 10).load().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].old_ip.as_deref(), Some("1.1.1.1"));
+        assert_eq!(entries[0].new_ip, "2.2.2.2");
+    }
+
+    /// Tests that `CREATE_MISSING` only records a change when a record is actually missing, not on
+    /// a forced tick over records that are all there.
+    #[tokio::test]
+    async fn test_create_missing_records_history_only_when_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.create_missing = true;
+
+        let forced = syncer(&provider, "2.2.2.2", &notifier).with_history(History::new(&path, 10)).with_force(true);
+        forced.run_once(&config, &mut State::default()).await.unwrap();
+        assert!(History::new(&path, 10).load().await.unwrap().is_empty());
+
+        provider.records.lock().unwrap().clear();
+        let creating = syncer(&provider, "2.2.2.2", &notifier).with_history(History::new(&path, 10));
+        creating.run_once(&config, &mut State::default()).await.unwrap();
+        let entries = History::new(&path, 10).load().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].old_ip, None);
+    }
+
+    /// Tests that an IP which flaps back before the debounce threshold never gets applied.
+    #[tokio::test]
+    async fn test_debounce_ignores_flap() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.change_debounce_ticks = 2;
+        let mut state = State::default();
+
+        let outcomes = [
+            only(syncer(&provider, "1.1.1.1", &notifier).run_once(&config, &mut state).await.unwrap()),
+            only(syncer(&provider, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap()),
+            only(syncer(&provider, "1.1.1.1", &notifier).run_once(&config, &mut state).await.unwrap()),
+            only(syncer(&provider, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap()),
+        ];
+
+        assert_eq!(outcomes[1], TickOutcome::Pending { ip: "2.2.2.2".to_string(), seen: 1, required: 2 });
+        assert_eq!(outcomes[3], TickOutcome::Pending { ip: "2.2.2.2".to_string(), seen: 1, required: 2 });
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert!(notifier.events.lock().unwrap().is_empty());
+    }
+
+    /// Tests that an IP stable for the whole debounce window is applied.
+    #[tokio::test]
+    async fn test_debounce_applies_stable_change() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let mut config = test_config();
+        config.change_debounce_ticks = 2;
+        let mut state = State::default();
+
+        assert!(matches!(only(syncer.run_once(&config, &mut state).await.unwrap()), TickOutcome::Pending { .. }));
+        assert!(matches!(only(syncer.run_once(&config, &mut state).await.unwrap()), TickOutcome::Updated { .. }));
+        assert_eq!(provider.writes.lock().unwrap().len(), 1);
+    }
+
+    /// Tests that a change seen outside the update window waits, and is applied once it opens.
+    #[tokio::test]
+    async fn test_update_window_defers_change() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let mut config = test_config();
+        config.change_debounce_ticks = 2;
+        let now = config.timezone.now().time();
+        let window = |start: i64, end: i64| Some(UpdateWindow {
+            start: now + chrono::Duration::minutes(start),
+            end: now + chrono::Duration::minutes(end),
+        });
+        let mut state = State::default();
+
+        syncer.run_once(&config, &mut state).await.unwrap();
+        config.update_window = window(60, 120);
+        let outcome = only(syncer.run_once(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Deferred { ip: "2.2.2.2".to_string(), opens: now + chrono::Duration::minutes(60) });
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert!(notifier.events.lock().unwrap().is_empty());
+
+        // The window has just opened
+        config.update_window = window(-1, 60);
+        let outcome = only(syncer.run_once(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+    }
+
+    /// Tests that a record written with the long form of the public IPv6 address is left alone.
+    #[tokio::test]
+    async fn test_expanded_ipv6_is_current() {
+        let expanded = DnsRecord { r#type: "AAAA".to_string(), ..record("home.example.com", "2001:0db8:0000:0000:0000:0000:0000:0001") };
+        let provider = MockProvider::with_records(vec![expanded]);
+        let notifier = RecordingNotifier::default();
+        let mut state = State::default();
+
+        let outcome = only(syncer(&provider, "2001:db8::1", &notifier).run_once(&test_config(), &mut state).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Unchanged { ip: "2001:db8::1".to_string() });
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert!(notifier.events.lock().unwrap().is_empty());
+    }
+
+    /// Tests that `COMPARE_MODE=resolve` rewrites a record resolvers still serve stale, even
+    /// though its content is current, and leaves it alone once they serve the public IP.
+    #[cfg(feature = "hickory-dns")]
+    #[tokio::test]
+    async fn test_compares_resolved_address() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let mut state = State { last_public_ip: Some("2.2.2.2".to_string()), ..State::default() };
+
+        let stale = Resolver::new(&[crate::resolver::tests::dns_server("1.1.1.1".parse().unwrap()).await]);
+        let outcome = only(syncer(&provider, "2.2.2.2", &notifier).with_resolver(stale).run_once(&test_config(), &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+
+        let current = Resolver::new(&[crate::resolver::tests::dns_server("2.2.2.2".parse().unwrap()).await]);
+        let outcome = only(syncer(&provider, "2.2.2.2", &notifier).with_resolver(current).run_once(&test_config(), &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Unchanged { ip: "2.2.2.2".to_string() });
+        assert_eq!(provider.writes.lock().unwrap().len(), 1);
+    }
+
+    /// Tests that a record already pointing at the IP is left alone.
+    #[tokio::test]
+    async fn test_run_once_unchanged() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let mut state = State::default();
+
+        for _ in 0..2 {
+            let outcome = only(syncer.run_once(&test_config(), &mut state).await.unwrap());
+            assert_eq!(outcome, TickOutcome::Unchanged { ip: "2.2.2.2".to_string() });
+        }
+
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert!(notifier.events.lock().unwrap().is_empty());
+    }
+
+    /// Tests that records already pointing at the IP on startup are adopted without an update or a notification.
+    #[tokio::test]
+    async fn test_reconcile_adopts_correct_records() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let mut state = State { last_public_ip: Some("1.1.1.1".to_string()), ..State::default() };
+
+        let outcome = only(syncer.reconcile(&test_config(), &mut state).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Unchanged { ip: "2.2.2.2".to_string() });
+        assert_eq!(state.last_public_ip.as_deref(), Some("2.2.2.2"));
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert!(notifier.events.lock().unwrap().is_empty());
+    }
+
+    /// Tests that a record changed while SkySync was down is fixed even though the saved IP matches.
+    #[tokio::test]
+    async fn test_reconcile_ignores_saved_ip() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "9.9.9.9")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let mut state = State { last_public_ip: Some("2.2.2.2".to_string()), ..State::default() };
+
+        syncer.reconcile(&test_config(), &mut state).await.unwrap();
+
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+        assert_eq!(notifier.events.lock().unwrap().len(), 1);
+    }
+
+    /// Tests that a startup with some stale records reads them all from one listing, and
+    /// announces their updates in a single summary.
+    #[tokio::test]
+    async fn test_reconcile_batches_startup_updates() {
+        let provider = MockProvider::with_records(vec![
+            record("a.example.com", "1.1.1.1"),
+            record("b.example.com", "2.2.2.2"),
+            record("c.example.com", "1.1.1.1"),
+        ]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.zones[0].records = vec!["a.example.com".to_string(), "b.example.com".to_string(), "c.example.com".to_string()];
+        config.sources.insert("c.example.com".to_string(), vec![RecordSource { address: AddressSource::HttpV4, record_type: "A".to_string() }]);
+        let syncer = syncer(&provider, "2.2.2.2", &notifier).with_sources(HashMap::from([
+            (("c.example.com".to_string(), "A".to_string()), Box::new(StaticIp("2.2.2.2")) as Box<dyn IpSource>),
+        ]));
+
+        let summary = syncer.reconcile(&config, &mut State::default()).await.unwrap();
+
+        assert_eq!(summary.failed(), 0);
+        assert_eq!(*provider.listings.lock().unwrap(), 1);
+        assert_eq!(provider.writes.lock().unwrap().len(), 2);
+        let events = notifier.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].status, EventStatus::Reconciled { updated: 2, current: 1, failed: 0 });
+        assert_eq!(events[0].domain, "a.example.com, b.example.com, c.example.com");
+        assert_eq!(notifier::reconciled(2, 1, 0), "3 records reconciled: 2 updated, 1 already current");
+    }
+
+    /// Tests that the default Discord notifier without a webhook is left out with a warning, and
+    /// the record is still updated.
+    #[tokio::test]
+    async fn test_updates_without_discord_webhook() {
+        let config = test_config();
+        assert_eq!(config.notifiers, vec![crate::config::NotifierKind::Discord]);
+        assert!(config.warnings()[0].contains("DISCORD_WEBHOOK_ID is empty"), "{:?}", config.warnings());
+
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifiers = notifier::from_config(&config, &reqwest::Client::new());
+        assert!(notifiers.is_empty());
+        let syncer = Syncer::new(Box::new(provider.clone()), Box::new(StaticIp("2.2.2.2")), notifiers);
+
+        let outcome = only(syncer.run_once(&config, &mut State::default()).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(provider.writes.lock().unwrap().len(), 1);
+    }
+
+    /// Tests that a rejected update is reported and retried on the next tick.
+    #[tokio::test]
+    async fn test_run_once_failed_update() {
+        let provider = MockProvider { fail_writes: true, ..MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]) };
+        let notifier = RecordingNotifier::default();
+        let mut state = State::default();
+
+        let outcome = only(syncer(&provider, "2.2.2.2", &notifier).run_once(&test_config(), &mut state).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Failed { error: "rejected".to_string() });
+        assert_eq!(state.last_public_ip, None);
+        assert_eq!(notifier.events.lock().unwrap()[0].status, EventStatus::Failure("rejected".to_string()));
+    }
+
+    /// Tests that failed ticks are counted, that reaching the threshold alerts once, and that the
+    /// next success reports the recovery and resets the streak but not the total.
+    #[tokio::test]
+    async fn test_failure_alert_threshold() {
+        let provider = MockProvider { fail_writes: true, ..MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]) };
+        let notifier = RecordingNotifier::default();
+        let config = Config { failure_alert_threshold: 3, ..test_config() };
+        let mut state = State::default();
+        let alerts = |notifier: &RecordingNotifier| -> Vec<EventStatus> {
+            notifier.events.lock().unwrap().iter()
+                .map(|event| event.status.clone())
+                .filter(|status| matches!(status, EventStatus::Degraded { .. } | EventStatus::Recovered { .. }))
+                .collect()
+        };
+
+        let failing = syncer(&provider, "2.2.2.2", &notifier);
+        for _ in 0..2 {
+            failing.run_once(&config, &mut state).await.unwrap();
+        }
+        assert_eq!((state.failure_streak, state.total_failures), (2, 2));
+        assert!(alerts(&notifier).is_empty());
+
+        for _ in 0..2 {
+            failing.run_once(&config, &mut state).await.unwrap();
+        }
+        assert_eq!((state.failure_streak, state.total_failures), (4, 4));
+        assert_eq!(alerts(&notifier), vec![EventStatus::Degraded { error: "Failed to update DNS record home.example.com: rejected".to_string(), failures: 3 }]);
+
+        let recovered = MockProvider { fail_writes: false, ..provider.clone() };
+        syncer(&recovered, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap();
+        assert_eq!((state.failure_streak, state.total_failures), (0, 4));
+        assert_eq!(alerts(&notifier).last(), Some(&EventStatus::Recovered { failures: 4 }));
+
+        // A success without a preceding alert has nothing to report
+        syncer(&recovered, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap();
+        assert_eq!(alerts(&notifier).len(), 2);
+    }
+
+    /// Tests that the circuit breaker opens once the provider failed enough ticks in a row, skips
+    /// ticks during the cooldown, reopens when the test listing fails, and goes through half-open
+    /// to closed once the provider answers again.
+    #[tokio::test]
+    async fn test_circuit_breaker_transitions() {
+        let down = MockProvider { fail_listings: true, ..MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]) };
+        let notifier = RecordingNotifier::default();
+        let config = Config { circuit_breaker_threshold: 2, circuit_breaker_cooldown: Duration::from_secs(600), ..test_config() };
+        let mut state = State::default();
+        let failing = syncer(&down, "2.2.2.2", &notifier);
+        let cooled_down = |state: &mut State| state.circuit.retry_at = Some(config.timezone.now() - TimeDelta::seconds(1));
+
+        assert!(matches!(failing.run_once(&config, &mut state).await, Err(SkySyncError::Provider(_))));
+        assert_eq!((state.circuit.state, state.circuit.failures), (CircuitState::Closed, 1));
+        assert!(matches!(failing.run_once(&config, &mut state).await, Err(SkySyncError::Provider(_))));
+        assert_eq!((state.circuit.state, state.circuit.failures), (CircuitState::Open, 2));
+        let opened: Vec<_> = notifier.events.lock().unwrap().iter().map(|event| event.status.clone()).collect();
+        assert_eq!(opened, vec![EventStatus::CircuitOpen { error: "Failed to fetch DNS records: service unavailable".to_string(), failures: 2, minutes: 10 }]);
+
+        // During the cooldown the provider isn't called at all, and the tick isn't counted
+        let listed = *down.listings.lock().unwrap();
+        assert!(matches!(failing.run_once(&config, &mut state).await, Err(SkySyncError::CircuitOpen { .. })));
+        assert_eq!((*down.listings.lock().unwrap(), state.failure_streak), (listed, 2));
+
+        // After it, a failed test listing opens the circuit for another cooldown, without notifying again
+        cooled_down(&mut state);
+        assert!(matches!(failing.run_once(&config, &mut state).await, Err(SkySyncError::CircuitOpen { .. })));
+        assert_eq!(*down.listings.lock().unwrap(), listed + 1);
+        assert_eq!(state.circuit.state, CircuitState::Open);
+        assert!(state.circuit.retry_at.unwrap() > config.timezone.now());
+        assert_eq!(notifier.events.lock().unwrap().len(), 1);
+
+        let up = MockProvider { fail_listings: false, ..down.clone() };
+        let recovered = syncer(&up, "2.2.2.2", &notifier);
+        cooled_down(&mut state);
+        recovered.test_circuit(&config, &mut state, config.timezone.now()).await.unwrap();
+        assert_eq!(state.circuit.state, CircuitState::HalfOpen);
+
+        let outcome = only(recovered.run_once(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(state.circuit, Circuit::default());
+    }
+
+    /// Tests that a long outage escalates exactly once at the threshold, and resolves once.
+    #[tokio::test]
+    async fn test_outage_escalates_once() {
+        let provider = MockProvider { fail_writes: true, ..MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]) };
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.escalate_after = vec![std::time::Duration::from_secs(3600)];
+        let mut state = State::default();
+        let escalations = |notifier: &RecordingNotifier| notifier.events.lock().unwrap().iter()
+            .filter(|event| matches!(event.status, EventStatus::Escalated { .. }))
+            .count();
+
+        let failing = syncer(&provider, "2.2.2.2", &notifier);
+        failing.run_once(&config, &mut state).await.unwrap();
+        assert!(state.outage_since.is_some());
+
+        // Simulate the streak going on for just under, and then just past, an hour
+        for (minutes, expected) in [(59, 0), (61, 1), (62, 1), (120, 1)] {
+            state.outage_since = Some(config.timezone.now() - chrono::Duration::minutes(minutes));
+            failing.run_once(&config, &mut state).await.unwrap();
+            assert_eq!(escalations(&notifier), expected, "after {} minutes", minutes);
+        }
+        assert_eq!(state.failure_streak, 5);
+
+        let recovered = MockProvider { fail_writes: false, ..provider.clone() };
+        syncer(&recovered, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap();
+
+        let events = notifier.events.lock().unwrap();
+        assert!(matches!(events.last().unwrap().status, EventStatus::Resolved { minutes: 120 }), "{:?}", events.last());
+        assert_eq!(state.outage_since, None);
+        assert_eq!(state.escalations, 0);
+    }
+
+    /// Tests that a missing record is an error unless creation is enabled.
+    #[tokio::test]
+    async fn test_run_once_missing_record() {
+        let provider = MockProvider::default();
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let mut config = test_config();
+
+        let summary = syncer.run_once(&config, &mut State::default()).await.unwrap();
+        assert!(matches!(summary.records[0].result, Err(SkySyncError::RecordNotFound(_))));
+
+        config.create_missing = true;
+        let outcome = only(syncer.run_once(&config, &mut State::default()).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Created { ip: "2.2.2.2".to_string() });
+        assert_eq!(provider.records.lock().unwrap().len(), 1);
+    }
+
+    /// Tests that `SELF_HEAL` recreates a record that was deleted since it was last seen, and
+    /// notifies it, but leaves records it never saw to `CF_CREATE_MISSING`.
+    #[tokio::test]
+    async fn test_self_heal_recreates_deleted_record() {
+        let provider = MockProvider::default();
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let config = Config { self_heal: true, ..test_config() };
+
+        let summary = syncer.run_once(&config, &mut State::default()).await.unwrap();
+        assert!(matches!(summary.records[0].result, Err(SkySyncError::RecordNotFound(_))));
+
+        let mut state = State::default();
+        state.records.insert("home.example.com".to_string(), "1.1.1.1".to_string());
+        let outcome = only(syncer.run_once(&config, &mut state).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Created { ip: "2.2.2.2".to_string() });
+        assert_eq!(provider.records.lock().unwrap().len(), 1);
+        let events = notifier.events.lock().unwrap();
+        assert_eq!((events[0].old_ip.as_deref(), &events[0].status), (Some("1.1.1.1"), &EventStatus::Recreated));
+        assert_eq!(state.records["home.example.com"], "2.2.2.2");
+    }
+
+    /// Tests that a record pointing at neither the address it was last seen at nor the public IP
+    /// is taken for a manual change: with `FLAG_MANUAL_DRIFT` it's notified and stamped before it's
+    /// pointed back, without it it's only pointed back.
+    #[tokio::test]
+    async fn test_manual_drift() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "9.9.9.9")]);
+        let notifier = RecordingNotifier::default();
+        let config = Config { flag_manual_drift: true, ..test_config() };
+        let mut state = State::default();
+        state.records.insert("home.example.com".to_string(), "1.1.1.1".to_string());
+
+        let outcome = only(syncer(&provider, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Updated { old: "9.9.9.9".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+        let comment = provider.records.lock().unwrap()[0].comment.clone().unwrap();
+        assert!(comment.starts_with("SkySync: manual change to 9.9.9.9 reverted to 2.2.2.2 on "), "{}", comment);
+        let events: Vec<_> = notifier.events.lock().unwrap().iter().map(|event| (event.old_ip.clone(), event.status.clone())).collect();
+        assert_eq!(events, vec![(Some("9.9.9.9".to_string()), EventStatus::Drifted)]);
+
+        // A record left behind by a change of the public IP isn't a manual change
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        only(syncer(&provider, "3.3.3.3", &notifier).run_once(&config, &mut state).await.unwrap());
+        assert!(notifier.events.lock().unwrap().iter().all(|event| event.status != EventStatus::Drifted));
+        assert!(provider.records.lock().unwrap()[0].comment.is_none());
+
+        // Without the flag the record is pointed back like any other
+        let provider = MockProvider::with_records(vec![record("home.example.com", "9.9.9.9")]);
+        let notifier = RecordingNotifier::default();
+        let outcome = only(syncer(&provider, "4.4.4.4", &notifier).run_once(&test_config(), &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Updated { old: "9.9.9.9".to_string(), new: "4.4.4.4".to_string() });
+        assert!(notifier.events.lock().unwrap().iter().all(|event| event.status != EventStatus::Drifted));
+        assert!(provider.records.lock().unwrap()[0].comment.is_none());
+    }
+
+    /// Tests that with `RECORD_RETRY_BACKOFF` a failed record is left alone until its backoff is
+    /// over, then retried on its own, while the record that succeeded isn't written again.
+    #[tokio::test]
+    async fn test_record_retry_backoff() {
+        let records = vec![record("home.example.com", "1.1.1.1"), record("www.example.com", "1.1.1.1")];
+        let flaky = MockProvider { failing: vec!["www.example.com".to_string()], ..MockProvider::with_records(records) };
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.zones[0].records = vec!["home.example.com".to_string(), "www.example.com".to_string()];
+        config.record_retry_backoff = Duration::from_secs(60);
+        let mut state = State::default();
+        let outcomes = |summary: TickSummary| summary.records.into_iter().map(|record| record.result.unwrap()).collect::<Vec<_>>();
+
+        let first = outcomes(syncer(&flaky, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap());
+        assert!(matches!(first[..], [TickOutcome::Updated { .. }, TickOutcome::Failed { .. }]), "{:?}", first);
+        let failed = state.record_health["www.example.com"].clone();
+        assert_eq!(failed.failures, 1);
+        assert_eq!(failed.retry_at, Some(state.last_check.unwrap() + TimeDelta::seconds(60)));
+        assert_eq!(state.record_health["home.example.com"].last_success, state.last_check);
+
+        // The provider recovered, but the record is still backing off
+        let recovered = MockProvider { failing: Vec::new(), ..flaky.clone() };
+        let second = outcomes(syncer(&recovered, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap());
+        assert!(matches!(&second[..], [TickOutcome::Unchanged { .. }, TickOutcome::BackingOff { failures: 1, .. }]), "{:?}", second);
+        assert_eq!(state.record_health["www.example.com"], failed);
+
+        state.record_health.get_mut("www.example.com").unwrap().retry_at = Some(state.last_check.unwrap());
+        let third = outcomes(syncer(&recovered, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap());
+        assert!(matches!(third[..], [TickOutcome::Unchanged { .. }, TickOutcome::Updated { .. }]), "{:?}", third);
+        assert_eq!(state.record_health["www.example.com"], RecordHealth { last_success: state.last_check, failures: 0, retry_at: None, disabled: false });
+        assert_eq!(*flaky.writes.lock().unwrap(), vec![
+            ("home.example.com".to_string(), "2.2.2.2".to_string()),
+            ("www.example.com".to_string(), "2.2.2.2".to_string()),
+        ]);
+
+        assert_eq!(retry_backoff(Duration::from_secs(60), 3), Duration::from_secs(240));
+        assert_eq!(retry_backoff(Duration::from_secs(60), 40), MAX_RECORD_RETRY_BACKOFF);
+    }
+
+    /// Tests that a disabled record is left alone by the first tick and the ones after it, and
+    /// reported as disabled, while the enabled record next to it still updates.
+    #[tokio::test]
+    async fn test_disabled_record_is_skipped() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1"), record("www.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.zones[0].records = vec!["home.example.com".to_string(), "www.example.com".to_string()];
+        config.disabled = BTreeSet::from(["www.example.com".to_string()]);
+        let mut state = State::default();
+        let outcomes = |summary: TickSummary| summary.records.into_iter().map(|record| (record.name, record.result.unwrap())).collect::<Vec<_>>();
+
+        let reconciled = outcomes(syncer(&provider, "2.2.2.2", &notifier).reconcile(&config, &mut state).await.unwrap());
+        assert_eq!(reconciled, vec![
+            ("home.example.com".to_string(), TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() }),
+            ("www.example.com".to_string(), TickOutcome::Disabled),
+        ]);
+        let ticked = outcomes(syncer(&provider, "3.3.3.3", &notifier).run_once(&config, &mut state).await.unwrap());
+        assert_eq!(ticked[1], ("www.example.com".to_string(), TickOutcome::Disabled));
+
+        assert_eq!(*provider.writes.lock().unwrap(), vec![
+            ("home.example.com".to_string(), "2.2.2.2".to_string()),
+            ("home.example.com".to_string(), "3.3.3.3".to_string()),
+        ]);
+        assert!(notifier.events.lock().unwrap().iter().all(|event| event.domain == "home.example.com"));
+        assert!(state.record_health["www.example.com"].disabled);
+        assert!(!state.record_health["home.example.com"].disabled);
+    }
+
+    /// Tests that every record is attempted when one of them fails, and the summary says so.
+    #[tokio::test]
+    async fn test_run_once_updates_records_independently() {
+        let names = ["a.example.com", "b.example.com", "c.example.com", "d.example.com"];
+        let provider = MockProvider {
+            failing: vec!["b.example.com".to_string()],
+            ..MockProvider::with_records(names.iter().map(|name| record(name, "1.1.1.1")).collect())
+        };
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.zones[0].records = names.iter().map(|name| name.to_string()).collect();
+        config.update_concurrency = 2;
+        let mut state = State::default();
+
+        let summary = syncer(&provider, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap();
+
+        let outcomes: Vec<(&str, &TickOutcome)> = summary.records.iter()
+            .map(|record| (record.name.as_str(), record.result.as_ref().unwrap()))
+            .collect();
+        let updated = TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() };
+        let failed = TickOutcome::Failed { error: "rejected".to_string() };
+        assert_eq!(outcomes, vec![
+            ("a.example.com", &updated),
+            ("b.example.com", &failed),
+            ("c.example.com", &updated),
+            ("d.example.com", &updated),
+        ]);
+        assert_eq!(summary.failed(), 1);
+        assert!(summary.to_string().starts_with("3 of 4 records in sync\n"));
+        assert_eq!(provider.writes.lock().unwrap().len(), 3);
+        // The failure on its own, and the three updates coalesced into one
+        let events = notifier.events.lock().unwrap().clone();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0].status, EventStatus::Failure(_)));
+        assert_eq!(events[1].domain, "a.example.com, c.example.com, d.example.com");
+        // The failed record is retried on the next tick
+        assert_eq!(state.last_public_ip, None);
+    }
+
+    /// Tests that a record with its own notifiers only notifies those, and the rest the global ones.
+    #[tokio::test]
+    async fn test_routes_events_per_record() {
+        let provider = MockProvider::with_records(vec![record("a.example.com", "1.1.1.1"), record("b.example.com", "1.1.1.1")]);
+        let global = RecordingNotifier::default();
+        let routed = RecordingNotifier::default();
+        let mut config = test_config();
+        config.zones[0].records = vec!["a.example.com".to_string(), "b.example.com".to_string()];
+        let routes = HashMap::from([("b.example.com".to_string(), vec![Box::new(routed.clone()) as Box<dyn Notifier>])]);
+        let syncer = syncer(&provider, "2.2.2.2", &global).with_routes(routes);
+
+        syncer.run_once(&config, &mut State::default()).await.unwrap();
+
+        let domains = |notifier: &RecordingNotifier| -> Vec<String> {
+            notifier.events.lock().unwrap().iter().map(|event| event.domain.clone()).collect()
+        };
+        assert_eq!(domains(&global), vec!["a.example.com"]);
+        assert_eq!(domains(&routed), vec!["b.example.com"]);
+    }
+
+    /// Tests each `DUPLICATE_RECORDS` policy against two round-robin records of the same name.
+    #[tokio::test]
+    async fn test_duplicate_records() {
+        let round_robin = || MockProvider::with_records(vec![
+            record("home.example.com", "1.1.1.1"),
+            DnsRecord { id: "id-second".to_string(), ..record("home.example.com", "3.3.3.3") },
+        ]);
+        let contents = |provider: &MockProvider| -> Vec<String> {
+            provider.records.lock().unwrap().iter().map(|record| record.content.clone()).collect()
+        };
+        let run = |provider: &MockProvider, policy| {
+            let syncer = syncer(provider, "2.2.2.2", &RecordingNotifier::default());
+            let config = Config { duplicate_records: policy, ..test_config() };
+            async move { syncer.run_once(&config, &mut State::default()).await.unwrap() }
+        };
+
+        let provider = round_robin();
+        let summary = run(&provider, DuplicatePolicy::Error).await;
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert_eq!(summary.records.len(), 1);
+        let err = summary.records.into_iter().next().unwrap().result.unwrap_err();
+        assert!(matches!(err, SkySyncError::DuplicateRecords { count: 2, .. }), "{:?}", err);
+        assert_eq!(err.to_string(), "Found 2 DNS records named home.example.com, set DUPLICATE_RECORDS to update-all or update-first to update them");
+
+        let provider = round_robin();
+        let summary = run(&provider, DuplicatePolicy::UpdateFirst).await;
+        assert!(matches!(only(summary), TickOutcome::Updated { .. }));
+        assert_eq!(contents(&provider), vec!["2.2.2.2", "3.3.3.3"]);
+
+        let provider = round_robin();
+        let summary = run(&provider, DuplicatePolicy::UpdateAll).await;
+        assert_eq!(summary.records.len(), 2);
+        assert!(summary.records.iter().all(|record| record.name == "home.example.com" && matches!(record.result, Ok(TickOutcome::Updated { .. }))));
+        assert_eq!(contents(&provider), vec!["2.2.2.2", "2.2.2.2"]);
+    }
+
+    /// Tests that a failing change hook is only logged: the record is updated and notified anyway.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failing_hook_doesnt_fail_update() {
+        use crate::config::HookConfig;
+
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let hook = ChangeHook::new(HookConfig { command: "exit 1;".to_string(), timeout: std::time::Duration::from_secs(5) });
+        let syncer = syncer(&provider, "2.2.2.2", &notifier).with_hook(hook);
+
+        let summary = syncer.run_once(&test_config(), &mut State::default()).await.unwrap();
+
+        assert!(matches!(only(summary), TickOutcome::Updated { .. }));
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+        assert_eq!(notifier.events.lock().unwrap().len(), 1);
+    }
+
+    /// Tests that a record edited behind a matching cached IP is left alone until it's due a
+    /// recheck, then read and pointed back at the IP.
+    #[tokio::test]
+    async fn test_rechecks_records_behind_cached_ip() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let config = Config { change_debounce_ticks: 3, ..test_config() };
+        let mut state = State::default();
+
+        syncer.run_once(&config, &mut state).await.unwrap();
+        provider.records.lock().unwrap()[0].content = "9.9.9.9".to_string();
+
+        let outcome = only(syncer.run_once(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Unchanged { ip: "2.2.2.2".to_string() });
+        assert!(provider.writes.lock().unwrap().is_empty());
+
+        state.records_checked = state.records_checked.map(|checked| checked - chrono::Duration::hours(2));
+        let outcome = only(syncer.run_once(&config, &mut state).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Updated { old: "9.9.9.9".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+        assert_eq!(state.last_public_ip.as_deref(), Some("2.2.2.2"));
+    }
+
+    /// Tests that every `DRIFT_CHECK_EVERY` ticks a record is read and pointed back at the IP,
+    /// though the IP is unchanged and the records aren't due a recheck.
+    #[tokio::test]
+    async fn test_drift_check_every() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier);
+        let config = Config { drift_check_every: 3, ..test_config() };
+        let mut state = State::default();
+
+        syncer.run_once(&config, &mut state).await.unwrap();
+        provider.records.lock().unwrap()[0].content = "9.9.9.9".to_string();
+
+        for _ in 0..2 {
+            let outcome = only(syncer.run_once(&config, &mut state).await.unwrap());
+            assert_eq!(outcome, TickOutcome::Unchanged { ip: "2.2.2.2".to_string() });
+        }
+        assert!(provider.writes.lock().unwrap().is_empty());
+
+        let outcome = only(syncer.run_once(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Updated { old: "9.9.9.9".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(*provider.writes.lock().unwrap(), vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+        assert_eq!(state.unread_ticks, 0);
+    }
+
+    /// Tests that records follow the delegated IPv6 prefix, and that the host's address rotating
+    /// within a stable prefix doesn't touch them.
+    #[tokio::test]
+    async fn test_ipv6_prefix_ignores_rotating_suffix() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2001:db8:1:2::1")]);
+        let notifier = RecordingNotifier::default();
+        let config = Config { ipv6_prefix: Some(Ipv6Prefix { length: 64, suffix: "::1".parse().unwrap() }), ..test_config() };
+        let mut state = State::default();
+
+        let rotated = syncer(&provider, "2001:db8:1:2:aaaa:bbbb:cccc:dddd", &notifier);
+        let outcome = only(rotated.run_once(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Unchanged { ip: "2001:db8:1:2::1".to_string() });
+        assert_eq!(state.current_ip.as_deref(), Some("2001:db8:1:2:aaaa:bbbb:cccc:dddd"));
+
+        let moved = syncer(&provider, "2001:db8:1:3:aaaa:bbbb:cccc:dddd", &notifier);
+        let outcome = only(moved.run_once(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Updated { old: "2001:db8:1:2::1".to_string(), new: "2001:db8:1:3::1".to_string() });
+        assert_eq!(provider.writes.lock().unwrap().len(), 1);
+    }
+
+    /// Tests a record following the public IPv4 address next to one reading its address from an
+    /// interface, and that a source that can't be read only fails its own record.
+    #[tokio::test]
+    async fn test_records_with_own_sources() {
+        let provider = MockProvider::with_records(vec![record("v4.example.com", "1.1.1.1"), record("lan.example.com", "192.168.1.9")]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.zones = vec![ZoneConfig {
+            zone_id: "zone".to_string(),
+            records: vec!["v4.example.com".to_string(), "lan.example.com".to_string(), "broken.example.com".to_string()],
+        }];
+        config.sources = Sources::from([
+            ("v4.example.com".to_string(), vec![RecordSource { address: AddressSource::HttpV4, record_type: "A".to_string() }]),
+            ("lan.example.com".to_string(), vec![RecordSource { address: AddressSource::Interface("eth0".to_string()), record_type: "A".to_string() }]),
+            ("broken.example.com".to_string(), vec![RecordSource { address: AddressSource::Interface("eth1".to_string()), record_type: "A".to_string() }]),
+        ]);
+        let sources: HashMap<(String, String), Box<dyn IpSource>> = HashMap::from([
+            (("v4.example.com".to_string(), "A".to_string()), Box::new(StaticIp("2.2.2.2")) as Box<dyn IpSource>),
+            (("lan.example.com".to_string(), "A".to_string()), Box::new(StaticIp("192.168.1.10"))),
+            (("broken.example.com".to_string(), "A".to_string()), Box::new(StaticIp("interface eth1 is down"))),
+        ]);
+
+        let syncer = syncer(&provider, "9.9.9.9", &notifier).with_sources(sources);
+        let summary = syncer.run_once(&config, &mut State::default()).await.unwrap();
+
+        let names: Vec<&str> = summary.records.iter().map(|record| record.name.as_str()).collect();
+        assert_eq!(names, vec!["v4.example.com", "lan.example.com", "broken.example.com"]);
+        assert_eq!(summary.records[0].result.as_ref().unwrap(), &TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(summary.records[1].result.as_ref().unwrap(), &TickOutcome::Updated { old: "192.168.1.9".to_string(), new: "192.168.1.10".to_string() });
+        assert!(matches!(summary.records[2].result, Err(SkySyncError::IpFetch(_))));
+        assert_eq!(provider.writes.lock().unwrap().len(), 2);
+    }
+
+    /// Tests that one record kept for both types points its A record at the IPv4 source and its
+    /// AAAA record at the IPv6 one.
+    #[tokio::test]
+    async fn test_record_with_both_types() {
+        let v6 = DnsRecord { id: "home-v6".to_string(), r#type: "AAAA".to_string(), ..record("home.example.com", "2001:db8::1") };
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1"), v6]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.sources = Sources::from([("home.example.com".to_string(), vec![
+            RecordSource { address: AddressSource::HttpV4, record_type: "A".to_string() },
+            RecordSource { address: AddressSource::HttpV6, record_type: "AAAA".to_string() },
+        ])]);
+        let sources: HashMap<(String, String), Box<dyn IpSource>> = HashMap::from([
+            (("home.example.com".to_string(), "A".to_string()), Box::new(StaticIp("2.2.2.2")) as Box<dyn IpSource>),
+            (("home.example.com".to_string(), "AAAA".to_string()), Box::new(StaticIp("2001:db8::2"))),
+        ]);
+
+        let syncer = syncer(&provider, "9.9.9.9", &notifier).with_sources(sources);
+        let summary = syncer.run_once(&config, &mut State::default()).await.unwrap();
+
+        let outcomes: Vec<&TickOutcome> = summary.records.iter().map(|record| record.result.as_ref().unwrap()).collect();
+        assert_eq!(outcomes, vec![
+            &TickOutcome::Updated { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() },
+            &TickOutcome::Updated { old: "2001:db8::1".to_string(), new: "2001:db8::2".to_string() },
+        ]);
+        let records = provider.records.lock().unwrap();
+        let contents: Vec<(&str, &str)> = records.iter().map(|record| (record.r#type.as_str(), record.content.as_str())).collect();
+        assert_eq!(contents, vec![("A", "2.2.2.2"), ("AAAA", "2001:db8::2")]);
+    }
+
+    /// Tests that an A and an AAAA record changing in the same tick are announced by a single
+    /// notification listing both, and by one each with `COALESCE_NOTIFICATIONS=false`.
+    #[tokio::test]
+    async fn test_coalesces_dual_stack_changes() {
+        for coalesce in [true, false] {
+            let v6 = DnsRecord { r#type: "AAAA".to_string(), ..record("v6.example.com", "2001:db8::1") };
+            let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1"), v6]);
+            let notifier = RecordingNotifier::default();
+            let mut config = Config { coalesce_notifications: coalesce, ..test_config() };
+            config.zones[0].records = vec!["home.example.com".to_string(), "v6.example.com".to_string()];
+            config.sources = Sources::from([
+                ("v6.example.com".to_string(), vec![RecordSource { address: AddressSource::HttpV6, record_type: "AAAA".to_string() }]),
+            ]);
+            let sources: HashMap<(String, String), Box<dyn IpSource>> = HashMap::from([
+                (("v6.example.com".to_string(), "AAAA".to_string()), Box::new(StaticIp("2001:db8::2")) as Box<dyn IpSource>),
+            ]);
+
+            let syncer = syncer(&provider, "2.2.2.2", &notifier).with_sources(sources);
+            syncer.run_once(&config, &mut State::default()).await.unwrap();
+
+            assert_eq!(provider.writes.lock().unwrap().len(), 2);
+            let events = notifier.events.lock().unwrap().clone();
+            if coalesce {
+                assert_eq!(events.len(), 1);
+                assert_eq!(events[0].changes.len(), 2);
+            } else {
+                assert_eq!(events.len(), 2);
+                assert!(events.iter().all(|event| event.changes.is_empty()));
+            }
+        }
+    }
+
+    /// Tests that a tick updating the A record also points the heartbeat TXT record at the
+    /// filled-in template, and that a tick with nothing to update leaves it alone.
+    #[tokio::test]
+    async fn test_heartbeat_record() {
+        let txt = DnsRecord { id: "heartbeat".to_string(), r#type: "TXT".to_string(), ..record("_skysync.example.com", "skysync-last-update=never") };
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1"), txt]);
+        let notifier = RecordingNotifier::default();
+        let mut config = test_config();
+        config.heartbeat = Some(HeartbeatConfig { record: "_skysync.example.com".to_string(), template: "skysync-last-update={{timestamp}} ip={{ip}}".to_string() });
+        let mut state = State::default();
+
+        syncer(&provider, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap();
+
+        let checked = state.last_check.unwrap().to_utc().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let expected = format!("skysync-last-update={} ip=2.2.2.2", checked);
+        assert!(checked.ends_with('Z'), "{}", checked);
+        assert_eq!(*provider.writes.lock().unwrap(), vec![
+            ("home.example.com".to_string(), "2.2.2.2".to_string()),
+            ("_skysync.example.com".to_string(), expected.clone()),
+        ]);
+        assert_eq!(provider.records.lock().unwrap()[1].content, expected);
+
+        syncer(&provider, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap();
+        assert_eq!(provider.writes.lock().unwrap().len(), 2);
+    }
+
+    /// Tests that notifications only link to the updated record with `INCLUDE_DASHBOARD_LINK`.
+    #[tokio::test]
+    async fn test_dashboard_link() {
+        for include in [true, false] {
+            let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+            let notifier = RecordingNotifier::default();
+            let config = Config { include_dashboard_link: include, ..test_config() };
+
+            syncer(&provider, "2.2.2.2", &notifier).run_once(&config, &mut State::default()).await.unwrap();
+
+            let link = notifier.events.lock().unwrap()[0].dashboard_link.clone();
+            assert_eq!(link, include.then(|| "https://dash.example/zone/id-home.example.com".to_string()));
+        }
+    }
+
+    /// Tests that with `NOTIFY_RATE_WINDOW` every change is written, but only the first of a window
+    /// is notified, and the rest are summed up once the window is over, even by a quiet tick.
+    #[tokio::test]
+    async fn test_notify_rate_window() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let config = Config { notify_rate_window: Duration::from_secs(3600), ..test_config() };
+        let mut state = State::default();
+        let statuses = || notifier.events.lock().unwrap().iter().map(|event| (event.status.clone(), event.new_ip.clone())).collect::<Vec<_>>();
+        let window_over = |state: &mut State| state.throttle.notified_at = Some(state.throttle.notified_at.unwrap() - TimeDelta::minutes(61));
+
+        for ip in ["2.2.2.2", "3.3.3.3", "4.4.4.4"] {
+            syncer(&provider, ip, &notifier).run_once(&config, &mut state).await.unwrap();
+        }
+        assert_eq!(provider.writes.lock().unwrap().len(), 3);
+        assert_eq!(statuses(), vec![(EventStatus::Success, "2.2.2.2".to_string())]);
+
+        window_over(&mut state);
+        syncer(&provider, "5.5.5.5", &notifier).run_once(&config, &mut state).await.unwrap();
+        assert_eq!(provider.writes.lock().unwrap().len(), 4);
+        assert_eq!(statuses()[1], (EventStatus::Throttled { changes: 3, minutes: 61 }, "5.5.5.5".to_string()));
+
+        syncer(&provider, "6.6.6.6", &notifier).run_once(&config, &mut state).await.unwrap();
+        window_over(&mut state);
+        syncer(&provider, "6.6.6.6", &notifier).run_once(&config, &mut state).await.unwrap();
+        assert_eq!(provider.writes.lock().unwrap().len(), 5);
+        assert_eq!(statuses()[2], (EventStatus::Throttled { changes: 1, minutes: 61 }, "6.6.6.6".to_string()));
+        assert_eq!(statuses().len(), 3);
+        assert_eq!(state.throttle.held, None);
+    }
+
+    /// Tests that with `ATOMIC_DUAL_STACK`, an A record updated in a tick whose AAAA record
+    /// failed is pointed back at its previous address and reported as failed.
+    #[tokio::test]
+    async fn test_atomic_dual_stack_rolls_back() {
+        for atomic in [true, false] {
+            let v6 = DnsRecord { r#type: "AAAA".to_string(), ..record("v6.example.com", "2001:db8::1") };
+            let provider = MockProvider {
+                failing: vec!["v6.example.com".to_string()],
+                ..MockProvider::with_records(vec![record("home.example.com", "1.1.1.1"), v6])
+            };
+            let notifier = RecordingNotifier::default();
+            let mut config = Config { atomic_dual_stack: atomic, ..test_config() };
+            config.zones[0].records = vec!["home.example.com".to_string(), "v6.example.com".to_string()];
+            config.sources = Sources::from([
+                ("v6.example.com".to_string(), vec![RecordSource { address: AddressSource::HttpV6, record_type: "AAAA".to_string() }]),
+            ]);
+            let sources: HashMap<(String, String), Box<dyn IpSource>> = HashMap::from([
+                (("v6.example.com".to_string(), "AAAA".to_string()), Box::new(StaticIp("2001:db8::2")) as Box<dyn IpSource>),
+            ]);
+            let mut state = State::default();
+
+            let summary = syncer(&provider, "2.2.2.2", &notifier).with_sources(sources).run_once(&config, &mut state).await.unwrap();
+
+            let writes = provider.writes.lock().unwrap().clone();
+            if atomic {
+                assert!(matches!(&summary.records[0].result, Ok(TickOutcome::Failed { error }) if error.contains("rolled back to 1.1.1.1")), "{:?}", summary.records[0]);
+                assert_eq!(writes, vec![("home.example.com".to_string(), "2.2.2.2".to_string()), ("home.example.com".to_string(), "1.1.1.1".to_string())]);
+                assert_eq!(provider.records.lock().unwrap()[0].content, "1.1.1.1");
+                assert_eq!(state.records.get("home.example.com").map(String::as_str), Some("1.1.1.1"));
+                assert_eq!(state.last_public_ip, None);
+                let events = notifier.events.lock().unwrap().clone();
+                assert!(events.iter().all(|event| matches!(event.status, EventStatus::Failure(_))), "{:?}", events);
+            } else {
+                assert!(matches!(summary.records[0].result, Ok(TickOutcome::Updated { .. })));
+                assert_eq!(writes, vec![("home.example.com".to_string(), "2.2.2.2".to_string())]);
+            }
+            assert!(matches!(summary.records[1].result, Ok(TickOutcome::Failed { .. })));
+        }
+    }
+
+    /// Tests that `REFUSE_CGNAT` leaves the records alone behind carrier-grade NAT, and only
+    /// notifies the first time the address is seen.
+    #[tokio::test]
+    async fn test_refuses_cgnat_address() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let config = Config { refuse_cgnat: true, ..test_config() };
+        let mut state = State::default();
+
+        for _ in 0..2 {
+            let outcome = only(syncer(&provider, "100.72.1.2", &notifier).run_once(&config, &mut state).await.unwrap());
+            assert!(matches!(&outcome, TickOutcome::Failed { error } if error.contains("carrier-grade NAT")), "{:?}", outcome);
+        }
+
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert_eq!(notifier.events.lock().unwrap().len(), 1);
+        assert_eq!(state.failure_streak, 2);
+    }
+
+    /// Tests that an empty answer from the IP source fails the tick instead of blanking the records.
+    #[tokio::test]
+    async fn test_empty_ip_is_a_fetch_error() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let mut state = State::default();
+
+        let err = syncer(&provider, " \r", &notifier).run_once(&test_config(), &mut state).await.unwrap_err();
+
+        assert!(matches!(&err, SkySyncError::IpFetch(inner) if inner.to_string() == "the IP source answered with nothing"), "{:?}", err);
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert_eq!(state.current_ip, None);
+    }
+
+    /// Tests that monitor mode notifies about a changed IP without touching the records.
+    #[tokio::test]
+    async fn test_monitor_notifies_without_updating() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let config = Config { mode: Mode::Monitor, ..test_config() };
+        let mut state = State { last_public_ip: Some("1.1.1.1".to_string()), ..State::default() };
+
+        let outcome = only(syncer(&provider, "1.1.1.1", &notifier).reconcile(&config, &mut state).await.unwrap());
+        assert_eq!(outcome, TickOutcome::Unchanged { ip: "1.1.1.1".to_string() });
+        assert!(notifier.events.lock().unwrap().is_empty());
+
+        let outcome = only(syncer(&provider, "2.2.2.2", &notifier).run_once(&config, &mut state).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Detected { old: "1.1.1.1".to_string(), new: "2.2.2.2".to_string() });
+        assert!(provider.writes.lock().unwrap().is_empty());
+        assert_eq!(state.last_public_ip.as_deref(), Some("2.2.2.2"));
+        let events = notifier.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].domain.as_str(), &events[0].status), ("home.example.com", &EventStatus::Changed));
+    }
+
+    /// Tests that forcing rewrites a record that already points at the IP.
+    #[tokio::test]
+    async fn test_force_rewrites_current_record() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "2.2.2.2")]);
+        let notifier = RecordingNotifier::default();
+        let mut state = State { last_public_ip: Some("2.2.2.2".to_string()), ..State::default() };
+
+        let syncer = syncer(&provider, "2.2.2.2", &notifier).with_force(true);
+        let outcome = only(syncer.run_once(&test_config(), &mut state).await.unwrap());
+
+        assert_eq!(outcome, TickOutcome::Updated { old: "2.2.2.2".to_string(), new: "2.2.2.2".to_string() });
+        assert_eq!(provider.writes.lock().unwrap().len(), 1);
+        assert_eq!(state.last_public_ip.as_deref(), Some("2.2.2.2"));
+    }
+
+    /// Tests that whatever the IP source returns is validated before reaching the provider.
+    #[tokio::test]
+    async fn test_rejects_invalid_ip() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+
+        let syncer = syncer(&provider, "<html>rate limited</html>", &notifier).with_force(true);
+        let result = syncer.run_once(&test_config(), &mut State::default()).await;
+
+        assert!(matches!(result, Err(SkySyncError::IpFetch(_))));
+        assert!(provider.writes.lock().unwrap().is_empty());
+    }
+
+    /// Tests that ticks time every stage they go through for `GET /metrics`.
+    #[tokio::test]
+    async fn test_records_stage_timings() {
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier).with_force(true);
+        let mut state = State::default();
+
+        for _ in 0..3 {
+            syncer.run_once(&test_config(), &mut state).await.unwrap();
+        }
+
+        // Other tests tick through the same metrics, so there are at least as many observations
+        for stage in [Stage::IpFetch, Stage::List, Stage::Update] {
+            let histogram = Metrics::global().histogram(stage);
+            assert!(histogram.count >= 3 && histogram.sum >= 0.0, "{:?}: {:?}", stage, histogram);
+        }
+        assert!(Metrics::global().render().contains("skysync_tick_duration_seconds "));
+    }
+
+    /// Tests what each `ON_DETECTION_FAILURE` policy makes of a failed lookup: none of them
+    /// writes a record, but only `reuse-last` lets the tick succeed and only `error` notifies.
+    #[tokio::test]
+    async fn test_on_detection_failure() {
+        for policy in [DetectionFailure::Skip, DetectionFailure::ReuseLast, DetectionFailure::Error] {
+            // The record was changed by hand, which a tick that went through would revert
+            let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+            let notifier = RecordingNotifier::default();
+            let config = Config { on_detection_failure: policy, ..test_config() };
+            let mut state = State { last_public_ip: Some("2.2.2.2".to_string()), ..State::default() };
+
+            let result = syncer(&provider, "<html>rate limited</html>", &notifier).run_once(&config, &mut state).await;
+
+            assert!(provider.writes.lock().unwrap().is_empty(), "{:?}", policy);
+            let events = notifier.events.lock().unwrap().clone();
+            match policy {
+                DetectionFailure::Skip => {
+                    assert!(matches!(result, Err(SkySyncError::IpFetch(_))));
+                    assert!(events.is_empty());
+                }
+                DetectionFailure::ReuseLast => {
+                    assert_eq!(only(result.unwrap()), TickOutcome::Unchanged { ip: "2.2.2.2".to_string() });
+                    assert!(events.is_empty());
+                    assert_eq!(state.failure_streak, 0);
+                }
+                DetectionFailure::Error => {
+                    assert!(matches!(result, Err(SkySyncError::IpFetch(_))));
+                    assert_eq!(events.len(), 1);
+                    assert!(matches!(&events[0].status, EventStatus::Failure(error) if error.contains("is not an IP address")), "{:?}", events[0].status);
+                }
+            }
+        }
+
+        // Without an IP to reuse, the tick fails like it would have anyway
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let config = Config { on_detection_failure: DetectionFailure::ReuseLast, ..test_config() };
+        let result = syncer(&provider, "", &notifier).run_once(&config, &mut State::default()).await;
+        assert!(matches!(result, Err(SkySyncError::IpFetch(_))));
+    }
+
+    /// Tests that notifications carry the location of the new IP, looked up once per address.
+    #[tokio::test]
+    async fn test_geo_enrichment_is_cached() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "status": "success", "country": "Brazil", "regionName": "São Paulo", "isp": "Example", "as": "AS64496"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier)
+            .with_geo(GeoLocator::new(server.uri(), reqwest::Client::new()))
+            .with_force(true);
+        let mut state = State::default();
+
+        syncer.run_once(&test_config(), &mut state).await.unwrap();
+        syncer.run_once(&test_config(), &mut state).await.unwrap();
+
+        let events = notifier.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.geo.as_ref().is_some_and(|geo| geo.country == "Brazil")));
+    }
+
+    /// Tests that a failed geo lookup still sends the notification, just without the location.
+    #[tokio::test]
+    async fn test_geo_enrichment_fails_soft() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(503)).mount(&server).await;
+        let provider = MockProvider::with_records(vec![record("home.example.com", "1.1.1.1")]);
+        let notifier = RecordingNotifier::default();
+        let syncer = syncer(&provider, "2.2.2.2", &notifier).with_geo(GeoLocator::new(server.uri(), reqwest::Client::new()));
+        let mut state = State::default();
+
+        let outcome = only(syncer.run_once(&test_config(), &mut state).await.unwrap());
+
+        assert!(matches!(outcome, TickOutcome::Updated { .. }));
+        assert_eq!(notifier.events.lock().unwrap()[0].geo, None);
+        assert!(state.geo_cache.is_empty());
+    }
+
+    /// Tests that the clients only stop fitting a reload that changes how they connect.
+    #[test]
+    fn test_clients_fit() {
+        let config = test_config();
+        let clients = Clients::from_config(&config);
+
+        assert!(clients.fit(&config));
+        assert!(clients.fit(&Config { cron_interval: Duration::from_secs(5), ..config.clone() }));
+        assert!(!clients.fit(&Config { pool: PoolConfig { max_idle: 0, ..config.pool.clone() }, ..config.clone() }));
+        assert!(!clients.fit(&Config { resolver: crate::resolver::PUBLIC_RESOLVERS.to_vec(), ..config.clone() }));
+        assert!(!clients.fit(&Config { ip_family: Some(AddressFamily::V6), ..config.clone() }));
+        let proxy = ProxyConfig { url: "socks5://127.0.0.1:1080".to_string(), user: None, pass: None };
+        assert!(!clients.fit(&Config { proxy: Some(proxy), ..config }));
+    }
+}
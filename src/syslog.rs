@@ -0,0 +1,104 @@
+//! Sends log messages to the system logger for `LOG_TARGET=syslog`. They go out as RFC 3164
+//! datagrams on the local socket, which both syslog daemons and journald read.
+use crate::log::Level;
+use chrono::{DateTime, Local};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
+
+/// The `daemon` facility, which messages are filed under.
+const FACILITY_DAEMON: u8 = 3;
+
+/// What messages are tagged with.
+const IDENT: &str = "skysync";
+
+/// Where the system logger listens, on Linux and then on the BSDs and macOS.
+#[cfg(unix)]
+const SOCKETS: [&str; 2] = ["/dev/log", "/var/run/syslog"];
+
+/// Formats one line of a message the way the system logger reads it, e.g.
+/// `<30>Oct 14 09:00:00 skysync[42]: Updated home.example.com`.
+///
+/// # Arguments
+/// * `level` - How important the message is.
+/// * `pid` - The process the message comes from.
+/// * `at` - When the message was logged.
+/// * `line` - The line itself.
+pub fn format(level: Level, pid: u32, at: DateTime<Local>, line: &str) -> String {
+    format!("<{}>{} {}[{}]: {}", FACILITY_DAEMON * 8 + level.severity(), at.format("%b %e %H:%M:%S"), IDENT, pid, line)
+}
+
+/// A connection to the system logger.
+#[cfg(unix)]
+pub struct Syslog {
+    socket: UnixDatagram,
+}
+
+#[cfg(unix)]
+impl Syslog {
+    /// Connects to the first socket the system logger listens on.
+    pub fn connect() -> std::io::Result<Syslog> {
+        let mut last = None;
+        for path in SOCKETS {
+            match Syslog::connect_to(Path::new(path)) {
+                Ok(syslog) => return Ok(syslog),
+                Err(err) => last = Some(err),
+            }
+        }
+        Err(last.unwrap_or_else(|| std::io::ErrorKind::NotFound.into()))
+    }
+
+    /// Connects to the system logger listening on `path`.
+    pub fn connect_to(path: &Path) -> std::io::Result<Syslog> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        // A logger that can't keep up loses lines rather than stalling the daemon
+        socket.set_nonblocking(true)?;
+        Ok(Syslog { socket })
+    }
+
+    /// Sends every non-empty line of `message`. Lines the logger doesn't take are dropped, as
+    /// the file can't be written to about them either.
+    pub fn send(&self, level: Level, message: &str) {
+        let pid = std::process::id();
+        for line in message.lines().filter(|line| !line.trim().is_empty()) {
+            let _ = self.socket.send(format(level, pid, Local::now(), line).as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Tests that each level is sent with its severity, under the daemon facility.
+    #[test]
+    fn test_severity_mapping() {
+        let at = Local.with_ymd_and_hms(2026, 10, 4, 9, 0, 0).unwrap();
+
+        assert_eq!(format(Level::Debug, 42, at, "Checking"), "<31>Oct  4 09:00:00 skysync[42]: Checking");
+        assert_eq!(format(Level::Info, 42, at, "Updated"), "<30>Oct  4 09:00:00 skysync[42]: Updated");
+        assert_eq!(format(Level::Warning, 42, at, "Failed"), "<28>Oct  4 09:00:00 skysync[42]: Failed");
+    }
+
+    /// Tests that each line of a message is sent as its own datagram.
+    #[cfg(unix)]
+    #[test]
+    fn test_sends_each_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let logger = UnixDatagram::bind(&path).unwrap();
+
+        Syslog::connect_to(&path).unwrap().send(Level::Info, "Updated home\n\nUpdated www\n");
+
+        let mut buffer = [0; 256];
+        for expected in ["Updated home", "Updated www"] {
+            let received = logger.recv(&mut buffer).unwrap();
+            let datagram = String::from_utf8_lossy(&buffer[..received]).to_string();
+            assert!(datagram.starts_with("<30>"), "{}", datagram);
+            assert!(datagram.ends_with(&format!("]: {}", expected)), "{}", datagram);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+/// The zone timestamps in logs and notifications are shown in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Timezone {
+    /// Whatever zone the host is configured with.
+    #[default]
+    Local,
+    /// An IANA zone like `America/Sao_Paulo` or `UTC`.
+    Named(Tz),
+}
+
+impl Timezone {
+    /// The current time in this zone.
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        self.convert(&Utc::now())
+    }
+
+    /// Shows an instant in this zone.
+    pub fn convert<T: TimeZone>(&self, time: &DateTime<T>) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Local => time.with_timezone(&Local).fixed_offset(),
+            Timezone::Named(tz) => time.with_timezone(tz).fixed_offset(),
+        }
+    }
+
+    /// The instant a wall-clock time in this zone stands for, the earlier one when a DST change
+    /// repeats it, and `None` when it skips it.
+    pub fn localize(&self, time: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Timezone::Local => Local.from_local_datetime(&time).earliest().map(|time| time.fixed_offset()),
+            Timezone::Named(tz) => tz.from_local_datetime(&time).earliest().map(|time| time.fixed_offset()),
+        }
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timezone::Local => write!(f, "local"),
+            Timezone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    /// Parses `local` or an IANA zone name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Timezone::Local);
+        }
+        s.parse::<Tz>().map(Timezone::Named).map_err(|_| "expected \"local\" or an IANA timezone like \"America/Sao_Paulo\"".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that a configured zone shows timestamps with its offset.
+    #[test]
+    fn test_named_zone_offset() {
+        let instant = DateTime::parse_from_rfc3339("2024-07-01T12:00:00Z").unwrap();
+        let timezone: Timezone = "America/Sao_Paulo".parse().unwrap();
+
+        assert_eq!(timezone.convert(&instant).to_rfc3339(), "2024-07-01T09:00:00-03:00");
+        assert_eq!("UTC".parse::<Timezone>().unwrap().convert(&instant).to_rfc3339(), "2024-07-01T12:00:00+00:00");
+        assert_eq!("Local".parse::<Timezone>(), Ok(Timezone::Local));
+        assert!("Mars/Olympus_Mons".parse::<Timezone>().is_err());
+    }
+}
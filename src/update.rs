@@ -0,0 +1,127 @@
+//! The opt-in startup check for newer SkySync releases, enabled with `CHECK_UPDATES`.
+//!
+//! The only thing sent is the running version, as the `User-Agent` of a single GET request.
+use crate::error::BoxError;
+use serde::Deserialize;
+
+/// The running version.
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The GitHub API endpoint describing the latest release.
+pub const DEFAULT_RELEASES_URL: &str = "https://api.github.com/repos/tockawaffle/SkySync/releases/latest";
+
+/// Where users are pointed to for the new release.
+const RELEASES_PAGE: &str = "https://github.com/tockawaffle/SkySync/releases/latest";
+
+/// The part of a GitHub release the check reads.
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// Asks the release endpoint for the latest version.
+pub struct UpdateChecker {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl UpdateChecker {
+    pub fn new(url: impl Into<String>, client: reqwest::Client) -> UpdateChecker {
+        UpdateChecker { url: url.into(), client }
+    }
+
+    /// Fetches the version of the latest release.
+    ///
+    /// # Returns
+    /// The version without its `v` prefix, e.g. `0.2.0`, or an error if the endpoint couldn't be
+    /// reached or answered something else.
+    pub async fn latest(&self) -> Result<String, BoxError> {
+        let release: Release = self.client
+            .get(&self.url)
+            // GitHub rejects requests without one
+            .header(reqwest::header::USER_AGENT, format!("SkySync/{}", CURRENT_VERSION))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(release.tag_name.trim_start_matches('v').to_string())
+    }
+
+    /// The notice to log when a newer release is out.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether `CHECK_UPDATES` is set. Nothing is requested otherwise.
+    ///
+    /// # Returns
+    /// A one-line notice, or `None` when disabled, up to date or the check failed for any reason.
+    pub async fn notice(&self, enabled: bool) -> Option<String> {
+        if !enabled {
+            return None;
+        }
+        let latest = self.latest().await.ok()?;
+        newer(&latest, CURRENT_VERSION).then(|| format!("SkySync {} is available, this is {}: {}", latest, CURRENT_VERSION, RELEASES_PAGE))
+    }
+}
+
+/// Whether `version` is a later release than `current`, comparing `major.minor.patch` numerically.
+/// Versions that don't parse are never newer.
+fn newer(version: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Option<Vec<u64>> {
+        let release = version.split(['-', '+']).next()?;
+        release.split('.').map(|part| part.parse().ok()).collect()
+    };
+    match (parse(version), parse(current)) {
+        (Some(version), Some(current)) => version > current,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Tests that nothing is requested while the check is disabled.
+    #[tokio::test]
+    async fn test_skipped_when_disabled() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "tag_name": "v99.0.0" })))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        assert_eq!(UpdateChecker::new(server.uri(), reqwest::Client::new()).notice(false).await, None);
+    }
+
+    /// Tests that a newer release is noticed, sending only the version, and an older one or a
+    /// failed request is not.
+    #[tokio::test]
+    async fn test_notices_newer_release() {
+        let server = MockServer::start().await;
+        let user_agent = format!("SkySync/{}", CURRENT_VERSION);
+        Mock::given(method("GET"))
+            .and(header("User-Agent", user_agent.as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "tag_name": "v99.0.0", "name": "SkySync 99" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notice = UpdateChecker::new(server.uri(), reqwest::Client::new()).notice(true).await.unwrap();
+        assert!(notice.starts_with(&format!("SkySync 99.0.0 is available, this is {}", CURRENT_VERSION)), "{}", notice);
+        assert_eq!(UpdateChecker::new("http://127.0.0.1:9", reqwest::Client::new()).notice(true).await, None);
+    }
+
+    /// Tests the version comparison.
+    #[test]
+    fn test_newer() {
+        assert!(newer("0.10.0", "0.9.3"));
+        assert!(newer("1.0.0", "0.1.0"));
+        assert!(!newer("0.1.0", "0.1.0"));
+        assert!(!newer("0.0.9", "0.1.0"));
+        assert!(!newer("nightly", "0.1.0"));
+    }
+}